@@ -0,0 +1,25 @@
+// Shader that covers every area that isn't reached by any light with fog.
+
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+@group(0) @binding(0)
+var screen_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var lightmap_texture: texture_2d<f32>;
+
+@group(0) @binding(2)
+var texture_sampler: sampler;
+
+const FOG_COLOR: vec3<f32> = vec3<f32>(0.04, 0.04, 0.1);
+
+@fragment
+fn fragment(vo: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(screen_texture, texture_sampler, vo.uv);
+    let light = textureSample(lightmap_texture, texture_sampler, vo.uv).rgb;
+
+    // how much the fragment is lit, ignoring the ambient light 
+    let visibility = smoothstep(0.0, 0.2, max(light.r, max(light.g, light.b)));
+
+    return vec4<f32>(mix(FOG_COLOR, scene.rgb, visibility), scene.a);
+}
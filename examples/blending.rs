@@ -12,6 +12,7 @@ fn main() {
     app.add_plugins(FireflyPlugin);
 
     app.add_systems(Startup, setup);
+    app.add_systems(Update, controls);
 
     app.run();
 }
@@ -48,4 +49,61 @@ fn setup(mut commands: Commands) {
         },
         Transform::from_translation(vec3(30.0, 0.0, 0.0)),
     ));
+
+    // red filter: only casts shadows from the red part of the light, the rest passes through
+    commands.spawn((
+        Occluder2d::rectangle(4.0, 20.0).with_blocks_channels(vec3(1.0, 0.0, 0.0)),
+        Transform::from_translation(vec3(0.0, 0.0, 0.0)),
+    ));
+
+    // regular occluder, blocking both lights
+    commands.spawn((
+        Occluder2d::circle(4.0),
+        Transform::from_translation(vec3(0.0, 30.0, 0.0)),
+    ));
+}
+
+// Up / Down: change exposure, L: toggle luminance limit, H: toggle hdr, C: toggle red and blue lights
+fn controls(
+    camera: Single<(Entity, &mut FireflyConfig, Has<Hdr>)>,
+    mut lights: Query<(&mut PointLight2d, &Transform)>,
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+    mut commands: Commands,
+) {
+    let (entity, mut config, hdr) = camera.into_inner();
+
+    if keys.pressed(KeyCode::ArrowUp) {
+        config.exposure += time.delta_secs();
+    }
+    if keys.pressed(KeyCode::ArrowDown) {
+        config.exposure = (config.exposure - time.delta_secs()).max(0.0);
+    }
+
+    if keys.just_pressed(KeyCode::KeyL) {
+        config.max_luminance = match config.max_luminance {
+            None => Some(1.0),
+            Some(_) => None,
+        };
+    }
+
+    if keys.just_pressed(KeyCode::KeyH) {
+        match hdr {
+            true => commands.entity(entity).remove::<Hdr>(),
+            false => commands.entity(entity).insert(Hdr),
+        };
+    }
+
+    if keys.just_pressed(KeyCode::KeyC) {
+        for (mut light, transform) in &mut lights {
+            light.color = match (
+                light.color == Color::Srgba(WHITE),
+                transform.translation.x < 0.0,
+            ) {
+                (false, _) => Color::Srgba(WHITE),
+                (true, true) => Color::Srgba(RED),
+                (true, false) => Color::Srgba(BLUE),
+            };
+        }
+    }
 }
@@ -0,0 +1,73 @@
+use bevy::{camera::Hdr, prelude::*};
+use bevy_firefly::prelude::*;
+
+// Example that slides a light through round occluders, to check their shadows at close range.
+// The light passes through the occluders' centers, and along their edges while Space is held.
+// The shadows behind the occluders should stay solid, without flickering or leaking light.
+
+fn main() {
+    let mut app = App::new();
+
+    app.add_plugins((DefaultPlugins, FireflyPlugin, FireflyGizmosPlugin));
+
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, slide_light);
+
+    app.run();
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        Camera2d,
+        Hdr,
+        FireflyConfig {
+            ambient_brightness: 0.1,
+            ..default()
+        },
+    ));
+
+    commands.spawn((
+        PointLight2d {
+            intensity: 3.0,
+            radius: 400.0,
+            core: LightCore {
+                radius: 10.0,
+                ..default()
+            },
+            ..default()
+        },
+        Transform::default(),
+    ));
+
+    commands.spawn((
+        Occluder2d::horizontal_capsule(80., 20.),
+        Transform::from_translation(vec3(-250., 0., 0.)),
+    ));
+
+    commands.spawn((
+        Occluder2d::circle(30.),
+        Transform::from_translation(vec3(0., 0., 0.)),
+    ));
+
+    commands.spawn((
+        Occluder2d::round_rectangle(60., 40., 15.),
+        Transform::from_translation(vec3(250., 0., 0.)).with_rotation(Quat::from_rotation_z(0.3)),
+    ));
+}
+
+fn slide_light(
+    mut lights: Query<&mut Transform, With<PointLight2d>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    time: Res<Time>,
+) {
+    // the top edge of the capsule and the circle
+    let y = match keys.pressed(KeyCode::Space) {
+        true => 30.,
+        false => 0.,
+    };
+
+    for mut transform in &mut lights {
+        let x = (time.elapsed_secs() * 0.3).sin() * 400.;
+        transform.translation = vec3(x, y, 0.);
+    }
+}
@@ -34,38 +34,50 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     ));
 
     commands.spawn((
-        Sprite::from_image(asset_server.load("crate.png")),
+        lit_sprite(
+            "crate.png",
+            "crate_normal.png",
+            Occluder2d::rectangle(12., 5.1),
+            &asset_server,
+        ),
         Anchor(vec2(0.0, -0.5 + 3.0 / 18.0)),
-        NormalMap::from_file("crate_normal.png", &asset_server),
         Transform::from_translation(vec3(0., -20., 20.)),
-        Occluder2d::rectangle(12., 5.1),
         // component added to simulate height for the normal maps. Could be useful if the object is floating above the ground.
         // this can safely not be added, and it defaults to 0.
         SpriteHeight(0.),
     ));
 
     commands.spawn((
-        Sprite::from_image(asset_server.load("crate.png")),
+        lit_sprite(
+            "crate.png",
+            "crate_normal.png",
+            Occluder2d::rectangle(12., 5.1),
+            &asset_server,
+        ),
         Anchor(vec2(0.0, -0.5 + 3.0 / 18.0)),
-        NormalMap::from_file("crate_normal.png", &asset_server),
         Transform::from_translation(vec3(-20., 20., 0.)),
-        Occluder2d::rectangle(12., 5.1),
     ));
 
     commands.spawn((
-        Sprite::from_image(asset_server.load("vase.png")),
+        lit_sprite(
+            "vase.png",
+            "vase_normal.png",
+            Occluder2d::round_rectangle(5.4, 0.5, 3.),
+            &asset_server,
+        ),
         Anchor(vec2(0.0, -0.5 + 5.0 / 19.0)),
-        NormalMap::from_file("vase_normal.png", &asset_server),
         Transform::from_translation(vec3(0., 20., 0.)),
-        Occluder2d::round_rectangle(5.4, 0.5, 3.),
     ));
 
     commands.spawn((
-        Sprite::from_image(asset_server.load("vase.png")),
+        lit_sprite(
+            "vase.png",
+            "vase_normal.png",
+            Occluder2d::round_rectangle(5.4, 0.5, 3.),
+            &asset_server,
+        ),
         Anchor(vec2(0.0, -0.5 + 5.0 / 19.0)),
-        NormalMap::from_file("vase_normal.png", &asset_server),
         Transform::from_translation(vec3(10., -20., 0.)),
-        Occluder2d::round_rectangle(5.4, 0.5, 3.),
     ));
 
     commands.spawn((
@@ -121,10 +133,7 @@ fn drag_objects(
     mut dragged: ResMut<Dragged>,
     mut gizmos: Gizmos,
 ) {
-    let Some(cursor_position) = window
-        .cursor_position()
-        .and_then(|cursor| camera.0.viewport_to_world_2d(&camera.1, cursor).ok())
-    else {
+    let Some(cursor_position) = world_cursor(&window, *camera) else {
         dragged.0 = None;
         return;
     };
@@ -242,10 +242,7 @@ fn move_light(
         light.1.angle.outer = light.1.angle.inner + 30.0;
     }
 
-    let Some(mouse_pos) = window
-        .cursor_position()
-        .and_then(|cursor| camera.0.viewport_to_world_2d(&camera.1, cursor).ok())
-    else {
+    let Some(mouse_pos) = world_cursor(&window, *camera) else {
         return;
     };
 
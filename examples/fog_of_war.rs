@@ -0,0 +1,176 @@
+//! This example demonstrates how to read the lightmap inside your own render pass.
+//!
+//! The [`LightMapTexture`] component is added to each camera with a [`FireflyConfig`] and is available
+//! to any pass that runs after Firefly's `create_lightmap` pass. Here, a custom pass uses it
+//! to cover every area that isn't reached by any light with fog.
+
+use bevy::{
+    camera::Hdr,
+    color::palettes::css::WHITE,
+    core_pipeline::{Core2d, FullscreenShader, tonemapping::tonemapping},
+    prelude::*,
+    render::{
+        RenderApp, RenderStartup,
+        render_resource::{
+            BindGroupEntries, BindGroupLayoutDescriptor, BindGroupLayoutEntries,
+            CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState, PipelineCache,
+            RenderPassColorAttachment, RenderPassDescriptor, RenderPipelineDescriptor, Sampler,
+            SamplerBindingType, SamplerDescriptor, ShaderStages, TextureSampleType,
+            binding_types::{sampler, texture_2d},
+        },
+        renderer::{RenderContext, RenderDevice, ViewQuery},
+        view::ViewTarget,
+    },
+};
+use bevy_firefly::{LightMapTexture, nodes::apply_lightmap, prelude::*};
+
+// The shader that will draw the fog over the camera's output.
+const FOG_SHADER_ASSET_PATH: &str = "shaders/fog_of_war.wgsl";
+
+fn main() {
+    let mut app = App::new();
+
+    app.add_plugins((DefaultPlugins, FireflyPlugin))
+        .add_systems(Startup, setup)
+        .add_systems(Update, move_light);
+
+    let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+        return;
+    };
+
+    render_app
+        .add_systems(RenderStartup, init_fog_pipeline)
+        // The lightmap can be read by any pass that runs after `create_lightmap`.
+        // We run after `apply_lightmap` as well, so that the fog is drawn over the already lit scene.
+        .add_systems(Core2d, fog_of_war.after(apply_lightmap).before(tonemapping));
+
+    app.run();
+}
+
+fn setup(mut commands: Commands) {
+    commands.spawn((
+        Camera2d,
+        Hdr,
+        FireflyConfig {
+            ambient_brightness: 0.5,
+            ..default()
+        },
+    ));
+
+    commands.spawn((
+        PointLight2d {
+            color: Color::Srgba(WHITE),
+            intensity: 2.0,
+            radius: 200.,
+            ..default()
+        },
+        Transform::default(),
+    ));
+
+    for pos in [vec2(60., 0.), vec2(-60., 0.), vec2(0., 60.), vec2(0., -60.)] {
+        commands.spawn((
+            Occluder2d::rectangle(30., 30.),
+            Transform::from_translation(pos.extend(0.)),
+        ));
+    }
+}
+
+fn move_light(mut light: Single<&mut Transform, With<PointLight2d>>, time: Res<Time>) {
+    let t = time.elapsed_secs() * 0.5;
+    light.translation = vec3(t.cos(), t.sin(), 0.) * 100.;
+}
+
+#[derive(Resource)]
+struct FogPipeline {
+    layout: BindGroupLayoutDescriptor,
+    sampler: Sampler,
+    pipeline_id: CachedRenderPipelineId,
+}
+
+fn init_fog_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    fullscreen_shader: Res<FullscreenShader>,
+    asset_server: Res<AssetServer>,
+    pipeline_cache: Res<PipelineCache>,
+) {
+    let layout = BindGroupLayoutDescriptor::new(
+        "fog of war layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                // screen texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // lightmap texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // sampler
+                sampler(SamplerBindingType::Filtering),
+            ),
+        ),
+    );
+
+    let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+
+    let pipeline_id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+        label: Some("fog of war pipeline".into()),
+        layout: vec![layout.clone()],
+        vertex: fullscreen_shader.to_vertex_state(),
+        fragment: Some(FragmentState {
+            shader: asset_server.load(FOG_SHADER_ASSET_PATH),
+            targets: vec![Some(ColorTargetState {
+                format: ViewTarget::TEXTURE_FORMAT_HDR,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+            ..default()
+        }),
+        ..default()
+    });
+
+    commands.insert_resource(FogPipeline {
+        layout,
+        sampler,
+        pipeline_id,
+    });
+}
+
+fn fog_of_war(
+    view_query: ViewQuery<(&ViewTarget, &LightMapTexture)>,
+    fog_pipeline: Res<FogPipeline>,
+    pipeline_cache: Res<PipelineCache>,
+    mut render_context: RenderContext,
+) {
+    let (view_target, light_map_texture) = view_query.into_inner();
+
+    let Some(render_pipeline) = pipeline_cache.get_render_pipeline(fog_pipeline.pipeline_id) else {
+        return;
+    };
+
+    let post_process = view_target.post_process_write();
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "fog of war bind group",
+        &pipeline_cache.get_bind_group_layout(&fog_pipeline.layout),
+        &BindGroupEntries::sequential((
+            post_process.source,
+            // The lightmap is a regular `CachedTexture`, so it can be bound like any other texture.
+            &light_map_texture.0.default_view,
+            &fog_pipeline.sampler,
+        )),
+    );
+
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("fog of war pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: post_process.destination,
+            resolve_target: None,
+            ops: default(),
+            depth_slice: None,
+        })],
+        ..default()
+    });
+
+    render_pass.set_render_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
@@ -152,10 +152,7 @@ fn drag_objects(
     mut dragged: ResMut<Dragged>,
     mut gizmos: Gizmos,
 ) {
-    let Some(cursor_position) = window
-        .cursor_position()
-        .and_then(|cursor| camera.0.viewport_to_world_2d(&camera.1, cursor).ok())
-    else {
+    let Some(cursor_position) = world_cursor(&window, *camera) else {
         dragged.0 = None;
         return;
     };
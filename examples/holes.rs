@@ -0,0 +1,85 @@
+use bevy::{camera::Hdr, color::palettes::css::ORANGE, prelude::*};
+use bevy_firefly::prelude::*;
+
+fn main() {
+    let mut app = App::new();
+
+    app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()));
+    app.add_plugins(FireflyPlugin);
+
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, move_light);
+
+    app.run();
+}
+
+#[derive(Component)]
+struct MovingLight;
+
+fn setup(mut commands: Commands) {
+    let mut proj = OrthographicProjection::default_2d();
+    proj.scale = 0.15;
+
+    commands.spawn((
+        Camera2d,
+        Hdr::default(),
+        Projection::Orthographic(proj),
+        FireflyConfig::default(),
+    ));
+
+    // a light placed inside the window, blocked by its walls
+    commands.spawn((
+        PointLight2d {
+            radius: 80.0,
+            color: Color::Srgba(ORANGE),
+            core: LightCore::from_radius_boost(2.0, 3.0),
+            ..default()
+        },
+        Transform::from_translation(vec3(0.0, 0.0, 0.0)),
+    ));
+
+    // a light moving around the stone arch
+    commands.spawn((
+        PointLight2d {
+            radius: 100.0,
+            core: LightCore::from_radius_boost(3.0, 3.0),
+            ..default()
+        },
+        Transform::default(),
+        MovingLight,
+    ));
+
+    // a stone arch with a window in the middle and an opening at the bottom
+    let outer = vec![
+        vec2(-20.0, -20.0),
+        vec2(-8.0, -20.0),
+        vec2(-8.0, -12.0),
+        vec2(8.0, -12.0),
+        vec2(8.0, -20.0),
+        vec2(20.0, -20.0),
+        vec2(20.0, 20.0),
+        vec2(-20.0, 20.0),
+    ];
+    let window = vec![
+        vec2(-5.0, -3.0),
+        vec2(5.0, -3.0),
+        vec2(5.0, 8.0),
+        vec2(0.0, 12.0),
+        vec2(-5.0, 8.0),
+    ];
+
+    let pieces = Occluder2d::polygon_with_holes(outer, vec![window]).unwrap();
+
+    commands
+        .spawn((Transform::default(), Visibility::default()))
+        .with_children(|parent| {
+            for piece in pieces {
+                parent.spawn(piece);
+            }
+        });
+}
+
+fn move_light(mut light: Single<&mut Transform, With<MovingLight>>, time: Res<Time>) {
+    let t = time.elapsed_secs() * 0.5;
+    light.translation = vec3(t.cos() * 40.0, t.sin() * 40.0, 0.0);
+}
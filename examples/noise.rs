@@ -102,10 +102,7 @@ fn move_light(
         return;
     }
 
-    let Some(cursor_position) = window
-        .cursor_position()
-        .and_then(|cursor| camera.0.viewport_to_world_2d(&camera.1, cursor).ok())
-    else {
+    let Some(cursor_position) = world_cursor(&window, *camera) else {
         return;
     };
 
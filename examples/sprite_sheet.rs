@@ -0,0 +1,153 @@
+use std::f32::consts::PI;
+
+use bevy::{
+    asset::RenderAssetUsages,
+    camera::Hdr,
+    prelude::*,
+    render::render_resource::{Extent3d, TextureDimension, TextureFormat},
+};
+use bevy_firefly::{data::NormalMode, prelude::*};
+
+const FRAME_SIZE: u32 = 32;
+const FRAMES: u32 = 8;
+
+fn main() {
+    let mut app = App::new();
+
+    app.add_plugins(DefaultPlugins.set(ImagePlugin::default_nearest()));
+    app.add_plugins(FireflyPlugin);
+
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, (animate, move_light));
+
+    app.run();
+}
+
+#[derive(Component)]
+struct Animation(Timer);
+
+fn setup(
+    mut commands: Commands,
+    mut images: ResMut<Assets<Image>>,
+    mut layouts: ResMut<Assets<TextureAtlasLayout>>,
+) {
+    let mut proj = OrthographicProjection::default_2d();
+    proj.scale = 0.15;
+
+    commands.spawn((
+        Camera2d,
+        Hdr,
+        Projection::Orthographic(proj),
+        FireflyConfig {
+            normal_mode: NormalMode::Simple,
+            ambient_brightness: 0.1,
+            ..default()
+        },
+    ));
+
+    // the color and normal sheets share the same layout, so each frame samples its matching normals
+    let (color, normal) = coin_sheets();
+    let layout = layouts.add(TextureAtlasLayout::from_grid(
+        UVec2::splat(FRAME_SIZE),
+        FRAMES,
+        1,
+        None,
+        None,
+    ));
+
+    commands.spawn((
+        Sprite::from_atlas_image(images.add(color), TextureAtlas { layout, index: 0 }),
+        NormalMap::from_image(images.add(normal)),
+        Animation(Timer::from_seconds(0.1, TimerMode::Repeating)),
+    ));
+
+    commands.spawn((
+        PointLight2d {
+            intensity: 2.,
+            radius: 80.,
+            color: Color::srgb(1.0, 0.9, 0.8),
+            ..default()
+        },
+        Transform::from_translation(vec3(30., 0., 10.)),
+    ));
+}
+
+fn animate(mut sprites: Query<(&mut Sprite, &mut Animation)>, time: Res<Time>) {
+    for (mut sprite, mut animation) in &mut sprites {
+        animation.0.tick(time.delta());
+
+        if animation.0.just_finished()
+            && let Some(atlas) = &mut sprite.texture_atlas
+        {
+            atlas.index = (atlas.index + 1) % FRAMES as usize;
+        }
+    }
+}
+
+fn move_light(mut lights: Query<&mut Transform, With<PointLight2d>>, time: Res<Time>) {
+    for mut transform in &mut lights {
+        let angle = time.elapsed_secs() * 0.5;
+        transform.translation = vec3(angle.cos() * 30., angle.sin() * 30., 10.);
+    }
+}
+
+// generates the sprite sheet of a spinning coin, along with its normal map
+fn coin_sheets() -> (Image, Image) {
+    let width = FRAME_SIZE * FRAMES;
+    let mut color = vec![0; (width * FRAME_SIZE * 4) as usize];
+    let mut normal = vec![0; (width * FRAME_SIZE * 4) as usize];
+
+    let radius = FRAME_SIZE as f32 / 2. - 2.;
+
+    for frame in 0..FRAMES {
+        let half_width = (radius * (frame as f32 / FRAMES as f32 * PI).cos().abs()).max(1.);
+
+        for y in 0..FRAME_SIZE {
+            for x in 0..FRAME_SIZE {
+                let dx = (x as f32 + 0.5 - FRAME_SIZE as f32 / 2.) / half_width;
+                let dy = (y as f32 + 0.5 - FRAME_SIZE as f32 / 2.) / radius;
+
+                let d = dx * dx + dy * dy;
+                if d > 1. {
+                    continue;
+                }
+
+                // image rows go downwards, while normals point upwards
+                let n = vec3(dx, -dy, (1. - d).sqrt()).normalize();
+
+                let i = (((y * width) + frame * FRAME_SIZE + x) * 4) as usize;
+                color[i..i + 4].copy_from_slice(&[230, 180, 60, 255]);
+                normal[i..i + 4].copy_from_slice(&[
+                    ((n.x * 0.5 + 0.5) * 255.) as u8,
+                    ((n.y * 0.5 + 0.5) * 255.) as u8,
+                    ((n.z * 0.5 + 0.5) * 255.) as u8,
+                    255,
+                ]);
+            }
+        }
+    }
+
+    let size = Extent3d {
+        width,
+        height: FRAME_SIZE,
+        depth_or_array_layers: 1,
+    };
+
+    (
+        Image::new(
+            size,
+            TextureDimension::D2,
+            color,
+            TextureFormat::Rgba8UnormSrgb,
+            RenderAssetUsages::default(),
+        ),
+        // normal maps must not be gamma corrected
+        Image::new(
+            size,
+            TextureDimension::D2,
+            normal,
+            TextureFormat::Rgba8Unorm,
+            RenderAssetUsages::default(),
+        ),
+    )
+}
@@ -54,13 +54,14 @@ fn main() {
         LogDiagnosticsPlugin::default(),
         FrameTimeDiagnosticsPlugin::default(),
         EntityCountDiagnosticsPlugin::default(),
+        FireflyDiagnosticsPlugin,
         // SystemInformationDiagnosticsPlugin,
         // bevy::render::diagnostic::RenderDiagnosticsPlugin,
     ));
 
     app.add_systems(Startup, setup);
 
-    app.add_systems(Update, (change_scale, move_camera));
+    app.add_systems(Update, (change_scale, move_camera, toggle_accumulation));
 
     app.add_systems(Update, (spawn_lights, move_lights));
     app.add_systems(Update, (spawn_occluders, move_occluders));
@@ -116,6 +117,16 @@ fn change_scale(
     }
 }
 
+// press T to toggle temporal accumulation, which steadies the soft shadows of the moving lights
+fn toggle_accumulation(mut config: Single<&mut FireflyConfig>, keys: Res<ButtonInput<KeyCode>>) {
+    if keys.just_pressed(KeyCode::KeyT) {
+        config.temporal_accumulation = match config.temporal_accumulation {
+            Some(_) => None,
+            None => Some(TemporalAccumulation::default()),
+        };
+    }
+}
+
 const CAMERA_SPEED: f32 = 2000.0;
 fn move_camera(
     mut camera: Single<&mut Transform, With<FireflyConfig>>,
@@ -270,6 +281,11 @@ const FRAME_TIME_DIAGNOSTICS: [DiagnosticPath; 3] = [
     FrameTimeDiagnosticsPlugin::FRAME_TIME,
 ];
 const ENTITY_COUNT_DIAGNOSTICS: [DiagnosticPath; 1] = [EntityCountDiagnosticsPlugin::ENTITY_COUNT];
+const FIREFLY_DIAGNOSTICS: [DiagnosticPath; 3] = [
+    FireflyDiagnosticsPlugin::VISIBLE_LIGHTS,
+    FireflyDiagnosticsPlugin::VISIBLE_OCCLUDERS,
+    FireflyDiagnosticsPlugin::VERTEX_BUFFER_LEN,
+];
 const SYSTEM_INFO_DIAGNOSTICS: [DiagnosticPath; 4] = [
     SystemInformationDiagnosticsPlugin::PROCESS_CPU_USAGE,
     SystemInformationDiagnosticsPlugin::PROCESS_MEM_USAGE,
@@ -300,6 +316,9 @@ fn filters_inputs(
                 if filters.system_info {
                     enable_filters(&mut log_state, SYSTEM_INFO_DIAGNOSTICS);
                 }
+                if filters.firefly {
+                    enable_filters(&mut log_state, FIREFLY_DIAGNOSTICS);
+                }
                 LogDiagnosticsStatus::Enabled
             }
         };
@@ -336,6 +355,16 @@ fn filters_inputs(
             }
         }
     }
+    if keys.just_pressed(KeyCode::Digit5) {
+        filters.firefly = !filters.firefly;
+        if enabled {
+            if filters.firefly {
+                enable_filters(&mut log_state, FIREFLY_DIAGNOSTICS);
+            } else {
+                disable_filters(&mut log_state, FIREFLY_DIAGNOSTICS);
+            }
+        }
+    }
 }
 
 fn enable_filters(
@@ -455,6 +484,23 @@ fn update_commands(
                     )
                 ]
             ),
+            (
+                Node {
+                    flex_direction: FlexDirection::Row,
+                    column_gap: px(5),
+                    ..default()
+                },
+                children![
+                    (
+                        Text::new("[5] Firefly:"),
+                        TextColor(Color::WHITE.with_alpha(alpha))
+                    ),
+                    (
+                        Text::new(format!("{:?}", filters.firefly)),
+                        TextColor(enabled_color(filters.firefly).with_alpha(alpha))
+                    )
+                ]
+            ),
         ]);
 }
 
@@ -472,6 +518,7 @@ struct LogDiagnosticsFilters {
     frame_time: bool,
     entity_count: bool,
     system_info: bool,
+    firefly: bool,
     #[expect(
         dead_code,
         reason = "Currently the diagnostic paths referent to RenderDiagnosticPlugin are private"
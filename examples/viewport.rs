@@ -0,0 +1,97 @@
+use bevy::{
+    camera::Viewport,
+    color::palettes::css::{BLUE, RED},
+    prelude::*,
+};
+use bevy_firefly::prelude::*;
+
+// Example of a lit camera that only renders to the left half of the window, while an unlit camera renders the right half.
+// The light follows the cursor when it's over the lit half.
+
+fn main() {
+    let mut app = App::new();
+
+    app.add_plugins((DefaultPlugins, FireflyPlugin));
+    app.add_systems(Startup, setup);
+    app.add_systems(Update, (update_viewports, move_light));
+
+    app.run();
+}
+
+#[derive(Component)]
+struct LitCamera;
+
+#[derive(Component)]
+struct UnlitCamera;
+
+fn setup(mut commands: Commands) {
+    commands.spawn((Camera2d, FireflyConfig::default(), LitCamera));
+
+    commands.spawn((
+        Camera2d,
+        Camera {
+            order: 1,
+            clear_color: ClearColorConfig::None,
+            ..default()
+        },
+        UnlitCamera,
+    ));
+
+    commands.spawn((
+        PointLight2d {
+            color: Color::Srgba(RED),
+            intensity: 1.0,
+            radius: 300.,
+            ..default()
+        },
+        Transform::default(),
+    ));
+
+    commands.spawn((
+        Sprite::from_color(Color::Srgba(BLUE), vec2(600., 400.)),
+        Transform::from_translation(vec3(0., 0., -1.)),
+    ));
+
+    for pos in [vec2(80., 0.), vec2(-80., 0.), vec2(0., 80.), vec2(0., -80.)] {
+        commands.spawn((
+            Occluder2d::round_rectangle(20., 10., 5.),
+            Transform::from_translation(pos.extend(0.)),
+        ));
+    }
+}
+
+fn update_viewports(
+    window: Single<&Window>,
+    mut lit: Single<&mut Camera, (With<LitCamera>, Without<UnlitCamera>)>,
+    mut unlit: Single<&mut Camera, (With<UnlitCamera>, Without<LitCamera>)>,
+) {
+    let size = window.physical_size();
+    let half = uvec2(size.x / 2, size.y).max(UVec2::ONE);
+
+    if lit.viewport.as_ref().map(|viewport| viewport.physical_size) == Some(half) {
+        return;
+    }
+
+    lit.viewport = Some(Viewport {
+        physical_position: UVec2::ZERO,
+        physical_size: half,
+        ..default()
+    });
+
+    unlit.viewport = Some(Viewport {
+        physical_position: uvec2(size.x / 2, 0),
+        physical_size: half,
+        ..default()
+    });
+}
+
+fn move_light(
+    camera: Single<(&Camera, &GlobalTransform), With<LitCamera>>,
+    window: Single<&Window>,
+    mut light: Single<&mut Transform, With<PointLight2d>>,
+) {
+    // the right half of the window belongs to the unlit camera, which is outside of the lit camera's viewport
+    if let Some(pos) = world_cursor(&window, *camera) {
+        light.translation = pos.extend(0.);
+    }
+}
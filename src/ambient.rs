@@ -0,0 +1,299 @@
+//! Module containing [ambient zones](AmbientZone), regions that locally override a camera's ambient light.
+
+use bevy::{color::palettes::css::WHITE, prelude::*};
+
+use crate::data::FireflyConfig;
+
+/// Region that overrides the [ambient light](FireflyConfig::ambient_brightness) of cameras
+/// whose [focus](AmbientZoneFocus) is inside it.
+///
+/// Useful for giving rooms or areas their own mood (e.g. a dim blue cave inside a bright forest).
+/// The zone is positioned via the [Transform] component.
+///
+/// The effect ramps up over [blend_distance](AmbientZone::blend_distance) from the zone's edge inwards,
+/// so moving in and out of the zone transitions smoothly. Multiple overlapping zones are blended
+/// based on how deep the focus is inside each of them.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((
+///     AmbientZone::rectangle(200., 100.)
+///         .with_color(Color::srgb(0.3, 0.4, 1.0))
+///         .with_brightness(0.2),
+///     Transform::from_xyz(500., 0., 0.),
+/// ));
+/// ```
+#[derive(Debug, Component, Clone, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[require(Transform)]
+pub struct AmbientZone {
+    shape: AmbientZoneShape,
+
+    /// Ambient color used inside the zone.
+    ///
+    /// **Default:** White.
+    pub color: Color,
+
+    /// Ambient brightness used inside the zone.
+    ///
+    /// **Default:** 0.
+    pub brightness: f32,
+
+    /// Distance from the zone's edge over which the zone blends with the surrounding ambient.
+    ///
+    /// If 0, the ambient changes abruptly when crossing the edge.
+    ///
+    /// **Default:** 50.
+    pub blend_distance: f32,
+}
+
+/// Shape of an [`AmbientZone`], relative to its position.
+#[derive(Debug, Clone, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AmbientZoneShape {
+    Rectangle { half_width: f32, half_height: f32 },
+    Polygon { vertices: Vec<Vec2> },
+}
+
+impl Default for AmbientZone {
+    fn default() -> Self {
+        Self {
+            shape: AmbientZoneShape::Rectangle {
+                half_width: 50.,
+                half_height: 50.,
+            },
+            color: Color::Srgba(WHITE),
+            brightness: 0.,
+            blend_distance: 50.,
+        }
+    }
+}
+
+impl AmbientZone {
+    /// Construct a rectangle zone with the given width and height.
+    pub fn rectangle(width: f32, height: f32) -> Self {
+        Self {
+            shape: AmbientZoneShape::Rectangle {
+                half_width: width / 2.,
+                half_height: height / 2.,
+            },
+            ..default()
+        }
+    }
+
+    /// Construct a polygon zone from the given vertices. The polygon can be concave.
+    ///
+    /// Returns `None` if less than 3 vertices are given.
+    pub fn polygon(vertices: impl Into<Vec<Vec2>>) -> Option<Self> {
+        let vertices = vertices.into();
+
+        if vertices.len() < 3 {
+            warn!("Tried to create an ambient zone polygon with less than 3 vertices");
+            return None;
+        }
+
+        Some(Self {
+            shape: AmbientZoneShape::Polygon { vertices },
+            ..default()
+        })
+    }
+
+    /// Construct a new zone with the specified [color](AmbientZone::color).
+    pub fn with_color(&self, color: Color) -> Self {
+        let mut res = self.clone();
+        res.color = color;
+        res
+    }
+
+    /// Construct a new zone with the specified [brightness](AmbientZone::brightness).
+    pub fn with_brightness(&self, brightness: f32) -> Self {
+        let mut res = self.clone();
+        res.brightness = brightness;
+        res
+    }
+
+    /// Construct a new zone with the specified [blend distance](AmbientZone::blend_distance).
+    pub fn with_blend_distance(&self, blend_distance: f32) -> Self {
+        let mut res = self.clone();
+        res.blend_distance = blend_distance;
+        res
+    }
+
+    /// Get the shape of the zone.
+    pub fn shape(&self) -> &AmbientZoneShape {
+        &self.shape
+    }
+
+    /// Vertices of the zone in world space.
+    fn world_vertices(&self, transform: &GlobalTransform) -> Vec<Vec2> {
+        let vertices = match &self.shape {
+            AmbientZoneShape::Rectangle {
+                half_width,
+                half_height,
+            } => vec![
+                vec2(-half_width, -half_height),
+                vec2(*half_width, -half_height),
+                vec2(*half_width, *half_height),
+                vec2(-half_width, *half_height),
+            ],
+            AmbientZoneShape::Polygon { vertices } => vertices.clone(),
+        };
+
+        vertices
+            .into_iter()
+            .map(|v| transform.transform_point(v.extend(0.)).truncate())
+            .collect()
+    }
+
+    /// How much the zone affects the given point, from 0 (outside) to 1 (fully inside).
+    fn weight(&self, transform: &GlobalTransform, point: Vec2) -> f32 {
+        let vertices = self.world_vertices(transform);
+
+        if !contains(&vertices, point) {
+            return 0.;
+        }
+
+        if self.blend_distance <= 0. {
+            return 1.;
+        }
+
+        let depth = vertices
+            .iter()
+            .zip(vertices.iter().cycle().skip(1))
+            .map(|(&a, &b)| segment_distance(point, a, b))
+            .fold(f32::MAX, f32::min);
+
+        let x = (depth / self.blend_distance).clamp(0., 1.);
+        x * x * (3. - 2. * x)
+    }
+}
+
+/// Optional camera component that makes [ambient zones](AmbientZone) be evaluated at the position of
+/// another entity (e.g. the player), instead of the camera's.
+#[derive(Debug, Component, Clone, Copy, Reflect)]
+pub struct AmbientZoneFocus(pub Entity);
+
+/// Ambient light of a camera outside of any [zone](AmbientZone).
+///
+/// Inserted automatically the first time zones affect a camera. If the camera's [`FireflyConfig`] ambient
+/// is changed by the user afterwards, that becomes the new base ambient.
+#[derive(Component)]
+struct BaseAmbient {
+    color: Color,
+    brightness: f32,
+    written: (Color, f32),
+}
+
+/// Plugin that applies [ambient zones](AmbientZone). Added automatically by [`FireflyPlugin`](crate::prelude::FireflyPlugin).
+pub struct AmbientPlugin;
+
+impl Plugin for AmbientPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            apply_ambient_zones.after(TransformSystems::Propagate),
+        );
+    }
+}
+
+fn apply_ambient_zones(
+    mut commands: Commands,
+    mut cameras: Query<(
+        Entity,
+        &mut FireflyConfig,
+        &GlobalTransform,
+        Option<&AmbientZoneFocus>,
+        Option<&mut BaseAmbient>,
+    )>,
+    zones: Query<(&AmbientZone, &GlobalTransform)>,
+    transforms: Query<&GlobalTransform>,
+) {
+    for (entity, mut config, transform, focus, base) in &mut cameras {
+        let current = (config.ambient_color, config.ambient_brightness);
+
+        let (base_color, base_brightness) = match &base {
+            Some(base) if base.written == current => (base.color, base.brightness),
+            _ if zones.is_empty() => continue,
+            _ => current,
+        };
+
+        let point = focus
+            .and_then(|focus| transforms.get(focus.0).ok())
+            .unwrap_or(transform)
+            .translation()
+            .truncate();
+
+        let mut total = 0.;
+        let mut color = Vec3::ZERO;
+        let mut brightness = 0.;
+
+        for (zone, zone_transform) in &zones {
+            let weight = zone.weight(zone_transform, point);
+            if weight <= 0. {
+                continue;
+            }
+
+            total += weight;
+            color += zone.color.to_linear().to_vec3() * weight;
+            brightness += zone.brightness * weight;
+        }
+
+        if total > 1. {
+            color /= total;
+            brightness /= total;
+        } else {
+            let rest = 1. - total;
+            color += base_color.to_linear().to_vec3() * rest;
+            brightness += base_brightness * rest;
+        }
+
+        let color = Color::LinearRgba(LinearRgba::from_vec3(color));
+
+        if config.ambient_color != color || config.ambient_brightness != brightness {
+            config.ambient_color = color;
+            config.ambient_brightness = brightness;
+        }
+
+        let written = (config.ambient_color, config.ambient_brightness);
+
+        match base {
+            Some(mut base) => {
+                base.color = base_color;
+                base.brightness = base_brightness;
+                base.written = written;
+            }
+            None => {
+                commands.entity(entity).insert(BaseAmbient {
+                    color: base_color,
+                    brightness: base_brightness,
+                    written,
+                });
+            }
+        }
+    }
+}
+
+fn contains(vertices: &[Vec2], point: Vec2) -> bool {
+    let mut inside = false;
+
+    for (&a, &b) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+        if (a.y > point.y) != (b.y > point.y)
+            && point.x < a.x + (point.y - a.y) * (b.x - a.x) / (b.y - a.y)
+        {
+            inside = !inside;
+        }
+    }
+
+    inside
+}
+
+fn segment_distance(point: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let len = ab.length_squared();
+    let t = match len > 0. {
+        true => ((point - a).dot(ab) / len).clamp(0., 1.),
+        false => 0.,
+    };
+    point.distance(a + ab * t)
+}
@@ -1,24 +1,33 @@
 //! Module containing core plugins and logic to be added to a bevy app.
 
-use std::f32::consts::{FRAC_PI_2, PI};
+use std::{fmt::Write, path::PathBuf};
 
 use bevy::{
     color::palettes::css::{GREY, PINK, WHITE},
-    core_pipeline::{Core2d, core_2d::main_transparent_pass_2d, tonemapping::tonemapping},
+    core_pipeline::{
+        Core2d, core_2d::main_transparent_pass_2d, tonemapping::tonemapping, upscaling::upscaling,
+    },
+    math::bounding::{Aabb2d, IntersectsVolume},
     prelude::*,
     render::RenderApp,
 };
 
 use crate::{
+    ambient::AmbientPlugin,
     buffers::BuffersPlugin,
     change::ChangePlugin,
+    data::warn_invalid_configs,
     extract::ExtractPlugin,
     lights::LightPlugin,
-    nodes::{apply_lightmap, create_lightmap, sprite},
-    occluders::{Occluder2dShape, OccluderPlugin, translate_vertices},
+    nodes::{
+        apply_lightmap, create_lightmap, lightmap_exposure, lightmap_readback, occluder_coverage,
+        resolve_lightmap, sprite, volumetric_lightmap,
+    },
+    occluders::{Occluder2dShape, OccluderPlugin, polyline_points, translate_vertices},
     pipelines::PipelinePlugin,
+    readback::ReadbackPlugin,
     sprites::SpritesPlugin,
-    visibility::VisibilityPlugin,
+    visibility::{OccluderAabb, VisibilityPlugin},
     *,
 };
 use crate::{prelude::*, prepare::PreparePlugin};
@@ -26,8 +35,41 @@ use crate::{prelude::*, prepare::PreparePlugin};
 /// Plugin necessary to use Firefly.
 ///
 /// You will also need to add [`FireflyConfig`] to your camera.
+///
+/// To change where the lightmap is applied in the render schedule, insert a [`LightmapPlacement`] before adding this plugin.
 pub struct FireflyPlugin;
 
+/// Resource that decides where Firefly's lightmap passes are placed in the [`Core2d`] schedule.
+///
+/// This is read when the [`FireflyPlugin`] is built, so it needs to be inserted **before** adding the plugin.
+///
+/// # Example
+///
+/// ```
+/// app.insert_resource(LightmapPlacement::AfterTonemapping);
+/// app.add_plugins(FireflyPlugin);
+/// ```
+///
+/// **Default:** BeforeTonemapping.
+#[derive(Resource, Clone, Copy, Default, Debug, PartialEq, Eq, Reflect)]
+pub enum LightmapPlacement {
+    /// The lightmap is applied over the HDR scene, before tonemapping. This way, lights
+    /// are tonemapped together with the rest of the scene.
+    #[default]
+    BeforeTonemapping,
+    /// The lightmap is applied after tonemapping, over the already tonemapped scene.
+    /// Useful if your lighting should stay unaffected by the tonemapping curve.
+    AfterTonemapping,
+    /// The [`occluder_coverage`], [`create_lightmap`], [`resolve_lightmap`], [`volumetric_lightmap`], [`lightmap_exposure`],
+    /// [`lightmap_readback`] and [`apply_lightmap`] passes aren't added to the schedule.
+    ///
+    /// You need to add them yourself, e.g. to fit a custom post-processing stack.
+    /// `occluder_coverage` must run after the [`sprite`] pass, `create_lightmap` after `occluder_coverage`, `resolve_lightmap` after `create_lightmap`,
+    /// `volumetric_lightmap` after `resolve_lightmap`, `lightmap_exposure` after `volumetric_lightmap`, `lightmap_readback` after `lightmap_exposure`,
+    /// and `apply_lightmap` after `lightmap_readback`.
+    Manual,
+}
+
 impl Plugin for FireflyPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
@@ -38,40 +80,255 @@ impl Plugin for FireflyPlugin {
             VisibilityPlugin,
             ChangePlugin,
         ));
-        app.add_plugins((LightPlugin, OccluderPlugin, SpritesPlugin));
+        app.add_plugins((
+            LightPlugin,
+            OccluderPlugin,
+            SpritesPlugin,
+            AmbientPlugin,
+            ReadbackPlugin,
+        ));
+
+        app.add_systems(PostUpdate, warn_invalid_configs);
+
+        register_types(app);
+
+        let placement = app
+            .world()
+            .get_resource::<LightmapPlacement>()
+            .copied()
+            .unwrap_or_default();
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
-        render_app
-            .add_systems(Core2d, sprite.after(main_transparent_pass_2d))
-            .add_systems(Core2d, create_lightmap.after(sprite))
-            .add_systems(
-                Core2d,
-                apply_lightmap.after(create_lightmap).before(tonemapping),
-            );
+        render_app.add_systems(Core2d, sprite.after(main_transparent_pass_2d));
+
+        match placement {
+            LightmapPlacement::BeforeTonemapping => {
+                render_app
+                    .add_systems(Core2d, occluder_coverage.after(sprite))
+                    .add_systems(Core2d, create_lightmap.after(occluder_coverage))
+                    .add_systems(Core2d, resolve_lightmap.after(create_lightmap))
+                    .add_systems(Core2d, volumetric_lightmap.after(resolve_lightmap))
+                    .add_systems(Core2d, lightmap_exposure.after(volumetric_lightmap))
+                    .add_systems(Core2d, lightmap_readback.after(lightmap_exposure))
+                    .add_systems(
+                        Core2d,
+                        apply_lightmap.after(lightmap_readback).before(tonemapping),
+                    );
+            }
+            LightmapPlacement::AfterTonemapping => {
+                render_app
+                    .add_systems(Core2d, occluder_coverage.after(sprite))
+                    .add_systems(Core2d, create_lightmap.after(occluder_coverage))
+                    .add_systems(Core2d, resolve_lightmap.after(create_lightmap))
+                    .add_systems(Core2d, volumetric_lightmap.after(resolve_lightmap))
+                    .add_systems(Core2d, lightmap_exposure.after(volumetric_lightmap))
+                    .add_systems(Core2d, lightmap_readback.after(lightmap_exposure))
+                    .add_systems(
+                        Core2d,
+                        apply_lightmap
+                            .after(lightmap_readback)
+                            .after(tonemapping)
+                            .before(upscaling),
+                    );
+            }
+            LightmapPlacement::Manual => {}
+        }
     }
 }
 
+/// Registers Firefly's public types for reflection, so tools like inspectors can show and edit them.
+fn register_types(app: &mut App) {
+    app.register_type::<FireflyConfig>()
+        .register_type::<CombinationMode>()
+        .register_type::<LightmapBlend>()
+        .register_type::<LightAccumulation>()
+        .register_type::<LightmapSize>()
+        .register_type::<AutoExposure>()
+        .register_type::<TemporalAccumulation>()
+        .register_type::<NormalMode>()
+        .register_type::<CombineLightmapTo>()
+        .register_type::<CombinedLightmaps>()
+        .register_type::<PointLight2d>()
+        .register_type::<Falloff>()
+        .register_type::<LightAngle>()
+        .register_type::<LightCore>()
+        .register_type::<LightHeight>()
+        .register_type::<LightMask>()
+        .register_type::<LightTextureTransform>()
+        .register_type::<LineLight2d>()
+        .register_type::<VolumetricLight>()
+        .register_type::<Occluder2d>()
+        .register_type::<Occluder2dShape>()
+        .register_type::<Occluder2dEnabled>()
+        .register_type::<OcclusionLayers>()
+        .register_type::<OccluderThrottle>()
+        .register_type::<OccluderFade>()
+        .register_type::<StaticOccluder>()
+        .register_type::<AmbientZone>()
+        .register_type::<AmbientZoneShape>()
+        .register_type::<AmbientZoneFocus>()
+        .register_type::<NormalMap>()
+        .register_type::<SpecularMap>()
+        .register_type::<HeightMap>()
+        .register_type::<SpriteHeight>()
+        .register_type::<LightGroup>()
+        .register_type::<SpriteBlendMode>()
+        .register_type::<LightmapPlacement>()
+        .register_type::<BufferGrowth>()
+        .register_type::<GrowthPolicy>()
+        .register_type::<IndexAllocation>();
+}
+
 /// Plugin that shows gizmos for firefly occluders.
 ///
 /// Useful for debugging. Insert the [`FireflyGizmoStyle`] resource to configure.
+///
+/// Also handles [`ExportOccludersSvg`] messages.
 pub struct FireflyGizmosPlugin;
 
 impl Plugin for FireflyGizmosPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<FireflyGizmoStyle>();
-        app.add_systems(Update, draw_gizmos);
+        app.register_type::<FireflyGizmoStyle>();
+        app.add_message::<ExportOccludersSvg>();
+        app.add_systems(Update, (draw_gizmos, export_occluders_svg));
     }
 }
 
+/// Message that dumps all occluders to an SVG file at the given path, handled by the [`FireflyGizmosPlugin`].
+///
+/// Useful for inspecting the occlusion geometry of a level outside of the game, e.g. to check
+/// that occluders generated from tilemaps or colliders look right. See [`occluders_svg`] for the format.
+///
+/// # Example
+///
+/// ```
+/// fn export(keys: Res<ButtonInput<KeyCode>>, mut export: MessageWriter<ExportOccludersSvg>) {
+///     if keys.just_pressed(KeyCode::F9) {
+///         export.write(ExportOccludersSvg("occluders.svg".into()));
+///     }
+/// }
+/// ```
+#[derive(Message, Clone, Debug)]
+pub struct ExportOccludersSvg(pub PathBuf);
+
+fn export_occluders_svg(
+    mut messages: MessageReader<ExportOccludersSvg>,
+    occluders: Query<(&GlobalTransform, &Occluder2d)>,
+) {
+    for message in messages.read() {
+        match std::fs::write(&message.0, occluders_svg(&occluders)) {
+            Ok(()) => info!("Exported occluders to {:?}", message.0),
+            Err(err) => error!("Couldn't export occluders to {:?}: {err}", message.0),
+        }
+    }
+}
+
+/// Generate an SVG image of the given occluders, in world space.
+///
+/// Polygons and polylines are drawn using their world vertices, and round rectangles as rounded `rect` elements.
+/// The y axis is flipped to match SVG's coordinate system, and the view box fits all occluders.
+pub fn occluders_svg(occluders: &Query<(&GlobalTransform, &Occluder2d)>) -> String {
+    let mut min = Vec2::splat(f32::MAX);
+    let mut max = Vec2::splat(f32::MIN);
+    let mut body = String::new();
+
+    // svg's y axis points down
+    let flip = |v: Vec2| vec2(v.x, -v.y);
+
+    for (transform, occluder) in occluders {
+        let pos = transform.translation().truncate() + occluder.offset.xy();
+        let rot = Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2);
+
+        let (element, vertices) = match occluder.shape() {
+            Occluder2dShape::Polygon { vertices, .. } => ("polygon", vertices.as_slice()),
+            Occluder2dShape::Polyline { vertices, .. } => ("polyline", polyline_points(vertices)),
+            Occluder2dShape::RoundRectangle {
+                half_width,
+                half_height,
+                radius,
+            } => {
+                let center = flip(pos);
+                let half_size = vec2(half_width + radius, half_height + radius);
+
+                // conservative bounds, regardless of rotation
+                min = min.min(center - half_size.length());
+                max = max.max(center + half_size.length());
+
+                let _ = writeln!(
+                    body,
+                    "  <rect x=\"{}\" y=\"{}\" width=\"{}\" height=\"{}\" rx=\"{radius}\" transform=\"rotate({} {} {})\" />",
+                    center.x - half_size.x,
+                    center.y - half_size.y,
+                    half_size.x * 2.,
+                    half_size.y * 2.,
+                    -rot.as_degrees(),
+                    center.x,
+                    center.y,
+                );
+                continue;
+            }
+        };
+
+        let points = translate_vertices(vertices.to_vec(), pos, rot)
+            .into_iter()
+            .map(flip)
+            .inspect(|v| {
+                min = min.min(*v);
+                max = max.max(*v);
+            })
+            .map(|v| format!("{},{}", v.x, v.y))
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        let _ = writeln!(body, "  <{element} points=\"{points}\" />");
+    }
+
+    if min.x > max.x {
+        min = Vec2::ZERO;
+        max = Vec2::ZERO;
+    }
+
+    let margin = ((max - min).max_element() * 0.05).max(1.0);
+    let (min, size) = (min - margin, max - min + margin * 2.);
+    let stroke_width = size.max_element() / 500.;
+
+    format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n<g fill=\"none\" stroke=\"black\" stroke-width=\"{stroke_width}\">\n{body}</g>\n</svg>\n",
+        min.x, min.y, size.x, size.y,
+    )
+}
+
 /// Resource that can be manually inserted to change the look of Firefly gizmos.
-#[derive(Resource)]
+#[derive(Resource, Reflect)]
 pub struct FireflyGizmoStyle {
     pub light_outer_color: Color,
     pub light_inner_color: Color,
+    /// Color of occluders without their own [gizmo color](Occluder2d::gizmo_color).
+    ///
+    /// **Default:** Pink.
     pub occluder_color: Color,
+    /// Intensity thresholds at which rings are drawn around each light, based on its [falloff](PointLight2d::falloff).
+    ///
+    /// E.g. a threshold of 0.5 draws a ring where the light has half of its intensity.
+    /// Lights without falloff don't get any rings. Leave empty to disable.
+    ///
+    /// **Default:** [0.75, 0.5, 0.25, 0.1].
+    pub falloff_rings: Vec<f32>,
+    pub falloff_ring_color: Color,
+    /// If true, only occluders that intersect the view of a camera with a [`FireflyConfig`] are drawn.
+    ///
+    /// **Default:** true.
+    pub cull_occluders: bool,
+    /// Maximum number of occluders drawn each frame. If more are in view, the rest are skipped and a warning is logged.
+    ///
+    /// Useful to keep the gizmos usable in scenes with thousands of occluders.
+    ///
+    /// **Default:** Some(2000).
+    pub max_occluders: Option<usize>,
 }
 
 impl Default for FireflyGizmoStyle {
@@ -80,141 +337,134 @@ impl Default for FireflyGizmoStyle {
             light_outer_color: Color::Srgba(GREY),
             light_inner_color: Color::Srgba(WHITE),
             occluder_color: Color::Srgba(PINK),
+            falloff_rings: vec![0.75, 0.5, 0.25, 0.1],
+            falloff_ring_color: Color::Srgba(GREY.with_alpha(0.4)),
+            cull_occluders: true,
+            max_occluders: Some(2000),
+        }
+    }
+}
+
+/// Find the normalized distance at which the falloff reaches the given intensity threshold.
+fn falloff_threshold(falloff: Falloff, threshold: f32) -> Option<f32> {
+    if matches!(falloff, Falloff::None) || !(0.0..1.0).contains(&threshold) {
+        return None;
+    }
+
+    // the falloff is decreasing over [0, 1], so a binary search is enough
+    let (mut low, mut high) = (0.0, 1.0);
+    for _ in 0..24 {
+        let mid = (low + high) * 0.5;
+        if falloff.at(mid) > threshold {
+            low = mid;
+        } else {
+            high = mid;
         }
     }
+
+    Some((low + high) * 0.5)
 }
 
 fn draw_gizmos(
     mut gizmos: Gizmos,
     style: Res<FireflyGizmoStyle>,
-    occluders: Query<(&GlobalTransform, &Occluder2d)>,
+    occluders: Query<(&GlobalTransform, &Occluder2d, &OccluderAabb)>,
     lights: Query<(&GlobalTransform, &PointLight2d)>,
+    cameras: Query<(&GlobalTransform, &Projection), With<FireflyConfig>>,
 ) {
     for (transform, light) in lights {
         let isometry = Isometry2d::from_translation(transform.translation().xy());
 
         gizmos.circle_2d(isometry, light.core.radius, style.light_inner_color);
         gizmos.circle_2d(isometry, light.radius, style.light_outer_color);
+
+        for threshold in &style.falloff_rings {
+            let Some(x) = falloff_threshold(light.falloff, *threshold) else {
+                continue;
+            };
+
+            let radius = light.core.radius + x * (light.radius - light.core.radius);
+            gizmos.circle_2d(isometry, radius, style.falloff_ring_color);
+        }
     }
 
-    for (transform, occluder) in &occluders {
-        match occluder.shape().clone() {
-            Occluder2dShape::Polygon { vertices, .. } => {
-                let vertices = translate_vertices(
-                    vertices,
-                    transform.translation().truncate() + occluder.offset.xy(),
-                    Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2),
-                );
+    let camera_aabbs = cameras
+        .iter()
+        .filter_map(|(transform, projection)| {
+            let Projection::Orthographic(projection) = projection else {
+                return None;
+            };
+            Some(Aabb2d {
+                min: projection.area.min + transform.translation().truncate(),
+                max: projection.area.max + transform.translation().truncate(),
+            })
+        })
+        .collect::<Vec<_>>();
 
-                for line in vertices.windows(2) {
-                    gizmos.line_2d(line[0], line[1], style.occluder_color);
-                }
-                gizmos.line_2d(
-                    vertices[0],
-                    vertices[vertices.len() - 1],
-                    style.occluder_color,
-                );
-            }
-            Occluder2dShape::Polyline { vertices, .. } => {
-                let vertices = translate_vertices(
-                    vertices,
-                    transform.translation().truncate() + occluder.offset.xy(),
-                    Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2),
-                );
+    let visible_occluders = occluders.iter().filter(|(_, _, aabb)| {
+        !style.cull_occluders
+            || camera_aabbs
+                .iter()
+                .any(|camera_aabb| aabb.0.intersects(camera_aabb))
+    });
 
-                for line in vertices.windows(2) {
-                    gizmos.line_2d(line[0], line[1], style.occluder_color);
-                }
-            }
-            Occluder2dShape::RoundRectangle {
-                half_width,
-                half_height,
-                radius,
-            } => {
-                let center = transform.translation().truncate() + occluder.offset.xy();
+    let mut drawn = 0;
 
-                let rot = Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2);
-                let rotate =
-                    |v: Vec2| vec2(v.x * rot.cos - v.y * rot.sin, v.x * rot.sin + v.y * rot.cos);
+    for (transform, occluder, _) in visible_occluders {
+        if let Some(max_occluders) = style.max_occluders
+            && drawn >= max_occluders
+        {
+            warn_once!(
+                "More than {max_occluders} occluders are in view, only the first {max_occluders} are drawn as gizmos. \
+                You can change this limit via FireflyGizmoStyle::max_occluders."
+            );
+            break;
+        }
+        drawn += 1;
 
-                // top line
-                gizmos.line_2d(
-                    center + rotate(vec2(-half_width, half_height + radius)),
-                    center + rotate(vec2(half_width, half_height + radius)),
-                    style.occluder_color,
-                );
+        gizmos.linestrip_2d(
+            occluder.debug_vertices(transform),
+            occluder.gizmo_color.unwrap_or(style.occluder_color),
+        );
+    }
+}
 
-                // right line
-                gizmos.line_2d(
-                    center + rotate(vec2(half_width + radius, half_height)),
-                    center + rotate(vec2(half_width + radius, -half_height)),
-                    style.occluder_color,
-                );
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::reflect::serde::{ReflectDeserializer, ReflectSerializer};
+    use serde::de::DeserializeSeed;
+    use std::time::Duration;
 
-                // bottom line
-                gizmos.line_2d(
-                    center + rotate(vec2(-half_width, -half_height - radius)),
-                    center + rotate(vec2(half_width, -half_height - radius)),
-                    style.occluder_color,
-                );
+    #[test]
+    fn firefly_config_round_trips_through_reflection() {
+        let mut app = App::new();
+        register_types(&mut app);
 
-                // left line
-                gizmos.line_2d(
-                    center + rotate(vec2(-half_width - radius, half_height)),
-                    center + rotate(vec2(-half_width - radius, -half_height)),
-                    style.occluder_color,
-                );
+        let config = FireflyConfig {
+            ambient_brightness: 0.4,
+            normal_mode: NormalMode::TopDownY,
+            max_lights: Some(12),
+            extra_channels: vec![1, 3],
+            light_budget_fade: Duration::from_millis(250),
+            ..default()
+        };
 
-                // top-left arc
-                gizmos.arc_2d(
-                    Isometry2d {
-                        translation: center + rotate(vec2(-half_width, half_height)),
-                        rotation: Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2),
-                    },
-                    FRAC_PI_2,
-                    radius,
-                    style.occluder_color,
-                );
+        let registry = app.world().resource::<AppTypeRegistry>().read();
 
-                // top-right arc
-                gizmos.arc_2d(
-                    Isometry2d {
-                        translation: center + rotate(vec2(half_width, half_height)),
-                        rotation: Rot2::radians(
-                            transform.rotation().to_euler(EulerRot::XYZ).2 - FRAC_PI_2,
-                        ),
-                    },
-                    FRAC_PI_2,
-                    radius,
-                    style.occluder_color,
-                );
+        let serialized = ron::to_string(&ReflectSerializer::new(&config, &registry)).unwrap();
+        let mut deserializer = ron::Deserializer::from_str(&serialized).unwrap();
+        let reflected = ReflectDeserializer::new(&registry)
+            .deserialize(&mut deserializer)
+            .unwrap();
 
-                // bottom-right arc
-                gizmos.arc_2d(
-                    Isometry2d {
-                        translation: center + rotate(vec2(half_width, -half_height)),
-                        rotation: Rot2::radians(
-                            transform.rotation().to_euler(EulerRot::XYZ).2 + PI,
-                        ),
-                    },
-                    FRAC_PI_2,
-                    radius,
-                    style.occluder_color,
-                );
+        let round_tripped = FireflyConfig::from_reflect(reflected.as_partial_reflect()).unwrap();
 
-                // bottom-left arc
-                gizmos.arc_2d(
-                    Isometry2d {
-                        translation: center + rotate(vec2(-half_width, -half_height)),
-                        rotation: Rot2::radians(
-                            transform.rotation().to_euler(EulerRot::XYZ).2 + FRAC_PI_2,
-                        ),
-                    },
-                    FRAC_PI_2,
-                    radius,
-                    style.occluder_color,
-                );
-            }
-        }
+        assert_eq!(round_tripped.ambient_brightness, 0.4);
+        assert!(matches!(round_tripped.normal_mode, NormalMode::TopDownY));
+        assert_eq!(round_tripped.max_lights, Some(12));
+        assert_eq!(round_tripped.extra_channels, vec![1, 3]);
+        assert_eq!(round_tripped.light_budget_fade, Duration::from_millis(250));
+        assert!(round_tripped.ambient_texture.is_none());
     }
 }
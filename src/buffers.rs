@@ -4,17 +4,19 @@
 //!
 //! Round and Polygonal Occluders are stores in separate buffers due to having significantly different structures.   
 //!
-//! Vertices for Polygonal Occluders are stored in a global [`VertexBuffer`].
+//! Vertices for Polygonal Occluders are stored in a global [`VertexBuffer`], shared between occluders with identical shapes.
 
 use core::f32;
 use std::{
     array,
-    collections::{BinaryHeap, VecDeque},
+    collections::{BTreeMap, BinaryHeap, VecDeque},
     f32::consts::{PI, TAU},
+    sync::{Arc, Mutex},
 };
 
 use bevy::{
-    platform::collections::HashMap,
+    ecs::entity::EntityHashSet,
+    platform::collections::{HashMap, HashSet},
     prelude::*,
     render::{
         Render, RenderApp, RenderStartup, RenderSystems,
@@ -29,10 +31,10 @@ use bevy::{
 use bytemuck::{NoUninit, Pod, Zeroable};
 
 use crate::{
-    lights::{ExtractedPointLight, Falloff, LightIndex, UniformPointLight},
+    lights::{ExtractedPointLight, LightIndex, UniformPointLight},
     occluders::{
         ExtractedOccluder, Occluder2dShape, PolyOccluderIndex, RoundOccluderIndex, UniformOccluder,
-        UniformRoundOccluder,
+        UniformRoundOccluder, UniformVertex,
     },
     visibility::NotVisible,
 };
@@ -42,14 +44,24 @@ pub struct BuffersPlugin;
 
 impl Plugin for BuffersPlugin {
     fn build(&self, app: &mut App) {
+        let shared_stats = SharedBufferStats::default();
+
+        app.init_resource::<BufferStats>();
+        app.insert_resource(shared_stats.clone());
+        app.add_systems(First, receive_buffer_stats);
+
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
+        render_app.init_resource::<BufferStats>();
+        render_app.insert_resource(shared_stats);
+
         render_app.add_systems(RenderStartup, spawn_observers);
         render_app.add_systems(
             Render,
-            (prepare_occluders, prepare_lights)
+            ((prepare_occluders, prepare_lights), update_buffer_stats)
+                .chain()
                 .in_set(RenderSystems::Prepare)
                 .before(crate::prepare::prepare_data),
         );
@@ -58,10 +70,17 @@ impl Plugin for BuffersPlugin {
     }
 
     fn finish(&self, app: &mut App) {
+        let growth = app
+            .world()
+            .get_resource::<BufferGrowth>()
+            .copied()
+            .unwrap_or_default();
+
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
+        render_app.insert_resource(growth);
         render_app.init_resource::<BufferManager<UniformRoundOccluder>>();
         render_app.init_resource::<BufferManager<UniformOccluder>>();
         render_app.init_resource::<BufferManager<UniformPointLight>>();
@@ -69,6 +88,104 @@ impl Plugin for BuffersPlugin {
     }
 }
 
+/// Resource that decides how Firefly's GPU buffers grow once they run out of space, and how their slots are reused.
+///
+/// This is read when the [`FireflyPlugin`](crate::prelude::FireflyPlugin) is finished, so it needs to be
+/// inserted **before** adding the plugin.
+///
+/// # Example
+///
+/// ```
+/// app.insert_resource(BufferGrowth {
+///     elements: GrowthPolicy::new(256, 4096),
+///     ..default()
+/// });
+/// app.add_plugins(FireflyPlugin);
+/// ```
+#[derive(Resource, Clone, Copy, Debug, Reflect)]
+pub struct BufferGrowth {
+    /// Growth of the light and occluder buffers, counted in elements.
+    ///
+    /// **Default:** Granularity of 1024, max step of 16384.
+    pub elements: GrowthPolicy,
+
+    /// Growth of the vertex buffer, counted in vertices.
+    ///
+    /// **Default:** Granularity of 4096, max step of 65536.
+    pub vertices: GrowthPolicy,
+
+    /// How the slots (and thus the indices) of the light and occluder buffers are handed out.
+    ///
+    /// **Default:** [Compact](IndexAllocation::Compact).
+    pub indices: IndexAllocation,
+}
+
+impl Default for BufferGrowth {
+    fn default() -> Self {
+        Self {
+            elements: GrowthPolicy::new(1024, 16384),
+            vertices: GrowthPolicy::new(4096, 65536),
+            indices: IndexAllocation::Compact,
+        }
+    }
+}
+
+/// How a [`BufferManager`] hands out slots to new entities. See [`BufferGrowth::indices`].
+///
+/// The default keeps the buffers small, but an entity's index depends on the order other entities were removed in,
+/// and can change while it's alive. The other modes trade memory for indices that can be reproduced
+/// across runs and clients, which is useful for debugging networked games.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Reflect)]
+pub enum IndexAllocation {
+    /// Reuse freed slots in the order they were freed, and move values from the end of the buffer
+    /// into freed slots once it gets fragmented.
+    #[default]
+    Compact,
+
+    /// Always reuse the lowest freed slot, and never move values.
+    ///
+    /// The same sequence of insertions and removals always produces the same indices.
+    /// The buffer can stay partly empty after many entities are removed.
+    Lowest,
+
+    /// Never reuse slots, handing out indices in insertion order until the buffer is [reset](BufferManager::reset).
+    ///
+    /// The buffer grows with every entity added, so this is mostly useful for debugging and test harnesses.
+    Sequential,
+}
+
+/// How a single GPU buffer grows. See [`BufferGrowth`].
+///
+/// When a buffer runs out of space its capacity is doubled, growing by at most [`max_step`](GrowthPolicy::max_step),
+/// and rounded up to a multiple of [`granularity`](GrowthPolicy::granularity).
+#[derive(Clone, Copy, Debug, Reflect)]
+pub struct GrowthPolicy {
+    /// The capacity is always a multiple of this. Lower values waste less VRAM in small scenes.
+    pub granularity: usize,
+
+    /// Max number of elements added to the capacity at once. Higher values mean less
+    /// reallocations in huge scenes, but possibly more unused VRAM.
+    pub max_step: usize,
+}
+
+impl GrowthPolicy {
+    /// Construct a new policy with the given [granularity](GrowthPolicy::granularity) and [max step](GrowthPolicy::max_step).
+    pub fn new(granularity: usize, max_step: usize) -> Self {
+        Self {
+            granularity,
+            max_step,
+        }
+    }
+
+    /// Capacity a buffer should grow to, given its current capacity and the length it needs to fit.
+    pub fn next_capacity(&self, capacity: usize, required: usize) -> usize {
+        let granularity = self.granularity.max(1);
+        let grown = (capacity * 2).min(capacity + self.max_step).max(required);
+
+        grown.div_ceil(granularity) * granularity
+    }
+}
+
 fn spawn_observers(mut commands: Commands) {
     commands.spawn(Observer::new(on_occluder_removed));
     commands.spawn(Observer::new(on_light_removed));
@@ -77,12 +194,17 @@ fn spawn_observers(mut commands: Commands) {
 // handles buffer when the light gets despawned or the component is removed
 fn on_light_removed(
     trigger: On<Remove, ExtractedPointLight>,
-    mut lights: Query<&mut LightIndex>,
+    mut lights: Query<(&mut LightIndex, &mut OccluderDataCache)>,
     mut light_manager: ResMut<BufferManager<UniformPointLight>>,
 ) {
-    if let Ok(mut index) = lights.get_mut(trigger.entity)
-        && let Some(old_index) = index.0
-    {
+    let Ok((mut index, mut cache)) = lights.get_mut(trigger.entity) else {
+        return;
+    };
+
+    // occluders might change while the light isn't extracted
+    cache.0.clear();
+
+    if let Some(old_index) = index.0 {
         light_manager.free_index(old_index);
         index.0 = None;
     }
@@ -115,7 +237,7 @@ fn on_occluder_removed(
                 poly_index.occluder = None;
             }
             if let Some(old_index) = poly_index.vertices {
-                vertex_buffer.free_indices(occluder.shape.n_vertices(), old_index.generation);
+                vertex_buffer.free_indices(old_index, trigger.entity);
                 poly_index.vertices = None;
             }
         }
@@ -152,7 +274,7 @@ fn handle_not_visible_entities(
                 poly_index.occluder = None;
             }
             if let Some(old_index) = poly_index.vertices {
-                vertex_buffer.free_indices(occluder.shape.n_vertices(), old_index.generation);
+                vertex_buffer.free_indices(old_index, id);
                 poly_index.vertices = None;
             }
         }
@@ -172,44 +294,150 @@ fn handle_not_visible_entities(
     }
 }
 
+/// Usage of one of Firefly's GPU buffers. See [`BufferStats`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BufferStat {
+    /// Number of elements the buffer has room for before it needs to grow.
+    pub capacity: usize,
+    /// Number of slots handed out so far, including empty ones.
+    pub len: usize,
+    /// Number of slots that were freed and are waiting to be reused or reclaimed by defragmentation.
+    pub empty_slots: usize,
+}
+
+impl BufferStat {
+    /// Fraction of the used slots that are empty, from 0 to 1.
+    pub fn fragmentation(&self) -> f32 {
+        match self.len {
+            0 => 0.,
+            len => self.empty_slots as f32 / len as f32,
+        }
+    }
+}
+
+/// Resource with the usage of Firefly's GPU buffers, updated every frame after they're written.
+///
+/// Useful for monitoring how fragmented the buffers get in dynamic scenes. It's available in both worlds:
+/// the Main World copy is updated at the start of each frame with the stats of the last rendered frame,
+/// while the Render World copy is up to date for systems that run after [`RenderSystems::Prepare`].
+#[derive(Resource, Clone, Copy, Debug, Default)]
+pub struct BufferStats {
+    pub lights: BufferStat,
+    pub round_occluders: BufferStat,
+    pub poly_occluders: BufferStat,
+    /// Counted in vertices.
+    pub vertices: BufferStat,
+}
+
+/// Latest [`BufferStats`], written by the Render World and read by the Main World.
+#[derive(Resource, Clone, Default)]
+struct SharedBufferStats(Arc<Mutex<BufferStats>>);
+
+fn receive_buffer_stats(shared_stats: Res<SharedBufferStats>, mut stats: ResMut<BufferStats>) {
+    if let Ok(shared_stats) = shared_stats.0.lock() {
+        *stats = *shared_stats;
+    }
+}
+
+fn update_buffer_stats(
+    mut stats: ResMut<BufferStats>,
+    shared_stats: Res<SharedBufferStats>,
+    light_manager: Res<BufferManager<UniformPointLight>>,
+    round_manager: Res<BufferManager<UniformRoundOccluder>>,
+    poly_manager: Res<BufferManager<UniformOccluder>>,
+    vertex_buffer: Res<VertexBuffer>,
+) {
+    *stats = BufferStats {
+        lights: light_manager.stats(),
+        round_occluders: round_manager.stats(),
+        poly_occluders: poly_manager.stats(),
+        vertices: vertex_buffer.stats(),
+    };
+
+    if let Ok(mut shared_stats) = shared_stats.0.lock() {
+        *shared_stats = *stats;
+    }
+}
+
 // adds lights to buffer for use in prepare system
 fn prepare_lights(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
-    mut lights: Query<(&ExtractedPointLight, &mut LightIndex)>,
+    mut lights: Query<(Entity, &ExtractedPointLight, &mut LightIndex)>,
     mut light_manager: ResMut<BufferManager<UniformPointLight>>,
 ) {
-    for (light, mut index) in &mut lights {
+    #[cfg(feature = "trace_buffers")]
+    let _span = info_span!("firefly_prepare_lights").entered();
+
+    for (entity, new_index) in light_manager.defragment(&render_device, &render_queue) {
+        if let Ok((_, _, mut index)) = lights.get_mut(entity) {
+            index.0 = Some(new_index);
+        }
+    }
+
+    for (entity, light, mut index) in &mut lights {
         let changed = light.changes.0;
 
+        let (core_falloff, core_falloff_intensity) = light.core.falloff.to_uniform();
+        let (falloff, falloff_intensity) = light.falloff.to_uniform();
+
         let light = UniformPointLight {
             pos: light.pos,
             intensity: light.intensity,
             radius: light.radius,
             color: light.color.to_linear().to_vec4(),
+            inner_color: light.inner_color.to_linear().to_vec4(),
             z: light.z,
-            core_radius: light.core.radius,
+            core_radius: light.core.radius.clamp(0.0, light.radius),
             core_boost: light.core.boost,
-            core_falloff: match light.core.falloff {
-                Falloff::InverseSquare { .. } => 0,
-                Falloff::Linear { .. } => 1,
-                Falloff::None => 2,
-            },
-            core_falloff_intensity: light.core.falloff.intensity(),
-            falloff: match light.falloff {
-                Falloff::InverseSquare { .. } => 0,
-                Falloff::Linear { .. } => 1,
-                Falloff::None => 2,
-            },
-            falloff_intensity: light.falloff.intensity(),
+            core_falloff,
+            core_falloff_intensity,
+            falloff,
+            falloff_intensity,
             inner_angle: light.angle.inner / 180. * PI,
             outer_angle: light.angle.outer / 180. * PI,
             dir: light.dir,
             height: light.height,
+            has_light_group: match light.light_group {
+                Some(_) => 1,
+                None => 0,
+            },
+            light_group: light.light_group.unwrap_or(0),
+            has_falloff_gradient: match light.falloff_gradient {
+                Some(_) => 1,
+                None => 0,
+            },
+            excluded_groups: light.excluded_groups,
+            screen_space_shadows: match light.cast_shadows && light.screen_space_shadows {
+                true => 1,
+                false => 0,
+            },
+            has_z_range: match light.z_range {
+                Some(_) => 1,
+                None => 0,
+            },
+            z_min: light.z_range.as_ref().map_or(0.0, |range| range.start),
+            z_max: light.z_range.as_ref().map_or(0.0, |range| range.end),
+            cookie_rotation: Vec2::from_angle(light.cookie_transform.rotation),
+            cookie_scale: light.cookie_transform.scale.abs().max(Vec2::splat(0.0001)),
+            cookie_offset: light.cookie_transform.offset,
+            segment_samples: match light.segment {
+                Some(_) => light.segment_samples,
+                None => 0,
+            },
+            segment_range: light.segment_range,
+            segment_a: light.segment.map_or(Vec2::ZERO, |[a, _]| a),
+            segment_b: light.segment.map_or(Vec2::ZERO, |[_, b]| b),
         };
 
-        let new_index =
-            light_manager.set_value(&light, index.0, changed, &render_device, &render_queue);
+        let new_index = light_manager.set_value(
+            &light,
+            entity,
+            index.0,
+            changed,
+            &render_device,
+            &render_queue,
+        );
         index.0 = Some(new_index);
     }
 
@@ -221,6 +449,7 @@ fn prepare_occluders(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
     mut occluders: Query<(
+        Entity,
         &ExtractedOccluder,
         &mut RoundOccluderIndex,
         &mut PolyOccluderIndex,
@@ -229,7 +458,31 @@ fn prepare_occluders(
     mut poly_manager: ResMut<BufferManager<UniformOccluder>>,
     mut vertex_buffer: ResMut<VertexBuffer>,
 ) {
-    for (occluder, mut round_index, mut poly_index) in &mut occluders {
+    #[cfg(feature = "trace_buffers")]
+    let _span = info_span!("firefly_prepare_occluders").entered();
+
+    for (entity, new_index) in round_manager.defragment(&render_device, &render_queue) {
+        if let Ok((_, _, mut round_index, _)) = occluders.get_mut(entity) {
+            round_index.0 = Some(new_index);
+        }
+    }
+
+    for (entity, new_index) in poly_manager.defragment(&render_device, &render_queue) {
+        if let Ok((_, _, _, mut poly_index)) = occluders.get_mut(entity) {
+            poly_index.occluder = Some(new_index);
+        }
+    }
+
+    // occluders whose vertices were moved need to point to their new start vertex
+    let mut moved_vertices = HashSet::new();
+    for (entity, new_index) in vertex_buffer.defragment(&render_queue) {
+        if let Ok((_, _, _, mut poly_index)) = occluders.get_mut(entity) {
+            poly_index.vertices = Some(new_index);
+            moved_vertices.insert(entity);
+        }
+    }
+
+    for (entity, occluder, mut round_index, mut poly_index) in &mut occluders {
         let changed = occluder.changes.0;
         if let Occluder2dShape::RoundRectangle {
             half_width,
@@ -246,12 +499,25 @@ fn prepare_occluders(
                 // padding: default(),
                 z: occluder.z,
                 color: occluder.color.to_linear().to_vec4(),
+                blocks_channels: occluder.blocks_channels.extend(0.),
                 opacity: occluder.opacity,
                 z_sorting: match occluder.z_sorting {
                     true => 1,
                     false => 0,
                 },
-                _pad1: [0, 0, 0],
+                tint_strength: occluder.tint_strength,
+                shade_self: occluder.self_shading(),
+                hard_shadows: match occluder.hard_shadows {
+                    true => 1,
+                    false => 0,
+                },
+                casts_shadow: match occluder.casts_shadow {
+                    true => 1,
+                    false => 0,
+                },
+                feather: occluder.feather.max(0.),
+                shadow_fade_distance: occluder.shadow_fade_distance.max(0.),
+                glow_through: occluder.glow_through.max(0.),
             };
 
             // assert_eq!(std::mem::size_of::<UniformRoundOccluder>(), 64);
@@ -259,6 +525,7 @@ fn prepare_occluders(
 
             let new_index = round_manager.set_value(
                 &value,
+                entity,
                 round_index.0,
                 changed,
                 &render_device,
@@ -267,11 +534,12 @@ fn prepare_occluders(
             round_index.0 = Some(new_index);
         } else {
             let vertex_index = vertex_buffer.write_vertices(
-                occluder,
+                &occluder.shape,
+                entity,
                 poly_index.vertices,
                 &render_device,
                 &render_queue,
-                changed,
+                occluder.shape_changed,
             );
             poly_index.vertices = Some(vertex_index);
 
@@ -280,18 +548,37 @@ fn prepare_occluders(
                 n_vertices: occluder.shape.n_vertices(),
                 z: occluder.z,
                 color: occluder.color.to_linear().to_vec4(),
+                blocks_channels: occluder.blocks_channels.extend(0.),
                 opacity: occluder.opacity,
                 z_sorting: match occluder.z_sorting {
                     true => 1,
                     false => 0,
                 },
-                _pad1: [0, 0, 0],
+                tint_strength: occluder.tint_strength,
+                shade_self: occluder.self_shading(),
+                hard_shadows: match occluder.hard_shadows {
+                    true => 1,
+                    false => 0,
+                },
+                casts_shadow: match occluder.casts_shadow {
+                    true => 1,
+                    false => 0,
+                },
+                shadow_fade_distance: occluder.shadow_fade_distance.max(0.),
+                pos: occluder.pos,
+                rotation: Vec2::from_angle(occluder.rot),
+                vertex_opacity: match occluder.shape.vertex_opacities().is_empty() {
+                    true => 0,
+                    false => 1,
+                },
+                glow_through: occluder.glow_through.max(0.),
             };
 
             let new_index = poly_manager.set_value(
                 &value,
+                entity,
                 poly_index.occluder,
-                changed,
+                changed || moved_vertices.contains(&entity),
                 &render_device,
                 &render_queue,
             );
@@ -301,56 +588,61 @@ fn prepare_occluders(
 
     round_manager.flush(&render_device, &render_queue);
     poly_manager.flush(&render_device, &render_queue);
-    vertex_buffer.pass(&render_device, &render_queue);
 }
 
 /// The max number of elements that will be written in a single command by [`BufferManager`].
 const MAX_SINGLE_WRITE_LENGTH: usize = 64;
 
+/// The max number of elements a [`BufferManager`] moves into empty slots each frame while defragmenting.
+const MAX_DEFRAGMENT_MOVES: usize = 256;
+
+/// The max number of vertices the [`VertexBuffer`] moves each frame while defragmenting.
+const MAX_DEFRAGMENT_VERTICES: usize = 4096;
+
 /// This resource is a wrapper around [`RawBufferVec`] that reserves and distributes VRAM slots to
 /// a set of entities that are intended to be transferred to the GPU. It is currently used for Occluders and Lights.
+///
+/// Which entity owns which slot is tracked by its [`BufferSlots`], while this only keeps the values in sync with VRAM.
 #[derive(Resource)]
 pub struct BufferManager<T: ShaderType + WriteInto + Default + NoUninit> {
     buffer: RawBufferVec<T>,
-    next_index: usize,
-    free_indices: VecDeque<usize>,
+    slots: BufferSlots,
     write_min: usize,
     write_max: usize,
-    current_generation: u32,
+    growth: GrowthPolicy,
 }
 
 impl<T: ShaderType + WriteInto + Default + NoUninit> FromWorld for BufferManager<T> {
     fn from_world(world: &mut bevy::prelude::World) -> BufferManager<T> {
         let device = world.resource::<RenderDevice>();
         let queue = world.resource::<RenderQueue>();
+        let growth = world.resource::<BufferGrowth>();
 
-        Self::new(device, queue)
+        Self::new(growth.elements, growth.indices, device, queue)
     }
 }
 
 impl<T: ShaderType + WriteInto + Default + NoUninit> BufferManager<T> {
-    fn new_index(&mut self) -> usize {
-        self.free_indices.pop_back().unwrap_or_else(|| {
-            self.next_index += 1;
-            self.next_index - 1
-        })
-    }
-
-    fn new(device: &RenderDevice, queue: &RenderQueue) -> Self {
+    fn new(
+        growth: GrowthPolicy,
+        allocation: IndexAllocation,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+    ) -> Self {
         let mut res = Self {
             buffer: RawBufferVec::<T>::new(BufferUsages::STORAGE),
-            next_index: 2,
-            free_indices: default(),
+            slots: BufferSlots::new(allocation),
             write_min: usize::MAX,
             write_max: usize::MIN,
-            current_generation: 0,
+            growth,
         };
 
         res.buffer.set_label("global buffer".into());
 
-        // empty value is added so the buffer can be written to VRAM from the start
-        res.buffer.push(default());
-        res.buffer.push(default());
+        // empty values are added for the reserved slots, so the buffer can be written to VRAM from the start
+        for _ in 0..BufferSlots::RESERVED {
+            res.buffer.push(default());
+        }
         res.buffer.write_buffer(device, queue);
 
         res
@@ -364,12 +656,14 @@ impl<T: ShaderType + WriteInto + Default + NoUninit> BufferManager<T> {
     /// Called by an entity to pass it's current index and value to the buffer.
     /// It returns back it's (possibly changed) index.  
     ///
-    /// It is an entity's responsibility to store the received index and use it in subsequent calls.
+    /// It is an entity's responsibility to store the received index and use it in subsequent calls,
+    /// as well as the indices returned for it by [`defragment`](BufferManager::defragment).
     ///
     /// If an entity didn't have any changes, it shouldn't call this.
     pub fn set_value(
         &mut self,
         value: &T,
+        owner: Entity,
         index: Option<BufferIndex>,
         changed: bool,
         device: &RenderDevice,
@@ -377,28 +671,26 @@ impl<T: ShaderType + WriteInto + Default + NoUninit> BufferManager<T> {
     ) -> BufferIndex {
         if !changed
             && let Some(index) = index
-            && index.generation == self.current_generation
+            && self.slots.is_valid(index)
         {
             return index;
         }
 
-        let index = match index {
-            None => self.new_index(),
-            Some(BufferIndex { index, generation }) => {
-                if index < self.next_index && generation == self.current_generation {
-                    index
-                } else {
-                    self.new_index()
-                }
-            }
-        };
+        let index = self.slots.claim(owner, index);
 
-        if index >= self.buffer.len() {
+        if index.index >= self.buffer.len() {
             self.buffer.push(*value);
         } else {
-            self.buffer.set(index as u32, *value);
+            self.buffer.set(index.index as u32, *value);
         }
 
+        self.mark_changed(index.index, device, queue);
+
+        index
+    }
+
+    // extends the range that will be written, writing the current one first if it gets too long
+    fn mark_changed(&mut self, index: usize, device: &RenderDevice, queue: &RenderQueue) {
         let next_min = self.write_min.min(index);
         let next_max = self.write_max.max(index);
 
@@ -411,20 +703,15 @@ impl<T: ShaderType + WriteInto + Default + NoUninit> BufferManager<T> {
             self.write_min = next_min;
             self.write_max = next_max;
         }
-
-        BufferIndex {
-            index,
-            generation: self.current_generation,
-        }
     }
 
     fn write(&mut self, device: &RenderDevice, queue: &RenderQueue) {
         if self.write_min != usize::MAX {
             if self.write_max >= self.buffer.capacity() {
-                self.buffer.reserve(
-                    ((self.write_max + 1) as f32 / 1024.0).ceil() as usize * 1024,
-                    device,
-                );
+                let capacity = self
+                    .growth
+                    .next_capacity(self.buffer.capacity(), self.write_max + 1);
+                self.buffer.reserve(capacity, device);
                 self.buffer.write_buffer(device, queue);
             } else {
                 self.buffer
@@ -433,45 +720,258 @@ impl<T: ShaderType + WriteInto + Default + NoUninit> BufferManager<T> {
             }
         }
 
-        // info!(
-        //     "Finished writing! Buffer length: {}, Element size: {}, Buffer size: {}, Buffer capacity: {}, Unoccupied: {}",
-        //     self.buffer.len(),
-        //     T::min_size().get(),
-        //     self.buffer.buffer().unwrap().size(),
-        //     self.buffer.capacity(),
-        //     self.free_indices.len(),
-        // );
+        #[cfg(feature = "trace_buffers")]
+        trace!(
+            "Wrote {}. Length: {}, element size: {}, capacity: {}, empty slots: {}",
+            std::any::type_name::<T>(),
+            self.buffer.len(),
+            T::min_size().get(),
+            self.buffer.capacity(),
+            self.slots.empty_slots(),
+        );
+    }
+
+    /// Number of elements the buffer has room for before it needs to grow.
+    pub fn capacity(&self) -> usize {
+        self.buffer.capacity()
+    }
+
+    /// Current usage of the buffer.
+    pub fn stats(&self) -> BufferStat {
+        BufferStat {
+            capacity: self.buffer.capacity(),
+            len: self.slots.len(),
+            empty_slots: self.slots.empty_slots(),
+        }
     }
 
     /// Flush the changes at the end of a render frame. This writes all changes to the GPU.
     pub fn flush(&mut self, device: &RenderDevice, queue: &RenderQueue) {
         self.write(device, queue);
 
-        // Refragmentation. Because of wasted space the buffer will empty itself and pass all-new data next frame. This can be optimized
-        if self.free_indices.len() > 500 && self.free_indices.len() > self.buffer.capacity() / 2 {
-            let old_generation = self.current_generation;
-            *self = Self::new(device, queue);
-            self.current_generation = old_generation + 1;
-        }
-
         self.write_min = usize::MAX;
         self.write_max = usize::MIN;
     }
 
+    /// Called at the start of a frame, before any [`set_value`](BufferManager::set_value).
+    ///
+    /// If a significant part of the buffer is empty, this moves up to [`MAX_DEFRAGMENT_MOVES`] values
+    /// from the end of the buffer into empty slots, so the cost of defragmenting is spread over multiple frames.
+    ///
+    /// Returns the owners of the moved values along with their new indices, which they have to store.
+    pub fn defragment(
+        &mut self,
+        device: &RenderDevice,
+        queue: &RenderQueue,
+    ) -> Vec<(Entity, BufferIndex)> {
+        let moves = self.slots.defragment();
+
+        #[cfg(feature = "trace_buffers")]
+        if !moves.is_empty() {
+            debug!(
+                "Defragmented {} by moving {} values",
+                std::any::type_name::<T>(),
+                moves.len(),
+            );
+        }
+
+        moves
+            .into_iter()
+            .map(|slot_move| {
+                let value = self.buffer.values()[slot_move.from];
+                self.buffer.set(slot_move.to.index as u32, value);
+                self.mark_changed(slot_move.to.index, device, queue);
+
+                (slot_move.owner, slot_move.to)
+            })
+            .collect()
+    }
+
     /// An entity that has gone out of view, been despawned, or is no longer intended to be rendered,
     /// has to call this method to free it's Buffer slot.
     ///
     /// The index / slot will be automatically redistributed to another entity when needed.
     pub fn free_index(&mut self, index: BufferIndex) {
+        self.slots.free(index);
+    }
+
+    /// Free every slot and start handing out indices from the beginning again, e.g. between the runs of a test harness.
+    ///
+    /// All previously returned indices become invalid, so their owners get new ones the next time they call
+    /// [`set_value`](BufferManager::set_value), even if they didn't change.
+    pub fn reset(&mut self) {
+        self.slots.reset();
+    }
+}
+
+/// Keeps track of which entity owns each slot of a [`BufferManager`], and which slots are free to be handed out again.
+///
+/// This doesn't touch the GPU, the manager mirrors every change to the slots in its buffer.
+pub(crate) struct BufferSlots {
+    owners: Vec<Entity>,
+    next_index: usize,
+    free_indices: VecDeque<usize>,
+    allocation: IndexAllocation,
+    current_generation: u32,
+}
+
+/// A value moved by [`BufferSlots::defragment`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct SlotMove {
+    pub owner: Entity,
+    pub from: usize,
+    pub to: BufferIndex,
+}
+
+impl BufferSlots {
+    /// Number of slots at the start of the buffer that are never handed out.
+    pub const RESERVED: usize = 2;
+
+    pub fn new(allocation: IndexAllocation) -> Self {
+        Self {
+            owners: vec![Entity::PLACEHOLDER; Self::RESERVED],
+            next_index: Self::RESERVED,
+            free_indices: default(),
+            allocation,
+            current_generation: 0,
+        }
+    }
+
+    /// Number of slots handed out so far, including empty ones.
+    pub fn len(&self) -> usize {
+        self.next_index
+    }
+
+    /// Number of slots that were freed and are waiting to be reused or reclaimed.
+    pub fn empty_slots(&self) -> usize {
+        self.free_indices.len()
+    }
+
+    /// Whether an index was handed out since the last [reset](BufferSlots::reset), and can still be used as-is.
+    pub fn is_valid(&self, index: BufferIndex) -> bool {
+        index.generation == self.current_generation
+    }
+
+    fn new_index(&mut self) -> usize {
+        self.free_indices.pop_back().unwrap_or_else(|| {
+            self.next_index += 1;
+            self.next_index - 1
+        })
+    }
+
+    /// Gives a slot to an entity, keeping its current index if it's still valid.
+    pub fn claim(&mut self, owner: Entity, index: Option<BufferIndex>) -> BufferIndex {
+        let index = match index {
+            Some(BufferIndex { index, generation })
+                if index < self.next_index && generation == self.current_generation =>
+            {
+                index
+            }
+            _ => self.new_index(),
+        };
+
+        if index >= self.owners.len() {
+            self.owners.push(owner);
+        } else {
+            self.owners[index] = owner;
+        }
+
+        BufferIndex {
+            index,
+            generation: self.current_generation,
+        }
+    }
+
+    /// If a significant part of the slots are empty, moves up to [`MAX_DEFRAGMENT_MOVES`] owners
+    /// from the end into empty slots. Moves are returned in the order they have to be applied.
+    ///
+    /// Only [compact](IndexAllocation::Compact) slots are defragmented, since moving changes the owners' indices.
+    pub fn defragment(&mut self) -> Vec<SlotMove> {
+        let mut moved = vec![];
+
+        if self.allocation != IndexAllocation::Compact {
+            return moved;
+        }
+
+        if self.free_indices.len() <= 64 || self.free_indices.len() <= self.next_index / 4 {
+            return moved;
+        }
+
+        self.trim();
+
+        while moved.len() < MAX_DEFRAGMENT_MOVES
+            && let Some(slot) = self.free_indices.pop_back()
+        {
+            // the slot was already reclaimed by trimming
+            if slot >= self.next_index {
+                continue;
+            }
+
+            let last = self.next_index - 1;
+            let owner = self.owners[last];
+
+            self.owners[slot] = owner;
+            self.owners[last] = Entity::PLACEHOLDER;
+            self.next_index -= 1;
+
+            moved.push(SlotMove {
+                owner,
+                from: last,
+                to: BufferIndex {
+                    index: slot,
+                    generation: self.current_generation,
+                },
+            });
+
+            self.trim();
+        }
+
+        let next_index = self.next_index;
+        self.free_indices.retain(|slot| *slot < next_index);
+
+        moved
+    }
+
+    // reclaims the empty slots at the end
+    fn trim(&mut self) {
+        while self.next_index > Self::RESERVED
+            && self.owners[self.next_index - 1] == Entity::PLACEHOLDER
+        {
+            self.next_index -= 1;
+        }
+    }
+
+    /// Frees the slot of an index, so it can be handed out again. Stale indices are ignored.
+    pub fn free(&mut self, index: BufferIndex) {
         if index.generation != self.current_generation {
             return;
         };
 
-        if index.index >= self.buffer.len() {
+        if index.index >= self.next_index {
             return;
         }
 
-        self.free_indices.push_front(index.index);
+        self.owners[index.index] = Entity::PLACEHOLDER;
+
+        match self.allocation {
+            IndexAllocation::Compact => self.free_indices.push_front(index.index),
+            // kept sorted from highest to lowest, so the lowest slot is reused first
+            IndexAllocation::Lowest => {
+                let pos = self
+                    .free_indices
+                    .partition_point(|slot| *slot > index.index);
+                self.free_indices.insert(pos, index.index);
+            }
+            IndexAllocation::Sequential => {}
+        }
+    }
+
+    /// Frees every slot and invalidates all previously returned indices.
+    pub fn reset(&mut self) {
+        self.owners[Self::RESERVED..].fill(Entity::PLACEHOLDER);
+        self.next_index = Self::RESERVED;
+        self.free_indices.clear();
+        self.current_generation = self.current_generation.wrapping_add(1);
     }
 }
 
@@ -609,6 +1109,22 @@ impl BinBuffer {
     }
 }
 
+/// Light component caching the [`OccluderData`] computed for each occluder in its range, keyed by
+/// the occluder's render entity.
+///
+/// Entries are reused while neither the light nor the occluder changed, and dropped once the occluder
+/// is no longer processed for the light.
+#[derive(Component, Default)]
+pub struct OccluderDataCache(pub HashMap<Entity, CachedOccluderData>);
+
+/// The [`OccluderData`] of an occluder as seen from a light, along with the values it was computed with.
+pub struct CachedOccluderData {
+    pub occluder_index: u32,
+    pub start_vertex: u32,
+    pub soft_shadows: bool,
+    pub data: Vec<OccluderData>,
+}
+
 /// CPU struct describing an occluder or edge.
 #[derive(Clone)]
 pub struct OccluderData {
@@ -667,40 +1183,40 @@ impl Ord for OccluderPointer {
 /// A global buffer in which all visible vertices are stored.
 ///
 /// This is different from the [`BufferManager`] in order to use a specific allocation
-/// that suits vertices better. They are quickly added on top of each other without keeping track
-/// of their position for re-allocation. When an occluder disappears, it's number of vertices is simply
-/// subtracted from the total lenght of the buffer, and the buffer refragments itself when
-/// there is a significant amount of wasted space.  
+/// that suits vertices better. They are quickly added on top of each other without reusing
+/// the space of removed occluders. When there is a significant amount of wasted space, the buffer
+/// gradually slides the remaining vertices over the gaps, a few thousand vertices each frame.
+///
+/// Vertices are stored relative to their occluder, so occluders with identical shapes (e.g. hundreds of trees)
+/// share a single chain of vertices instead of each writing their own.
 #[derive(Resource)]
 pub struct VertexBuffer {
-    vertices: RawBufferVec<Vec2>,
-    next_index: usize,
-    empty_slots: u32,
-    current_generation: u32,
+    vertices: RawBufferVec<UniformVertex>,
+    chains: VertexChains,
+    growth: GrowthPolicy,
 }
 
 impl FromWorld for VertexBuffer {
     fn from_world(world: &mut World) -> Self {
         let device = world.resource::<RenderDevice>();
         let queue = world.resource::<RenderQueue>();
+        let growth = world.resource::<BufferGrowth>().vertices;
 
-        Self::new(device, queue)
+        Self::new(growth, device, queue)
     }
 }
 
 impl VertexBuffer {
-    fn new(device: &RenderDevice, queue: &RenderQueue) -> Self {
+    fn new(growth: GrowthPolicy, device: &RenderDevice, queue: &RenderQueue) -> Self {
         let mut res = Self {
-            vertices: RawBufferVec::<Vec2>::new(BufferUsages::STORAGE),
-            next_index: 1,
-            empty_slots: 0,
-            current_generation: 0,
+            vertices: RawBufferVec::<UniformVertex>::new(BufferUsages::STORAGE),
+            chains: VertexChains::new(),
+            growth,
         };
 
         res.vertices.set_label("vertex buffer".into());
 
         // empty value is added so the buffer can be written to VRAM from the start
-
         res.vertices.push(default());
         res.vertices.write_buffer(device, queue);
 
@@ -712,11 +1228,28 @@ impl VertexBuffer {
         self.vertices.binding().unwrap()
     }
 
-    /// Insert all of an occluder's vertices to this buffer. This
-    /// function also automatically writes them to the GPU.  
+    /// Number of vertices the buffer has room for before it needs to grow.
+    pub fn capacity(&self) -> usize {
+        self.vertices.capacity()
+    }
+
+    /// Current usage of the buffer, counted in vertices.
+    pub fn stats(&self) -> BufferStat {
+        BufferStat {
+            capacity: self.vertices.capacity(),
+            len: self.chains.len(),
+            empty_slots: self.chains.empty_slots(),
+        }
+    }
+
+    /// Insert the vertices of an occluder's shape to this buffer, or share the vertices of an identical shape
+    /// that's already in it. This function also automatically writes them to the GPU.
+    ///
+    /// The vertices are stored relative to the occluder, and transformed by its position and rotation in the shader.
     pub fn write_vertices(
         &mut self,
-        occluder: &ExtractedOccluder,
+        shape: &Occluder2dShape,
+        owner: Entity,
         index: Option<BufferIndex>,
         device: &RenderDevice,
         queue: &RenderQueue,
@@ -724,111 +1257,694 @@ impl VertexBuffer {
     ) -> BufferIndex {
         if !changed
             && let Some(index) = index
-            && index.generation == self.current_generation
+            && self.chains.is_valid(index)
         {
             return index;
         }
 
-        let index = match index {
-            None => self.next_index,
-            Some(BufferIndex { index, generation }) => {
-                if index < self.next_index && generation == self.current_generation {
-                    index
-                } else {
-                    self.next_index
-                }
-            }
-        };
+        let claim = self.chains.claim(owner, index, shape_key(shape));
 
-        // change existent vertices
-        if index < self.next_index {
-            let mut last_index = index;
-            for vertex in occluder.vertices_iter() {
-                if last_index >= self.vertices.len() {
-                    self.vertices.push(vertex);
-                    warn!("hmm.. what?");
-                } else {
-                    self.vertices.set(last_index as u32, vertex);
-                }
+        if !claim.new {
+            return claim.index;
+        }
 
-                last_index += 1;
-            }
+        // add new vertices
+        let opacities = shape.vertex_opacities();
+        for (i, vertex) in shape.local_vertices().iter().enumerate() {
+            self.vertices.push(UniformVertex {
+                pos: *vertex,
+                opacity: opacities.get(i).copied().unwrap_or(1.0),
+                _pad: 0.0,
+            });
+        }
+
+        let end = self.chains.len();
 
+        if end >= self.vertices.capacity() {
+            let capacity = self.growth.next_capacity(self.vertices.capacity(), end);
+            self.vertices.reserve(capacity, device);
+            self.vertices.write_buffer(device, queue);
+        } else {
             self.vertices
-                .write_buffer_range(queue, index..last_index)
+                .write_buffer_range(queue, claim.index.index..end)
                 .expect("couldn't write range");
+        }
 
-            // for vertex in self.vertices.values() {
-            //     info!("{vertex}");
-            // }
+        #[cfg(feature = "trace_buffers")]
+        trace!(
+            "Wrote vertices. Capacity: {}, length: {}, empty slots: {}, shared shapes: {}",
+            self.vertices.capacity(),
+            self.vertices.len(),
+            self.chains.empty_slots(),
+            self.chains.n_shapes()
+        );
 
-            return BufferIndex {
-                index,
-                generation: self.current_generation,
-            };
-        }
+        claim.index
+    }
 
-        // add new vertices
-        for vertex in occluder.vertices_iter() {
-            self.vertices.push(vertex);
-            self.next_index += 1;
-        }
+    /// Number of distinct shapes stored in the buffer. Occluders with identical shapes count once.
+    pub fn n_shapes(&self) -> usize {
+        self.chains.n_shapes()
+    }
 
-        // if self.next_index % 2 == 1 {
-        //     self.vertices.push(default());
-        //     self.next_index += 1;
-        // }
+    /// Called at the start of a frame, before any [`write_vertices`](VertexBuffer::write_vertices).
+    ///
+    /// If a significant part of the buffer is empty, this starts moving chains of vertices over the empty
+    /// slots before them, up to [`MAX_DEFRAGMENT_VERTICES`] each frame, until there are no gaps left.
+    ///
+    /// Returns the owners of the moved chains along with their new indices, which they have to store.
+    pub fn defragment(&mut self, queue: &RenderQueue) -> Vec<(Entity, BufferIndex)> {
+        let moves = self.chains.defragment();
+
+        #[cfg(feature = "trace_buffers")]
+        if !moves.is_empty() {
+            debug!(
+                "Defragmented the vertex buffer by moving {} chains",
+                moves.len()
+            );
+        }
 
-        if self.next_index >= self.vertices.capacity() {
-            self.vertices.reserve(
-                (self.next_index as f32 / 4096.0).ceil() as usize * 4096,
-                device,
+        // chains are moved in order and only backwards, so they never overwrite the ones not moved yet
+        for chain_move in &moves {
+            self.vertices.values_mut().copy_within(
+                chain_move.from..chain_move.from + chain_move.len,
+                chain_move.to.index,
             );
-            self.vertices.write_buffer(device, queue);
-        } else {
+        }
+
+        if let (Some(first), Some(last)) = (moves.first(), moves.last()) {
             self.vertices
-                .write_buffer_range(queue, index..self.next_index)
+                .write_buffer_range(queue, first.to.index..last.to.index + last.len)
                 .expect("couldn't write range");
         }
 
-        // info!(
-        //     "Vertex buffer capacity: {}, length: {}, empty slots: {}",
-        //     self.vertices.capacity(),
-        //     self.vertices.len(),
-        //     self.empty_slots
-        // );
+        // the vertices past the last chain were reclaimed once defragmenting finished
+        self.vertices.truncate(self.chains.len());
+
+        // every occluder sharing a chain has to point to its new start vertex
+        moves
+            .into_iter()
+            .flat_map(|chain_move| {
+                chain_move
+                    .owners
+                    .into_iter()
+                    .map(move |owner| (owner, chain_move.to))
+            })
+            .collect()
+    }
 
-        BufferIndex {
+    /// Called by an occluder to stop using its vertices. Their space is freed once no occluder shares them anymore.
+    pub fn free_indices(&mut self, index: BufferIndex, owner: Entity) {
+        self.chains.free(index, owner);
+    }
+}
+
+/// Keeps track of the chains of vertices in a [`VertexBuffer`]: where each one starts, which occluders share it,
+/// and how much space was freed by removed chains.
+///
+/// This doesn't touch the GPU, the buffer mirrors every change to the chains in its vertices.
+pub(crate) struct VertexChains {
+    /// The owners and number of vertices of each chain of vertices, keyed by its first index.
+    chains: BTreeMap<usize, VertexChain>,
+    /// The first index of the chain of each distinct shape.
+    shapes: HashMap<ShapeKey, usize>,
+    next_index: usize,
+    empty_slots: u32,
+    defragmenting: bool,
+    current_generation: u32,
+}
+
+/// A chain of vertices in the [`VertexBuffer`], shared by all the occluders with the same shape.
+struct VertexChain {
+    owners: EntityHashSet,
+    len: u32,
+    key: ShapeKey,
+}
+
+/// The chain of vertices handed out by [`VertexChains::claim`].
+pub(crate) struct ChainClaim {
+    pub index: BufferIndex,
+    /// Whether the chain is new, and its vertices have to be added at the end of the buffer.
+    pub new: bool,
+}
+
+/// A chain moved by [`VertexChains::defragment`], along with the occluders sharing it.
+pub(crate) struct ChainMove {
+    pub from: usize,
+    pub to: BufferIndex,
+    pub len: usize,
+    pub owners: Vec<Entity>,
+}
+
+impl VertexChains {
+    pub fn new() -> Self {
+        Self {
+            chains: default(),
+            shapes: default(),
+            // the first vertex is reserved
+            next_index: 1,
+            empty_slots: 0,
+            defragmenting: false,
+            current_generation: 0,
+        }
+    }
+
+    /// Number of vertices handed out so far, including empty ones.
+    pub fn len(&self) -> usize {
+        self.next_index
+    }
+
+    /// Number of vertices that were freed and are waiting to be reclaimed by defragmentation.
+    pub fn empty_slots(&self) -> usize {
+        self.empty_slots as usize
+    }
+
+    /// Number of distinct shapes. Occluders with identical shapes count once.
+    pub fn n_shapes(&self) -> usize {
+        self.chains.len()
+    }
+
+    /// Whether an index can still be used as-is.
+    pub fn is_valid(&self, index: BufferIndex) -> bool {
+        index.generation == self.current_generation
+    }
+
+    /// Gives an occluder the chain of its shape, sharing the chain of an identical shape if there is one.
+    ///
+    /// An occluder whose shape changed stops sharing its previous chain.
+    pub fn claim(
+        &mut self,
+        owner: Entity,
+        index: Option<BufferIndex>,
+        key: ShapeKey,
+    ) -> ChainClaim {
+        // moving the occluder doesn't change its vertices, only its shape does
+        if let Some(index) = index
+            && self.is_valid(index)
+        {
+            if self
+                .chains
+                .get(&index.index)
+                .is_some_and(|chain| chain.key == key)
+            {
+                return ChainClaim { index, new: false };
+            }
+
+            self.free(index, owner);
+        }
+
+        // identical shapes share the same vertices
+        if let Some(&start) = self.shapes.get(&key)
+            && let Some(chain) = self.chains.get_mut(&start)
+        {
+            chain.owners.insert(owner);
+
+            return ChainClaim {
+                index: BufferIndex {
+                    index: start,
+                    generation: self.current_generation,
+                },
+                new: false,
+            };
+        }
+
+        let index = self.next_index;
+        let len = key.len();
+        self.next_index += len;
+
+        self.chains.insert(
             index,
-            generation: self.current_generation,
+            VertexChain {
+                owners: EntityHashSet::from_iter([owner]),
+                len: len as u32,
+                key: key.clone(),
+            },
+        );
+        self.shapes.insert(key, index);
+
+        ChainClaim {
+            index: BufferIndex {
+                index,
+                generation: self.current_generation,
+            },
+            new: true,
         }
     }
 
-    /// Called at the end of a frame. Potentially triggers refragmentation.
-    pub fn pass(&mut self, device: &RenderDevice, queue: &RenderQueue) {
-        if self.empty_slots > 500 && self.empty_slots > self.vertices.capacity() as u32 / 2 {
-            let old_generation = self.current_generation;
-            *self = Self::new(device, queue);
-            self.current_generation = old_generation + 1;
+    /// If a significant part of the vertices are empty, this starts moving chains over the empty
+    /// slots before them, up to [`MAX_DEFRAGMENT_VERTICES`] each call, until there are no gaps left.
+    ///
+    /// Moves are returned in the order they have to be applied. Once there are no gaps left,
+    /// the [length](VertexChains::len) shrinks to the end of the last chain.
+    pub fn defragment(&mut self) -> Vec<ChainMove> {
+        if !self.defragmenting
+            && (self.empty_slots <= 500 || self.empty_slots as usize <= self.next_index / 4)
+        {
+            return vec![];
         }
+
+        // (old index, new index)
+        let mut moves = vec![];
+        let mut next_index = 1;
+        let mut budget = MAX_DEFRAGMENT_VERTICES;
+        let mut finished = true;
+
+        for (&index, chain) in &self.chains {
+            if index != next_index {
+                if budget < chain.len as usize && !moves.is_empty() {
+                    finished = false;
+                    break;
+                }
+
+                budget = budget.saturating_sub(chain.len as usize);
+                moves.push((index, next_index));
+            }
+
+            next_index += chain.len as usize;
+        }
+
+        let mut moved = vec![];
+
+        for (index, new_index) in moves {
+            let Some(chain) = self.chains.remove(&index) else {
+                continue;
+            };
+
+            moved.push(ChainMove {
+                from: index,
+                to: BufferIndex {
+                    index: new_index,
+                    generation: self.current_generation,
+                },
+                len: chain.len as usize,
+                owners: chain.owners.iter().copied().collect(),
+            });
+
+            self.shapes.insert(chain.key.clone(), new_index);
+            self.chains.insert(new_index, chain);
+        }
+
+        if finished {
+            self.next_index = next_index;
+            self.empty_slots = 0;
+        }
+
+        self.defragmenting = !finished;
+
+        moved
     }
 
-    /// Called by an occluder to subtract it's total number of vertices from the allocated space.
-    pub fn free_indices(&mut self, n_indices: u32, generation: u32) {
-        if generation != self.current_generation {
+    /// Removes an occluder from the chain it shares. The chain's space is freed once no occluder shares it anymore.
+    pub fn free(&mut self, index: BufferIndex, owner: Entity) {
+        if !self.is_valid(index) {
             return;
         }
 
-        self.empty_slots += n_indices;
+        let Some(chain) = self.chains.get_mut(&index.index) else {
+            return;
+        };
+
+        chain.owners.remove(&owner);
+
+        if chain.owners.is_empty()
+            && let Some(chain) = self.chains.remove(&index.index)
+        {
+            self.shapes.remove(&chain.key);
+            self.empty_slots += chain.len;
+        }
     }
 }
 
+/// The exact bits of a shape's local vertices and their opacities, used to find identical shapes.
+type ShapeKey = Vec<[u32; 3]>;
+
+fn shape_key(shape: &Occluder2dShape) -> ShapeKey {
+    let opacities = shape.vertex_opacities();
+    shape
+        .local_vertices()
+        .iter()
+        .enumerate()
+        .map(|(i, vertex)| {
+            let opacity = opacities.get(i).copied().unwrap_or(1.0);
+            [vertex.x.to_bits(), vertex.y.to_bits(), opacity.to_bits()]
+        })
+        .collect()
+}
+
 /// An index given and returned to the various buffer structures.
 ///
 /// This is used for storing an entity's slot in the buffer, and
 /// contains a generation to keep track of buffer refragmentations.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub struct BufferIndex {
     pub index: usize,
     pub generation: u32,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::occluders::Occluder2d;
+
+    fn entities(n: usize) -> Vec<Entity> {
+        let mut world = World::new();
+        (0..n).map(|_| world.spawn_empty().id()).collect()
+    }
+
+    fn claim_all(slots: &mut BufferSlots, entities: &[Entity]) -> HashMap<Entity, BufferIndex> {
+        entities
+            .iter()
+            .map(|entity| (*entity, slots.claim(*entity, None)))
+            .collect()
+    }
+
+    // applies the moves like the owners would, checking that each one starts where its owner was
+    fn apply_moves(indices: &mut HashMap<Entity, BufferIndex>, moves: &[SlotMove]) {
+        for slot_move in moves {
+            assert_eq!(indices[&slot_move.owner].index, slot_move.from);
+            indices.insert(slot_move.owner, slot_move.to);
+        }
+    }
+
+    fn assert_compact(slots: &BufferSlots, indices: &HashMap<Entity, BufferIndex>) {
+        assert_eq!(slots.len(), BufferSlots::RESERVED + indices.len());
+        assert_eq!(slots.empty_slots(), 0);
+
+        for (entity, index) in indices {
+            assert!(slots.is_valid(*index));
+            assert!(index.index >= BufferSlots::RESERVED && index.index < slots.len());
+            assert_eq!(slots.owners[index.index], *entity);
+        }
+    }
+
+    #[test]
+    fn claim_keeps_valid_indices() {
+        let mut slots = BufferSlots::new(IndexAllocation::Compact);
+        let entities = entities(3);
+        let indices = claim_all(&mut slots, &entities);
+
+        for entity in &entities {
+            let index = indices[entity];
+            assert_eq!(slots.claim(*entity, Some(index)), index);
+        }
+        assert_eq!(slots.len(), BufferSlots::RESERVED + 3);
+    }
+
+    #[test]
+    fn defragment_compacts_slots() {
+        let mut slots = BufferSlots::new(IndexAllocation::Compact);
+        let entities = entities(200);
+        let mut indices = claim_all(&mut slots, &entities);
+
+        for entity in entities.iter().step_by(2) {
+            slots.free(indices.remove(entity).unwrap());
+        }
+        assert_eq!(slots.empty_slots(), 100);
+
+        let moves = slots.defragment();
+        assert!(!moves.is_empty());
+        apply_moves(&mut indices, &moves);
+
+        assert_compact(&slots, &indices);
+    }
+
+    #[test]
+    fn defragment_is_spread_over_frames() {
+        let mut slots = BufferSlots::new(IndexAllocation::Compact);
+        let entities = entities(1000);
+        let mut indices = claim_all(&mut slots, &entities);
+
+        for entity in &entities[..600] {
+            slots.free(indices.remove(entity).unwrap());
+        }
+
+        let first = slots.defragment();
+        assert_eq!(first.len(), MAX_DEFRAGMENT_MOVES);
+        apply_moves(&mut indices, &first);
+
+        loop {
+            let moves = slots.defragment();
+            if moves.is_empty() {
+                break;
+            }
+            assert!(moves.len() <= MAX_DEFRAGMENT_MOVES);
+            apply_moves(&mut indices, &moves);
+        }
+
+        assert_compact(&slots, &indices);
+    }
+
+    #[test]
+    fn defragment_waits_for_enough_empty_slots() {
+        let mut slots = BufferSlots::new(IndexAllocation::Compact);
+        let entities = entities(200);
+        let mut indices = claim_all(&mut slots, &entities);
+
+        for entity in &entities[..10] {
+            slots.free(indices.remove(entity).unwrap());
+        }
+
+        assert!(slots.defragment().is_empty());
+        assert_eq!(slots.empty_slots(), 10);
+    }
+
+    #[test]
+    fn defragment_keeps_stable_indices() {
+        for allocation in [IndexAllocation::Lowest, IndexAllocation::Sequential] {
+            let mut slots = BufferSlots::new(allocation);
+            let entities = entities(200);
+            let mut indices = claim_all(&mut slots, &entities);
+
+            for entity in entities.iter().step_by(2) {
+                slots.free(indices.remove(entity).unwrap());
+            }
+
+            assert!(slots.defragment().is_empty());
+        }
+    }
+
+    #[test]
+    fn growth_doubles_capacity() {
+        let policy = GrowthPolicy::new(1024, 16384);
+
+        assert_eq!(policy.next_capacity(0, 1), 1024);
+        assert_eq!(policy.next_capacity(1024, 1025), 2048);
+        assert_eq!(policy.next_capacity(4096, 4097), 8192);
+    }
+
+    #[test]
+    fn growth_is_capped_by_max_step() {
+        let policy = GrowthPolicy::new(1024, 16384);
+
+        assert_eq!(policy.next_capacity(32768, 32769), 32768 + 16384);
+    }
+
+    #[test]
+    fn growth_fits_the_required_length() {
+        let policy = GrowthPolicy::new(1024, 16384);
+
+        // more than double is needed at once, rounded up to the granularity
+        assert_eq!(policy.next_capacity(1024, 5000), 5120);
+
+        for (capacity, required) in [(0, 3000), (2048, 70000), (100, 101), (16384, 16385)] {
+            let next = policy.next_capacity(capacity, required);
+            assert!(next >= required);
+            assert_eq!(next % policy.granularity, 0);
+        }
+    }
+
+    #[test]
+    fn growth_ignores_zero_granularity() {
+        assert_eq!(GrowthPolicy::new(0, 100).next_capacity(10, 11), 20);
+    }
+
+    // a distinct shape key with the given number of vertices
+    fn chain_key(id: u32, len: usize) -> ShapeKey {
+        (0..len as u32).map(|i| [id, i, 0]).collect()
+    }
+
+    #[test]
+    fn identical_shapes_share_a_chain() {
+        let mut chains = VertexChains::new();
+        let hexagon = Occluder2d::polygon(
+            (0..6)
+                .map(|i| Vec2::from_angle(i as f32 * TAU / 6.) * 10.)
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let key = shape_key(hexagon.shape());
+
+        let claims: Vec<_> = entities(100)
+            .into_iter()
+            .map(|entity| chains.claim(entity, None, key.clone()))
+            .collect();
+
+        assert!(claims[0].new);
+        assert!(claims[1..].iter().all(|claim| !claim.new));
+        assert!(claims.iter().all(|claim| claim.index == claims[0].index));
+
+        assert_eq!(chains.n_shapes(), 1);
+        assert_eq!(chains.len(), 1 + key.len());
+    }
+
+    #[test]
+    fn chains_are_freed_with_their_last_owner() {
+        let mut chains = VertexChains::new();
+        let entities = entities(2);
+        let key = chain_key(0, 10);
+
+        let first = chains.claim(entities[0], None, key.clone()).index;
+        let second = chains.claim(entities[1], None, key.clone()).index;
+
+        chains.free(first, entities[0]);
+        assert_eq!(chains.n_shapes(), 1);
+        assert_eq!(chains.empty_slots(), 0);
+
+        chains.free(second, entities[1]);
+        assert_eq!(chains.n_shapes(), 0);
+        assert_eq!(chains.empty_slots(), 10);
+
+        // the shape isn't shared with the freed chain anymore
+        let claim = chains.claim(entities[0], None, key);
+        assert!(claim.new);
+        assert_eq!(claim.index.index, 11);
+    }
+
+    #[test]
+    fn shape_change_reuses_existing_chain() {
+        let mut chains = VertexChains::new();
+        let entities = entities(2);
+
+        let first = chains.claim(entities[0], None, chain_key(0, 10)).index;
+        let second = chains.claim(entities[1], None, chain_key(1, 20)).index;
+
+        // moving without changing the shape keeps the chain
+        let claim = chains.claim(entities[0], Some(first), chain_key(0, 10));
+        assert!(!claim.new);
+        assert_eq!(claim.index, first);
+
+        let claim = chains.claim(entities[0], Some(first), chain_key(1, 20));
+        assert!(!claim.new);
+        assert_eq!(claim.index, second);
+
+        // the first occluder was the only one using its old chain
+        assert_eq!(chains.n_shapes(), 1);
+        assert_eq!(chains.empty_slots(), 10);
+        assert_eq!(chains.len(), 31);
+    }
+
+    #[test]
+    fn defragment_moves_shared_chains() {
+        let mut chains = VertexChains::new();
+        let entities = entities(5);
+
+        let removed = chains.claim(entities[0], None, chain_key(0, 600)).index;
+        let shared = chains.claim(entities[1], None, chain_key(1, 100)).index;
+        chains.claim(entities[2], None, chain_key(1, 100));
+        chains.claim(entities[3], None, chain_key(1, 100));
+        let last = chains.claim(entities[4], None, chain_key(2, 50)).index;
+        assert_eq!(chains.n_shapes(), 3);
+
+        // too few empty slots to defragment yet
+        assert!(chains.defragment().is_empty());
+
+        chains.free(removed, entities[0]);
+        let moves = chains.defragment();
+
+        assert_eq!(moves.len(), 2);
+        assert_eq!(
+            (moves[0].from, moves[0].to.index, moves[0].len),
+            (shared.index, 1, 100)
+        );
+        assert_eq!(
+            (moves[1].from, moves[1].to.index, moves[1].len),
+            (last.index, 101, 50)
+        );
+
+        let mut owners = moves[0].owners.clone();
+        owners.sort();
+        assert_eq!(owners, entities[1..4]);
+        assert_eq!(moves[1].owners, vec![entities[4]]);
+
+        assert_eq!(chains.len(), 151);
+        assert_eq!(chains.empty_slots(), 0);
+
+        // the moved chain is still shared under its new index
+        let claim = chains.claim(entities[0], None, chain_key(1, 100));
+        assert!(!claim.new);
+        assert_eq!(claim.index, moves[0].to);
+        assert!(chains.defragment().is_empty());
+    }
+
+    // claims and frees slots in a fixed order, returning the indices handed out
+    fn scripted_indices(allocation: IndexAllocation) -> Vec<usize> {
+        let mut slots = BufferSlots::new(allocation);
+        let entities = entities(100);
+
+        let mut indices: Vec<_> = entities[..80]
+            .iter()
+            .map(|entity| slots.claim(*entity, None))
+            .collect();
+
+        for index in indices.iter().step_by(3).rev() {
+            slots.free(*index);
+        }
+
+        assert!(slots.defragment().is_empty());
+
+        indices.extend(
+            entities[80..]
+                .iter()
+                .map(|entity| slots.claim(*entity, None)),
+        );
+
+        indices.iter().map(|index| index.index).collect()
+    }
+
+    #[test]
+    fn lowest_allocation_is_deterministic() {
+        let indices = scripted_indices(IndexAllocation::Lowest);
+        assert_eq!(indices, scripted_indices(IndexAllocation::Lowest));
+
+        // the freed slots are reused from the lowest one up
+        let reused: Vec<_> = (0..20).map(|i| BufferSlots::RESERVED + i * 3).collect();
+        assert_eq!(indices[80..], reused);
+    }
+
+    #[test]
+    fn sequential_allocation_is_deterministic() {
+        let indices = scripted_indices(IndexAllocation::Sequential);
+        assert_eq!(indices, scripted_indices(IndexAllocation::Sequential));
+
+        // freed slots are never reused
+        let expected: Vec<_> = (BufferSlots::RESERVED..BufferSlots::RESERVED + 100).collect();
+        assert_eq!(indices, expected);
+    }
+
+    #[test]
+    fn reset_restarts_allocation() {
+        for allocation in [IndexAllocation::Lowest, IndexAllocation::Sequential] {
+            let mut slots = BufferSlots::new(allocation);
+            let entities = entities(4);
+
+            let old = claim_all(&mut slots, &entities[..3]);
+            slots.free(old[&entities[1]]);
+            slots.reset();
+
+            assert_eq!(slots.len(), BufferSlots::RESERVED);
+            assert_eq!(slots.empty_slots(), 0);
+            assert!(old.values().all(|index| !slots.is_valid(*index)));
+
+            // stale indices are neither kept nor freed
+            let index = slots.claim(entities[0], Some(old[&entities[0]]));
+            assert_eq!(index.index, BufferSlots::RESERVED);
+            assert!(slots.is_valid(index));
+
+            slots.free(old[&entities[2]]);
+            assert_eq!(slots.empty_slots(), 0);
+
+            let index = slots.claim(entities[3], None);
+            assert_eq!(index.index, BufferSlots::RESERVED + 1);
+        }
+    }
+}
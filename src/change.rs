@@ -1,41 +1,496 @@
 //! Module containing logic for change detection.
 
-use bevy::prelude::*;
+use bevy::{ecs::entity::EntityHashSet, prelude::*};
 
-use crate::{lights::PointLight2d, prelude::Occluder2d};
+use crate::{
+    lights::{LightMask, PointLight2d},
+    occluders::Occluder2dShape,
+    prelude::{Occluder2d, Occluder2dEnabled},
+};
 
 /// Component that stores whether an entity has changed or not.
 #[derive(Component, Clone, Default)]
 pub struct Changes(pub bool);
 
+/// Component that stores whether an [occluder's](Occluder2d) shape has changed, along with the shape it was last compared to.
+///
+/// Moving an occluder or changing its other fields only marks its [`Changes`], so its vertices aren't rewritten.
+#[derive(Component, Clone, Default)]
+pub(crate) struct ShapeChanges {
+    pub changed: bool,
+    previous: Option<Occluder2dShape>,
+}
+
+/// Optional component that limits how often a moving [occluder](Occluder2d) is rebuilt and re-uploaded to the GPU.
+///
+/// Useful for occluders that move every frame (e.g. following physics). While the occluder moves,
+/// its shadows keep using the last rebuilt transform until either [frames](OccluderThrottle::frames) frames pass,
+/// or it moves further than the [distance](OccluderThrottle::distance) or [angle](OccluderThrottle::angle) thresholds.
+///
+/// This bounds the per-frame upload cost at the expense of shadows lagging behind the occluder.
+/// Changes to the [`Occluder2d`] component itself are always applied immediately.
+///
+/// Shadows are **not** interpolated between rebuilds: they stay in place and then jump to the occluder's
+/// current transform when it's rebuilt. Lower thresholds make the jumps smaller, but rebuild more often.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((Occluder2d::circle(10.), OccluderThrottle::new(4, 5.)));
+/// ```
+#[derive(Component, Clone, Reflect)]
+pub struct OccluderThrottle {
+    /// Maximum number of frames between rebuilds while the occluder is moving.
+    ///
+    /// **Default:** 4.
+    pub frames: u32,
+    /// Distance the occluder can move before being rebuilt immediately.
+    ///
+    /// **Default:** 8.
+    pub distance: f32,
+    /// Angle (in radians) the occluder can rotate before being rebuilt immediately.
+    ///
+    /// **Default:** 0.1.
+    pub angle: f32,
+
+    frames_since_rebuild: u32,
+    rebuilt_transform: Option<GlobalTransform>,
+}
+
+impl Default for OccluderThrottle {
+    fn default() -> Self {
+        Self {
+            frames: 4,
+            distance: 8.,
+            angle: 0.1,
+            frames_since_rebuild: 0,
+            rebuilt_transform: None,
+        }
+    }
+}
+
+impl OccluderThrottle {
+    /// Construct a new throttle with the given frame and distance thresholds.
+    pub fn new(frames: u32, distance: f32) -> Self {
+        Self {
+            frames,
+            distance,
+            ..default()
+        }
+    }
+
+    /// Construct a new throttle with the specified [angle](OccluderThrottle::angle) threshold.
+    pub fn with_angle(&self, angle: f32) -> Self {
+        let mut res = self.clone();
+        res.angle = angle;
+        res
+    }
+
+    /// The transform the occluder was last rebuilt with. This is what its shadows currently use, as-is.
+    pub fn rebuilt_transform(&self) -> Option<GlobalTransform> {
+        self.rebuilt_transform
+    }
+}
+
+/// Marker component for [occluders](Occluder2d) that never move or change.
+///
+/// Static occluders are extracted and written to the GPU once, and then skipped by change detection
+/// and extraction, which saves per-frame work in scenes with a lot of level geometry.
+/// Changes to their [`Occluder2d`] or [`Transform`] are **not** picked up automatically; send an
+/// [`InvalidateStaticOccluder`] message to re-extract one after changing it.
+///
+/// They are still culled normally, and their buffer slots are freed when they stop being visible or get despawned.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((Occluder2d::rectangle(100., 20.), StaticOccluder));
+/// ```
+#[derive(Component, Clone, Copy, Default, Reflect)]
+pub struct StaticOccluder;
+
+/// Message that makes a [`StaticOccluder`] get re-extracted and re-uploaded to the GPU on the current frame.
+///
+/// # Example
+///
+/// ```
+/// fn move_wall(mut wall: Single<(Entity, &mut Transform), With<StaticOccluder>>, mut invalidate: MessageWriter<InvalidateStaticOccluder>) {
+///     wall.1.translation.x += 10.;
+///     invalidate.write(InvalidateStaticOccluder(wall.0));
+/// }
+/// ```
+#[derive(Message, Clone, Copy, Debug)]
+pub struct InvalidateStaticOccluder(pub Entity);
+
+/// Optional component that gradually moves an [occluder's](Occluder2d) [opacity](Occluder2d::opacity) towards a target.
+///
+/// Useful for dissolving walls, fading fog, etc. Once the fade brings the opacity down to 0 the occluder is
+/// [disabled](Occluder2dEnabled) and stops being sent to the GPU, without being despawned.
+/// Fading it back up from 0 re-enables it.
+///
+/// [`Occluder2dEnabled`] is only changed at these two points, so occluders you disable yourself stay disabled while fading.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((Occluder2d::circle(10.), OccluderFade::new(0., 0.5)));
+/// ```
+#[derive(Component, Clone, Reflect)]
+pub struct OccluderFade {
+    /// Opacity the occluder is fading towards.
+    ///
+    /// **Default:** 0.
+    pub target: f32,
+    /// How much the opacity changes per second.
+    ///
+    /// **Default:** 1.
+    pub speed: f32,
+}
+
+impl Default for OccluderFade {
+    fn default() -> Self {
+        Self {
+            target: 0.,
+            speed: 1.,
+        }
+    }
+}
+
+impl OccluderFade {
+    /// Construct a new fade towards the given target opacity, with the given speed (opacity per second).
+    pub fn new(target: f32, speed: f32) -> Self {
+        Self { target, speed }
+    }
+
+    /// Construct a new fade towards the given [target](OccluderFade::target) opacity.
+    pub fn with_target(&self, target: f32) -> Self {
+        let mut res = self.clone();
+        res.target = target;
+        res
+    }
+
+    /// Construct a new fade with the given [speed](OccluderFade::speed).
+    pub fn with_speed(&self, speed: f32) -> Self {
+        let mut res = self.clone();
+        res.speed = speed;
+        res
+    }
+}
+
 /// Plugin that handles change detection. Added automatically by [`FireflyPlugin`](crate::prelude::FireflyPlugin).
 pub struct ChangePlugin;
 
 impl Plugin for ChangePlugin {
     fn build(&self, app: &mut App) {
+        app.add_message::<InvalidateStaticOccluder>();
+
         app.add_systems(PreUpdate, reset_changes);
-        app.add_systems(Update, (changed_occluders, changed_lights));
+        app.add_systems(
+            Update,
+            (
+                fade_occluders,
+                (
+                    changed_occluders,
+                    changed_occluder_shapes,
+                    throttled_occluders,
+                    changed_static_occluders,
+                    changed_lights,
+                ),
+            )
+                .chain(),
+        );
+    }
+}
+
+fn fade_occluders(
+    mut occluders: Query<(&OccluderFade, &mut Occluder2d, &mut Occluder2dEnabled)>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_secs();
+
+    for (fade, mut occluder, mut enabled) in &mut occluders {
+        let target = fade.target.clamp(0., 1.);
+
+        if occluder.opacity == target {
+            continue;
+        }
+
+        let was_visible = occluder.opacity > 0.;
+
+        let step = fade.speed.max(0.) * delta;
+        let diff = target - occluder.opacity;
+
+        occluder.opacity = match diff.abs() <= step {
+            true => target,
+            false => occluder.opacity + step * diff.signum(),
+        };
+
+        // only toggled when the fade itself crosses 0, so it doesn't override the user
+        let visible = occluder.opacity > 0.;
+        if visible != was_visible {
+            enabled.0 = visible;
+        }
     }
 }
 
 fn changed_occluders(
-    mut occluders: Query<&mut Changes, Or<(Changed<GlobalTransform>, Changed<Occluder2d>)>>,
+    mut occluders: Query<
+        &mut Changes,
+        (
+            Or<(Changed<GlobalTransform>, Changed<Occluder2d>)>,
+            Without<OccluderThrottle>,
+            Without<StaticOccluder>,
+        ),
+    >,
 ) {
     for mut changed in &mut occluders {
         changed.0 = true;
     }
 }
 
+fn changed_occluder_shapes(
+    mut occluders: Query<(&Occluder2d, &mut ShapeChanges), Changed<Occluder2d>>,
+) {
+    for (occluder, mut shape_changes) in &mut occluders {
+        if shape_changes.previous.as_ref() != Some(occluder.shape()) {
+            shape_changes.changed = true;
+            shape_changes.previous = Some(occluder.shape().clone());
+        }
+    }
+}
+
+fn throttled_occluders(
+    mut occluders: Query<
+        (
+            &mut Changes,
+            &mut OccluderThrottle,
+            &GlobalTransform,
+            Ref<Occluder2d>,
+        ),
+        Without<StaticOccluder>,
+    >,
+) {
+    for (mut changed, mut throttle, transform, occluder) in &mut occluders {
+        throttle.frames_since_rebuild = throttle.frames_since_rebuild.saturating_add(1);
+
+        let rebuild = match throttle.rebuilt_transform {
+            None => true,
+            Some(rebuilt) if rebuilt == *transform => occluder.is_changed(),
+            Some(rebuilt) => {
+                occluder.is_changed()
+                    || throttle.frames_since_rebuild >= throttle.frames
+                    || rebuilt.translation().distance(transform.translation()) > throttle.distance
+                    || rebuilt.rotation().angle_between(transform.rotation()) > throttle.angle
+            }
+        };
+
+        if rebuild {
+            changed.0 = true;
+            throttle.frames_since_rebuild = 0;
+            throttle.rebuilt_transform = Some(*transform);
+        }
+    }
+}
+
+fn changed_static_occluders(
+    mut occluders: Query<(Entity, &mut Changes, Ref<StaticOccluder>)>,
+    mut invalidated: MessageReader<InvalidateStaticOccluder>,
+) {
+    let invalidated: EntityHashSet = invalidated.read().map(|x| x.0).collect();
+
+    for (entity, mut changed, marker) in &mut occluders {
+        if marker.is_added() || invalidated.contains(&entity) {
+            changed.0 = true;
+        }
+    }
+}
+
 fn changed_lights(
-    mut lights: Query<&mut Changes, Or<(Changed<GlobalTransform>, Changed<PointLight2d>)>>,
+    mut lights: ParamSet<(
+        Query<
+            &mut Changes,
+            Or<(
+                Changed<GlobalTransform>,
+                Changed<PointLight2d>,
+                Changed<LightMask>,
+            )>,
+        >,
+        Query<&mut Changes, With<PointLight2d>>,
+    )>,
+    mut removed_masks: RemovedComponents<LightMask>,
 ) {
-    for mut changed in &mut lights {
+    for mut changed in &mut lights.p0() {
         changed.0 = true;
     }
+
+    // removing the mask un-excludes its groups, which isn't caught by change detection
+    let mut lights = lights.p1();
+    for entity in removed_masks.read() {
+        if let Ok(mut changed) = lights.get_mut(entity) {
+            changed.0 = true;
+        }
+    }
 }
 
-fn reset_changes(mut entities: Query<&mut Changes>) {
+fn reset_changes(mut entities: Query<&mut Changes>, mut shapes: Query<&mut ShapeChanges>) {
     for mut changed in &mut entities {
         *changed = default();
     }
+
+    for mut shape_changes in &mut shapes {
+        shape_changes.changed = false;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn app() -> App {
+        let mut app = App::new();
+        app.init_resource::<Time>().add_plugins(ChangePlugin);
+        app
+    }
+
+    fn advance(app: &mut App, secs: f32) {
+        app.world_mut()
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(secs));
+        app.update();
+    }
+
+    fn state(app: &App, entity: Entity) -> (f32, bool) {
+        let entity = app.world().entity(entity);
+        (
+            entity.get::<Occluder2d>().unwrap().opacity,
+            entity.get::<Occluder2dEnabled>().unwrap().0,
+        )
+    }
+
+    #[test]
+    fn fade_disables_and_reenables() {
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn((Occluder2d::circle(10.), OccluderFade::new(0., 1.)))
+            .id();
+
+        advance(&mut app, 0.5);
+        assert_eq!(state(&app, entity), (0.5, true));
+
+        advance(&mut app, 1.);
+        assert_eq!(state(&app, entity), (0., false));
+
+        app.world_mut()
+            .entity_mut(entity)
+            .get_mut::<OccluderFade>()
+            .unwrap()
+            .target = 1.;
+
+        advance(&mut app, 0.25);
+        assert_eq!(state(&app, entity), (0.25, true));
+    }
+
+    #[test]
+    fn fade_keeps_user_disabled() {
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn((
+                Occluder2d::circle(10.).with_opacity(0.5),
+                OccluderFade::new(1., 1.),
+                Occluder2dEnabled(false),
+            ))
+            .id();
+
+        advance(&mut app, 0.25);
+        assert_eq!(state(&app, entity), (0.75, false));
+
+        advance(&mut app, 1.);
+        assert_eq!(state(&app, entity), (1., false));
+    }
+
+    #[test]
+    fn removing_light_mask_marks_changes() {
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn((PointLight2d::default(), LightMask::excluding([1])))
+            .id();
+
+        app.update();
+        app.update();
+        assert!(!app.world().get::<Changes>(entity).unwrap().0);
+
+        app.world_mut().entity_mut(entity).remove::<LightMask>();
+        app.update();
+        assert!(app.world().get::<Changes>(entity).unwrap().0);
+    }
+
+    fn changes(app: &App, entity: Entity) -> (bool, bool) {
+        let entity = app.world().entity(entity);
+        (
+            entity.get::<Changes>().unwrap().0,
+            entity.get::<ShapeChanges>().unwrap().changed,
+        )
+    }
+
+    fn square() -> Vec<Vec2> {
+        vec![vec2(0., 0.), vec2(10., 0.), vec2(10., 10.), vec2(0., 10.)]
+    }
+
+    #[test]
+    fn moving_occluder_keeps_its_shape() {
+        let mut app = app();
+        app.add_plugins(TransformPlugin);
+
+        let entity = app
+            .world_mut()
+            .spawn(Occluder2d::polygon(square()).unwrap())
+            .id();
+
+        app.update();
+        assert_eq!(changes(&app, entity), (true, true));
+
+        app.update();
+        assert_eq!(changes(&app, entity), (false, false));
+
+        app.world_mut()
+            .get_mut::<Transform>(entity)
+            .unwrap()
+            .translation
+            .x += 10.;
+
+        // the global transform is propagated at the end of the frame, and picked up on the next one
+        app.update();
+        app.update();
+        assert_eq!(changes(&app, entity), (true, false));
+    }
+
+    #[test]
+    fn set_vertices_changes_the_shape() {
+        let mut app = app();
+        let entity = app
+            .world_mut()
+            .spawn(Occluder2d::polygon(square()).unwrap())
+            .id();
+
+        app.update();
+        app.update();
+        assert_eq!(changes(&app, entity), (false, false));
+
+        let mut occluder = app.world_mut().get_mut::<Occluder2d>(entity).unwrap();
+        assert!(occluder.set_vertices([vec2(0., 0.), vec2(20., 0.), vec2(10., 10.)]));
+
+        app.update();
+        assert_eq!(changes(&app, entity), (true, true));
+
+        // setting the same points again only marks the component as changed
+        let mut occluder = app.world_mut().get_mut::<Occluder2d>(entity).unwrap();
+        assert!(occluder.set_vertices([vec2(0., 0.), vec2(20., 0.), vec2(10., 10.)]));
+
+        app.update();
+        assert_eq!(changes(&app, entity), (true, false));
+    }
 }
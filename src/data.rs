@@ -1,8 +1,9 @@
-use std::usize;
+use std::{time::Duration, usize};
 
 use bevy::{
     camera::visibility::RenderLayers,
     color::palettes::css::WHITE,
+    ecs::entity::EntityHashMap,
     prelude::*,
     render::{extract_component::ExtractComponent, render_resource::ShaderType},
 };
@@ -27,9 +28,40 @@ pub struct FireflyConfig {
 
     /// Brightness for the ambient light. If 0 and no lights are present, everything will be completely black.
     ///
+    /// The ambient light is applied along with the lightmap, where each pixel gets the brighter of the two.
+    /// For a base value that lights are accumulated over instead, see [lightmap_clear_color](FireflyConfig::lightmap_clear_color).
+    ///
     /// **Default:** 0.
     pub ambient_brightness: f32,
 
+    /// Optional texture used instead of the flat [ambient color](FireflyConfig::ambient_color), e.g. for sky gradients.
+    ///
+    /// The texture is sampled in **screen space**: it is stretched over the camera's view and doesn't move with the camera.
+    /// Its color is scaled by the [ambient brightness](FireflyConfig::ambient_brightness), just like the ambient color.
+    ///
+    /// The flat ambient color is used until the image has finished loading.
+    ///
+    /// **Performance Impact:** Minor.
+    ///
+    /// **Default:** None.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub ambient_texture: Option<Handle<Image>>,
+
+    /// Color the lightmap is cleared to before any lights are rendered into it, acting as a base value for every pixel.
+    ///
+    /// Unlike the [ambient light](FireflyConfig::ambient_brightness), which is only taken into account when the lightmap is applied,
+    /// this is part of the lightmap itself. Lights are [accumulated](FireflyConfig::light_accumulation) over it, so with
+    /// [Max](LightAccumulation::Max) only the lights brighter than it are visible, while with [Add](LightAccumulation::Add) they're added on top.
+    /// It's also kept when the lightmap is [combined](CombineLightmapTo) into another camera's, and read back by a
+    /// [`LightmapReadback`](crate::prelude::LightmapReadback). The ambient light is still applied over it.
+    ///
+    /// Only the main lightmap is cleared to this color, the [extra channels](FireflyConfig::extra_channels) start out black.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** [Color::NONE] (transparent black).
+    pub lightmap_clear_color: Color,
+
     /// Light bands will divide the lightmap into brackets of the given size.
     ///
     /// E.g. with `light_bands: Some(0.3)`, all color channels in the `[0-0.3]` interval will be the same color,
@@ -45,10 +77,50 @@ pub struct FireflyConfig {
     /// **Default:** true.
     pub soft_shadows: bool,
 
+    /// Number of samples taken across a light's [core](crate::prelude::LightCore) to compute the penumbra of soft shadows.
+    ///
+    /// With 1, the penumbra is approximated analytically from the angle to the occluder's edge.
+    /// Higher values instead measure how much of the light's core is hidden by the occluder, which gives
+    /// a more physically accurate falloff. Few samples will show visible steps in the penumbra.
+    ///
+    /// Must be between 1 and 255, other values are clamped. Each value compiles its own pipeline.
+    ///
+    /// **Performance Impact:** Scales with the number of samples, only for pixels in a penumbra.
+    ///
+    /// **Default:** 1.
+    pub soft_shadow_samples: u32,
+
+    /// Width, in pixels, over which the hard edges of shadows are smoothed out.
+    ///
+    /// This only anti-aliases the boundary between light and shadow, so it stays crisp but isn't jagged at low resolutions
+    /// or when zoomed in. The width is in screen pixels, so it doesn't change with the camera's scale.
+    /// It's unrelated to [soft shadows](FireflyConfig::soft_shadows), which model the penumbra of lights with a [core](crate::prelude::LightCore).
+    /// Set to 0 to disable.
+    ///
+    /// **Performance Impact:** Minor.
+    ///
+    /// **Default:** 1.
+    pub shadow_edge_feather: f32,
+
+    /// Minimum fraction of a light that still reaches the fragments it casts shadows on, between 0 and 1.
+    ///
+    /// Unlike the [ambient light](FireflyConfig::ambient_brightness), this only brightens shadows, and only within each light's range,
+    /// so that fully opaque occluders don't cast pitch-black shadows. E.g. with 0.2, shadowed fragments still receive 20% of the light.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** 0.
+    pub min_shadow_light: f32,
+
     /// Whether to use occlusion z-sorting or not.
     ///
     /// If this is enabled, shadows cast by occluders won't affect sprites with a higher z position.
     ///
+    /// Every occluder is compared on its own against the z of the topmost opaque sprite on each pixel,
+    /// so overlapping occluders at different z values still shadow only the sprites below them.
+    /// With 16 bit [stencils](FireflyConfig::enable_32bit_stencils), large z values lose precision, which can
+    /// break this ordering. A warning is logged if z-sorted occluders are placed at such values.
+    ///
     /// Very useful for top-down games.
     ///
     /// **Performance Impact:** None.
@@ -100,6 +172,34 @@ pub struct FireflyConfig {
     /// **Default**: true.
     pub lightmap_filtering: bool,
 
+    /// Enables temporal upsampling of the lightmap.
+    ///
+    /// Meant to be used along with a reduced [`lightmap_size`](FireflyConfig::lightmap_size). Each frame, the lightmap is
+    /// rendered over a slightly shifted (jittered) grid, and the result is accumulated into a full resolution history,
+    /// reprojected based on the camera's movement. This reconstructs most of the detail of a full resolution lightmap,
+    /// at the cost of some blurring and lag on fast moving lights and occluders.
+    ///
+    /// Ignored for cameras that are [combined into](crate::prelude::CombineLightmapTo) other lightmaps, and for lightmaps
+    /// rendered at the [window's size](LightmapSize::Window) unless [temporal accumulation](FireflyConfig::temporal_accumulation) is enabled.
+    ///
+    /// **Performance Impact:** Minor (one extra full resolution pass and two extra textures).
+    ///
+    /// **Default**: false.
+    pub temporal_upsampling: bool,
+
+    /// Optional temporal accumulation of the lightmap, which blends each frame into the previous ones.
+    ///
+    /// This reduces the shimmering of soft shadows around fast moving lights. The previous result is reprojected
+    /// based on the camera's movement, and clamped to the range of the current frame's neighbouring texels to avoid ghosting.
+    /// If [temporal upsampling](FireflyConfig::temporal_upsampling) is also enabled, these settings control its accumulation as well.
+    ///
+    /// Ignored for cameras that are [combined into](crate::prelude::CombineLightmapTo) other lightmaps.
+    ///
+    /// **Performance Impact:** Minor (one extra full resolution pass and two extra textures).
+    ///
+    /// **Default**: None.
+    pub temporal_accumulation: Option<TemporalAccumulation>,
+
     /// Enables 32 bit sizes for the sprite stencil textures
     /// (textures in which the sprite's z coordinate and other values are stored when
     /// used in e.g. occluion z-sorting).
@@ -107,11 +207,265 @@ pub struct FireflyConfig {
     /// Normally, WebGPU limits these to 16 bits, however, this can cause
     /// imprecise z-sorting and normal maps since bevy's f32s will be limited to f16 precision.
     ///
-    /// Enabling this fixes those precision issues; however, it will prevent your app
-    /// from running on web.    
+    /// Enabling this fixes those precision issues. It requires the `FLOAT32_BLENDABLE` feature,
+    /// which most WebGPU devices don't support, in which case 16 bit stencils are used instead and a warning is logged.
     ///
     /// **Default**: false.
     pub enable_32bit_stencils: bool,
+
+    /// Enables the shadow mask texture.
+    ///
+    /// When enabled, a single-channel [`ShadowMaskTexture`](crate::ShadowMaskTexture) is rendered
+    /// alongside the lightmap, storing the total occlusion of each pixel independent of the lights' colors.
+    /// This can be used for gameplay logic (e.g. stealth) or custom effects layered on top of Firefly.
+    ///
+    /// Cameras that combine their lightmap into another camera via [`CombineLightmapTo`] don't produce a shadow mask.
+    ///
+    /// **Performance Impact:** Minor.
+    ///
+    /// **Default**: false.
+    pub shadow_mask: bool,
+
+    /// Distance, in world units, by which each pixel is moved towards the light before testing it against occluders.
+    ///
+    /// Small values (e.g. 0.1 - 0.5) remove the flickering "acne" that can appear at the edges of
+    /// shadows cast by thin polylines or by occluders very close to a light.
+    /// Values that are too large will visibly detach shadows from the occluders casting them.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default**: 0.
+    pub shadow_bias: f32,
+
+    /// How the final lightmap is blended over the scene.
+    ///
+    /// The [ambient light](FireflyConfig::ambient_brightness) is part of the lightmap, so it's applied the same way in every mode.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default**: [LightmapBlend::Multiply].
+    pub lightmap_blend: LightmapBlend,
+
+    /// How the light of overlapping lights is accumulated in the lightmap.
+    ///
+    /// Each mode compiles its own pipeline.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default**: [LightAccumulation::Max].
+    pub light_accumulation: LightAccumulation,
+
+    /// Optional fill light, given as `(direction, color, strength)`.
+    ///
+    /// Unlike the flat ambient light, the fill light comes predominantly from one direction (e.g. the sky),
+    /// shading normal-mapped sprites accordingly even when no lights are nearby. It doesn't cast any shadows.
+    /// Sprites facing the direction receive the full strength, sprites facing away receive none,
+    /// and sprites without a normal map receive half.
+    ///
+    /// Normals are only used if the [normal mode](FireflyConfig::normal_mode) is not [None](NormalMode::None).
+    ///
+    /// **Performance Impact:** Minor.
+    ///
+    /// **Default**: None.
+    pub fill_light: Option<(Vec2, Color, f32)>,
+
+    /// How long an occluder has to stay off-screen (and outside the range of every visible light)
+    /// before its data is freed from the GPU.
+    ///
+    /// Longer delays avoid re-uploading occluders in scenes where the camera pans back and forth quickly,
+    /// while shorter ones free memory sooner in scenes with a lot of occluders coming and going.
+    ///
+    /// Changing this only affects occluders that become visible afterwards. If multiple cameras
+    /// have a [`FireflyConfig`], the longest delay is used.
+    ///
+    /// **Performance Impact:** Longer delays use more GPU memory.
+    ///
+    /// **Default**: 0.1 seconds.
+    pub occluder_unload_delay: Duration,
+
+    /// Multiplier applied to the accumulated light before it's blended over the scene.
+    ///
+    /// Useful for tuning how bright stacked lights end up in the final image, especially with [Hdr](bevy::camera::Hdr) cameras
+    /// where values above 1 are kept and passed on to tonemapping and bloom.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default**: 1.
+    pub exposure: f32,
+
+    /// Optional upper limit for the luminance of the (exposed) light at each pixel.
+    ///
+    /// Brighter pixels are scaled down while keeping their hue, which prevents areas lit by many lights from blowing out.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default**: None.
+    pub max_luminance: Option<f32>,
+
+    /// Optional automatic exposure, which keeps the overall brightness of the lightmap balanced as lights enter or leave the view.
+    ///
+    /// The average luminance of the lightmap is measured every frame, and the light is scaled by a gain that moves it
+    /// towards the [target](AutoExposure::target_luminance). The gain adapts over time and is applied on top of the [exposure](FireflyConfig::exposure).
+    ///
+    /// **Performance Impact:** One extra small pass that samples the lightmap.
+    ///
+    /// **Default**: None.
+    pub auto_exposure: Option<AutoExposure>,
+
+    /// Optional budget for the number of lights rendered by this camera each frame.
+    ///
+    /// When more lights are visible, only the ones with the highest [priority](crate::prelude::PointLight2d::priority)
+    /// are rendered, with ties broken by their distance to the camera. The rest are skipped entirely for that frame.
+    ///
+    /// This trades correctness for performance: skipped lights simply disappear, which can be noticeable
+    /// if the budget is much lower than the number of lights on screen. Useful for weaker hardware.
+    /// To avoid lights popping in and out, see [light_budget_fade](FireflyConfig::light_budget_fade).
+    ///
+    /// **Performance Impact:** Lowers the cost of rendering and of preparing shadows when the budget is exceeded.
+    ///
+    /// **Default:** None.
+    pub max_lights: Option<usize>,
+
+    /// How long lights take to fade in or out when they enter or leave the [light budget](FireflyConfig::max_lights).
+    ///
+    /// Without a fade, lights that drop out of the budget disappear instantly, which is visible as a snap
+    /// when the camera pans and the set of the nearest lights changes. With a fade, their intensity is ramped
+    /// down to 0 instead, and lights that enter the budget are ramped up from 0.
+    ///
+    /// Lights that are fading out are still rendered, so the budget can be briefly exceeded while lights swap places.
+    /// The fade of a light seen by multiple cameras follows the camera with the fastest fade.
+    ///
+    /// **Performance Impact:** Minor. Lights are ranked on the CPU every frame.
+    ///
+    /// **Default:** 0 (no fade).
+    pub light_budget_fade: Duration,
+
+    /// Strength of the ambient occlusion around occluders, from 0 (disabled) to 1.
+    ///
+    /// Pixels close to an occluder's edge receive slightly less light, regardless of the light's direction,
+    /// which adds some contact shadows and depth to the scene. Only the light coming from lights is darkened, not the ambient light.
+    ///
+    /// **Performance Impact:** Medium, since nearby occluders need to be checked for every pixel reached by a light.
+    ///
+    /// **Default:** 0.
+    pub ao_strength: f32,
+
+    /// Distance from an occluder's edge over which the [ambient occlusion](FireflyConfig::ao_strength) fades out.
+    ///
+    /// **Performance Impact:** Larger radii check more occluders.
+    ///
+    /// **Default:** 4.
+    pub ao_radius: f32,
+
+    /// Enables screen-space shadows for the [lights that opt into them](crate::prelude::PointLight2d::screen_space_shadows).
+    ///
+    /// When enabled, the camera's occluders are first drawn into an [`OccluderCoverageTexture`](crate::OccluderCoverageTexture).
+    /// Opted-in lights then find their shadows by marching from each pixel towards the light through that texture,
+    /// instead of testing the pixel against the edges of every occluder in range. The cost no longer grows with
+    /// the number of occluders, which makes this much faster in scenes with many of them.
+    ///
+    /// In exchange, the shadows are less accurate:
+    /// - They are hard, without any [soft shadows](FireflyConfig::soft_shadows) or [ambient occlusion](FireflyConfig::ao_strength).
+    /// - Only occluders on the screen cast shadows, so shadows of off-screen occluders pop in at the screen's edges.
+    /// - Occluders block all light channels and aren't tinted by their color, only their opacity is kept.
+    /// - Polyline occluders, [z-sorting](FireflyConfig::z_sorting) and [occlusion layers](crate::prelude::OcclusionLayers) are ignored.
+    /// - Very thin occluders far from a light can be skipped over by the march, and shadow edges follow the coverage texture's resolution.
+    ///
+    /// Pixels covered by an occluder aren't shadowed by that occluder itself, unless the light is inside it.
+    ///
+    /// **Performance Impact:** One extra pass that draws the occluders on screen, and one extra texture the size of the lightmap.
+    ///
+    /// **Default:** false.
+    pub screen_space_shadows: bool,
+
+    /// [Channel](crate::prelude::PointLight2d::channel) of the lights that are rendered to this camera's lightmap.
+    ///
+    /// Lights on other channels don't affect the camera's output.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** 0.
+    pub channel: u8,
+
+    /// Extra light channels rendered by this camera, each into its own lightmap.
+    ///
+    /// Unlike the camera's main [channel](FireflyConfig::channel), these lightmaps aren't applied to the camera's output.
+    /// They can be fetched from the [`LightChannelTextures`](crate::LightChannelTextures) component in custom render passes,
+    /// e.g. to keep a "vision" channel apart from the lights that make up the scene's ambience.
+    ///
+    /// They skip [temporal upsampling](FireflyConfig::temporal_upsampling) and the [shadow mask](FireflyConfig::shadow_mask),
+    /// and aren't rendered by cameras that [combine their lightmap into another](CombineLightmapTo).
+    ///
+    /// **Performance Impact:** Each channel renders its lights into one more lightmap.
+    ///
+    /// **Default:** Empty.
+    pub extra_channels: Vec<u8>,
+}
+
+/// Settings of the [automatic exposure](FireflyConfig::auto_exposure) of a camera.
+#[derive(Clone, Copy, Reflect, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutoExposure {
+    /// The average luminance the lightmap is adjusted towards.
+    ///
+    /// **Default:** 0.5.
+    pub target_luminance: f32,
+
+    /// How fast the gain adapts to changes in brightness. Higher values adapt faster, and 0 freezes the gain.
+    ///
+    /// **Default:** 2.
+    pub speed: f32,
+
+    /// The lowest gain that can be applied, limiting how much bright scenes are darkened.
+    ///
+    /// **Default:** 0.25.
+    pub min_gain: f32,
+
+    /// The highest gain that can be applied, limiting how much dark scenes are brightened.
+    ///
+    /// **Default:** 4.
+    pub max_gain: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            target_luminance: 0.5,
+            speed: 2.0,
+            min_gain: 0.25,
+            max_gain: 4.0,
+        }
+    }
+}
+
+/// Settings of the [temporal accumulation](FireflyConfig::temporal_accumulation) of a camera's lightmap.
+#[derive(Clone, Copy, Reflect, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemporalAccumulation {
+    /// How much the current frame contributes to the accumulated lightmap, between 0 and 1.
+    ///
+    /// Lower values hide more noise, but make the lighting react slower to changes.
+    ///
+    /// **Default:** 0.1.
+    pub blend: f32,
+
+    /// How far the accumulated lightmap can stray outside the range of the current frame's neighbouring texels,
+    /// relative to the size of that range.
+    ///
+    /// At 0, history that doesn't match the current frame is rejected, which avoids ghosting behind moving lights and occluders.
+    /// Higher values keep more of the history, smoothing out more noise at the cost of some ghosting.
+    ///
+    /// **Default:** 0.
+    pub clamp_margin: f32,
+}
+
+impl Default for TemporalAccumulation {
+    fn default() -> Self {
+        Self {
+            blend: 0.1,
+            clamp_margin: 0.0,
+        }
+    }
 }
 
 /// Specifies how multiple textures will be combined.
@@ -128,6 +482,41 @@ pub enum CombinationMode {
     None,
 }
 
+/// Specifies how the lightmap is applied over the scene.
+///
+/// **Default:** Multiply.
+#[derive(Clone, Copy, Reflect, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LightmapBlend {
+    /// The scene is multiplied by the lightmap. Unlit areas are dark.
+    #[default]
+    Multiply,
+    /// The lightmap is added over the scene, creating a glow. Unlit areas keep their original color.
+    Add,
+    /// The lightmap is screen-blended over the scene. Similar to [Add](LightmapBlend::Add), but softer on bright areas.
+    Screen,
+    /// The lightmap is soft-light blended over the scene. A light level of 0.5 leaves the scene unchanged,
+    /// while lower and higher values gently darken or brighten it.
+    SoftLight,
+}
+
+/// Specifies how the light of overlapping lights is accumulated in the lightmap.
+///
+/// **Default:** Max.
+#[derive(Clone, Copy, Reflect, Default, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LightAccumulation {
+    /// Each channel keeps the brightest light reaching it. Overlapping lights don't get brighter,
+    /// and overlapping red and blue lights don't mix into purple.
+    #[default]
+    Max,
+    /// The light of all lights is summed, so overlapping lights get brighter and their colors mix.
+    ///
+    /// Without an [Hdr](bevy::camera::Hdr) camera, the lightmap saturates at 1. With one, the sum is only limited by the
+    /// lightmap's format, so consider setting a [max luminance](FireflyConfig::max_luminance) as well.
+    Add,
+}
+
 #[derive(Clone, Copy, Reflect, Default, Debug)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LightmapSize {
@@ -141,8 +530,11 @@ pub enum LightmapSize {
 ///
 /// In order to fully use normal maps, you will need to add the [NormalMap](crate::prelude::NormalMap) component to Sprites.
 ///
+/// It can also be added to sprites as a component, overriding the config's [normal mode](FireflyConfig::normal_mode) for that sprite.
+/// This is useful for mixed scenes, such as side-on characters standing on a top-down floor.
+///
 /// **Default:** [None](NormalMapMode::None).
-#[derive(Debug, Clone, Reflect)]
+#[derive(Component, Debug, Clone, Reflect)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NormalMode {
     /// No normal maps will be used in rendering.
@@ -164,13 +556,29 @@ pub enum NormalMode {
     TopDownZ,
 }
 
+impl NormalMode {
+    pub(crate) fn as_u32(&self) -> u32 {
+        match self {
+            NormalMode::None => 0,
+            NormalMode::Simple => 1,
+            NormalMode::TopDownY => 2,
+            NormalMode::TopDownZ => 3,
+        }
+    }
+}
+
 impl Default for FireflyConfig {
     fn default() -> Self {
         Self {
             ambient_color: Color::Srgba(WHITE),
             ambient_brightness: 0.0,
+            ambient_texture: None,
+            lightmap_clear_color: Color::NONE,
             light_bands: None,
             soft_shadows: true,
+            soft_shadow_samples: 1,
+            shadow_edge_feather: 1.0,
+            min_shadow_light: 0.0,
             z_sorting: true,
             z_sorting_error_margin: 0.0,
             normal_mode: NormalMode::None,
@@ -178,7 +586,215 @@ impl Default for FireflyConfig {
             combination_mode: CombinationMode::Multiply,
             lightmap_size: LightmapSize::Window,
             lightmap_filtering: true,
+            temporal_upsampling: false,
+            temporal_accumulation: None,
             enable_32bit_stencils: false,
+            shadow_mask: false,
+            shadow_bias: 0.0,
+            lightmap_blend: LightmapBlend::Multiply,
+            light_accumulation: LightAccumulation::Max,
+            fill_light: None,
+            occluder_unload_delay: Duration::from_secs_f32(0.1),
+            exposure: 1.0,
+            max_luminance: None,
+            auto_exposure: None,
+            max_lights: None,
+            light_budget_fade: Duration::ZERO,
+            ao_strength: 0.0,
+            ao_radius: 4.0,
+            screen_space_shadows: false,
+            channel: 0,
+            extra_channels: vec![],
+        }
+    }
+}
+
+impl FireflyConfig {
+    /// Check the config for invalid values, returning the first one found.
+    ///
+    /// Invalid values never cause errors while rendering, they're clamped to the closest valid value instead.
+    /// [FireflyPlugin](crate::prelude::FireflyPlugin) calls this whenever a camera's config changes,
+    /// and logs a warning naming the offending field.
+    pub fn validate(&self) -> Result<(), FireflyConfigError> {
+        fn non_negative(field: &'static str, value: f32) -> Result<(), FireflyConfigError> {
+            match value >= 0.0 {
+                true => Ok(()),
+                false => Err(FireflyConfigError::Negative { field, value }),
+            }
+        }
+
+        fn positive(field: &'static str, value: f32) -> Result<(), FireflyConfigError> {
+            match value > 0.0 {
+                true => Ok(()),
+                false => Err(FireflyConfigError::NotPositive { field, value }),
+            }
+        }
+
+        fn in_range(
+            field: &'static str,
+            value: f32,
+            min: f32,
+            max: f32,
+        ) -> Result<(), FireflyConfigError> {
+            match (min..=max).contains(&value) {
+                true => Ok(()),
+                false => Err(FireflyConfigError::OutOfRange {
+                    field,
+                    value,
+                    min,
+                    max,
+                }),
+            }
+        }
+
+        non_negative("ambient_brightness", self.ambient_brightness)?;
+
+        if let Some(light_bands) = self.light_bands {
+            positive("light_bands", light_bands)?;
+        }
+
+        in_range(
+            "soft_shadow_samples",
+            self.soft_shadow_samples as f32,
+            1.0,
+            255.0,
+        )?;
+        non_negative("shadow_edge_feather", self.shadow_edge_feather)?;
+        in_range("min_shadow_light", self.min_shadow_light, 0.0, 1.0)?;
+        non_negative("shadow_bias", self.shadow_bias)?;
+
+        if let LightmapSize::Scaled(scale) = self.lightmap_size {
+            positive("lightmap_size", scale)?;
+        }
+
+        if let Some(accumulation) = self.temporal_accumulation {
+            in_range("temporal_accumulation.blend", accumulation.blend, 0.0, 1.0)?;
+            non_negative(
+                "temporal_accumulation.clamp_margin",
+                accumulation.clamp_margin,
+            )?;
+        }
+
+        non_negative("exposure", self.exposure)?;
+
+        if let Some(max_luminance) = self.max_luminance {
+            non_negative("max_luminance", max_luminance)?;
+        }
+
+        if let Some(auto_exposure) = self.auto_exposure {
+            non_negative(
+                "auto_exposure.target_luminance",
+                auto_exposure.target_luminance,
+            )?;
+            non_negative("auto_exposure.speed", auto_exposure.speed)?;
+            non_negative("auto_exposure.min_gain", auto_exposure.min_gain)?;
+            in_range(
+                "auto_exposure.max_gain",
+                auto_exposure.max_gain,
+                auto_exposure.min_gain,
+                f32::INFINITY,
+            )?;
+        }
+
+        in_range("ao_strength", self.ao_strength, 0.0, 1.0)?;
+        non_negative("ao_radius", self.ao_radius)?;
+
+        Ok(())
+    }
+
+    /// Whether the lightmap is accumulated into a history over time, for
+    /// [temporal upsampling](FireflyConfig::temporal_upsampling) or [accumulation](FireflyConfig::temporal_accumulation).
+    ///
+    /// Temporal upsampling alone doesn't need one if the lightmap is already rendered at the [window's size](LightmapSize::Window).
+    pub(crate) fn uses_lightmap_history(&self) -> bool {
+        let upsampled =
+            self.temporal_upsampling && !matches!(self.lightmap_size, LightmapSize::Window);
+        upsampled || self.temporal_accumulation.is_some()
+    }
+}
+
+/// Invalid value found in a [`FireflyConfig`] by [`validate`](FireflyConfig::validate).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FireflyConfigError {
+    /// The field can't be negative.
+    Negative { field: &'static str, value: f32 },
+    /// The field needs to be greater than 0.
+    NotPositive { field: &'static str, value: f32 },
+    /// The field needs to be between `min` and `max`, inclusive.
+    OutOfRange {
+        field: &'static str,
+        value: f32,
+        min: f32,
+        max: f32,
+    },
+}
+
+impl FireflyConfigError {
+    /// Name of the offending field of [`FireflyConfig`].
+    pub fn field(&self) -> &'static str {
+        match self {
+            Self::Negative { field, .. }
+            | Self::NotPositive { field, .. }
+            | Self::OutOfRange { field, .. } => field,
+        }
+    }
+}
+
+impl std::fmt::Display for FireflyConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Negative { field, value } => {
+                write!(
+                    f,
+                    "FireflyConfig::{field} is {value}, but can't be negative"
+                )
+            }
+            Self::NotPositive { field, value } => {
+                write!(
+                    f,
+                    "FireflyConfig::{field} is {value}, but must be greater than 0"
+                )
+            }
+            Self::OutOfRange {
+                field,
+                value,
+                min,
+                max,
+            } => write!(
+                f,
+                "FireflyConfig::{field} is {value}, but must be between {min} and {max}"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FireflyConfigError {}
+
+/// Logs a warning for cameras with an invalid [`FireflyConfig`], once for each different error.
+pub(crate) fn warn_invalid_configs(
+    configs: Query<(Entity, Ref<FireflyConfig>)>,
+    mut removed: RemovedComponents<FireflyConfig>,
+    mut warned: Local<EntityHashMap<FireflyConfigError>>,
+) {
+    for entity in removed.read() {
+        warned.remove(&entity);
+    }
+
+    for (entity, config) in &configs {
+        if !config.is_changed() {
+            continue;
+        }
+
+        match config.validate() {
+            Ok(()) => {
+                warned.remove(&entity);
+            }
+            Err(err) => {
+                if warned.get(&entity) != Some(&err) {
+                    warn!("{err} on camera {entity}. It will be clamped.");
+                    warned.insert(entity, err);
+                }
+            }
         }
     }
 }
@@ -190,6 +806,8 @@ pub struct UniformFireflyConfig {
     pub ambient_brightness: f32,
     pub light_bands: f32,
     pub soft_shadows: u32,
+    pub shadow_edge_feather: f32,
+    pub min_shadow_light: f32,
     pub z_sorting: u32,
     pub z_sorting_error_margin: f32,
     pub normal_mode: u32,
@@ -197,6 +815,28 @@ pub struct UniformFireflyConfig {
     pub n_combined_lightmaps: u32,
     pub combination_mode: u32,
     pub texture_scale: Vec2,
+    pub shadow_bias: f32,
+    pub lightmap_blend: u32,
+    pub fill_direction: Vec2,
+    pub fill_color: Vec3,
+    pub fill_strength: f32,
+    pub jitter: Vec2,
+    pub history_offset: Vec2,
+    pub history_valid: u32,
+    pub temporal_blend: f32,
+    pub temporal_clamp_margin: f32,
+    pub exposure: f32,
+    pub max_luminance: f32,
+    pub auto_exposure: u32,
+    pub auto_exposure_target: f32,
+    pub auto_exposure_adaptation: f32,
+    pub auto_exposure_min_gain: f32,
+    pub auto_exposure_max_gain: f32,
+    pub has_ambient_texture: u32,
+    pub ao_strength: f32,
+    pub ao_radius: f32,
+    pub screen_space_shadows: u32,
+    pub viewport: Vec4,
 }
 
 /// Add this **relationship** component to a camera in order to combine it's lightmap into the result of another lightmap.
@@ -242,11 +882,11 @@ pub struct UniformFireflyConfig {
 /// into another camera (only the pre-combination lightmap will be combined).
 ///
 /// Ambient light from lightmaps is not transferred over when combined to other lightmaps.
-#[derive(Component)]
+#[derive(Component, Reflect)]
 #[relationship(relationship_target = CombinedLightmaps)]
 pub struct CombineLightmapTo(pub Entity);
 
-#[derive(Component)]
+#[derive(Component, Reflect)]
 #[relationship_target(relationship = CombineLightmapTo, linked_spawn)]
 pub struct CombinedLightmaps(Vec<Entity>);
 
@@ -255,3 +895,97 @@ pub struct ExtractedCombinedLightmaps(pub Vec<Entity>);
 
 #[derive(Component)]
 pub struct ExtractedCombineLightmapTo(pub Entity, pub u32);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validate(edit: impl FnOnce(&mut FireflyConfig)) -> Result<(), FireflyConfigError> {
+        let mut config = FireflyConfig::default();
+        edit(&mut config);
+        config.validate()
+    }
+
+    #[test]
+    fn default_config_is_valid() {
+        assert_eq!(FireflyConfig::default().validate(), Ok(()));
+        assert_eq!(
+            validate(|config| {
+                config.light_bands = Some(0.1);
+                config.lightmap_size = LightmapSize::Scaled(0.5);
+                config.auto_exposure = Some(AutoExposure::default());
+                config.temporal_accumulation = Some(TemporalAccumulation::default());
+            }),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn negative_fields_are_invalid() {
+        assert_eq!(
+            validate(|config| config.ambient_brightness = -1.0),
+            Err(FireflyConfigError::Negative {
+                field: "ambient_brightness",
+                value: -1.0
+            })
+        );
+        assert_eq!(
+            validate(|config| config.ao_radius = -2.0).map_err(|err| err.field()),
+            Err("ao_radius")
+        );
+    }
+
+    #[test]
+    fn non_positive_fields_are_invalid() {
+        assert_eq!(
+            validate(|config| config.light_bands = Some(0.0)),
+            Err(FireflyConfigError::NotPositive {
+                field: "light_bands",
+                value: 0.0
+            })
+        );
+        assert_eq!(
+            validate(|config| config.lightmap_size = LightmapSize::Scaled(-0.5)),
+            Err(FireflyConfigError::NotPositive {
+                field: "lightmap_size",
+                value: -0.5
+            })
+        );
+    }
+
+    #[test]
+    fn out_of_range_fields_are_invalid() {
+        assert_eq!(
+            validate(|config| config.min_shadow_light = 1.5),
+            Err(FireflyConfigError::OutOfRange {
+                field: "min_shadow_light",
+                value: 1.5,
+                min: 0.0,
+                max: 1.0
+            })
+        );
+        assert_eq!(
+            validate(|config| config.soft_shadow_samples = 0).map_err(|err| err.field()),
+            Err("soft_shadow_samples")
+        );
+    }
+
+    #[test]
+    fn auto_exposure_max_gain_below_min_gain_is_invalid() {
+        let auto_exposure = AutoExposure {
+            min_gain: 2.0,
+            max_gain: 1.0,
+            ..default()
+        };
+
+        assert_eq!(
+            validate(|config| config.auto_exposure = Some(auto_exposure)),
+            Err(FireflyConfigError::OutOfRange {
+                field: "auto_exposure.max_gain",
+                value: 1.0,
+                min: 2.0,
+                max: f32::INFINITY
+            })
+        );
+    }
+}
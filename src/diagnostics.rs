@@ -0,0 +1,100 @@
+//! Module containing the optional [`FireflyDiagnosticsPlugin`], which reports render statistics as bevy [diagnostics](bevy::diagnostic).
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use bevy::{
+    diagnostic::{Diagnostic, DiagnosticPath, Diagnostics, RegisterDiagnostic},
+    prelude::*,
+    render::{Render, RenderApp, RenderSystems},
+};
+
+use crate::{buffers::BufferStats, lights::ExtractedPointLight, occluders::ExtractedOccluder};
+
+/// Plugin that registers diagnostics with the number of lights and occluders Firefly is rendering.
+///
+/// It's not added by [`FireflyPlugin`](crate::prelude::FireflyPlugin). Add it yourself when profiling, e.g. along with
+/// [`LogDiagnosticsPlugin`](bevy::diagnostic::LogDiagnosticsPlugin) or your own performance UI.
+///
+/// # Example
+///
+/// ```
+/// app.add_plugins((FireflyPlugin, FireflyDiagnosticsPlugin, LogDiagnosticsPlugin::default()));
+/// ```
+pub struct FireflyDiagnosticsPlugin;
+
+impl FireflyDiagnosticsPlugin {
+    /// Number of lights that are currently loaded on the GPU, because they're visible or were visible recently.
+    pub const VISIBLE_LIGHTS: DiagnosticPath = DiagnosticPath::const_new("firefly/visible_lights");
+    /// Number of occluders that are currently loaded on the GPU, because they're visible or were visible recently.
+    pub const VISIBLE_OCCLUDERS: DiagnosticPath =
+        DiagnosticPath::const_new("firefly/visible_occluders");
+    /// Number of slots used in the occluder vertex buffer, including the empty ones left by removed occluders.
+    pub const VERTEX_BUFFER_LEN: DiagnosticPath =
+        DiagnosticPath::const_new("firefly/vertex_buffer_len");
+}
+
+/// Counters written by the Render World and read by the Main World.
+#[derive(Resource, Clone, Default)]
+struct FireflyStats(Arc<Stats>);
+
+#[derive(Default)]
+struct Stats {
+    lights: AtomicUsize,
+    occluders: AtomicUsize,
+    vertices: AtomicUsize,
+}
+
+impl Plugin for FireflyDiagnosticsPlugin {
+    fn build(&self, app: &mut App) {
+        let stats = FireflyStats::default();
+
+        app.register_diagnostic(Diagnostic::new(Self::VISIBLE_LIGHTS))
+            .register_diagnostic(Diagnostic::new(Self::VISIBLE_OCCLUDERS))
+            .register_diagnostic(Diagnostic::new(Self::VERTEX_BUFFER_LEN));
+
+        app.insert_resource(stats.clone());
+        app.add_systems(Update, measure_stats);
+
+        let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
+            return;
+        };
+
+        render_app.insert_resource(stats);
+        render_app.add_systems(
+            Render,
+            collect_stats.in_set(RenderSystems::PrepareBindGroups),
+        );
+    }
+}
+
+fn collect_stats(
+    stats: Res<FireflyStats>,
+    lights: Query<(), With<ExtractedPointLight>>,
+    occluders: Query<(), With<ExtractedOccluder>>,
+    buffer_stats: Res<BufferStats>,
+) {
+    stats.0.lights.store(lights.iter().len(), Ordering::Relaxed);
+    stats
+        .0
+        .occluders
+        .store(occluders.iter().len(), Ordering::Relaxed);
+    stats
+        .0
+        .vertices
+        .store(buffer_stats.vertices.len, Ordering::Relaxed);
+}
+
+fn measure_stats(mut diagnostics: Diagnostics, stats: Res<FireflyStats>) {
+    diagnostics.add_measurement(&FireflyDiagnosticsPlugin::VISIBLE_LIGHTS, || {
+        stats.0.lights.load(Ordering::Relaxed) as f64
+    });
+    diagnostics.add_measurement(&FireflyDiagnosticsPlugin::VISIBLE_OCCLUDERS, || {
+        stats.0.occluders.load(Ordering::Relaxed) as f64
+    });
+    diagnostics.add_measurement(&FireflyDiagnosticsPlugin::VERTEX_BUFFER_LEN, || {
+        stats.0.vertices.load(Ordering::Relaxed) as f64
+    });
+}
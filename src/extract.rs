@@ -18,18 +18,22 @@ use bevy::{
 
 use crate::{
     LightmapPhase,
-    change::Changes,
+    change::{Changes, OccluderThrottle, ShapeChanges, StaticOccluder},
     data::{
         CombineLightmapTo, CombinedLightmaps, ExtractedCombineLightmapTo,
-        ExtractedCombinedLightmaps, ExtractedWorldData, FireflyConfig,
+        ExtractedCombinedLightmaps, ExtractedWorldData, FireflyConfig, NormalMode,
     },
-    lights::{ExtractedPointLight, LightHeight, PointLight2d},
-    occluders::ExtractedOccluder,
-    phases::SpritePhase,
+    lights::{
+        ExtractedPointLight, LightBudgetFade, LightHeight, LightMask, LightTextureTransform,
+        LineLightSample, PointLight2d, VolumetricLight,
+    },
+    occluders::{ExtractedOccluder, OcclusionLayers},
+    phases::{SpritePhase, light_channel_view},
     prelude::Occluder2d,
+    readback::{ExtractedLightmapReadback, LightmapReadback},
     sprites::{
-        ExtractedSlices, ExtractedSprite, ExtractedSpriteKind, ExtractedSprites, NormalMap,
-        SpriteAssetEvents, SpriteHeight,
+        ExtractedSlices, ExtractedSprite, ExtractedSpriteKind, ExtractedSprites, HeightMap,
+        LightGroup, NormalMap, SpecularMap, SpriteAssetEvents, SpriteBlendMode, SpriteHeight,
     },
     visibility::{NotVisible, OccluderAabb, VisibilityTimer},
 };
@@ -51,6 +55,7 @@ impl Plugin for ExtractPlugin {
                 extract_sprites.in_set(SpriteSystems::ExtractSprites),
                 extract_sprite_events,
                 extract_world_data,
+                extract_lightmap_readbacks,
                 extract_lights,
                 extract_occluders,
             ),
@@ -61,12 +66,22 @@ impl Plugin for ExtractPlugin {
 fn extract_camera_phases(
     mut sprite_phases: ResMut<ViewSortedRenderPhases<SpritePhase>>,
     mut lightmap_phases: ResMut<ViewBinnedRenderPhases<LightmapPhase>>,
-    cameras: Extract<Query<(Entity, &Camera, Has<NoIndirectDrawing>), With<Camera2d>>>,
+    cameras: Extract<
+        Query<
+            (
+                Entity,
+                &Camera,
+                Has<NoIndirectDrawing>,
+                Option<&FireflyConfig>,
+            ),
+            With<Camera2d>,
+        >,
+    >,
     mut live_entities: Local<HashSet<RetainedViewEntity>>,
     gpu_preprocessing_support: Res<GpuPreprocessingSupport>,
 ) {
     live_entities.clear();
-    for (main_entity, camera, no_indirect_drawing) in &cameras {
+    for (main_entity, camera, no_indirect_drawing, config) in &cameras {
         if !camera.is_active {
             continue;
         }
@@ -90,6 +105,13 @@ fn extract_camera_phases(
         lightmap_phases.prepare_for_new_frame(retained_view_entity, gpu_preprocessing_mode);
 
         live_entities.insert(retained_view_entity);
+
+        for channel in config.iter().flat_map(|config| &config.extra_channels) {
+            let channel_view = light_channel_view(retained_view_entity, *channel);
+
+            lightmap_phases.prepare_for_new_frame(channel_view, gpu_preprocessing_mode);
+            live_entities.insert(channel_view);
+        }
     }
 
     // Clear out all dead views.
@@ -122,6 +144,11 @@ fn extract_sprites(
             &Anchor,
             Option<&SpriteHeight>,
             Option<&NormalMap>,
+            Option<&SpecularMap>,
+            Option<&HeightMap>,
+            Option<&LightGroup>,
+            Option<&NormalMode>,
+            Option<&SpriteBlendMode>,
             &GlobalTransform,
             Option<&super::utils::ComputedTextureSlices>,
         )>,
@@ -137,6 +164,11 @@ fn extract_sprites(
         anchor,
         height,
         normal_map,
+        specular_map,
+        height_map,
+        light_group,
+        normal_mode,
+        blend_mode,
         transform,
         slices,
     ) in sprite_query.iter()
@@ -145,7 +177,14 @@ fn extract_sprites(
             continue;
         }
 
+        // additive sprites don't cover what's behind them
+        let Some(coverage) = blend_mode.copied().unwrap_or_default().coverage_threshold() else {
+            continue;
+        };
+
         let height = height.map_or(0., |h| h.0);
+        let light_group = light_group.map_or(0, |g| g.0);
+        LightGroup::warn_unsupported(light_group);
 
         if let Some(slices) = slices {
             let start = extracted_slices.slices.len();
@@ -162,6 +201,13 @@ fn extract_sprites(
                 flip_y: sprite.flip_y,
                 image_handle_id: sprite.image.id(),
                 normal_handle_id: normal_map.map(|x| x.handle().id()),
+                specular_handle_id: specular_map.map(|x| x.handle().id()),
+                shininess: specular_map.map_or(0.0, |x| x.shininess),
+                height_map_handle_id: height_map.map(|x| x.handle().id()),
+                parallax: height_map.map_or(0.0, |x| x.scale),
+                light_group,
+                normal_mode: normal_mode.cloned(),
+                coverage,
                 kind: ExtractedSpriteKind::Slices {
                     indices: start..end,
                 },
@@ -192,6 +238,13 @@ fn extract_sprites(
                 flip_y: sprite.flip_y,
                 image_handle_id: sprite.image.id(),
                 normal_handle_id: normal_map.map(|x| x.handle().id()),
+                specular_handle_id: specular_map.map(|x| x.handle().id()),
+                shininess: specular_map.map_or(0.0, |x| x.shininess),
+                height_map_handle_id: height_map.map(|x| x.handle().id()),
+                parallax: height_map.map_or(0.0, |x| x.scale),
+                light_group,
+                normal_mode: normal_mode.cloned(),
+                coverage,
                 kind: ExtractedSpriteKind::Single {
                     anchor: anchor.as_vec(),
                     rect,
@@ -205,6 +258,26 @@ fn extract_sprites(
     }
 }
 
+fn extract_lightmap_readbacks(
+    mut commands: Commands,
+    cameras: Extract<Query<(RenderEntity, Option<&LightmapReadback>), With<FireflyConfig>>>,
+) {
+    for (entity, readback) in &cameras {
+        match readback.and_then(|readback| readback.image()) {
+            Some(image) => {
+                commands
+                    .entity(entity)
+                    .insert(ExtractedLightmapReadback(image.id()));
+            }
+            None => {
+                commands
+                    .entity(entity)
+                    .remove::<ExtractedLightmapReadback>();
+            }
+        }
+    }
+}
+
 fn extract_world_data(
     mut commands: Commands,
     cameras: Extract<Query<(&RenderEntity, &Camera), With<CombineLightmapTo>>>,
@@ -255,11 +328,31 @@ fn extract_lights(
             &VisibilityTimer,
             &Changes,
             &RenderLayers,
+            &OcclusionLayers,
+            Option<&VolumetricLight>,
+            Option<&LightMask>,
+            Option<&LightBudgetFade>,
+            Option<&LightTextureTransform>,
+            Option<&LineLightSample>,
         )>,
     >,
 ) {
-    for (entity, transform, light, height, visibility, visibility_timer, changes, render_layers) in
-        &lights
+    for (
+        entity,
+        transform,
+        light,
+        height,
+        visibility,
+        visibility_timer,
+        changes,
+        render_layers,
+        occlusion_layers,
+        volumetric,
+        mask,
+        budget_fade,
+        cookie_transform,
+        line_sample,
+    ) in &lights
     {
         if !visibility.get() {
             if visibility_timer.0.just_finished() {
@@ -269,20 +362,43 @@ fn extract_lights(
         }
 
         let pos = transform.translation().truncate() /*+ vec2(0.0, height.0)*/ + light.offset.xy();
+        let budget_fade = budget_fade.map_or(1.0, |fade| fade.0);
+
+        if let Some(group) = light.light_group {
+            LightGroup::warn_unsupported(group);
+        }
+
         commands.entity(entity).insert(ExtractedPointLight {
             pos,
-            color: light.color,
-            intensity: light.intensity,
+            color: light.emitted_color(),
+            inner_color: light.emitted_inner_color(),
+            intensity: light.intensity * budget_fade,
             radius: light.radius,
             z: transform.translation().z + light.offset.z,
             core: light.core,
             falloff: light.falloff,
             angle: light.angle,
             cast_shadows: light.cast_shadows,
-            dir: (transform.rotation() * Vec3::Y).xy(),
+            screen_space_shadows: light.screen_space_shadows,
+            max_occluders: light.max_occluders,
+            cookie: light.cookie.as_ref().map(|x| x.id()),
+            cookie_transform: cookie_transform.copied().unwrap_or_default(),
+            segment: line_sample.map(|sample| sample.world_segment(transform)),
+            segment_range: line_sample.map_or(0., |sample| sample.range),
+            segment_samples: line_sample.map_or(0, |sample| sample.samples),
+            falloff_gradient: light.falloff_gradient.as_ref().map(|x| x.id()),
+            light_group: light.light_group,
+            z_range: light.z_range.clone(),
+            excluded_groups: mask.map_or(0, |mask| mask.0),
+            priority: light.priority,
+            budget_fade,
+            channel: light.channel,
+            volumetric: volumetric.copied(),
+            dir: light.direction(transform),
             height: height.0,
             changes: changes.clone(),
             render_layers: render_layers.clone(),
+            occlusion_layers: *occlusion_layers,
         });
     }
 }
@@ -299,9 +415,14 @@ fn extract_occluders(
             &ViewVisibility,
             &VisibilityTimer,
             &Changes,
+            &ShapeChanges,
             &RenderLayers,
+            &OcclusionLayers,
+            Option<&OccluderThrottle>,
+            Has<StaticOccluder>,
         )>,
     >,
+    mut extracted: Query<&mut ExtractedOccluder>,
 ) {
     let mut values = Vec::with_capacity(*previous_len);
 
@@ -313,7 +434,11 @@ fn extract_occluders(
         visibility,
         visibility_timer,
         changes,
+        shape_changes,
         render_layers,
+        occlusion_layers,
+        throttle,
+        is_static,
     ) in &occluders
     {
         if !visibility.get() {
@@ -323,6 +448,22 @@ fn extract_occluders(
             continue;
         }
 
+        // static occluders keep their previous extraction until invalidated
+        if is_static
+            && !changes.0
+            && let Ok(mut extracted) = extracted.get_mut(entity)
+        {
+            if extracted.changes.0 {
+                extracted.changes.0 = false;
+            }
+            continue;
+        }
+
+        // throttled occluders keep the transform they were last rebuilt with
+        let global_transform = &throttle
+            .and_then(|x| x.rebuilt_transform())
+            .unwrap_or(*global_transform);
+
         let pos = global_transform.translation().truncate() + occluder.offset.xy();
 
         let extracted_occluder = ExtractedOccluder {
@@ -330,12 +471,25 @@ fn extract_occluders(
             rot: global_transform.rotation().to_euler(EulerRot::XYZ).2,
             shape: occluder.shape().clone(),
             aabb: aabb.0,
-            z: global_transform.translation().z + occluder.offset.z,
+            z: occluder.sorting_z(global_transform),
             color: occluder.color,
+            tint_strength: occluder.tint_strength,
+            blocks_channels: occluder.blocks_channels,
             opacity: occluder.opacity,
             z_sorting: occluder.z_sorting,
-            changes: changes.clone(),
+            shade_self: occluder.shade_self,
+            self_lit: occluder.self_lit,
+            casts_shadow: occluder.casts_shadow,
+            hard_shadows: occluder.hard_shadows,
+            feather: occluder.feather,
+            refraction: occluder.refraction,
+            shadow_fade_distance: occluder.shadow_fade_distance,
+            glow_through: occluder.glow_through,
+            // a static occluder only gets here when it's (re)extracted, so it always needs uploading
+            changes: Changes(changes.0 || is_static),
+            shape_changed: shape_changes.changed || is_static,
             render_layers: render_layers.clone(),
+            occlusion_layers: *occlusion_layers,
         };
 
         values.push((entity, extracted_occluder));
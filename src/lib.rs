@@ -75,6 +75,8 @@
 //! add the [NormalMap](crate::prelude::NormalMap) component to sprites. Normal maps need to have the same exact layout as their entity's sprite image.
 //! If [normal mode](crate::prelude::FireflyConfig::normal_mode) is set to [top down](crate::prelude::NormalMode::TopDown),
 //! you can use [LightHeight](crate::prelude::LightHeight) and [SpriteHeight](crate::prelude::SpriteHeight) to emulate 3d dimensions for the normal maps.  
+//! Sprites can also override the config's normal mode by adding a [NormalMode](crate::prelude::NormalMode) component.
+//! Sprites can get a [HeightMap](crate::prelude::HeightMap) for a parallax effect that shifts their lighting based on each light's direction.
 //!
 //! - **Light Banding**: You can enable [light bands](crate::prelude::FireflyConfig::light_bands) on [FireflyConfig](crate::prelude::FireflyConfig) to
 //! reduce the lightmap to a certain number of 'bands', creating a stylized look.
@@ -85,8 +87,24 @@
 //! - **Multiple Lightmaps**: You can connect cameras via the [CombineLightmapTo](prelude::CombineLightmapTo) relationship component to have multiple lightmaps
 //! combined into another. This can be used to achieve, for instance, an FOV effect, where there's a visbility lightmap multiplied over the main lightmap.
 //!
+//! - **Light Channels**: You can put lights on different [channels](crate::prelude::PointLight2d::channel) and have cameras render
+//! [extra channels](crate::prelude::FireflyConfig::extra_channels) into separate lightmaps, which you can composite in your own render passes.
+//!
+//! - **Ambient Zones**: You can spawn [AmbientZone](crate::prelude::AmbientZone) entities to locally override the ambient light
+//! while the camera (or its [focus](crate::prelude::AmbientZoneFocus)) is inside them, blending smoothly at their edges.
+//!
+//! - **Line Lights**: You can spawn [LineLight2d](crate::prelude::LineLight2d) entities for light emitted along a segment,
+//! such as laser beams or fluorescent tubes.
+//!
+//! - **Volumetric Lights**: You can add the [VolumetricLight](crate::prelude::VolumetricLight) component to lights to have them
+//! cast visible light shafts through the gaps between occluders.
+//!
+//! - **Screen-Space Shadows**: You can enable [screen-space shadows](crate::prelude::FireflyConfig::screen_space_shadows) on a camera
+//! and [opt lights into them](crate::prelude::PointLight2d::screen_space_shadows) for much cheaper, but less accurate, shadows in scenes with many occluders.
+//!
 //! - **Debug**: The [FireflyGizmosPlugin](crate::prelude::FireflyGizmosPlugin) shows the exact range and shape of lights and occluders. It can be configured
-//! via the [FireflyGizmoStyle](crate::prelude::FireflyGizmoStyle) resource.
+//! via the [FireflyGizmoStyle](crate::prelude::FireflyGizmoStyle) resource. The [FireflyDiagnosticsPlugin](crate::prelude::FireflyDiagnosticsPlugin)
+//! reports how many lights and occluders are being rendered as bevy diagnostics.
 //!
 //! # Upcoming Features
 //!
@@ -94,14 +112,17 @@
 //! - Sprite-based shadows.
 //! - Light textures.
 
-use bevy::{prelude::*, render::texture::CachedTexture};
+use bevy::{platform::collections::HashMap, prelude::*, render::texture::CachedTexture};
 
+pub mod ambient;
 pub mod app;
 pub mod buffers;
 pub mod change;
 pub mod data;
+pub mod diagnostics;
 pub mod lights;
 pub mod occluders;
+pub mod readback;
 pub mod visibility;
 
 pub mod extract;
@@ -111,25 +132,103 @@ pub mod pipelines;
 pub mod prepare;
 pub mod sprites;
 
-mod utils;
+pub mod utils;
 
 pub(crate) use phases::*;
 
 pub mod prelude {
-    pub use crate::app::{FireflyGizmoStyle, FireflyGizmosPlugin, FireflyPlugin};
+    pub use crate::ambient::{AmbientZone, AmbientZoneFocus, AmbientZoneShape};
+    pub use crate::app::{
+        ExportOccludersSvg, FireflyGizmoStyle, FireflyGizmosPlugin, FireflyPlugin,
+        LightmapPlacement, occluders_svg,
+    };
+    pub use crate::buffers::{BufferGrowth, GrowthPolicy, IndexAllocation};
+    pub use crate::change::{
+        InvalidateStaticOccluder, OccluderFade, OccluderThrottle, StaticOccluder,
+    };
     pub use crate::data::{
-        CombinationMode, CombineLightmapTo, CombinedLightmaps, FireflyConfig, LightmapSize,
-        NormalMode,
+        AutoExposure, CombinationMode, CombineLightmapTo, CombinedLightmaps, FireflyConfig,
+        FireflyConfigError, LightAccumulation, LightmapBlend, LightmapSize, NormalMode,
+        TemporalAccumulation,
+    };
+    pub use crate::diagnostics::FireflyDiagnosticsPlugin;
+    pub use crate::lights::{
+        Falloff, LightAngle, LightCore, LightHeight, LightMask, LightTextureTransform, LineLight2d,
+        PointLight2d, VolumetricLight,
     };
-    pub use crate::lights::{Falloff, LightAngle, LightCore, LightHeight, PointLight2d};
-    pub use crate::occluders::{Occluder2d, Occluder2dEnabled};
-    pub use crate::sprites::{NormalMap, SpriteHeight};
+    pub use crate::occluders::{
+        Occluder2d, Occluder2dEnabled, OccluderSpatialIndex, OcclusionLayers, grid_rects,
+        occluders_blocking,
+    };
+    pub use crate::pipelines::FireflyPipelinesReady;
+    pub use crate::readback::LightmapReadback;
+    pub use crate::sprites::{
+        HeightMap, LightGroup, NormalMap, SpecularMap, SpriteBlendMode, SpriteHeight, lit_sprite,
+    };
+    pub use crate::utils::world_cursor;
 }
 
 /// Camera component that stores the texture of the lightmap.
+///
+/// The lightmap contains the light reaching each pixel of the camera's view, before the
+/// [ambient light](crate::prelude::FireflyConfig::ambient_brightness) is applied.
+///
+/// It is written by the [`create_lightmap`](crate::nodes::create_lightmap) pass and applied to the camera's output by the
+/// [`apply_lightmap`](crate::nodes::apply_lightmap) pass. You can read or modify it in your own render passes by scheduling
+/// them after `create_lightmap` in the [`Core2d`](bevy::core_pipeline::Core2d) schedule.
+///
+/// Check the `fog_of_war` example for a render pass that reads the lightmap.
 #[derive(Component)]
 pub struct LightMapTexture(pub CachedTexture);
 
+/// Camera component that stores the lightmaps of the camera's [extra light channels](crate::prelude::FireflyConfig::extra_channels).
+///
+/// Each lightmap only contains the lights on its [channel](crate::prelude::PointLight2d::channel), and has the same
+/// format, size and uv mapping as the [`LightMapTexture`]. They are written during the `create_lightmap` pass but never
+/// applied to the camera's output, so they can be composited however you want in your own render passes.
+#[derive(Component)]
+pub struct LightChannelTextures(pub HashMap<u8, CachedTexture>);
+
+impl LightChannelTextures {
+    /// Get the lightmap of the given channel, if the camera renders it.
+    pub fn get(&self, channel: u8) -> Option<&CachedTexture> {
+        self.0.get(&channel)
+    }
+}
+
+/// Camera component that stores the full resolution lightmaps accumulated over time, if
+/// [`temporal_upsampling`](crate::prelude::FireflyConfig::temporal_upsampling) (with a reduced lightmap size) or
+/// [`temporal_accumulation`](crate::prelude::FireflyConfig::temporal_accumulation) is enabled.
+///
+/// The textures are owned by the camera and keep their content between frames, instead of coming from the
+/// [`TextureCache`](bevy::render::texture::TextureCache).
+///
+/// `write` is resolved in the [`resolve_lightmap`](crate::nodes::resolve_lightmap) pass and applied instead of the [`LightMapTexture`],
+/// while `read` holds the previous frame's result. The two are swapped every frame.
+#[derive(Component)]
+pub struct LightmapHistoryTextures {
+    pub read: CachedTexture,
+    pub write: CachedTexture,
+}
+
+/// Camera component that stores the gain of the [automatic exposure](crate::prelude::FireflyConfig::auto_exposure), if enabled.
+///
+/// Both textures are 1x1 and in the [`R32Float`](bevy::render::render_resource::TextureFormat::R32Float) format.
+/// `write` is computed in the [`lightmap_exposure`](crate::nodes::lightmap_exposure) pass from the lightmap and the
+/// previous gain in `read`, and then used by the [`apply_lightmap`](crate::nodes::apply_lightmap) pass. The two are swapped every frame.
+#[derive(Component)]
+pub struct ExposureTextures {
+    pub read: CachedTexture,
+    pub write: CachedTexture,
+}
+
+/// Camera component that stores the light shafts of [volumetric lights](crate::prelude::VolumetricLight).
+///
+/// It's only present while a volumetric light is visible. It is written by the [`volumetric_lightmap`](crate::nodes::volumetric_lightmap)
+/// pass and added over the lightmap by the [`apply_lightmap`](crate::nodes::apply_lightmap) pass.
+#[derive(Component)]
+pub struct VolumetricLightTexture(pub CachedTexture);
+
 /// Camera component that stores an array of lightmaps that will be combined.
 #[derive(Component)]
 pub struct CombinedLightMapTextures(pub CachedTexture);
@@ -141,3 +240,51 @@ pub struct SpriteStencilTexture(pub CachedTexture);
 /// Camera component that stores the normal map texture.  
 #[derive(Component)]
 pub struct NormalMapTexture(pub CachedTexture);
+
+/// Camera component that stores the specular map texture.
+///
+/// Also stores the [light group](crate::prelude::LightGroup) of each sprite, in the blue channel.
+///
+/// It's only present while a visible sprite has a [`SpecularMap`](crate::prelude::SpecularMap) or a light
+/// uses [light groups](crate::prelude::PointLight2d::light_group) or a [`LightMask`](crate::prelude::LightMask).
+/// Otherwise the lightmap reads a zeroed texture in its place.
+#[derive(Component)]
+pub struct SpecularMapTexture(pub CachedTexture);
+
+/// Camera component that stores the parallax offset of each sprite pixel, computed from their [height maps](crate::prelude::HeightMap).
+///
+/// Also stores each sprite's [normal mode](crate::prelude::NormalMode) override + 1 in the green channel, or 0 if it follows the config.
+#[derive(Component)]
+pub struct ParallaxTexture(pub CachedTexture);
+
+/// Camera component that stores the shadow mask texture, if [`shadow_mask`](crate::prelude::FireflyConfig::shadow_mask) is enabled.
+///
+/// The texture is in the [`R8Unorm`](bevy::render::render_resource::TextureFormat::R8Unorm) format and has the same size
+/// and uv mapping as the [`LightMapTexture`]: texel `(0, 0)` is the top-left corner of the camera's view.
+///
+/// Each texel stores the highest occlusion any light experiences at that position, where 0 means unoccluded
+/// (or outside of every light's range) and 1 means fully blocked.
+///
+/// It is written during the `create_lightmap` pass and can be read by any render pass that runs after it.
+#[derive(Component)]
+pub struct ShadowMaskTexture(pub CachedTexture);
+
+/// Camera component that stores the occluder coverage texture, if [`screen_space_shadows`](crate::prelude::FireflyConfig::screen_space_shadows) is enabled
+/// or a [refracting](crate::prelude::Occluder2d::refraction) occluder is visible.
+///
+/// The texture is in the [`R8Unorm`](bevy::render::render_resource::TextureFormat::R8Unorm) format and has the same size
+/// and uv mapping as the [`LightMapTexture`]. Each texel stores the highest opacity of the occluders covering it.
+///
+/// It is written by the [`occluder_coverage`](crate::nodes::occluder_coverage) pass, before the `create_lightmap` pass.
+#[derive(Component)]
+pub struct OccluderCoverageTexture(pub CachedTexture);
+
+/// Camera component that stores the refraction texture, written alongside the [`OccluderCoverageTexture`].
+///
+/// The texture is in the [`Rgba16Float`](bevy::render::render_resource::TextureFormat::Rgba16Float) format and has the same size
+/// and uv mapping as the [`LightMapTexture`]. The red and green channels store the uv offset the scene is sampled from
+/// through [refracting](crate::prelude::Occluder2d::refraction) occluders, and the alpha channel is 1 where such an occluder is drawn.
+///
+/// It is written by the [`occluder_coverage`](crate::nodes::occluder_coverage) pass and read by the `apply_lightmap` pass.
+#[derive(Component)]
+pub struct OccluderRefractionTexture(pub CachedTexture);
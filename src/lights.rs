@@ -1,16 +1,22 @@
+use std::{any::TypeId, cmp::Reverse, f32::consts::TAU, ops::Range};
+
 use bevy::{
-    camera::visibility::{RenderLayers, VisibilityClass, add_visibility_class},
+    camera::visibility::{
+        RenderLayers, VisibilityClass, VisibilitySystems, VisibleEntities, add_visibility_class,
+    },
     color::palettes::css::WHITE,
     core_pipeline::tonemapping::{DebandDither, Tonemapping},
     ecs::{
         change_detection::Tick,
+        entity::EntityHashMap,
         query::ROQueryItem,
         system::{
             SystemParamItem,
             lifetimeless::{Read, SRes},
         },
     },
-    platform::collections::HashMap,
+    math::FloatOrd,
+    platform::collections::{HashMap, HashSet},
     prelude::*,
     render::{
         Render, RenderApp, RenderSystems,
@@ -22,7 +28,7 @@ use bevy::{
             ViewBinnedRenderPhases,
         },
         render_resource::{
-            BindGroup, PipelineCache, ShaderType, SpecializedRenderPipelines, StorageBuffer,
+            BindGroup, DynamicUniformBuffer, PipelineCache, ShaderType, SpecializedRenderPipelines,
         },
         sync_world::SyncToRenderWorld,
         view::{ExtractedView, RenderVisibleEntities, RetainedViewEntity, ViewUniformOffset},
@@ -32,11 +38,15 @@ use bytemuck::NoUninit;
 
 use crate::{
     LightBatchSetKey,
-    buffers::{BinBuffers, BufferIndex},
+    buffers::{BinBuffers, BufferIndex, OccluderDataCache},
     change::Changes,
-    data::ExtractedCombineLightmapTo,
-    phases::LightmapPhase,
-    pipelines::{LightPipelineKey, LightmapCreationPipeline},
+    data::{ExtractedCombineLightmapTo, FireflyConfig, LightAccumulation},
+    occluders::OcclusionLayers,
+    phases::{LightmapPhase, light_channel_view},
+    pipelines::{
+        LightPipelineKey, LightmapCreationPipeline, SpecializedCreationPipelines,
+        report_pipeline_error,
+    },
     visibility::VisibilityTimer,
 };
 
@@ -51,7 +61,8 @@ use crate::{
     VisibilityTimer,
     LightHeight,
     Changes,
-    RenderLayers
+    RenderLayers,
+    OcclusionLayers
 )]
 #[component(on_add = add_visibility_class::<PointLight2d>)]
 pub struct PointLight2d {
@@ -60,6 +71,16 @@ pub struct PointLight2d {
     /// **Default:** White.
     pub color: Color,
 
+    /// Optional color at the center of the light.
+    ///
+    /// If set, the light's color is interpolated from this at the center to its [color](PointLight2d::color) at its radius,
+    /// e.g. for torches with a white-hot core and orange edges. [Gels](PointLight2d::gels) are applied over both colors. Alpha is ignored.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** None.
+    pub inner_color: Option<Color>,
+
     /// Intensity of the point light.
     ///
     /// **Default:** 1.
@@ -83,11 +104,22 @@ pub struct PointLight2d {
     /// Optional parameter to constrain the angle of a light.
     ///
     /// The direction of the angle is based on the **UP** direction of the entity.
-    /// Can be moved by rotating the entity.  
+    /// Can be moved by rotating the entity, or aimed independently with [direction_override](PointLight2d::direction_override).
     ///
     /// **Default:** LightAngle::FULL.
     pub angle: LightAngle,
 
+    /// If set, the light points in this direction instead of the **UP** direction of the entity.
+    ///
+    /// Useful for aiming a light independently of its entity's rotation, such as a flashlight held by a non-rotating character.
+    /// This affects the [angle](PointLight2d::angle) and the [cookie](PointLight2d::cookie). The direction is normalized,
+    /// and a zero vector is ignored.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** None.
+    pub direction_override: Option<Vec2>,
+
     /// Whether this light should cast shadows or not with the existent occluders.
     ///
     /// **Performance Impact:** Major.
@@ -95,6 +127,28 @@ pub struct PointLight2d {
     /// **Default:** true.
     pub cast_shadows: bool,
 
+    /// Whether this light's shadows are computed in screen-space, on cameras that enable
+    /// [`screen_space_shadows`](FireflyConfig::screen_space_shadows).
+    ///
+    /// This trades the accuracy of the shadows for a large speedup in scenes with many occluders. Check
+    /// the camera's field for the visual differences. Cameras without it enabled keep rendering the regular shadows.
+    ///
+    /// **Performance Impact:** Lowers the cost of [cast_shadows](PointLight2d::cast_shadows) considerably, when used.
+    ///
+    /// **Default:** false.
+    pub screen_space_shadows: bool,
+
+    /// Maximum number of occluders that can cast shadows from this light.
+    ///
+    /// If more occluders are in range, only the ones with the largest angular size
+    /// relative to the light (the nearest and largest ones) are kept and the rest are ignored.
+    /// Useful as a safety valve for lights that might end up overlapping a huge number of occluders.
+    ///
+    /// **Performance Impact:** Minor (sorting the occluders when the limit is exceeded).
+    ///
+    /// **Default:** None.
+    pub max_occluders: Option<u32>,
+
     /// Offset position of the light.
     ///
     /// Useful if you want to add a light component on an entity and change it's position,
@@ -102,19 +156,149 @@ pub struct PointLight2d {
     ///
     /// **Default:** [Vec3::ZERO].
     pub offset: Vec3,
+
+    /// Stack of color filters ("gels") placed in front of the light.
+    ///
+    /// Each gel is multiplied over the light's [color](PointLight2d::color), in linear space,
+    /// to obtain the color that is actually emitted. This lets you animate a single tint
+    /// while keeping a base color intact. Alpha is ignored.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** Empty.
+    pub gels: Vec<Color>,
+
+    /// Optional texture ("cookie") projected by the light, multiplying its color.
+    ///
+    /// The image is stretched over the square that contains the light's [radius](PointLight2d::radius)
+    /// and rotates along with the light. Its top side points in the light's **UP** direction.
+    ///
+    /// The image can also be the target of another camera ([`RenderTarget::Image`](bevy::camera::RenderTarget::Image)) or be modified
+    /// by a shader, in which case the projection updates every frame. While the image isn't loaded or prepared yet,
+    /// the light is rendered as if it had no cookie.
+    ///
+    /// The projection can be rotated, scaled, offset and animated with a [`LightTextureTransform`].
+    ///
+    /// **Performance Impact:** Minor.
+    ///
+    /// **Default:** None.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub cookie: Option<Handle<Image>>,
+
+    /// Optional gradient image that replaces the analytic [falloff](PointLight2d::falloff).
+    ///
+    /// The image is sampled horizontally by the normalized distance from the light's center, from the left edge (center)
+    /// to the right edge (radius). Its color multiplies the light's color and its alpha is used as the intensity at that distance,
+    /// allowing for art-directed falloffs and color shifts. A small image (e.g. 64x1) is enough.
+    ///
+    /// The [core](PointLight2d::core) and falloff are ignored while a gradient is set. While the image isn't loaded or prepared yet,
+    /// the light is rendered at full intensity over its whole radius.
+    ///
+    /// **Performance Impact:** Minor.
+    ///
+    /// **Default:** None.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub falloff_gradient: Option<Handle<Image>>,
+
+    /// If set, the light only illuminates sprites with a matching [LightGroup](crate::prelude::LightGroup).
+    ///
+    /// The rest of the scene, including pixels not covered by any sprite, receives no light from it.
+    /// Useful for selectively highlighting sprites, such as a glowing pickup, without lighting the floor around them.
+    /// Sprites without a [LightGroup](crate::prelude::LightGroup) are part of group 0.
+    /// Only groups up to [`LightGroup::MAX`](crate::prelude::LightGroup::MAX) are supported.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** None.
+    pub light_group: Option<u32>,
+
+    /// If set, the light only illuminates sprites whose z is within this range (end excluded).
+    ///
+    /// This is the light counterpart of [z-sorting](FireflyConfig::z_sorting): in layered scenes, such as ones with parallax
+    /// backgrounds, a light can be limited to a single layer without affecting the layers in front of or behind it.
+    /// Pixels not covered by any sprite receive no light from it.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** None (affects all z values).
+    pub z_range: Option<Range<f32>>,
+
+    /// Priority of the light when the camera's [light budget](FireflyConfig::max_lights) is exceeded.
+    ///
+    /// Lights with higher priorities are rendered first. Lights with equal priorities are ordered by their distance to the camera.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** 0.
+    pub priority: i32,
+
+    /// Light channel this light is rendered to.
+    ///
+    /// Cameras render the lights on their [channel](FireflyConfig::channel) into their lightmap, and the lights on their
+    /// [extra channels](FireflyConfig::extra_channels) into separate lightmaps, which can be composited differently.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** 0.
+    pub channel: u8,
+}
+
+impl PointLight2d {
+    /// Get the color emitted by this light, after all [gels](PointLight2d::gels) are applied over its [color](PointLight2d::color).
+    pub fn emitted_color(&self) -> Color {
+        self.filter_by_gels(self.color)
+    }
+
+    /// Get the color emitted at the center of this light, after all [gels](PointLight2d::gels) are applied over
+    /// its [inner color](PointLight2d::inner_color). This is the [emitted color](PointLight2d::emitted_color) if it has no inner color.
+    pub fn emitted_inner_color(&self) -> Color {
+        self.filter_by_gels(self.inner_color.unwrap_or(self.color))
+    }
+
+    /// Get the direction this light points in, given its entity's transform.
+    ///
+    /// This is the [direction override](PointLight2d::direction_override) if it's set and non-zero,
+    /// or the **UP** direction of the entity otherwise.
+    pub fn direction(&self, transform: &GlobalTransform) -> Vec2 {
+        self.direction_override
+            .and_then(Vec2::try_normalize)
+            .unwrap_or_else(|| (transform.rotation() * Vec3::Y).xy())
+    }
+
+    fn filter_by_gels(&self, color: Color) -> Color {
+        let color = self
+            .gels
+            .iter()
+            .fold(color.to_linear().to_vec3(), |color, gel| {
+                color * gel.to_linear().to_vec3()
+            });
+
+        Color::LinearRgba(LinearRgba::from_vec3(color))
+    }
 }
 
 impl Default for PointLight2d {
     fn default() -> Self {
         Self {
             color: bevy::prelude::Color::Srgba(WHITE),
+            inner_color: None,
             intensity: 1.,
             radius: 100.,
             falloff: Falloff::InverseSquare { intensity: 0.0 },
             core: default(),
             angle: LightAngle::FULL,
+            direction_override: None,
             cast_shadows: true,
+            screen_space_shadows: false,
+            max_occluders: None,
             offset: Vec3::ZERO,
+            gels: vec![],
+            cookie: None,
+            falloff_gradient: None,
+            light_group: None,
+            z_range: None,
+            priority: 0,
+            channel: 0,
         }
     }
 }
@@ -129,9 +313,366 @@ impl Default for PointLight2d {
 #[derive(Component, Default, Reflect)]
 pub struct LightHeight(pub f32);
 
+/// Optional component you can add to lights to stop them from illuminating the sprites in certain [light groups](crate::prelude::LightGroup).
+///
+/// The opposite of [light_group](PointLight2d::light_group): the light still reaches the rest of the scene,
+/// but skips every pixel covered by a sprite in an excluded group. Useful for e.g. a glow that shouldn't touch the player.
+///
+/// Each bit of the mask is a group, so only groups 0 to 31 can be excluded.
+/// Sprites without a [LightGroup](crate::prelude::LightGroup) are part of group 0.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((PointLight2d::default(), LightMask::excluding([1, 2])));
+/// ```
+#[derive(Component, Default, Clone, Copy, Debug, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LightMask(pub u32);
+
+impl LightMask {
+    /// Construct a mask that excludes the given light groups.
+    pub fn excluding(groups: impl IntoIterator<Item = u32>) -> Self {
+        let mut res = Self(0);
+        for group in groups {
+            if group >= 32 {
+                warn!(
+                    "Tried to exclude light group {group} with a LightMask. Only groups 0 to 31 are supported"
+                );
+                continue;
+            }
+            res.0 |= 1 << group;
+        }
+        res
+    }
+
+    /// Whether the given light group is excluded.
+    pub fn excludes(&self, group: u32) -> bool {
+        group < 32 && self.0 & (1 << group) != 0
+    }
+}
+
+/// Optional component you can add to lights to make them produce visible light shafts (god rays).
+///
+/// The lightmap is ray-marched from each pixel towards the light, accumulating the light along the way,
+/// so the shafts are cut by the shadows of occluders. The effect is limited to the light's [radius](PointLight2d::radius)
+/// and ignores its [angle](PointLight2d::angle).
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((PointLight2d::default(), VolumetricLight::new(0.5, 32)));
+/// ```
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct VolumetricLight {
+    /// Brightness of the light shafts.
+    ///
+    /// **Default:** 0.5.
+    pub strength: f32,
+
+    /// Number of lightmap samples taken for each pixel.
+    ///
+    /// Fewer samples are faster but can cause visible banding.
+    ///
+    /// **Performance Impact:** High, scales with the number of samples.
+    ///
+    /// **Default:** 32.
+    pub samples: u32,
+}
+
+impl Default for VolumetricLight {
+    fn default() -> Self {
+        Self {
+            strength: 0.5,
+            samples: 32,
+        }
+    }
+}
+
+impl VolumetricLight {
+    /// Construct a new volumetric light with the given strength and sample count.
+    pub fn new(strength: f32, samples: u32) -> Self {
+        Self { strength, samples }
+    }
+}
+
+/// Light that is emitted from a segment instead of a point, e.g. for laser beams or fluorescent tubes.
+///
+/// Every point within [range](LineLight2d::range) of the segment is lit based on its distance to the closest point of the segment.
+/// Shadows are cast from [samples](LineLight2d::samples) spread along the segment, so they get softer the longer the segment is.
+///
+/// Each sample is a child [`PointLight2d`] entity that's managed automatically, so lines are about as expensive as that many point lights.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((
+///     LineLight2d {
+///         a: vec2(-40., 0.),
+///         b: vec2(40., 0.),
+///         range: 60.,
+///         ..default()
+///     },
+///     Transform::from_translation(vec3(0., 100., 0.)),
+/// ));
+/// ```
+#[derive(Debug, Component, Clone, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[require(Transform, Visibility)]
+pub struct LineLight2d {
+    /// Start of the segment, relative to the entity's transform.
+    ///
+    /// **Default:** (-50, 0).
+    pub a: Vec2,
+
+    /// End of the segment, relative to the entity's transform.
+    ///
+    /// **Default:** (50, 0).
+    pub b: Vec2,
+
+    /// **Color** of the light.
+    ///
+    /// **Default:** White.
+    pub color: Color,
+
+    /// **Intensity** of the light.
+    ///
+    /// How the samples add up to it depends on the camera's [light accumulation](crate::prelude::FireflyConfig::light_accumulation):
+    /// - With [Max](crate::prelude::LightAccumulation::Max), every sample shines at the full intensity, and only the brightest one is kept.
+    ///   Points that any sample reaches are lit at the full intensity, so shadows have no penumbra.
+    /// - With [Add](crate::prelude::LightAccumulation::Add), the intensity is split evenly between the samples, which are summed.
+    ///   Points that only some samples reach are lit partially, which gives soft shadows.
+    ///
+    /// **Default:** 1.
+    pub intensity: f32,
+
+    /// Distance from the segment that the light reaches.
+    ///
+    /// **Default:** 100.
+    pub range: f32,
+
+    /// Number of point lights along the segment that shadows are cast from.
+    ///
+    /// With [Add](crate::prelude::LightAccumulation::Add) accumulation, more samples give smoother shadows.
+    /// A single sample casts hard shadows from the segment's middle.
+    ///
+    /// **Performance Impact:** High, each sample is rendered like a separate light.
+    ///
+    /// **Default:** 8.
+    pub samples: u32,
+}
+
+impl Default for LineLight2d {
+    fn default() -> Self {
+        Self {
+            a: vec2(-50., 0.),
+            b: vec2(50., 0.),
+            color: Color::Srgba(WHITE),
+            intensity: 1.,
+            range: 100.,
+            samples: 8,
+        }
+    }
+}
+
+/// Component of the [`PointLight2d`] samples of a [`LineLight2d`], with the segment relative to the sample.
+#[derive(Component, Clone, Copy)]
+pub(crate) struct LineLightSample {
+    pub a: Vec2,
+    pub b: Vec2,
+    pub range: f32,
+    /// Number of samples of the line, including this one.
+    pub samples: u32,
+}
+
+impl LineLightSample {
+    /// The segment's ends in world space, given the sample's transform.
+    pub fn world_segment(&self, transform: &GlobalTransform) -> [Vec2; 2] {
+        [
+            transform.transform_point(self.a.extend(0.)).truncate(),
+            transform.transform_point(self.b.extend(0.)).truncate(),
+        ]
+    }
+}
+
+/// Keeps the [samples](LineLightSample) of each [`LineLight2d`] in sync with it, spawning and despawning them as needed.
+fn sync_line_lights(
+    mut commands: Commands,
+    lines: Query<(Entity, Ref<LineLight2d>, Option<&Children>)>,
+    mut samples: Query<(
+        &mut LineLightSample,
+        &mut PointLight2d,
+        &mut Transform,
+        &mut Changes,
+    )>,
+    orphans: Query<(Entity, &ChildOf), With<LineLightSample>>,
+) {
+    for (entity, line, children) in &lines {
+        if !line.is_changed() {
+            continue;
+        }
+
+        let n = line.samples.max(1);
+        let mut existing = children
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(|child| samples.contains(*child));
+
+        for i in 0..n {
+            let pos = line.a.lerp(line.b, (i as f32 + 0.5) / n as f32);
+
+            let sample = LineLightSample {
+                a: line.a - pos,
+                b: line.b - pos,
+                range: line.range.max(0.),
+                samples: n,
+            };
+
+            // the sample has to reach every point the segment lights
+            let light = PointLight2d {
+                color: line.color,
+                intensity: line.intensity / n as f32,
+                radius: sample.range + sample.a.length().max(sample.b.length()),
+                ..default()
+            };
+
+            match existing.next() {
+                Some(child) => {
+                    let Ok((mut old_sample, mut old_light, mut transform, mut changes)) =
+                        samples.get_mut(child)
+                    else {
+                        continue;
+                    };
+
+                    *old_sample = sample;
+                    *old_light = light;
+                    transform.translation = pos.extend(0.);
+                    changes.0 = true;
+                }
+                None => {
+                    commands.spawn((
+                        sample,
+                        light,
+                        Transform::from_translation(pos.extend(0.)),
+                        ChildOf(entity),
+                    ));
+                }
+            }
+        }
+
+        for child in existing {
+            commands.entity(child).despawn();
+        }
+    }
+
+    // samples of entities that aren't line lights anymore
+    for (entity, child_of) in &orphans {
+        if !lines.contains(child_of.parent()) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+/// Optional component you can add to lights with a [cookie](PointLight2d::cookie) to transform and animate its projection.
+///
+/// The cookie is rotated around the light's center, scaled and offset, and repeats past its edges, so it can be
+/// scrolled (e.g. light flowing through water) or spun (e.g. rotating caustics) without running out.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((
+///     PointLight2d { cookie: Some(caustics), ..default() },
+///     LightTextureTransform::default().with_rotation_speed(0.5),
+/// ));
+/// ```
+#[derive(Component, Clone, Copy, Debug, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LightTextureTransform {
+    /// Counter-clockwise rotation of the cookie around the light's center, in radians.
+    ///
+    /// **Default:** 0.
+    pub rotation: f32,
+
+    /// Size of the cookie, relative to the square that contains the light's radius.
+    /// Values below 1 repeat the cookie across the light.
+    ///
+    /// **Default:** (1, 1).
+    pub scale: Vec2,
+
+    /// Offset of the cookie in the light's local space, in units of the cookie's size. Y points in the light's **UP** direction.
+    ///
+    /// **Default:** (0, 0).
+    pub offset: Vec2,
+
+    /// Amount the [offset](LightTextureTransform::offset) moves by every second.
+    ///
+    /// **Default:** (0, 0).
+    pub scroll_speed: Vec2,
+
+    /// Amount the [rotation](LightTextureTransform::rotation) changes by every second, in radians.
+    ///
+    /// **Default:** 0.
+    pub rotation_speed: f32,
+}
+
+impl Default for LightTextureTransform {
+    fn default() -> Self {
+        Self {
+            rotation: 0.,
+            scale: Vec2::ONE,
+            offset: Vec2::ZERO,
+            scroll_speed: Vec2::ZERO,
+            rotation_speed: 0.,
+        }
+    }
+}
+
+impl LightTextureTransform {
+    /// Construct a new transform with the specified [rotation](LightTextureTransform::rotation).
+    pub fn with_rotation(&self, rotation: f32) -> Self {
+        let mut res = *self;
+        res.rotation = rotation;
+        res
+    }
+
+    /// Construct a new transform with the specified [scale](LightTextureTransform::scale).
+    pub fn with_scale(&self, scale: Vec2) -> Self {
+        let mut res = *self;
+        res.scale = scale;
+        res
+    }
+
+    /// Construct a new transform with the specified [offset](LightTextureTransform::offset).
+    pub fn with_offset(&self, offset: Vec2) -> Self {
+        let mut res = *self;
+        res.offset = offset;
+        res
+    }
+
+    /// Construct a new transform with the specified [scroll speed](LightTextureTransform::scroll_speed).
+    pub fn with_scroll_speed(&self, scroll_speed: Vec2) -> Self {
+        let mut res = *self;
+        res.scroll_speed = scroll_speed;
+        res
+    }
+
+    /// Construct a new transform with the specified [rotation speed](LightTextureTransform::rotation_speed).
+    pub fn with_rotation_speed(&self, rotation_speed: f32) -> Self {
+        let mut res = *self;
+        res.rotation_speed = rotation_speed;
+        res
+    }
+}
+
 #[derive(Debug, Clone, Copy, Reflect)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 /// The angle of the light. Value is interpolated between inner and outer angles to create a smooth transition.
+///
+/// The intensity fades linearly, from full intensity at the inner angle to 0 at the outer angle.
+/// Equal inner and outer angles result in a hard edge.
 pub struct LightAngle {
     /// The inner angle of a light, in degrees. Should be less than or equial to the outer angle.
     pub inner: f32,
@@ -150,6 +691,16 @@ impl LightAngle {
         inner: 360.0,
         outer: 360.0,
     };
+
+    /// Creates a cone of the given angle, in degrees, whose edge fades out over the last `penumbra` degrees.
+    ///
+    /// A penumbra of 0 results in a hard edge.
+    pub fn cone(angle: f32, penumbra: f32) -> Self {
+        Self {
+            inner: (angle - penumbra).max(0.0),
+            outer: angle,
+        }
+    }
 }
 
 /// An enum describing the falloff of a light's intensity.
@@ -158,14 +709,23 @@ impl LightAngle {
 pub enum Falloff {
     /// The light decreases inversely proportial to the square distance towards the source.  
     ///
-    /// The intensity parameter will increase the speed at which the light fades. Can be negative or positive.
+    /// The intensity parameter will increase the speed at which the light fades. Can be negative or positive,
+    /// but values below -0.99 are clamped, since they would make the light grow back towards its edge.
     InverseSquare { intensity: f32 },
     /// The light decreases linearly with the distance towards the source.
     ///
-    /// The intensity parameter will increase the speed at which the light fades. Can be negative or positive.
+    /// The intensity parameter will increase the speed at which the light fades. Can be negative or positive,
+    /// but values below -0.99 are clamped, since they would make the light grow back towards its edge.
     Linear { intensity: f32 },
     /// There is no falloff. The light will have a constant intensity.  
     None,
+    /// The light decreases following `(1 - distance) ^ exponent`, where distance is normalized to the light's range.
+    ///
+    /// An exponent of 1 is equivalent to a [Linear](Falloff::Linear) falloff. Higher values make the light fade faster near the source,
+    /// while values between 0 and 1 keep it bright for longer before quickly fading at the edge.
+    Exponent { exponent: f32 },
+    /// The light fades along a smoothstep curve, staying bright near the source and smoothly reaching 0 at the edge.
+    Smoothstep,
 }
 
 impl Falloff {
@@ -185,20 +745,67 @@ impl Falloff {
         Falloff::None
     }
 
+    pub fn exponent(exponent: f32) -> Falloff {
+        Falloff::Exponent { exponent }
+    }
+
+    pub fn smoothstep() -> Falloff {
+        Falloff::Smoothstep
+    }
+
     pub fn intensity(&self) -> f32 {
         match *self {
             Falloff::InverseSquare { intensity } => intensity,
             Falloff::Linear { intensity } => intensity,
-            Falloff::None => 0.0,
+            _ => 0.0,
+        }
+    }
+
+    /// The tag and parameter of the falloff, as they are sent to the shaders.
+    pub(crate) fn to_uniform(&self) -> (u32, f32) {
+        match *self {
+            Falloff::InverseSquare { intensity } => (0, intensity.max(MIN_FALLOFF_INTENSITY)),
+            Falloff::Linear { intensity } => (1, intensity.max(MIN_FALLOFF_INTENSITY)),
+            Falloff::None => (2, 0.0),
+            Falloff::Exponent { exponent } => (3, exponent),
+            Falloff::Smoothstep => (4, 0.0),
+        }
+    }
+
+    /// Get the intensity multiplier of this falloff at `x`, where `x` is the normalized distance
+    /// from the start (0) to the end (1) of the falloff.
+    ///
+    /// This matches the falloff computed in the shaders. It's always 1 at the start and 0 at the end,
+    /// except for [None](Falloff::None), which is always 1.
+    pub fn at(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+
+        match *self {
+            Falloff::InverseSquare { intensity } => {
+                let x2 = x * x;
+                (1.0 - x2) * (1.0 - x2) / (1.0 + intensity.max(MIN_FALLOFF_INTENSITY) * x2)
+            }
+            Falloff::Linear { intensity } => {
+                (1.0 - x) / (1.0 + intensity.max(MIN_FALLOFF_INTENSITY) * x)
+            }
+            Falloff::None => 1.0,
+            Falloff::Exponent { exponent } => (1.0 - x).powf(exponent),
+            Falloff::Smoothstep => 1.0 - x * x * (3.0 - 2.0 * x),
         }
     }
 }
 
+// below -1, the falloff's denominator reaches 0 before the light's edge
+const MIN_FALLOFF_INTENSITY: f32 = -0.99;
+
 /// The light's core. This is what determines the softness of shadows if [soft_shadows](crate::prelude::FireflyConfig::soft_shadows) is enabled.
 #[derive(Clone, Copy, Debug, Reflect)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct LightCore {
-    /// The radius of the core. This must be less than the actual radius of the light.
+    /// The radius of the core. This must be less than the actual radius of the light, larger values are clamped to it.
+    ///
+    /// The light's [falloff](PointLight2d::falloff) starts at the edge of the core, so the light has a constant intensity
+    /// inside of it (plus the [boost](LightCore::boost)) and smoothly fades to 0 at the light's radius.
     ///
     /// **Default:** 5.0.
     pub radius: f32,
@@ -212,6 +819,10 @@ pub struct LightCore {
     pub boost: f32,
     /// The core's falloff.
     ///
+    /// The boost only fades out smoothly at the core's edge with falloffs that reach 0 there. With [None](Falloff::None)
+    /// (or an [exponent](Falloff::Exponent) of 0) the whole core is boosted evenly, so it ends in a hard edge
+    /// where the intensity drops from `intensity + boost` to `intensity`.
+    ///
     ///  **Default:** InverseSquare { intensity: 0.0 }
     pub falloff: Falloff,
 }
@@ -261,21 +872,38 @@ impl LightCore {
 
 /// The data that is extracted to the render world from a [`PointLight2d`].
 #[derive(Component, Clone)]
-#[require(BinBuffers, LightIndex, LightPointer)]
+#[require(BinBuffers, LightIndex, LightPointer, OccluderDataCache)]
 pub struct ExtractedPointLight {
     pub pos: Vec2,
     pub color: Color,
+    pub inner_color: Color,
     pub intensity: f32,
     pub radius: f32,
     pub falloff: Falloff,
     pub core: LightCore,
     pub angle: LightAngle,
     pub cast_shadows: bool,
+    pub screen_space_shadows: bool,
+    pub max_occluders: Option<u32>,
+    pub cookie: Option<AssetId<Image>>,
+    pub cookie_transform: LightTextureTransform,
+    pub segment: Option<[Vec2; 2]>,
+    pub segment_range: f32,
+    pub segment_samples: u32,
+    pub falloff_gradient: Option<AssetId<Image>>,
+    pub light_group: Option<u32>,
+    pub z_range: Option<Range<f32>>,
+    pub excluded_groups: u32,
+    pub priority: i32,
+    pub budget_fade: f32,
+    pub channel: u8,
+    pub volumetric: Option<VolumetricLight>,
     pub dir: Vec2,
     pub z: f32,
     pub height: f32,
     pub changes: Changes,
     pub render_layers: RenderLayers,
+    pub occlusion_layers: OcclusionLayers,
 }
 
 impl PartialEq for ExtractedPointLight {
@@ -284,6 +912,15 @@ impl PartialEq for ExtractedPointLight {
     }
 }
 
+/// Data that is sent to the GPU for each visible [`VolumetricLight`], in the uv space of the camera's view.
+#[derive(Default, Clone, Copy, ShaderType)]
+pub struct UniformVolumetricLight {
+    pub pos: Vec2,
+    pub radius: Vec2,
+    pub strength: f32,
+    pub samples: u32,
+}
+
 /// Data that is sent to the GPU for each visible [`PointLight2d`].
 #[repr(C)]
 #[derive(Default, Clone, Copy, ShaderType, NoUninit)]
@@ -293,6 +930,7 @@ pub struct UniformPointLight {
     pub radius: f32,
 
     pub color: Vec4,
+    pub inner_color: Vec4,
 
     pub core_radius: f32,
     pub core_boost: f32,
@@ -309,19 +947,61 @@ pub struct UniformPointLight {
 
     pub z: f32,
     pub height: f32,
+
+    pub has_light_group: u32,
+    pub light_group: u32,
+    pub has_falloff_gradient: u32,
+    pub excluded_groups: u32,
+    pub screen_space_shadows: u32,
+    pub has_z_range: u32,
+    pub z_min: f32,
+    pub z_max: f32,
+
+    pub cookie_rotation: Vec2,
+    pub cookie_scale: Vec2,
+    pub cookie_offset: Vec2,
+
+    pub segment_samples: u32,
+    pub segment_range: f32,
+    pub segment_a: Vec2,
+    pub segment_b: Vec2,
 }
 
-/// Render World component that contains the buffer a [`PointLight2d`] writes to each frame.   
+/// Render World component that contains the dynamic offset of a [`PointLight2d`]'s [index](LightIndex)
+/// in the shared buffer all light indices are written to each frame.
 #[derive(Component, Default)]
-pub struct LightPointer(pub StorageBuffer<u32>);
+pub struct LightPointer(pub u32);
+
+/// Buffer containing the [index](LightIndex) of every light, written once per frame.
+///
+/// Each light's draw selects its own index through its [`LightPointer`] offset.
+#[derive(Resource, Default)]
+pub(crate) struct LightPointers(pub DynamicUniformBuffer<u32>);
+
+/// Lights that were left out of a camera's [light budget](FireflyConfig::max_lights) this frame, by the camera's retained view.
+///
+/// Filled in [`queue_lights`], so that their shadow data isn't prepared for that camera.
+#[derive(Resource, Default)]
+pub(crate) struct BudgetSkippedLights(pub HashSet<(RetainedViewEntity, Entity)>);
 
 /// Plugin responsible for functionality related to lights. Added automatically
 /// by [`FireflyPlugin`](crate::prelude::FireflyPlugin).
 pub struct LightPlugin;
 impl Plugin for LightPlugin {
     fn build(&self, app: &mut App) {
+        app.add_systems(
+            PostUpdate,
+            (
+                fade_budgeted_lights.after(VisibilitySystems::CheckVisibility),
+                animate_light_textures,
+                sync_line_lights.before(TransformSystems::Propagate),
+            ),
+        );
+
         if let Some(render_app) = app.get_sub_app_mut(RenderApp) {
             render_app.init_resource::<LightBindGroups>();
+            render_app.init_resource::<LightPointers>();
+            render_app.init_resource::<BudgetSkippedLights>();
             render_app.init_resource::<DrawFunctions<LightmapPhase>>();
             render_app.init_resource::<ViewBinnedRenderPhases<LightmapPhase>>();
 
@@ -343,12 +1023,132 @@ impl Plugin for LightPlugin {
     }
 }
 
+/// Fraction of a light's intensity that is kept while it fades in or out of a camera's [light budget](FireflyConfig::max_lights).
+#[derive(Component, Clone, Copy)]
+pub(crate) struct LightBudgetFade(pub f32);
+
+/// Advances the [`LightTextureTransform`] of lights by its speeds, and marks lights whose transform changed.
+fn animate_light_textures(
+    mut lights: Query<(&mut LightTextureTransform, &mut Changes)>,
+    time: Res<Time>,
+) {
+    let delta = time.delta_secs();
+
+    for (mut transform, mut changes) in &mut lights {
+        if transform.is_changed() {
+            changes.0 = true;
+        }
+
+        if transform.scroll_speed == Vec2::ZERO && transform.rotation_speed == 0. {
+            continue;
+        }
+
+        // the cookie repeats every unit of offset and every turn, so both are wrapped to keep them precise
+        let offset = transform.offset + transform.scroll_speed * delta;
+        transform.offset = offset - offset.floor();
+        transform.rotation =
+            (transform.rotation + transform.rotation_speed * delta).rem_euclid(TAU);
+
+        changes.0 = true;
+    }
+}
+
+/// Ranks the lights of cameras with a [light budget fade](FireflyConfig::light_budget_fade) the same way as [`queue_lights`],
+/// and moves each light's [`LightBudgetFade`] towards whether it's in a budget or not.
+fn fade_budgeted_lights(
+    mut commands: Commands,
+    cameras: Query<(&GlobalTransform, &VisibleEntities, &FireflyConfig)>,
+    mut lights: Query<(
+        Entity,
+        &GlobalTransform,
+        &PointLight2d,
+        Option<&mut LightBudgetFade>,
+        &mut Changes,
+    )>,
+    time: Res<Time>,
+) {
+    // whether each light is in any budget, and the fastest speed it fades at
+    let mut targets: EntityHashMap<(bool, f32)> = default();
+
+    for (transform, visible_entities, config) in &cameras {
+        let Some(max_lights) = config.max_lights else {
+            continue;
+        };
+
+        if config.light_budget_fade.is_zero() {
+            continue;
+        }
+
+        let center = transform.translation().truncate();
+        let speed = 1.0 / config.light_budget_fade.as_secs_f32();
+
+        let mut channels: HashMap<u8, Vec<_>> = default();
+        for entity in visible_entities.get(TypeId::of::<PointLight2d>()) {
+            if let Ok((_, transform, light, _, _)) = lights.get(*entity) {
+                let pos = transform.translation().truncate() + light.offset.xy();
+                channels.entry(light.channel).or_default().push((
+                    *entity,
+                    Reverse(light.priority),
+                    FloatOrd(pos.distance_squared(center)),
+                ));
+            }
+        }
+
+        for mut ranked in channels.into_values() {
+            ranked.sort_by_key(|(_, priority, distance)| (*priority, *distance));
+
+            for (i, (entity, _, _)) in ranked.into_iter().enumerate() {
+                let target = targets.entry(entity).or_insert((false, 0.0));
+                target.0 |= i < max_lights;
+                target.1 = target.1.max(speed);
+            }
+        }
+    }
+
+    let delta = time.delta_secs();
+
+    for (entity, _, _, fade, mut changes) in &mut lights {
+        let Some(&(in_budget, speed)) = targets.get(&entity) else {
+            // lights out of every budget camera's view don't keep their fade
+            if fade.is_some() {
+                commands.entity(entity).remove::<LightBudgetFade>();
+                changes.0 = true;
+            }
+            continue;
+        };
+
+        let target = match in_budget {
+            true => 1.0,
+            false => 0.0,
+        };
+
+        // lights that just became visible don't fade
+        let Some(mut fade) = fade else {
+            commands.entity(entity).insert(LightBudgetFade(target));
+            changes.0 = true;
+            continue;
+        };
+
+        if fade.0 != target {
+            let step = speed * delta;
+            let diff = target - fade.0;
+
+            fade.0 = match diff.abs() <= step {
+                true => target,
+                false => fade.0 + step * diff.signum(),
+            };
+            changes.0 = true;
+        }
+    }
+}
+
 #[derive(Resource, Deref, DerefMut, Default)]
 pub(crate) struct LightBatches(pub HashMap<(RetainedViewEntity, Entity), LightBatch>);
 
 #[derive(PartialEq, Eq, Clone, Debug)]
 pub(crate) struct LightBatch {
     pub id: Entity,
+    pub pointer_offset: u32,
 }
 
 #[derive(Resource, Default)]
@@ -372,16 +1172,30 @@ fn queue_lights(
         Option<&Tonemapping>,
         Option<&DebandDither>,
         Option<&ExtractedCombineLightmapTo>,
+        Option<&FireflyConfig>,
+        Entity,
     )>,
+    lights: Query<&ExtractedPointLight>,
+    mut skipped_lights: ResMut<BudgetSkippedLights>,
     pipeline_cache: Res<PipelineCache>,
+    mut commands: Commands,
 ) {
     let draw_lightmap_function = light_draw_functions.read().id::<DrawLightmap>();
 
-    for (view, camera, visible_entities, msaa, tonemapping, dither, combined_lightmap) in &views {
-        let Some(lightmap_phase) = lightmap_phases.get_mut(&view.retained_view_entity) else {
-            continue;
-        };
+    skipped_lights.0.clear();
 
+    for (
+        view,
+        camera,
+        visible_entities,
+        msaa,
+        tonemapping,
+        dither,
+        combined_lightmap,
+        config,
+        entity,
+    ) in &views
+    {
         let (target_format, msaa) = if let Some(combined_lightmap) = combined_lightmap {
             let view = views.get(combined_lightmap.0).unwrap();
             (view.0.target_format, view.3)
@@ -392,6 +1206,20 @@ fn queue_lights(
         let msaa_key = LightPipelineKey::from_msaa_samples(msaa.samples());
         let mut view_key = LightPipelineKey::from_target_format(target_format) | msaa_key;
 
+        if combined_lightmap.is_none() && config.is_some_and(|c| c.shadow_mask) {
+            view_key |= LightPipelineKey::SHADOW_MASK;
+        }
+
+        if config.is_some_and(|c| c.light_accumulation == LightAccumulation::Add) {
+            view_key |= LightPipelineKey::ADDITIVE_LIGHTS;
+        }
+
+        if let Some(config) = config
+            && config.soft_shadows
+        {
+            view_key |= LightPipelineKey::from_soft_shadow_samples(config.soft_shadow_samples);
+        }
+
         if camera
             .compositing_space
             .is_some_and(|s| s == CompositingSpace::Srgb)
@@ -429,10 +1257,102 @@ fn queue_lights(
             }
         }
 
-        let pipeline = pipelines.specialize(&pipeline_cache, &pipeline, view_key);
+        let main_pipeline = pipelines.specialize(&pipeline_cache, &pipeline, view_key);
+        report_pipeline_error(&pipeline_cache, main_pipeline, "lightmap creation");
 
-        if let Some(visible_entities) = visible_entities.get::<PointLight2d>() {
-            for (render_entity, visible_entity) in visible_entities.iter_visible() {
+        // extra channels aren't combined into other lightmaps and don't write to the shadow mask
+        let extra_channels = match (combined_lightmap, config) {
+            (None, Some(config)) => config.extra_channels.as_slice(),
+            _ => &[],
+        };
+
+        let channel_pipeline = match extra_channels.is_empty() {
+            true => main_pipeline,
+            false => pipelines.specialize(
+                &pipeline_cache,
+                &pipeline,
+                view_key - LightPipelineKey::SHADOW_MASK,
+            ),
+        };
+
+        commands
+            .entity(entity)
+            .insert(SpecializedCreationPipelines {
+                main: main_pipeline,
+                channels: channel_pipeline,
+            });
+
+        let Some(visible_entities) = visible_entities.get::<PointLight2d>() else {
+            continue;
+        };
+
+        let channels = std::iter::once((
+            view.retained_view_entity,
+            config.map_or(0, |c| c.channel),
+            main_pipeline,
+        ))
+        .chain(extra_channels.iter().map(|channel| {
+            (
+                light_channel_view(view.retained_view_entity, *channel),
+                *channel,
+                channel_pipeline,
+            )
+        }));
+
+        for (phase_view, channel, pipeline) in channels {
+            let Some(lightmap_phase) = lightmap_phases.get_mut(&phase_view) else {
+                continue;
+            };
+
+            let mut visible_lights = visible_entities
+                .iter_visible()
+                .filter(|(render_entity, _)| {
+                    lights
+                        .get(*render_entity)
+                        .is_ok_and(|light| light.channel == channel)
+                })
+                .collect::<Vec<_>>();
+
+            if let Some(max_lights) = config.and_then(|c| c.max_lights)
+                && visible_lights.len() > max_lights
+            {
+                // with a fade, the lights were already ranked in the main world, and the ones out of the budget fade out instead of being dropped
+                if config.is_some_and(|c| !c.light_budget_fade.is_zero()) {
+                    visible_lights.retain(|(render_entity, _)| {
+                        let in_budget = lights
+                            .get(*render_entity)
+                            .is_ok_and(|light| light.budget_fade > 0.0);
+
+                        if !in_budget {
+                            skipped_lights
+                                .0
+                                .insert((view.retained_view_entity, *render_entity));
+                        }
+                        in_budget
+                    });
+                } else {
+                    let center = view.world_from_view.translation().truncate();
+
+                    visible_lights.sort_by_cached_key(|(render_entity, _)| {
+                        lights.get(*render_entity).map_or(
+                            (Reverse(i32::MIN), FloatOrd(f32::MAX)),
+                            |light| {
+                                (
+                                    Reverse(light.priority),
+                                    FloatOrd(light.pos.distance_squared(center)),
+                                )
+                            },
+                        )
+                    });
+                    for (render_entity, _) in visible_lights.split_off(max_lights) {
+                        skipped_lights
+                            .0
+                            .insert((view.retained_view_entity, *render_entity));
+                    }
+                }
+            }
+
+            for (render_entity, visible_entity) in visible_lights {
                 let batch_set_key = LightBatchSetKey {
                     pipeline,
                     draw_function: draw_lightmap_function,
@@ -479,7 +1399,7 @@ impl<P: PhaseItem> RenderCommand<P> for SetLightTextureBindGroup {
                 .unwrap()
                 .get(&view.retained_view_entity)
                 .unwrap(),
-            &[],
+            &[batch.pointer_offset],
         );
 
         RenderCommandResult::Success
@@ -508,3 +1428,190 @@ impl<P: PhaseItem> RenderCommand<P> for DrawLightBatch {
 /// corresponding to its [`BufferManager`](crate::buffers::BufferManager) slot.  
 #[derive(Component, Default)]
 pub struct LightIndex(pub Option<BufferIndex>);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+    use std::time::Duration;
+
+    const SAMPLES: usize = 10000;
+
+    fn falloffs() -> Vec<Falloff> {
+        let mut falloffs = vec![
+            Falloff::None,
+            Falloff::Smoothstep,
+            Falloff::exponent(0.5),
+            Falloff::exponent(1.),
+            Falloff::exponent(3.),
+        ];
+
+        // intensities below -0.99 are clamped
+        for intensity in [0., 1., 10., -0.5, -0.99, -5.] {
+            falloffs.push(Falloff::inverse_square(intensity));
+            falloffs.push(Falloff::linear(intensity));
+        }
+
+        falloffs
+    }
+
+    #[test]
+    fn falloffs_are_monotonic_and_continuous() {
+        for falloff in falloffs() {
+            let profile: Vec<f32> = (0..=SAMPLES)
+                .map(|i| falloff.at(i as f32 / SAMPLES as f32))
+                .collect();
+
+            for pair in profile.windows(2) {
+                assert!(pair[1] <= pair[0] + 1e-6, "{falloff:?} grows: {pair:?}");
+                assert!(pair[0] - pair[1] < 0.02, "{falloff:?} jumps: {pair:?}");
+            }
+
+            assert!(profile.iter().all(|x| x.is_finite() && *x >= 0.));
+        }
+    }
+
+    #[test]
+    fn falloffs_reach_zero_at_the_edge() {
+        for falloff in falloffs() {
+            assert_eq!(falloff.at(0.), 1., "{falloff:?}");
+
+            let end = match falloff {
+                Falloff::None => 1.,
+                _ => 0.,
+            };
+            assert_eq!(falloff.at(1.), end, "{falloff:?}");
+
+            // the distance is clamped
+            assert_eq!(falloff.at(-1.), falloff.at(0.), "{falloff:?}");
+            assert_eq!(falloff.at(2.), falloff.at(1.), "{falloff:?}");
+        }
+    }
+
+    fn visible_lights(lights: &[Entity]) -> VisibleEntities {
+        let mut visible = VisibleEntities::default();
+        for light in lights {
+            visible.push(*light, TypeId::of::<PointLight2d>());
+        }
+        visible
+    }
+
+    fn budget_world(lights: &[(Vec2, i32)]) -> (World, Entity, Vec<Entity>) {
+        let mut world = World::new();
+        world.init_resource::<Time>();
+
+        let lights: Vec<_> = lights
+            .iter()
+            .map(|(pos, priority)| {
+                world
+                    .spawn((
+                        PointLight2d {
+                            priority: *priority,
+                            ..default()
+                        },
+                        GlobalTransform::from_translation(pos.extend(0.)),
+                    ))
+                    .id()
+            })
+            .collect();
+
+        // only the closest light fits, and fading takes a second
+        let camera = world
+            .spawn((
+                FireflyConfig {
+                    max_lights: Some(1),
+                    light_budget_fade: Duration::from_secs(1),
+                    ..default()
+                },
+                GlobalTransform::default(),
+                visible_lights(&lights),
+            ))
+            .id();
+
+        (world, camera, lights)
+    }
+
+    fn step(world: &mut World, secs: f32) {
+        world
+            .resource_mut::<Time>()
+            .advance_by(Duration::from_secs_f32(secs));
+        world.run_system_once(fade_budgeted_lights).unwrap();
+    }
+
+    fn fades(world: &World, lights: &[Entity]) -> Vec<Option<f32>> {
+        lights
+            .iter()
+            .map(|light| world.get::<LightBudgetFade>(*light).map(|fade| fade.0))
+            .collect()
+    }
+
+    #[test]
+    fn budget_fade_ramps_between_lights() {
+        let (mut world, _, lights) = budget_world(&[(vec2(10., 0.), 0), (vec2(20., 0.), 0)]);
+
+        // lights that just became visible start at their target
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(1.), Some(0.)]);
+
+        // the second light moves closer, pushing the first one out of the budget
+        *world.get_mut::<GlobalTransform>(lights[1]).unwrap() =
+            GlobalTransform::from_xyz(5., 0., 0.);
+
+        for expected in [0.75, 0.5, 0.25, 0.] {
+            step(&mut world, 0.25);
+            assert_eq!(
+                fades(&world, &lights),
+                [Some(expected), Some(1. - expected)]
+            );
+        }
+
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(0.), Some(1.)]);
+
+        // and back, without overshooting
+        *world.get_mut::<GlobalTransform>(lights[1]).unwrap() =
+            GlobalTransform::from_xyz(20., 0., 0.);
+
+        step(&mut world, 0.75);
+        assert_eq!(fades(&world, &lights), [Some(0.75), Some(0.25)]);
+        step(&mut world, 0.75);
+        assert_eq!(fades(&world, &lights), [Some(1.), Some(0.)]);
+    }
+
+    #[test]
+    fn budget_fade_ranks_by_priority_then_distance() {
+        let (mut world, _, lights) =
+            budget_world(&[(vec2(10., 0.), 0), (vec2(50., 0.), 1), (vec2(5., 0.), 0)]);
+
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(0.), Some(1.), Some(0.)]);
+
+        // each channel has its own budget
+        world.get_mut::<PointLight2d>(lights[2]).unwrap().channel = 1;
+
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(0.), Some(1.), Some(0.25)]);
+    }
+
+    #[test]
+    fn budget_fade_is_removed_out_of_view() {
+        let (mut world, camera, lights) = budget_world(&[(vec2(10., 0.), 0), (vec2(20., 0.), 0)]);
+
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(1.), Some(0.)]);
+
+        world
+            .entity_mut(camera)
+            .insert(visible_lights(&lights[..1]));
+
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(1.), None]);
+        assert!(world.get::<Changes>(lights[1]).unwrap().0);
+
+        // coming back into view starts at the target again
+        world.entity_mut(camera).insert(visible_lights(&lights));
+
+        step(&mut world, 0.25);
+        assert_eq!(fades(&world, &lights), [Some(1.), Some(0.)]);
+    }
+}
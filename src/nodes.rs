@@ -1,43 +1,137 @@
 //! Module containg `Render Graph Nodes` used by Firefly.  
 
 use bevy::{
+    camera::Viewport,
     ecs::{query::QueryItem, system::lifetimeless::Read},
     prelude::*,
     render::{
+        camera::ExtractedCamera,
+        render_asset::RenderAssets,
         render_phase::{ViewBinnedRenderPhases, ViewSortedRenderPhases},
         render_resource::{
-            BindGroupEntries, PipelineCache, RenderPassColorAttachment, RenderPassDescriptor,
-            TextureAspect, TextureFormat, TextureUsages, TextureViewDescriptor,
-            TextureViewDimension,
+            BindGroupEntries, LoadOp, Operations, PipelineCache, RenderPassColorAttachment,
+            RenderPassDescriptor, StoreOp, TextureAspect, TextureFormat, TextureUsages,
+            TextureViewDescriptor, TextureViewDimension,
         },
         renderer::{RenderContext, ViewQuery},
-        view::{ExtractedView, ViewTarget},
+        texture::{CachedTexture, FallbackImage, FallbackImageZero, GpuImage},
+        view::{ExtractedView, ViewTarget, ViewUniformOffset, ViewUniforms},
     },
 };
 
 use crate::{
-    CombinedLightMapTextures, LightMapTexture, LightmapPhase, NormalMapTexture,
-    SpriteStencilTexture,
-    data::ExtractedCombineLightmapTo,
-    phases::SpritePhase,
-    pipelines::{LightmapApplicationPipeline, SpecializedApplicationPipeline},
-    prepare::BufferedFireflyConfig,
+    CombinedLightMapTextures, ExposureTextures, LightChannelTextures, LightMapTexture,
+    LightmapHistoryTextures, LightmapPhase, NormalMapTexture, OccluderCoverageTexture,
+    OccluderRefractionTexture, ParallaxTexture, ShadowMaskTexture, SpecularMapTexture,
+    SpriteStencilTexture, VolumetricLightTexture,
+    data::{ExtractedCombineLightmapTo, FireflyConfig},
+    phases::{SpritePhase, light_channel_view},
+    pipelines::{
+        LightmapApplicationPipeline, LightmapExposurePipeline, LightmapReadbackPipeline,
+        LightmapResolvePipeline, LightmapVolumetricPipeline, OccluderCoveragePipeline,
+        SpecializedApplicationPipeline, SpecializedResolvePipeline, SpecializedVolumetricPipeline,
+        report_pipeline_error,
+    },
+    prepare::{BufferedFireflyConfig, OccluderCoverageVertices, VolumetricLightsBuffer},
+    readback::ExtractedLightmapReadback,
 };
 
+pub fn occluder_coverage(
+    view_query: ViewQuery<(
+        Read<ViewUniformOffset>,
+        Read<OccluderCoverageTexture>,
+        Read<OccluderRefractionTexture>,
+        Read<OccluderCoverageVertices>,
+        Option<Read<ExtractedCamera>>,
+    )>,
+    mut render_context: RenderContext,
+    world: &World,
+) {
+    let (view_offset, coverage_texture, refraction_texture, vertices, camera) =
+        view_query.into_inner();
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<OccluderCoveragePipeline>();
+
+    let Some(render_pipeline) = pipeline_cache.get_render_pipeline(pipeline.id) else {
+        report_pipeline_error(pipeline_cache, pipeline.id, "occluder coverage");
+        return;
+    };
+
+    let Some(view_binding) = world.resource::<ViewUniforms>().uniforms.binding() else {
+        return;
+    };
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "occluder coverage bind group",
+        &pipeline_cache.get_bind_group_layout(&pipeline.layout),
+        &BindGroupEntries::single(view_binding),
+    );
+
+    // the textures are cleared even if there are no occluders on screen
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("occluder coverage pass"),
+        color_attachments: &[
+            Some(RenderPassColorAttachment {
+                view: &coverage_texture.0.default_view,
+                resolve_target: None,
+                ops: default(),
+                depth_slice: None,
+            }),
+            Some(RenderPassColorAttachment {
+                view: &refraction_texture.0.default_view,
+                resolve_target: None,
+                ops: default(),
+                depth_slice: None,
+            }),
+        ],
+        ..default()
+    });
+
+    if vertices.0.is_empty() {
+        return;
+    }
+
+    let Some(buffer) = vertices.0.buffer() else {
+        return;
+    };
+
+    if let Some(viewport) = texture_viewport(camera, &coverage_texture.0) {
+        render_pass.set_camera_viewport(&viewport);
+    }
+
+    render_pass.set_render_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[view_offset.offset]);
+    render_pass.set_vertex_buffer(0, buffer.slice(..));
+    render_pass.draw(0..vertices.0.len() as u32, 0..1);
+}
+
 pub fn create_lightmap(
     mut render_context: RenderContext,
     lightmap_phases: Res<ViewBinnedRenderPhases<LightmapPhase>>,
     view_query: ViewQuery<(
         &'static ExtractedView,
         &LightMapTexture,
+        &FireflyConfig,
         Option<&ExtractedCombineLightmapTo>,
+        Option<&ShadowMaskTexture>,
+        Option<&LightChannelTextures>,
     )>,
     world: &World,
 ) {
     let view_entity = view_query.entity();
-    let (view, lightmap_texture, combine_lightmap_to) = view_query.into_inner();
+    let (
+        view,
+        lightmap_texture,
+        config,
+        combine_lightmap_to,
+        shadow_mask_texture,
+        channel_textures,
+    ) = view_query.into_inner();
+
+    let retained_view_entity = view.retained_view_entity;
 
-    let Some(lightmap_phase) = lightmap_phases.get(&view.retained_view_entity) else {
+    let Some(lightmap_phase) = lightmap_phases.get(&retained_view_entity) else {
         return;
     };
 
@@ -65,10 +159,103 @@ pub fn create_lightmap(
     } else {
         &lightmap_texture.0.default_view
     };
+    let mut color_attachments = vec![Some(RenderPassColorAttachment {
+        view,
+        resolve_target: None,
+        ops: Operations {
+            load: LoadOp::Clear(config.lightmap_clear_color.to_linear().into()),
+            store: StoreOp::Store,
+        },
+        depth_slice: None,
+    })];
+
+    if let Some(shadow_mask_texture) = shadow_mask_texture {
+        color_attachments.push(Some(RenderPassColorAttachment {
+            view: &shadow_mask_texture.0.default_view,
+            resolve_target: None,
+            ops: default(),
+            depth_slice: None,
+        }));
+    }
+
     let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
         label: Some("lightmap pass"),
+        color_attachments: &color_attachments,
+        ..default()
+    });
+
+    if let Err(err) = lightmap_phase.render(&mut render_pass, world, view_entity) {
+        error!("Error encountered while rendering the stencil phase {err:?}");
+    }
+
+    drop(render_pass);
+
+    let Some(channel_textures) = channel_textures else {
+        return;
+    };
+
+    for (channel, texture) in &channel_textures.0 {
+        let Some(channel_phase) =
+            lightmap_phases.get(&light_channel_view(retained_view_entity, *channel))
+        else {
+            continue;
+        };
+
+        let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("light channel pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &texture.default_view,
+                resolve_target: None,
+                ops: default(),
+                depth_slice: None,
+            })],
+            ..default()
+        });
+
+        if let Err(err) = channel_phase.render(&mut render_pass, world, view_entity) {
+            error!("Error encountered while rendering light channel {channel}: {err:?}");
+        }
+    }
+}
+
+pub fn resolve_lightmap(
+    view_query: ViewQuery<(
+        Read<SpecializedResolvePipeline>,
+        Read<BufferedFireflyConfig>,
+        Read<LightMapTexture>,
+        Read<LightmapHistoryTextures>,
+    )>,
+    mut render_context: RenderContext,
+    world: &World,
+) {
+    let (pipeline_id, config, light_map_texture, history_textures) = view_query.into_inner();
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<LightmapResolvePipeline>();
+
+    let Some(render_pipeline) = pipeline_cache.get_render_pipeline(pipeline_id.0) else {
+        return;
+    };
+
+    let Some(config) = config.0.binding() else {
+        return;
+    };
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "resolve lightmap bind group",
+        &pipeline_cache.get_bind_group_layout(&pipeline.layout),
+        &BindGroupEntries::sequential((
+            &light_map_texture.0.default_view,
+            &history_textures.read.default_view,
+            &pipeline.sampler,
+            config,
+        )),
+    );
+
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("resolve lightmap pass"),
         color_attachments: &[Some(RenderPassColorAttachment {
-            view,
+            view: &history_textures.write.default_view,
             resolve_target: None,
             ops: default(),
             depth_slice: None,
@@ -76,9 +263,168 @@ pub fn create_lightmap(
         ..default()
     });
 
-    if let Err(err) = lightmap_phase.render(&mut render_pass, world, view_entity) {
-        error!("Error encountered while rendering the stencil phase {err:?}");
-    }
+    render_pass.set_render_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+pub fn volumetric_lightmap(
+    view_query: ViewQuery<(
+        Read<SpecializedVolumetricPipeline>,
+        Read<VolumetricLightsBuffer>,
+        Read<VolumetricLightTexture>,
+        Read<LightMapTexture>,
+        Option<Read<LightmapHistoryTextures>>,
+    )>,
+    mut render_context: RenderContext,
+    world: &World,
+) {
+    let (pipeline_id, lights, volumetric_texture, light_map_texture, history_textures) =
+        view_query.into_inner();
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<LightmapVolumetricPipeline>();
+
+    let Some(render_pipeline) = pipeline_cache.get_render_pipeline(pipeline_id.0) else {
+        report_pipeline_error(pipeline_cache, pipeline_id.0, "volumetric lightmap");
+        return;
+    };
+
+    let Some(lights) = lights.0.binding() else {
+        return;
+    };
+
+    // march through the same lightmap that will be applied
+    let light_map_view = history_textures.map_or(&light_map_texture.0.default_view, |history| {
+        &history.write.default_view
+    });
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "volumetric lightmap bind group",
+        &pipeline_cache.get_bind_group_layout(&pipeline.layout),
+        &BindGroupEntries::sequential((light_map_view, &pipeline.sampler, lights)),
+    );
+
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("volumetric lightmap pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: &volumetric_texture.0.default_view,
+            resolve_target: None,
+            ops: default(),
+            depth_slice: None,
+        })],
+        ..default()
+    });
+
+    render_pass.set_render_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+pub fn lightmap_readback(
+    view_query: ViewQuery<(
+        Read<ExtractedLightmapReadback>,
+        Read<LightMapTexture>,
+        Option<Read<LightmapHistoryTextures>>,
+    )>,
+    mut render_context: RenderContext,
+    world: &World,
+) {
+    let (readback, light_map_texture, history_textures) = view_query.into_inner();
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<LightmapReadbackPipeline>();
+
+    let Some(render_pipeline) = pipeline_cache.get_render_pipeline(pipeline.id) else {
+        report_pipeline_error(pipeline_cache, pipeline.id, "lightmap readback");
+        return;
+    };
+
+    let Some(target) = world.resource::<RenderAssets<GpuImage>>().get(readback.0) else {
+        return;
+    };
+
+    // copy the same lightmap that will be applied
+    let light_map_view = history_textures.map_or(&light_map_texture.0.default_view, |history| {
+        &history.write.default_view
+    });
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "lightmap readback bind group",
+        &pipeline_cache.get_bind_group_layout(&pipeline.layout),
+        &BindGroupEntries::sequential((light_map_view, &pipeline.sampler)),
+    );
+
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("lightmap readback pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: &target.texture_view,
+            resolve_target: None,
+            ops: default(),
+            depth_slice: None,
+        })],
+        ..default()
+    });
+
+    render_pass.set_render_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
+
+pub fn lightmap_exposure(
+    view_query: ViewQuery<(
+        Read<BufferedFireflyConfig>,
+        Read<LightMapTexture>,
+        Option<Read<LightmapHistoryTextures>>,
+        Read<ExposureTextures>,
+    )>,
+    mut render_context: RenderContext,
+    world: &World,
+) {
+    let (config, light_map_texture, history_textures, exposure_textures) = view_query.into_inner();
+
+    let pipeline_cache = world.resource::<PipelineCache>();
+    let pipeline = world.resource::<LightmapExposurePipeline>();
+
+    let Some(render_pipeline) = pipeline_cache.get_render_pipeline(pipeline.id) else {
+        report_pipeline_error(pipeline_cache, pipeline.id, "lightmap exposure");
+        return;
+    };
+
+    let Some(config) = config.0.binding() else {
+        return;
+    };
+
+    // measure the same lightmap that will be applied
+    let light_map_view = history_textures.map_or(&light_map_texture.0.default_view, |history| {
+        &history.write.default_view
+    });
+
+    let bind_group = render_context.render_device().create_bind_group(
+        "lightmap exposure bind group",
+        &pipeline_cache.get_bind_group_layout(&pipeline.layout),
+        &BindGroupEntries::sequential((
+            light_map_view,
+            &pipeline.sampler,
+            &exposure_textures.read.default_view,
+            config,
+        )),
+    );
+
+    let mut render_pass = render_context.begin_tracked_render_pass(RenderPassDescriptor {
+        label: Some("lightmap exposure pass"),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: &exposure_textures.write.default_view,
+            resolve_target: None,
+            ops: default(),
+            depth_slice: None,
+        })],
+        ..default()
+    });
+
+    render_pass.set_render_pipeline(render_pipeline);
+    render_pass.set_bind_group(0, &bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
 }
 
 pub fn apply_lightmap(
@@ -86,9 +432,15 @@ pub fn apply_lightmap(
         Read<ExtractedView>,
         Read<SpecializedApplicationPipeline>,
         Read<BufferedFireflyConfig>,
+        Read<FireflyConfig>,
         Read<ViewTarget>,
         Read<LightMapTexture>,
+        Read<NormalMapTexture>,
         Option<Read<CombinedLightMapTextures>>,
+        Option<Read<LightmapHistoryTextures>>,
+        Option<Read<VolumetricLightTexture>>,
+        Option<Read<ExposureTextures>>,
+        Option<Read<OccluderRefractionTexture>>,
         Has<ExtractedCombineLightmapTo>,
     )>,
     mut render_context: RenderContext,
@@ -99,9 +451,15 @@ pub fn apply_lightmap(
         view,
         pipeline_id,
         config,
+        firefly_config,
         view_target,
         light_map_texture,
+        normal_map_texture,
         combined_textures,
+        history_textures,
+        volumetric_texture,
+        exposure_textures,
+        refraction_texture,
         is_combined_to,
     ) = view_query.into_inner();
 
@@ -113,6 +471,7 @@ pub fn apply_lightmap(
     let pipeline = world.resource::<LightmapApplicationPipeline>();
 
     let Some(render_pipeline) = pipeline_cache.get_render_pipeline(pipeline_id.id) else {
+        report_pipeline_error(pipeline_cache, pipeline_id.id, "lightmap application");
         return;
     };
 
@@ -123,6 +482,36 @@ pub fn apply_lightmap(
 
     let format = view.target_format;
 
+    // with temporal upsampling, the accumulated full resolution lightmap is applied instead
+    let light_map_view = history_textures.map_or(&light_map_texture.0.default_view, |history| {
+        &history.write.default_view
+    });
+
+    let fallback_image = world.resource::<FallbackImage>();
+    let ambient_texture = firefly_config
+        .ambient_texture
+        .as_ref()
+        .and_then(|image| world.resource::<RenderAssets<GpuImage>>().get(image))
+        .map_or(&fallback_image.d2.texture_view, |image| &image.texture_view);
+
+    // without volumetric lights, nothing is added to the lightmap
+    let volumetric_view = volumetric_texture.map_or(
+        &world.resource::<FallbackImageZero>().texture_view,
+        |texture| &texture.0.default_view,
+    );
+
+    // only read if the automatic exposure is enabled
+    let exposure_view = exposure_textures.map_or(
+        &world.resource::<FallbackImageZero>().texture_view,
+        |textures| &textures.write.default_view,
+    );
+
+    // without refracting occluders, the scene isn't offset
+    let refraction_view = refraction_texture.map_or(
+        &world.resource::<FallbackImageZero>().texture_view,
+        |texture| &texture.0.default_view,
+    );
+
     let bind_group = if !pipeline_id.is_combined {
         render_context.render_device().create_bind_group(
             "apply lightmap bind group simple",
@@ -131,7 +520,7 @@ pub fn apply_lightmap(
             ),
             &BindGroupEntries::sequential((
                 post_process.source,
-                &light_map_texture.0.default_view,
+                light_map_view,
                 &pipeline.filtering_sampler,
                 if pipeline_id.filter_lightmap {
                     &pipeline.filtering_sampler
@@ -139,6 +528,11 @@ pub fn apply_lightmap(
                     &pipeline.non_filtering_sampler
                 },
                 config,
+                &normal_map_texture.0.default_view,
+                ambient_texture,
+                volumetric_view,
+                exposure_view,
+                refraction_view,
             )),
         )
     } else {
@@ -166,10 +560,15 @@ pub fn apply_lightmap(
             ),
             &BindGroupEntries::sequential((
                 post_process.source,
-                &light_map_texture.0.default_view,
+                light_map_view,
                 &pipeline.filtering_sampler,
                 &pipeline.filtering_sampler,
                 config,
+                &normal_map_texture.0.default_view,
+                ambient_texture,
+                volumetric_view,
+                exposure_view,
+                refraction_view,
                 &combined_view,
             )),
         )
@@ -192,12 +591,20 @@ pub fn apply_lightmap(
 }
 
 pub fn sprite(
-    view_query: ViewQuery<(&ExtractedView, &SpriteStencilTexture, &NormalMapTexture)>,
+    view_query: ViewQuery<(
+        &ExtractedView,
+        &SpriteStencilTexture,
+        &NormalMapTexture,
+        Option<&SpecularMapTexture>,
+        &ParallaxTexture,
+        Option<&ExtractedCamera>,
+    )>,
     mut render_context: RenderContext,
     world: &World,
 ) {
     let view_entity = view_query.entity();
-    let (view, stencil_texture, normal_map_texture) = view_query.into_inner();
+    let (view, stencil_texture, normal_map_texture, specular_map_texture, parallax_texture, camera) =
+        view_query.into_inner();
 
     let Some(sprite_phases) = world.get_resource::<ViewSortedRenderPhases<SpritePhase>>() else {
         return;
@@ -222,11 +629,48 @@ pub fn sprite(
                 ops: default(),
                 depth_slice: None,
             }),
+            specular_map_texture.map(|texture| RenderPassColorAttachment {
+                view: &texture.0.default_view,
+                resolve_target: None,
+                ops: default(),
+                depth_slice: None,
+            }),
+            Some(RenderPassColorAttachment {
+                view: &parallax_texture.0.default_view,
+                resolve_target: None,
+                ops: default(),
+                depth_slice: None,
+            }),
         ],
         ..default()
     });
 
+    if let Some(viewport) = texture_viewport(camera, &stencil_texture.0) {
+        render_pass.set_camera_viewport(&viewport);
+    }
+
     if let Err(err) = sprite_phase.render(&mut render_pass, world, view_entity) {
         error!("Error encountered while rendering the stencil phase {err:?}");
     }
 }
+
+/// The camera's [viewport](Camera::viewport), scaled to a texture that covers its whole render target.
+///
+/// Passes that rasterize geometry with the view's projection must be limited to it, so the geometry lines up with
+/// the fullscreen passes, which map each pixel of the render target to the world through the viewport.
+fn texture_viewport(camera: Option<&ExtractedCamera>, texture: &CachedTexture) -> Option<Viewport> {
+    let camera = camera?;
+    let viewport = camera.viewport.as_ref()?;
+    let target_size = camera.physical_target_size?.as_vec2();
+
+    let texture_size = texture.texture.size();
+    let scale = vec2(texture_size.width as f32, texture_size.height as f32) / target_size;
+
+    Some(Viewport {
+        physical_position: (viewport.physical_position.as_vec2() * scale).as_uvec2(),
+        physical_size: (viewport.physical_size.as_vec2() * scale)
+            .as_uvec2()
+            .max(UVec2::ONE),
+        depth: viewport.depth.clone(),
+    })
+}
@@ -3,15 +3,30 @@
 use bevy::{
     camera::visibility::{RenderLayers, VisibilityClass, add_visibility_class},
     color::palettes::css::BLACK,
-    math::bounding::{Aabb2d, BoundingVolume},
+    math::{
+        FloatOrd,
+        bounding::{Aabb2d, BoundingVolume},
+    },
+    mesh::{PrimitiveTopology, VertexAttributeValues},
+    platform::collections::{HashMap, HashSet},
     prelude::*,
-    render::{render_resource::ShaderType, sync_world::SyncToRenderWorld},
+    render::{
+        render_resource::{ShaderType, TextureFormat},
+        renderer::RenderDevice,
+        sync_world::SyncToRenderWorld,
+    },
 };
 use bytemuck::{NoUninit, Pod, Zeroable};
 use core::f32;
+use std::{cmp::Reverse, collections::BinaryHeap};
 
 use crate::visibility::{OccluderAabb, VisibilityTimer};
-use crate::{buffers::BufferIndex, change::Changes};
+use crate::{
+    buffers::BufferIndex,
+    change::{Changes, ShapeChanges},
+    data::FireflyConfig,
+    pipelines::stencil_format,
+};
 
 /// An occluder that blocks light.
 ///
@@ -20,7 +35,9 @@ use crate::{buffers::BufferIndex, change::Changes};
 ///
 /// Can be moved around or rotated by their transform.
 ///
-/// Only z-axis rotations are allowed, any other type of rotation can cause unexpected behavior and bugs.
+/// Only z-axis rotations are supported. Rotations around the x and y axes are ignored: the occluder keeps its untilted
+/// shape instead of being foreshortened, which won't match tilted sprites. A warning is logged once for each
+/// occluder that is tilted this way.
 #[derive(Debug, Component, Clone, Reflect, Default)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[require(
@@ -32,15 +49,28 @@ use crate::{buffers::BufferIndex, change::Changes};
     VisibilityTimer,
     OccluderAabb,
     Changes,
-    RenderLayers
+    ShapeChanges,
+    RenderLayers,
+    OcclusionLayers
 )]
 #[component(on_add = add_visibility_class::<Occluder2d>)]
 pub struct Occluder2d {
     shape: Occluder2dShape,
 
     /// Color of the occluder. **Alpha is ignored**.
+    ///
+    /// How strongly this color tints the cast shadow is controlled by [tint_strength](Occluder2d::tint_strength).
     pub color: Color,
 
+    /// Strength of the shadow's tint.
+    ///
+    /// The shadow is darkened based on the [opacity](Occluder2d::opacity) and tinted by `color × tint_strength`.
+    /// A value of 0 casts a plain dark shadow regardless of the color, which is useful for
+    /// stained-glass effects where the tint needs to be controlled separately from the opacity.
+    ///
+    /// **Default:** 1.
+    pub tint_strength: f32,
+
     /// Opacity of the occluder.
     ///
     /// An occluder of opacity 0 won't block any light.
@@ -49,15 +79,144 @@ pub struct Occluder2d {
     /// Anything in-between will cast a colored shadow depending on how opaque it is.
     pub opacity: f32,
 
+    /// How much the occluder blocks each color channel (red, green, blue) of the light.
+    ///
+    /// A channel of 1 is fully affected by the occluder, while a channel of 0 passes through it untouched.
+    /// For instance, `vec3(1., 0., 0.)` creates an occluder that only casts shadows from red light.
+    ///
+    /// **Default:** [Vec3::ONE] (blocks all channels).
+    pub blocks_channels: Vec3,
+
     /// If true, this occluder won't cast shadows over sprites with a higher z value.
     ///
     /// This does nothing if z_sorting is set to false in the [config](crate::prelude::FireflyConfig::z_sorting).
     pub z_sorting: bool,
 
+    /// If true, the occluder's own area is shaded by its [color](Occluder2d::color) and [opacity](Occluder2d::opacity),
+    /// just like the shadow it casts, for every light that reaches it. Polylines have no area, so they're unaffected.
+    ///
+    /// If false, its area is only darkened by the shadow cast by its own edges, unless it's [self-lit](Occluder2d::self_lit).
+    ///
+    /// **Performance Impact:** Scales with the occluder's vertex count per shaded pixel, for polygons. Round occluders are barely affected.
+    /// Occluders that don't shade themselves skip this check.
+    ///
+    /// **Default:** false.
+    pub shade_self: bool,
+
+    /// If true, the occluder never darkens its own area, not even with the shadow cast by its own edges.
+    /// This does nothing if the occluder [shades itself](Occluder2d::shade_self).
+    ///
+    /// **Performance Impact:** Scales with the occluder's vertex count per shadowed pixel, for polygons. Round occluders are barely affected.
+    ///
+    /// **Default:** false.
+    pub self_lit: bool,
+
+    /// If true, the occluder casts shadows outward, blocking light from reaching anything behind it.
+    ///
+    /// Combined with [shade_self](Occluder2d::shade_self) and [self_lit](Occluder2d::self_lit), this allows:
+    /// - **cast only** (`casts_shadow: true`, `self_lit: true`): e.g. a character that casts a shadow
+    ///   but remains fully lit by its own key light.
+    /// - **receive only** (`casts_shadow: false`, `shade_self: true`): e.g. a window frame whose own area
+    ///   is shaded, while light still passes through it.
+    ///
+    /// With both set to false, the occluder has no effect and is skipped entirely.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** true.
+    pub casts_shadow: bool,
+
+    /// If true, the occluder always casts sharp shadows, even if [soft shadows](crate::prelude::FireflyConfig::soft_shadows)
+    /// are enabled. Useful for objects that should stand out with crisp edges, like laser walls.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** false.
+    pub hard_shadows: bool,
+
+    /// Distance, in world units, that light has to travel through the occluder before being fully blocked.
+    ///
+    /// If 0, the occluder has a hard edge and blocks light as soon as it crosses it. Otherwise, the occluder behaves
+    /// like a volume of fog: the shadow's strength scales with the length of the light's path through the shape,
+    /// reaching the occluder's full [opacity](Occluder2d::opacity) once that path is `feather` long.
+    /// This creates soft fog banks, where light grazing the edge is barely dimmed while light crossing the middle is blocked.
+    ///
+    /// Only round occluders (circles, rectangles, capsules and round rectangles) support this. Polygons and polylines ignore it.
+    /// Feathered occluders don't cast [soft shadow](crate::prelude::FireflyConfig::soft_shadows) penumbras, since their edges are already soft.
+    ///
+    /// **Performance Impact:** Minor, only for feathered occluders.
+    ///
+    /// **Default:** 0.
+    pub feather: f32,
+
+    /// Strength of the screen-space refraction through the occluder, in world units.
+    ///
+    /// If not 0, the scene behind the occluder is distorted like through a lens of glass or water.
+    /// Each pixel is sampled from a point offset along the occluder's surface normal, which is derived from its edges:
+    /// pixels near the edges are shifted by up to `refraction` units, while the center is left untouched.
+    /// Positive values magnify what's behind the occluder, and negative values shrink it.
+    ///
+    /// The distortion is applied together with the lightmap, so it affects everything rendered by the camera before that.
+    /// Polylines have no area and are never refracting.
+    ///
+    /// **Performance Impact:** Cameras that see a refracting occluder draw their occluders into an extra texture
+    /// every frame, like with [screen-space shadows](crate::prelude::FireflyConfig::screen_space_shadows).
+    ///
+    /// **Default:** 0.
+    pub refraction: f32,
+
+    /// Distance from the occluder over which its shadow fades out, in world units.
+    ///
+    /// If 0, the shadow keeps its full strength over the whole range of the light. Otherwise, the shadow gets weaker
+    /// the farther the shadowed pixel is from the occluder's edge, letting all the light through past this distance.
+    /// This mimics the contact shadows of ambient occlusion, and is independent of the light's own [falloff](crate::prelude::PointLight2d::falloff).
+    ///
+    /// The occluder's own area, when [shaded](Occluder2d::shade_self), isn't faded.
+    ///
+    /// **Performance Impact:** Minor, only for occluders with a fade.
+    ///
+    /// **Default:** 0.
+    pub shadow_fade_distance: f32,
+
+    /// Fraction of the light reaching the occluder that it emits back in its own [color](Occluder2d::color), inside its area.
+    ///
+    /// This is added on top of the light, so combined with a partial [opacity](Occluder2d::opacity) it makes
+    /// stained glass that both tints its shadows and glows with the light shining through it.
+    /// The glow only comes from lights the occluder casts shadows from, and isn't rendered with
+    /// [screen-space shadows](crate::prelude::PointLight2d::screen_space_shadows). Polylines have no area and never glow.
+    ///
+    /// **Performance Impact:** Minor, only for occluders with a glow.
+    ///
+    /// **Default:** 0.
+    pub glow_through: f32,
+
+    /// Color of the occluder's outline when drawn by the [gizmos](crate::prelude::FireflyGizmosPlugin).
+    ///
+    /// Useful to tell categories of occluders apart while debugging. If None, the
+    /// [style's occluder color](crate::prelude::FireflyGizmoStyle::occluder_color) is used.
+    ///
+    /// **Performance Impact:** None.
+    ///
+    /// **Default:** None.
+    pub gizmo_color: Option<Color>,
+
     /// Offset to the position of the occluder.
     ///
+    /// The x and y components move the shadow-casting geometry. The z component doesn't affect the geometry,
+    /// it's only added to the occluder's z value used for [z-sorting](Occluder2d::z_sorting). This can be used
+    /// to nudge which sprites an occluder casts shadows over without moving the entity. It's ignored if a
+    /// [shadow layer](Occluder2d::shadow_layer) is set.
+    ///
     /// **Default**: [Vec3::ZERO].
     pub offset: Vec3,
+
+    /// Optional z value used for [z-sorting](Occluder2d::z_sorting) instead of the entity's z position.
+    ///
+    /// By default, the z value that decides which sprites the occluder shadows is its transform's z plus the [offset](Occluder2d::offset)'s z,
+    /// which ties shadow layering to render order. When set, this value is used as-is, so the entity's z is free to only control rendering.
+    ///
+    /// **Default**: None.
+    pub shadow_layer: Option<f32>,
 }
 
 #[derive(Debug, Component, Clone, Reflect)]
@@ -70,22 +229,209 @@ impl Default for Occluder2dEnabled {
     }
 }
 
+/// Bitmask component for [lights](crate::prelude::PointLight2d) and [occluders](Occluder2d).
+///
+/// An occluder only casts shadows from a light if their masks have at least one bit in common.
+/// Useful for grouping occluders in categories, e.g. a light that ignores all foliage.
+///
+/// This is much cheaper than filtering individual entities, since occluders are skipped before any shadow work is done.
+///
+/// **Default:** All bits set (every light interacts with every occluder).
+///
+/// # Example
+///
+/// ```
+/// const FOLIAGE: u32 = 1 << 1;
+///
+/// commands.spawn((Occluder2d::circle(10.), OcclusionLayers(FOLIAGE)));
+/// commands.spawn((PointLight2d::default(), OcclusionLayers(OcclusionLayers::ALL.0 & !FOLIAGE)));
+/// ```
+#[derive(Debug, Component, Clone, Copy, Reflect, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct OcclusionLayers(pub u32);
+
+impl OcclusionLayers {
+    pub const ALL: Self = Self(u32::MAX);
+    pub const NONE: Self = Self(0);
+
+    /// Whether the two masks have any bits in common.
+    pub fn intersects(&self, other: &OcclusionLayers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl Default for OcclusionLayers {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
+
 impl Occluder2d {
     /// Get the occluder's **internal shape**.
     pub fn shape(&self) -> &Occluder2dShape {
         &self.shape
     }
 
+    /// Replace the occluder's **internal shape**, without replacing the rest of the component.
+    ///
+    /// This is useful for deforming occluders in place, such as breathing shapes or morph targets. Its bounds and
+    /// buffers are updated automatically, as with any other change to the component.
+    /// To only move a polygon's or polyline's points, use [`set_vertices`](Occluder2d::set_vertices).
+    ///
+    /// The shape goes through the same normalization as the constructors, so it can also be built by hand or deserialized.
+    /// Polygons are handled like in [`polygon`](Occluder2d::polygon) or [`polygon_with_opacity`](Occluder2d::polygon_with_opacity),
+    /// and can become polylines if they're degenerate. Their `concave` field is recomputed.
+    /// Polylines can be either the points they were constructed from, or the vertices of another polyline's shape.
+    ///
+    /// ## Failure
+    /// This returns false and leaves the shape unchanged if the shape is rejected by its constructor,
+    /// or if a polygon's opacities aren't empty and don't match its vertices.
+    pub fn set_shape(&mut self, shape: Occluder2dShape) -> bool {
+        let occluder = match shape {
+            Occluder2dShape::Polygon {
+                vertices,
+                opacities,
+                ..
+            } => {
+                if opacities.is_empty() {
+                    Self::polygon(vertices)
+                } else if opacities.len() == vertices.len() {
+                    Self::polygon_with_opacity(
+                        vertices.into_iter().zip(opacities).collect::<Vec<_>>(),
+                    )
+                } else {
+                    None
+                }
+            }
+            Occluder2dShape::Polyline {
+                vertices,
+                double_sided,
+            } => Self::polyline_oriented(unmirrored_polyline(&vertices), double_sided),
+            shape @ Occluder2dShape::RoundRectangle { .. } => Some(Self::from_shape(shape)),
+        };
+
+        let Some(occluder) = occluder else {
+            return false;
+        };
+
+        self.shape = occluder.shape;
+        true
+    }
+
+    /// Replace the points of a polygonal or polyline occluder, keeping its kind of shape.
+    ///
+    /// The points are handled like in [`polygon`](Occluder2d::polygon) and [`polyline_oriented`](Occluder2d::polyline_oriented),
+    /// and a polyline stays double-sided or one-sided. Polygons lose their [vertex opacities](Occluder2d::polygon_with_opacity),
+    /// use [`set_shape`](Occluder2d::set_shape) to keep them.
+    ///
+    /// ## Failure
+    /// This returns false and leaves the shape unchanged if the occluder is a round rectangle,
+    /// or if the provided points are rejected by the shape's constructor.
+    pub fn set_vertices(&mut self, vertices: impl Into<Vec<Vec2>>) -> bool {
+        let occluder = match &self.shape {
+            Occluder2dShape::Polygon { .. } => Self::polygon(vertices),
+            Occluder2dShape::Polyline { double_sided, .. } => {
+                Self::polyline_oriented(vertices, *double_sided)
+            }
+            Occluder2dShape::RoundRectangle { .. } => None,
+        };
+
+        let Some(occluder) = occluder else {
+            return false;
+        };
+
+        self.shape = occluder.shape;
+        true
+    }
+
+    /// Check whether the occluder blocks the line between `a` and `b`, given its entity's transform.
+    ///
+    /// Follows the same rules as [`occluders_blocking`], and also runs entirely on the CPU.
+    pub fn blocks_segment(&self, a: Vec2, b: Vec2, transform: &GlobalTransform) -> bool {
+        let pos = transform.translation().truncate() + self.offset.xy();
+        let rot = Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2);
+        occluder_blocks(&self.shape, pos, rot, a, b)
+    }
+
+    /// Get the occluder's outline in world space, as the points of a line strip, given its entity's transform.
+    ///
+    /// Polygons are closed by repeating their first point, and the corners of round rectangles are
+    /// split into a few segments. This is the outline drawn by the [gizmos](crate::prelude::FireflyGizmosPlugin),
+    /// so it can be used by tools to draw or hit-test occluders.
+    pub fn debug_vertices(&self, transform: &GlobalTransform) -> Vec<Vec2> {
+        let pos = transform.translation().truncate() + self.offset.xy();
+        let rot = Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2);
+
+        match &self.shape {
+            Occluder2dShape::Polygon { vertices, .. } => {
+                let mut outline = translate_vertices(vertices.to_vec(), pos, rot);
+                outline.extend(outline.first().copied());
+                outline
+            }
+            Occluder2dShape::Polyline { vertices, .. } => {
+                translate_vertices(polyline_points(vertices).to_vec(), pos, rot)
+            }
+            Occluder2dShape::RoundRectangle {
+                half_width,
+                half_height,
+                radius,
+            } => {
+                const CORNER_SEGMENTS: u32 = 8;
+
+                let corners = [
+                    vec2(*half_width, *half_height),
+                    vec2(-half_width, *half_height),
+                    vec2(-half_width, -half_height),
+                    vec2(*half_width, -half_height),
+                ];
+
+                // sharp corners are a single point
+                let segments = if *radius > 0. { CORNER_SEGMENTS } else { 0 };
+
+                let mut vertices = vec![];
+                for (i, corner) in corners.into_iter().enumerate() {
+                    for j in 0..=segments {
+                        let angle =
+                            (i as f32 + j as f32 / segments.max(1) as f32) * f32::consts::FRAC_PI_2;
+                        vertices.push(corner + Vec2::from_angle(angle) * radius);
+                    }
+                }
+
+                let mut outline = translate_vertices(vertices, pos, rot);
+                outline.extend(outline.first().copied());
+                outline
+            }
+        }
+    }
+
     fn from_shape(shape: Occluder2dShape) -> Self {
         Self {
             shape,
             opacity: 1.,
             color: bevy::prelude::Color::Srgba(BLACK),
+            tint_strength: 1.,
+            blocks_channels: Vec3::ONE,
             z_sorting: true,
+            shade_self: false,
+            self_lit: false,
+            casts_shadow: true,
+            hard_shadows: false,
+            feather: 0.,
+            refraction: 0.,
+            shadow_fade_distance: 0.,
+            glow_through: 0.,
+            gizmo_color: None,
             offset: default(),
+            shadow_layer: None,
         }
     }
 
+    /// The z value used for [z-sorting](Occluder2d::z_sorting), given the occluder entity's transform.
+    pub(crate) fn sorting_z(&self, transform: &GlobalTransform) -> f32 {
+        self.shadow_layer
+            .unwrap_or(transform.translation().z + self.offset.z)
+    }
+
     /// Construct a new occluder with the specified [color](Occluder2d::color).
     pub fn with_color(&self, color: Color) -> Self {
         let mut res = self.clone();
@@ -93,6 +439,20 @@ impl Occluder2d {
         res
     }
 
+    /// Construct a new occluder with the specified [tint strength](Occluder2d::tint_strength).
+    pub fn with_tint_strength(&self, tint_strength: f32) -> Self {
+        let mut res = self.clone();
+        res.tint_strength = tint_strength;
+        res
+    }
+
+    /// Construct a new occluder with the specified [blocked channels](Occluder2d::blocks_channels).
+    pub fn with_blocks_channels(&self, blocks_channels: Vec3) -> Self {
+        let mut res = self.clone();
+        res.blocks_channels = blocks_channels;
+        res
+    }
+
     /// Construct a new occluder with the specified [opacity](Occluder2d::opacity).
     pub fn with_opacity(&self, opacity: f32) -> Self {
         let mut res = self.clone();
@@ -107,6 +467,76 @@ impl Occluder2d {
         res
     }
 
+    /// Construct a new occluder with the specified [self-shading](Occluder2d::shade_self).
+    pub fn with_shade_self(&self, shade_self: bool) -> Self {
+        let mut res = self.clone();
+        res.shade_self = shade_self;
+        res
+    }
+
+    /// Construct a new occluder with the specified [self-lighting](Occluder2d::self_lit).
+    pub fn with_self_lit(&self, self_lit: bool) -> Self {
+        let mut res = self.clone();
+        res.self_lit = self_lit;
+        res
+    }
+
+    /// Construct a new occluder with the specified [shadow casting](Occluder2d::casts_shadow).
+    pub fn with_casts_shadow(&self, casts_shadow: bool) -> Self {
+        let mut res = self.clone();
+        res.casts_shadow = casts_shadow;
+        res
+    }
+
+    /// Construct a new occluder with the specified [hard shadows](Occluder2d::hard_shadows).
+    pub fn with_hard_shadows(&self, hard_shadows: bool) -> Self {
+        let mut res = self.clone();
+        res.hard_shadows = hard_shadows;
+        res
+    }
+
+    /// Construct a new occluder with the specified [feather](Occluder2d::feather).
+    pub fn with_feather(&self, feather: f32) -> Self {
+        let mut res = self.clone();
+        res.feather = feather;
+        res
+    }
+
+    /// Construct a new occluder with the specified [refraction](Occluder2d::refraction).
+    pub fn with_refraction(&self, refraction: f32) -> Self {
+        let mut res = self.clone();
+        res.refraction = refraction;
+        res
+    }
+
+    /// Construct a new occluder with the specified [shadow fade distance](Occluder2d::shadow_fade_distance).
+    pub fn with_shadow_fade_distance(&self, shadow_fade_distance: f32) -> Self {
+        let mut res = self.clone();
+        res.shadow_fade_distance = shadow_fade_distance;
+        res
+    }
+
+    /// Construct a new occluder with the specified [glow through](Occluder2d::glow_through).
+    pub fn with_glow_through(&self, glow_through: f32) -> Self {
+        let mut res = self.clone();
+        res.glow_through = glow_through;
+        res
+    }
+
+    /// Construct a new occluder with the specified [gizmo color](Occluder2d::gizmo_color).
+    pub fn with_gizmo_color(&self, gizmo_color: Color) -> Self {
+        let mut res = self.clone();
+        res.gizmo_color = Some(gizmo_color);
+        res
+    }
+
+    /// Construct a new occluder with the specified [shadow layer](Occluder2d::shadow_layer).
+    pub fn with_shadow_layer(&self, shadow_layer: f32) -> Self {
+        let mut res = self.clone();
+        res.shadow_layer = Some(shadow_layer);
+        res
+    }
+
     /// Construct a new occluder with the specified [offset](Occluder2d::offset).
     pub fn with_offset(&self, offset: Vec3) -> Self {
         let mut res = self.clone();
@@ -128,8 +558,13 @@ impl Occluder2d {
     /// If you wish to bypass this check, you can use the [`polygon_cc`](Occluder2d::polygon_cc)
     /// and [`polygon_ccw`](Occluder2d::polygon_ccw) methods.
     ///
+    /// ## Degenerate Polygons
+    /// Repeated consecutive points are removed. If the remaining points don't enclose any area
+    /// (e.g. they're all collinear), a [`polyline`](Occluder2d::polyline) between the two furthest points is returned instead.
+    /// This means that two distinct points always make a double-sided polyline between them, rather than failing.
+    ///
     /// ## Failure
-    /// This returns None if the provided list doesn't contain at least 2 vertices.
+    /// This returns None if the provided list doesn't contain at least 2 distinct vertices.
     pub fn polygon(vertices: impl Into<Vec<Vec2>>) -> Option<Self> {
         let vertices = vertices.into();
 
@@ -137,10 +572,181 @@ impl Occluder2d {
             return None;
         }
 
-        Some(Self::from_shape(Occluder2dShape::Polygon {
-            concave: is_concave(&vertices),
-            vertices: normalize_vertices(vertices),
-        }))
+        Self::from_polygon(normalize_vertices(vertices), vec![])
+    }
+
+    /// Construct a polygonal occluder from the given points, each with its own opacity.
+    ///
+    /// The opacity of the cast shadow is interpolated between the vertices of the edge the light passes through,
+    /// and multiplied by the occluder's [opacity](Occluder2d::opacity). This can be used for stylized soft edges,
+    /// e.g. a shape that fades from an opaque center to transparent tips.
+    ///
+    /// Otherwise this works the same as [`polygon`](Occluder2d::polygon). Vertex opacities are only
+    /// used by the lightmap, [screen-space shadows](crate::prelude::FireflyConfig::screen_space_shadows) use the occluder's opacity.
+    ///
+    /// ## Failure
+    /// This returns None if the provided list doesn't contain at least 2 distinct vertices.
+    /// Degenerate polygons are handled like in [`polygon`](Occluder2d::polygon), dropping the vertex opacities.
+    pub fn polygon_with_opacity(vertices: impl Into<Vec<(Vec2, f32)>>) -> Option<Self> {
+        let mut vertices = vertices.into();
+
+        if vertices.len() < 2 {
+            return None;
+        }
+
+        let points = vertices.iter().map(|(point, _)| *point).collect::<Vec<_>>();
+        if !has_normal_order(&points) {
+            vertices.reverse();
+        }
+
+        let (vertices, opacities): (Vec<_>, Vec<_>) = vertices
+            .into_iter()
+            .map(|(point, opacity)| (point, opacity.clamp(0.0, 1.0)))
+            .unzip();
+
+        Self::from_polygon(vertices, opacities)
+    }
+
+    /// Decompose a concave polygon into convex pieces, returning an occluder for each of them.
+    ///
+    /// Complex concave polygons can sometimes cast slightly incorrect shadows. Splitting them into
+    /// convex pieces avoids these edge cases, at the cost of having more occluders.
+    ///
+    /// The pieces keep the same coordinates as the original points, so they should all be spawned
+    /// with the same transform (e.g. as children of a single entity).
+    ///
+    /// The points can be in either clockwise or counter-clockwise order, but shouldn't self-intersect.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let pieces = Occluder2d::polygon_decomposed(points).unwrap();
+    ///
+    /// commands.spawn(Transform::default()).with_children(|parent| {
+    ///     for piece in pieces {
+    ///         parent.spawn(piece);
+    ///     }
+    /// });
+    /// ```
+    ///
+    /// ## Failure
+    /// This returns None if the provided list doesn't contain at least 3 vertices.
+    pub fn polygon_decomposed(vertices: impl Into<Vec<Vec2>>) -> Option<Vec<Self>> {
+        let vertices = vertices.into();
+
+        if vertices.len() < 3 {
+            return None;
+        }
+
+        convex_decomposition(vertices)
+            .into_iter()
+            .map(Self::polygon)
+            .collect()
+    }
+
+    /// Construct a polygon with interior holes, such as a ring or a window in a wall, returning an occluder for each convex piece.
+    ///
+    /// Each hole is connected to the outer contour, and the resulting shape is split into convex pieces
+    /// like in [`polygon_decomposed`](Occluder2d::polygon_decomposed). Light passes through the holes,
+    /// and lights placed inside a hole are blocked by its walls.
+    ///
+    /// The pieces keep the same coordinates as the original points, so they should all be spawned
+    /// with the same transform (e.g. as children of a single entity).
+    ///
+    /// The contours can be in either clockwise or counter-clockwise order. The holes should be fully inside
+    /// the outer contour and shouldn't overlap each other. Holes that aren't inside the outer contour are ignored.
+    ///
+    /// **Performance Impact:** Every hole adds at least two more pieces, and so more occluders.
+    ///
+    /// ## Failure
+    /// This returns None if any of the contours doesn't contain at least 3 vertices.
+    pub fn polygon_with_holes(
+        outer: impl Into<Vec<Vec2>>,
+        holes: Vec<Vec<Vec2>>,
+    ) -> Option<Vec<Self>> {
+        let outer = outer.into();
+
+        if outer.len() < 3 || holes.iter().any(|hole| hole.len() < 3) {
+            return None;
+        }
+
+        convex_decomposition(bridge_holes(outer, holes))
+            .into_iter()
+            .map(Self::polygon)
+            .collect()
+    }
+
+    /// Construct a polygonal occluder from the outline of a 2d mesh, such as an existing collider mesh.
+    ///
+    /// The x and y coordinates of the mesh's positions are used, relative to the entity's translation.
+    ///
+    /// For indexed triangle list meshes, the outline is built from the triangle edges that aren't shared with
+    /// other triangles. Only the outer boundary is used, so holes in the mesh are ignored (filled).
+    /// If the mesh has no indices, isn't a triangle list, its boundary edges don't form simple closed loops,
+    /// or it has disconnected parts (loops that aren't inside the outer boundary), the convex hull of its vertices is used instead.
+    ///
+    /// ## Failure
+    /// This returns None if the mesh doesn't have a position attribute, or if it has less than 3 distinct vertices.
+    pub fn from_mesh(mesh: &Mesh) -> Option<Self> {
+        let positions: Vec<Vec2> = match mesh.attribute(Mesh::ATTRIBUTE_POSITION)? {
+            VertexAttributeValues::Float32x3(positions) => {
+                positions.iter().map(|p| vec2(p[0], p[1])).collect()
+            }
+            VertexAttributeValues::Float32x2(positions) => {
+                positions.iter().map(|p| vec2(p[0], p[1])).collect()
+            }
+            _ => return None,
+        };
+
+        let outline = match (mesh.primitive_topology(), mesh.indices()) {
+            (PrimitiveTopology::TriangleList, Some(indices)) => {
+                mesh_outline(&positions, &indices.iter().collect::<Vec<_>>())
+            }
+            _ => None,
+        }
+        .unwrap_or_else(|| convex_hull(positions));
+
+        if outline.len() < 3 {
+            return None;
+        }
+
+        Self::polygon(outline)
+    }
+
+    /// Same as [`from_mesh`](Occluder2d::from_mesh), but the outline is [decimated](Occluder2d::polygon_decimated)
+    /// to at most `max_vertices` vertices.
+    pub fn from_mesh_decimated(mesh: &Mesh, max_vertices: usize) -> Option<Self> {
+        let occluder = Self::from_mesh(mesh)?;
+
+        let Occluder2dShape::Polygon { vertices, .. } = occluder.shape() else {
+            return Some(occluder);
+        };
+
+        Self::polygon_decimated(vertices.clone(), max_vertices)
+    }
+
+    /// Construct a polygonal occluder from the given points, removing the least significant vertices
+    /// until at most `max_vertices` are left.
+    ///
+    /// Useful for very detailed polygons, such as ones traced from images or imported from paths, whose vertex count
+    /// would otherwise make them expensive to upload and to cast shadows with. The vertices enclosing the smallest
+    /// area with their neighbours are removed first (Visvalingam-Whyatt), which preserves the overall shape.
+    ///
+    /// A `max_vertices` lower than 3 is treated as 3.
+    ///
+    /// The result isn't checked for self-intersections, which can appear when decimating thin parts of
+    /// a polygon (e.g. a narrow spike next to another edge) down to very few vertices.
+    ///
+    /// ## Failure
+    /// This returns None if the provided list doesn't contain at least 3 vertices.
+    pub fn polygon_decimated(vertices: impl Into<Vec<Vec2>>, max_vertices: usize) -> Option<Self> {
+        let vertices = vertices.into();
+
+        if vertices.len() < 3 {
+            return None;
+        }
+
+        Self::polygon(decimate(vertices, max_vertices.max(3)))
     }
 
     /// Construct a polygonal occluder from the given points.
@@ -154,7 +760,8 @@ impl Occluder2d {
     /// Compared to [`polygon`](Occluder2d::polygon), this method assumed the vertices are in **clockwise** order.
     ///
     /// ## Failure
-    /// This returns None if the provided list doesn't contain at least 2 vertices.
+    /// This returns None if the provided list doesn't contain at least 2 distinct vertices.
+    /// Degenerate polygons are handled like in [`polygon`](Occluder2d::polygon).
     pub fn polygon_cc(vertices: impl Into<Vec<Vec2>>) -> Option<Self> {
         let vertices = vertices.into();
 
@@ -162,10 +769,7 @@ impl Occluder2d {
             return None;
         }
 
-        Some(Self::from_shape(Occluder2dShape::Polygon {
-            concave: is_concave(&vertices),
-            vertices,
-        }))
+        Self::from_polygon(vertices, vec![])
     }
 
     /// Construct a polygonal occluder from the given points.
@@ -179,7 +783,8 @@ impl Occluder2d {
     /// Compared to [`polygon`](Occluder2d::polygon), this method assumed the vertices are in **counter-clockwise** order.
     ///
     /// ## Failure
-    /// This returns None if the provided list doesn't contain at least 2 vertices.
+    /// This returns None if the provided list doesn't contain at least 2 distinct vertices.
+    /// Degenerate polygons are handled like in [`polygon`](Occluder2d::polygon).
     pub fn polygon_ccw(vertices: impl Into<Vec<Vec2>>) -> Option<Self> {
         let mut vertices = vertices.into();
 
@@ -188,9 +793,34 @@ impl Occluder2d {
         }
         vertices.reverse();
 
+        Self::from_polygon(vertices, vec![])
+    }
+
+    // builds a polygon from already ordered vertices, falling back to a polyline if it has no area
+    fn from_polygon(vertices: Vec<Vec2>, opacities: Vec<f32>) -> Option<Self> {
+        let (vertices, opacities) = dedup_vertices(vertices, opacities);
+
+        if has_no_area(&vertices) {
+            let furthest_from = |origin: Vec2| {
+                vertices.iter().copied().max_by(|a, b| {
+                    a.distance_squared(origin)
+                        .total_cmp(&b.distance_squared(origin))
+                })
+            };
+
+            let a = furthest_from(*vertices.first()?)?;
+            let b = furthest_from(a)?;
+
+            if a == b {
+                return None;
+            }
+            return Self::polyline(vec![a, b]);
+        }
+
         Some(Self::from_shape(Occluder2dShape::Polygon {
             concave: is_concave(&vertices),
             vertices,
+            opacities,
         }))
     }
 
@@ -200,9 +830,28 @@ impl Occluder2d {
     ///
     /// The points should be relative to the entity's translation.
     ///
+    /// The polyline is double-sided, casting shadows from lights on both of its sides.
+    /// For a one-sided polyline, check [`Occluder2d::polyline_oriented()`].
+    ///
     /// # Failure
     /// This returns None if the provided list doesn't contain at least 2 vertices.
     pub fn polyline(vertices: impl Into<Vec<Vec2>>) -> Option<Self> {
+        Self::polyline_oriented(vertices, true)
+    }
+
+    /// Construct a polyline occluder from the given points, choosing whether it's double-sided.
+    ///
+    /// A one-sided polyline only casts shadows from lights on its **front** side, which is the **left** side
+    /// when walking the points in order. For instance, a polyline that traces the outline of a shape clockwise
+    /// only blocks lights that are outside of it. If the polyline bends, the side is decided by the segment closest to the light.
+    ///
+    /// Having self-intersections can cause unexpected behavior.
+    ///
+    /// The points should be relative to the entity's translation.
+    ///
+    /// # Failure
+    /// This returns None if the provided list doesn't contain at least 2 vertices.
+    pub fn polyline_oriented(vertices: impl Into<Vec<Vec2>>, double_sided: bool) -> Option<Self> {
         let mut vertices = vertices.into();
 
         if vertices.len() < 2 {
@@ -213,10 +862,60 @@ impl Occluder2d {
 
         vertices_clone.reverse();
         vertices.extend_from_slice(&vertices_clone[1..vertices_clone.len() - 1]);
-        Some(Self::from_shape(Occluder2dShape::Polyline { vertices }))
+        Some(Self::from_shape(Occluder2dShape::Polyline {
+            vertices,
+            double_sided,
+        }))
     }
 
-    /// Construct a rectangle occluder from width and height.
+    /// Construct a polygonal occluder from a line of the given thickness along the given points, e.g. for walls.
+    ///
+    /// Unlike [`polyline`](Occluder2d::polyline), which has no width, the line is offset by half of the thickness
+    /// on each side into a closed polygon. Corners are mitered, with very sharp ones clamped to avoid long spikes,
+    /// and the ends are capped flat at the first and last points. Having self-intersections can cause unexpected behavior.
+    ///
+    /// The points should be relative to the entity's translation.
+    ///
+    /// ## Failure
+    /// This returns None if the provided list doesn't contain at least 2 distinct vertices, or if the thickness isn't positive.
+    pub fn thick_polyline(points: impl Into<Vec<Vec2>>, thickness: f32) -> Option<Self> {
+        // how far a corner can stick out, relative to the line's half thickness
+        const MITER_LIMIT: f32 = 4.;
+
+        let mut points = points.into();
+        points.dedup();
+
+        if points.len() < 2 || thickness <= 0. {
+            return None;
+        }
+
+        let half = thickness * 0.5;
+        let normal = |a: Vec2, b: Vec2| (b - a).normalize().perp();
+
+        let offsets = (0..points.len())
+            .map(|i| {
+                let prev = i.checked_sub(1).map(|j| normal(points[j], points[i]));
+                let next = points.get(i + 1).map(|p| normal(points[i], *p));
+
+                match (prev, next) {
+                    (Some(prev), Some(next)) => {
+                        // a line that turns back on itself has no miter, so it's extended along the normal
+                        let miter = (prev + next).normalize_or(next);
+                        miter * (half / miter.dot(next).max(1. / MITER_LIMIT))
+                    }
+                    (Some(normal), None) | (None, Some(normal)) => normal * half,
+                    (None, None) => unreachable!(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let left = points.iter().zip(&offsets).map(|(p, offset)| p + offset);
+        let right = points.iter().zip(&offsets).map(|(p, offset)| p - offset);
+
+        Self::polygon(left.chain(right.rev()).collect::<Vec<_>>())
+    }
+
+    /// Construct a rectangle occluder from width and height.
     pub fn rectangle(width: f32, height: f32) -> Self {
         Self::round_rectangle(width, height, 0.)
     }
@@ -254,6 +953,132 @@ impl Occluder2d {
     pub fn capsule(length: f32, radius: f32) -> Self {
         Self::vertical_capsule(length, radius)
     }
+
+    /// Greedily merge axis-aligned rectangles into as few rectangle occluders as possible.
+    ///
+    /// Rectangles that share a full edge (or overlap along it) are joined, first along rows and then along columns.
+    /// The area covered by the resulting occluders is the same as the one covered by the input, but with much fewer
+    /// edges, which makes this useful for turning solid tiles into occluders. See [`grid_rects`] for building the input
+    /// from a tile grid.
+    ///
+    /// Each occluder has its [offset](Occluder2d::offset) set to its rectangle's center, so they should all be spawned
+    /// with the same transform (e.g. as children of the tilemap entity).
+    ///
+    /// Empty rectangles are ignored.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let rects = grid_rects(&solid_tiles, columns, Vec2::splat(16.));
+    ///
+    /// commands.spawn(Transform::default()).with_children(|parent| {
+    ///     for occluder in Occluder2d::merge_rects(&rects) {
+    ///         parent.spawn((occluder, StaticOccluder));
+    ///     }
+    /// });
+    /// ```
+    pub fn merge_rects(rects: &[Rect]) -> Vec<Self> {
+        let rects = rects
+            .iter()
+            .filter(|rect| !rect.is_empty())
+            .copied()
+            .collect();
+        let rects = merge_rect_runs(merge_rect_runs(rects, true), false);
+
+        rects
+            .into_iter()
+            .map(|rect| {
+                Self::rectangle(rect.width(), rect.height()).with_offset(rect.center().extend(0.))
+            })
+            .collect()
+    }
+}
+
+/// Coalesce a grid of solid tiles into a small set of rectangles, ready to be passed to [`Occluder2d::merge_rects`].
+///
+/// The grid is stored row by row, with the tile at column `x` and row `y` at index `y * columns + x`.
+/// Row 0 is the bottom one, and each tile covers `tile_size` starting from the grid's origin.
+///
+/// Every solid tile is covered by exactly one rectangle, and no rectangle covers an empty tile.
+/// Rectangles are grown greedily: as far right as possible, then as far up as the whole span allows.
+pub fn grid_rects(solid: &[bool], columns: usize, tile_size: Vec2) -> Vec<Rect> {
+    if columns == 0 {
+        return vec![];
+    }
+
+    let rows = solid.len().div_ceil(columns);
+    let is_solid = |x: usize, y: usize| solid.get(y * columns + x).copied().unwrap_or(false);
+
+    let mut used = vec![false; solid.len()];
+    let mut rects = vec![];
+
+    for y in 0..rows {
+        for x in 0..columns {
+            let free = |x: usize, y: usize| is_solid(x, y) && !used[y * columns + x];
+
+            if !free(x, y) {
+                continue;
+            }
+
+            let mut width = 1;
+            while x + width < columns && free(x + width, y) {
+                width += 1;
+            }
+
+            let mut height = 1;
+            while y + height < rows && (x..x + width).all(|x| free(x, y + height)) {
+                height += 1;
+            }
+
+            for row in y..y + height {
+                used[row * columns + x..row * columns + x + width].fill(true);
+            }
+
+            rects.push(Rect::from_corners(
+                vec2(x as f32, y as f32) * tile_size,
+                vec2((x + width) as f32, (y + height) as f32) * tile_size,
+            ));
+        }
+    }
+
+    rects
+}
+
+const RECT_MERGE_EPSILON: f32 = 1e-4;
+
+// merges consecutive rects that span the same range on the other axis
+fn merge_rect_runs(mut rects: Vec<Rect>, horizontal: bool) -> Vec<Rect> {
+    // swap the axes so that runs are always merged along x
+    let axes = |v: Vec2| match horizontal {
+        true => v,
+        false => v.yx(),
+    };
+
+    rects.sort_by_key(|rect| {
+        let (min, max) = (axes(rect.min), axes(rect.max));
+        (FloatOrd(min.y), FloatOrd(max.y), FloatOrd(min.x))
+    });
+
+    let mut merged: Vec<Rect> = Vec::with_capacity(rects.len());
+
+    for rect in rects {
+        if let Some(last) = merged.last_mut() {
+            let (last_min, last_max) = (axes(last.min), axes(last.max));
+            let (min, max) = (axes(rect.min), axes(rect.max));
+
+            if (last_min.y - min.y).abs() <= RECT_MERGE_EPSILON
+                && (last_max.y - max.y).abs() <= RECT_MERGE_EPSILON
+                && min.x <= last_max.x + RECT_MERGE_EPSILON
+            {
+                *last = last.union(rect);
+                continue;
+            }
+        }
+
+        merged.push(rect);
+    }
+
+    merged
 }
 
 /// Component with data extracted to the Render World from Occluders.
@@ -266,10 +1091,23 @@ pub struct ExtractedOccluder {
     pub aabb: Aabb2d,
     pub z: f32,
     pub color: Color,
+    pub tint_strength: f32,
+    pub blocks_channels: Vec3,
     pub opacity: f32,
     pub z_sorting: bool,
+    pub shade_self: bool,
+    pub self_lit: bool,
+    pub casts_shadow: bool,
+    pub hard_shadows: bool,
+    pub feather: f32,
+    pub refraction: f32,
+    pub shadow_fade_distance: f32,
+    pub glow_through: f32,
     pub changes: Changes,
+    /// Whether the shape changed, in which case its vertices are rewritten. Only moving the occluder doesn't change them.
+    pub shape_changed: bool,
     pub render_layers: RenderLayers,
+    pub occlusion_layers: OcclusionLayers,
 }
 
 impl PartialEq for ExtractedOccluder {
@@ -279,6 +1117,15 @@ impl PartialEq for ExtractedOccluder {
 }
 
 impl ExtractedOccluder {
+    /// How the occluder shades its own area in the shaders: 0 only by the shadow of its edges, 1 fully, 2 never.
+    pub(crate) fn self_shading(&self) -> u32 {
+        match (self.shade_self, self.self_lit) {
+            (true, _) => 1,
+            (false, true) => 2,
+            (false, false) => 0,
+        }
+    }
+
     /// Get the occluder's vertices. This will be an empty Vec if the occluder has no vertices.
     pub fn vertices(&self) -> Vec<Vec2> {
         self.shape.vertices(self.pos, Rot2::radians(self.rot))
@@ -289,10 +1136,131 @@ impl ExtractedOccluder {
             .vertices_iter(self.pos, Rot2::radians(self.rot))
             .unwrap()
     }
+
+    /// Whether the occluder can cast shadows from a light at the given position.
+    ///
+    /// This is always true, except for one-sided polylines with the light behind them.
+    pub fn faces(&self, light_pos: Vec2) -> bool {
+        let Occluder2dShape::Polyline {
+            vertices,
+            double_sided: false,
+        } = &self.shape
+        else {
+            return true;
+        };
+
+        let points = translate_vertices(
+            polyline_points(vertices).to_vec(),
+            self.pos,
+            Rot2::radians(self.rot),
+        );
+
+        polyline_faces(&points, light_pos)
+    }
+
+    /// Convex polygons covering the occluder's area, in world space. Used to draw the [`OccluderCoverageTexture`](crate::OccluderCoverageTexture).
+    ///
+    /// Polylines don't cover any area, so they return no polygons. Round rectangles are approximated with a few segments per corner.
+    pub(crate) fn convex_pieces(&self) -> Vec<Vec<Vec2>> {
+        match &self.shape {
+            Occluder2dShape::Polygon { concave: true, .. } => convex_decomposition(self.vertices()),
+            Occluder2dShape::Polygon { .. } => vec![self.vertices()],
+            Occluder2dShape::Polyline { .. } => vec![],
+            Occluder2dShape::RoundRectangle {
+                half_width,
+                half_height,
+                radius,
+            } => {
+                const CORNER_SEGMENTS: u32 = 4;
+
+                let corners = [
+                    vec2(*half_width, *half_height),
+                    vec2(-half_width, *half_height),
+                    vec2(-half_width, -half_height),
+                    vec2(*half_width, -half_height),
+                ];
+
+                let mut vertices = vec![];
+                for (i, corner) in corners.into_iter().enumerate() {
+                    for j in 0..=CORNER_SEGMENTS {
+                        let angle =
+                            (i as f32 + j as f32 / CORNER_SEGMENTS as f32) * f32::consts::FRAC_PI_2;
+                        vertices.push(corner + Vec2::from_angle(angle) * radius);
+                    }
+                }
+
+                vec![translate_vertices(
+                    vertices,
+                    self.pos,
+                    Rot2::radians(self.rot),
+                )]
+            }
+        }
+    }
+}
+
+/// The original points of a polyline. The first half of its vertices are the points, the rest are mirrored.
+pub(crate) fn polyline_points(vertices: &[Vec2]) -> &[Vec2] {
+    &vertices[..(vertices.len() + 2) / 2]
+}
+
+/// Get the points a polyline was constructed from, if the vertices are already mirrored like in [`Occluder2dShape::Polyline`].
+/// Otherwise the vertices are returned as they are.
+fn unmirrored_polyline(vertices: &[Vec2]) -> &[Vec2] {
+    if vertices.len() < 2 {
+        return vertices;
+    }
+
+    let points = polyline_points(vertices);
+    let mirrored = points
+        .iter()
+        .rev()
+        .skip(1)
+        .take(points.len().saturating_sub(2));
+
+    if vertices.len() == points.len() + mirrored.len()
+        && vertices[points.len()..].iter().eq(mirrored)
+    {
+        points
+    } else {
+        vertices
+    }
+}
+
+/// Whether the light is on the front (left) side of the polyline segment closest to it.
+fn polyline_faces(points: &[Vec2], light_pos: Vec2) -> bool {
+    let Some((a, b)) = points
+        .windows(2)
+        .map(|segment| (segment[0], segment[1]))
+        .min_by_key(|(a, b)| FloatOrd(distance_to_segment(light_pos, *a, *b)))
+    else {
+        return true;
+    };
+
+    (b - a).perp_dot(light_pos - a) >= 0.
+}
+
+fn distance_to_segment(p: Vec2, a: Vec2, b: Vec2) -> f32 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_squared().max(f32::EPSILON)).clamp(0., 1.);
+    p.distance(a + ab * t)
 }
 
 /// Rotates vertices to be clockwise.
 fn normalize_vertices(mut vertices: Vec<Vec2>) -> Vec<Vec2> {
+    if !has_normal_order(&vertices) {
+        vertices.reverse();
+    }
+    vertices
+}
+
+// whether the vertices are already in the order the shaders expect
+fn has_normal_order(vertices: &[Vec2]) -> bool {
+    winding_sum(vertices) >= 0.0
+}
+
+// twice the signed area of the polygon, positive if clockwise
+fn winding_sum(vertices: &[Vec2]) -> f32 {
     let mut sum = 0.0;
 
     for i in 0..vertices.len() {
@@ -300,12 +1268,410 @@ fn normalize_vertices(mut vertices: Vec<Vec2>) -> Vec<Vec2> {
         sum += (vertices[j].x - vertices[i].x) * (vertices[j].y + vertices[i].y);
     }
 
-    if sum >= 0.0 {
-        vertices
-    } else {
-        vertices.reverse();
-        vertices
+    sum
+}
+
+/// Removes repeated consecutive vertices (including a last vertex equal to the first one), along with their opacities.
+fn dedup_vertices(mut vertices: Vec<Vec2>, mut opacities: Vec<f32>) -> (Vec<Vec2>, Vec<f32>) {
+    let mut len = 0;
+
+    for i in 0..vertices.len() {
+        if len > 0 && vertices[i] == vertices[len - 1] {
+            continue;
+        }
+
+        vertices[len] = vertices[i];
+        if let Some(&opacity) = opacities.get(i) {
+            opacities[len] = opacity;
+        }
+        len += 1;
+    }
+
+    if len > 1 && vertices[len - 1] == vertices[0] {
+        len -= 1;
+    }
+
+    vertices.truncate(len);
+    opacities.truncate(len);
+    (vertices, opacities)
+}
+
+// whether the polygon is too thin to cast a meaningful shadow, e.g. all of its vertices are collinear
+fn has_no_area(vertices: &[Vec2]) -> bool {
+    if vertices.len() < 3 {
+        return true;
+    }
+
+    let (min, max) = vertices
+        .iter()
+        .fold((vertices[0], vertices[0]), |(min, max), v| {
+            (min.min(*v), max.max(*v))
+        });
+
+    // relative to the size of the polygon, so it works at any scale
+    winding_sum(vertices).abs() <= 1e-6 * (max - min).length_squared()
+}
+
+/// Removes the vertices with the smallest effective area until at most `max_vertices` are left (Visvalingam-Whyatt).
+///
+/// Runs in `O(N log N)`, using a heap whose stale entries are skipped when popped. Removals aren't checked against
+/// the rest of the outline, so thin parts of a polygon can end up self-intersecting.
+fn decimate(vertices: Vec<Vec2>, max_vertices: usize) -> Vec<Vec2> {
+    let n = vertices.len();
+    if n <= max_vertices {
+        return vertices;
+    }
+
+    let mut prev: Vec<usize> = (0..n).map(|i| (i + n - 1) % n).collect();
+    let mut next: Vec<usize> = (0..n).map(|i| (i + 1) % n).collect();
+    let mut removed = vec![false; n];
+
+    let area = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - a).abs() * 0.5;
+    let mut areas: Vec<f32> = (0..n)
+        .map(|i| area(vertices[prev[i]], vertices[i], vertices[next[i]]))
+        .collect();
+
+    // entries are invalidated when their vertex's area changes, instead of being removed from the heap
+    let mut heap: BinaryHeap<_> = (0..n).map(|i| Reverse((FloatOrd(areas[i]), i))).collect();
+
+    let mut remaining = n;
+    while remaining > max_vertices {
+        let Some(Reverse((FloatOrd(vertex_area), i))) = heap.pop() else {
+            break;
+        };
+
+        if removed[i] || vertex_area != areas[i] {
+            continue;
+        }
+
+        removed[i] = true;
+        remaining -= 1;
+        let (p, q) = (prev[i], next[i]);
+        next[p] = q;
+        prev[q] = p;
+
+        for j in [p, q] {
+            areas[j] = area(vertices[prev[j]], vertices[j], vertices[next[j]]);
+            heap.push(Reverse((FloatOrd(areas[j]), j)));
+        }
+    }
+
+    vertices
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| !removed[*i])
+        .map(|(_, v)| v)
+        .collect()
+}
+
+/// Traces the outer boundary of a triangle mesh.
+///
+/// Vertices with identical positions are merged, and edges that belong to a single triangle are chained into loops.
+/// Returns the loop with the largest area, as long as every other loop is a hole inside it.
+/// Returns None if the boundary edges don't form simple loops, or if the mesh has disconnected parts.
+fn mesh_outline(positions: &[Vec2], indices: &[usize]) -> Option<Vec<Vec2>> {
+    if indices.len() < 3 || indices.len() % 3 != 0 {
+        return None;
+    }
+
+    // merge duplicated vertices, which are common in meshes with per-face attributes
+    let mut welded = HashMap::new();
+    let mut points = vec![];
+    let mut remap = Vec::with_capacity(positions.len());
+    for p in positions {
+        let id = *welded
+            .entry((p.x.to_bits(), p.y.to_bits()))
+            .or_insert_with(|| {
+                points.push(*p);
+                points.len() - 1
+            });
+        remap.push(id);
+    }
+
+    let mut edges: HashMap<(usize, usize), u32> = HashMap::new();
+    for triangle in indices.chunks_exact(3) {
+        let triangle = [
+            *remap.get(triangle[0])?,
+            *remap.get(triangle[1])?,
+            *remap.get(triangle[2])?,
+        ];
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            if a != b {
+                *edges.entry((a.min(b), a.max(b))).or_default() += 1;
+            }
+        }
+    }
+
+    let mut neighbours: HashMap<usize, Vec<usize>> = HashMap::new();
+    for (&(a, b), &count) in &edges {
+        if count == 1 {
+            neighbours.entry(a).or_default().push(b);
+            neighbours.entry(b).or_default().push(a);
+        }
+    }
+
+    if neighbours.values().any(|n| n.len() != 2) {
+        return None;
+    }
+
+    let mut contours: Vec<(f32, Vec<Vec2>)> = vec![];
+    let mut visited = vec![false; points.len()];
+    for &start in neighbours.keys() {
+        if visited[start] {
+            continue;
+        }
+
+        let mut contour = vec![];
+        let (mut prev, mut current) = (usize::MAX, start);
+        loop {
+            visited[current] = true;
+            contour.push(points[current]);
+
+            let n = &neighbours[&current];
+            let next = if n[0] != prev { n[0] } else { n[1] };
+            (prev, current) = (current, next);
+
+            if current == start {
+                break;
+            }
+            if visited[current] {
+                return None;
+            }
+        }
+
+        contours.push((polygon_area(&contour).abs(), contour));
+    }
+
+    let outer = contours
+        .iter()
+        .enumerate()
+        .max_by_key(|(_, (area, _))| FloatOrd(*area))?
+        .0;
+    let (_, outline) = contours.swap_remove(outer);
+
+    // any other loop is either a hole inside the outline, or a disconnected part of the mesh
+    let aabb = Aabb2d::from_point_cloud(Isometry2d::IDENTITY, &outline);
+    if contours
+        .iter()
+        .any(|(_, contour)| !point_inside_poly(contour[0], &outline, aabb, false))
+    {
+        return None;
+    }
+
+    Some(outline)
+}
+
+/// Signed area of a polygon, positive if the vertices are in counter-clockwise order.
+fn polygon_area(vertices: &[Vec2]) -> f32 {
+    (0..vertices.len())
+        .map(|i| vertices[i].perp_dot(vertices[(i + 1) % vertices.len()]))
+        .sum::<f32>()
+        * 0.5
+}
+
+/// Computes the convex hull of a set of points (Andrew's monotone chain).
+fn convex_hull(mut points: Vec<Vec2>) -> Vec<Vec2> {
+    points.sort_by_key(|p| (FloatOrd(p.x), FloatOrd(p.y)));
+    points.dedup();
+
+    if points.len() < 3 {
+        return points;
+    }
+
+    let mut hull: Vec<Vec2> = Vec::with_capacity(points.len() * 2);
+    for pass in 0..2 {
+        let start = hull.len();
+        let iter: Box<dyn Iterator<Item = &Vec2>> = match pass {
+            0 => Box::new(points.iter()),
+            _ => Box::new(points.iter().rev()),
+        };
+
+        for &p in iter {
+            while hull.len() >= start + 2
+                && (hull[hull.len() - 1] - hull[hull.len() - 2]).perp_dot(p - hull[hull.len() - 2])
+                    <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        // the last point of each chain is the first point of the other one
+        hull.pop();
+    }
+
+    hull
+}
+
+/// Connects each hole to the outer contour through a zero-width bridge, resulting in a single
+/// weakly simple polygon in counter-clockwise order.
+fn bridge_holes(outer: Vec<Vec2>, mut holes: Vec<Vec<Vec2>>) -> Vec<Vec2> {
+    let mut polygon = normalize_vertices(outer);
+    polygon.reverse();
+
+    // holes are bridged from right to left, so that later bridges can't cross earlier ones
+    let rightmost = |hole: &Vec<Vec2>| {
+        (0..hole.len())
+            .max_by_key(|&i| FloatOrd(hole[i].x))
+            .unwrap()
+    };
+    holes.sort_by_key(|hole| Reverse(FloatOrd(hole[rightmost(hole)].x)));
+
+    for hole in holes {
+        // holes go in the opposite direction of the outer contour
+        let mut hole = normalize_vertices(hole);
+        let start = rightmost(&hole);
+        hole.rotate_left(start);
+        let m = hole[0];
+
+        // closest edge hit by a ray cast from the hole's rightmost vertex towards +x
+        let n = polygon.len();
+        let mut hit: Option<(f32, usize)> = None;
+        for i in 0..n {
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            if (a.y > m.y) == (b.y > m.y) {
+                continue;
+            }
+
+            let x = a.x + (m.y - a.y) * (b.x - a.x) / (b.y - a.y);
+            if x >= m.x && hit.is_none_or(|(best, _)| x < best) {
+                hit = Some((x, i));
+            }
+        }
+
+        let Some((x, i)) = hit else {
+            continue;
+        };
+
+        // the bridge goes to the endpoint of the edge furthest along the ray, unless another
+        // vertex is in the way, in which case the one closest in angle to the ray is used
+        let intersection = vec2(x, m.y);
+        let mut p = match polygon[i].x > polygon[(i + 1) % n].x {
+            true => i,
+            false => (i + 1) % n,
+        };
+        let (tri_a, tri_b, tri_c) = (m, intersection, polygon[p]);
+        let inside = |v: Vec2| {
+            let d1 = (tri_b - tri_a).perp_dot(v - tri_a);
+            let d2 = (tri_c - tri_b).perp_dot(v - tri_b);
+            let d3 = (tri_a - tri_c).perp_dot(v - tri_c);
+            (d1 > 0. && d2 > 0. && d3 > 0.) || (d1 < 0. && d2 < 0. && d3 < 0.)
+        };
+        let slope = |v: Vec2| ((v.y - m.y).abs() / (v.x - m.x), v.distance_squared(m));
+        for j in 0..n {
+            if j != p && inside(polygon[j]) && slope(polygon[j]) < slope(polygon[p]) {
+                p = j;
+            }
+        }
+
+        let mut bridged = Vec::with_capacity(n + hole.len() + 2);
+        bridged.extend_from_slice(&polygon[..=p]);
+        bridged.extend_from_slice(&hole);
+        bridged.push(m);
+        bridged.extend_from_slice(&polygon[p..]);
+        polygon = bridged;
+    }
+
+    polygon
+}
+
+/// Splits a simple polygon into convex pieces, by triangulating it through ear clipping
+/// and then merging adjacent pieces while they stay convex (Hertel-Mehlhorn).
+fn convex_decomposition(mut vertices: Vec<Vec2>) -> Vec<Vec<Vec2>> {
+    // work in counter-clockwise order
+    vertices = normalize_vertices(vertices);
+    vertices.reverse();
+
+    let is_convex = |a: Vec2, b: Vec2, c: Vec2| (b - a).perp_dot(c - b) >= 0.;
+
+    let mut remaining: Vec<usize> = (0..vertices.len()).collect();
+    let mut pieces: Vec<Vec<usize>> = vec![];
+
+    while remaining.len() > 3 {
+        let n = remaining.len();
+
+        let ear = (0..n).find(|&i| {
+            let (a, b, c) = (
+                remaining[(i + n - 1) % n],
+                remaining[i],
+                remaining[(i + 1) % n],
+            );
+            let (va, vb, vc) = (vertices[a], vertices[b], vertices[c]);
+
+            (vb - va).perp_dot(vc - vb) > 0.
+                && !remaining.iter().any(|&p| {
+                    // duplicated vertices (e.g. from hole bridges) don't block the ear
+                    ![va, vb, vc].contains(&vertices[p])
+                        && is_convex(va, vb, vertices[p])
+                        && is_convex(vb, vc, vertices[p])
+                        && is_convex(vc, va, vertices[p])
+                })
+        });
+
+        // degenerate polygon, keep the rest as a single piece
+        let Some(i) = ear else {
+            break;
+        };
+
+        pieces.push(vec![
+            remaining[(i + n - 1) % n],
+            remaining[i],
+            remaining[(i + 1) % n],
+        ]);
+        remaining.remove(i);
+    }
+    pieces.push(remaining);
+
+    // merge pieces that share an edge while the result stays convex
+    'merge: loop {
+        for p in 0..pieces.len() {
+            for q in (p + 1)..pieces.len() {
+                let Some(merged) = merge_pieces(&pieces[p], &pieces[q]) else {
+                    continue;
+                };
+
+                let m = merged.len();
+                if (0..m).all(|i| {
+                    is_convex(
+                        vertices[merged[i]],
+                        vertices[merged[(i + 1) % m]],
+                        vertices[merged[(i + 2) % m]],
+                    )
+                }) {
+                    pieces[p] = merged;
+                    pieces.remove(q);
+                    continue 'merge;
+                }
+            }
+        }
+        break;
+    }
+
+    pieces
+        .into_iter()
+        .map(|piece| piece.into_iter().map(|i| vertices[i]).collect())
+        .collect()
+}
+
+/// Merges two counter-clockwise pieces along their shared edge, if they have one.
+fn merge_pieces(p: &[usize], q: &[usize]) -> Option<Vec<usize>> {
+    let (np, nq) = (p.len(), q.len());
+
+    for i in 0..np {
+        let (a, b) = (p[i], p[(i + 1) % np]);
+
+        // the shared edge is traversed in opposite directions by the two pieces
+        let Some(j) = (0..nq).find(|&j| q[j] == b && q[(j + 1) % nq] == a) else {
+            continue;
+        };
+
+        // p from b around to a, then q from a around to b (without the shared vertices)
+        let mut merged: Vec<usize> = (1..=np).map(|k| p[(i + k) % np]).collect();
+        merged.extend((2..nq).map(|k| q[(j + k) % nq]));
+        return Some(merged);
     }
+
+    None
 }
 
 fn is_concave(vertices: &Vec<Vec2>) -> bool {
@@ -394,7 +1760,95 @@ pub(crate) fn point_inside_poly(p: Vec2, poly: &Vec<Vec2>, aabb: Aabb2d, concave
 pub struct OccluderPlugin;
 
 impl Plugin for OccluderPlugin {
-    fn build(&self, _app: &mut App) {}
+    fn build(&self, app: &mut App) {
+        app.init_resource::<OccluderSpatialIndex>();
+
+        app.add_systems(
+            PostUpdate,
+            (
+                warn_tilted_occluders,
+                warn_imprecise_z_sorting,
+                update_occluder_spatial_index,
+            )
+                .after(TransformSystems::Propagate),
+        );
+    }
+}
+
+/// Warns once for every occluder rotated around the x or y axes, since only its z rotation is used.
+fn warn_tilted_occluders(
+    occluders: Query<
+        (Entity, &GlobalTransform, Option<&Name>),
+        (With<Occluder2d>, Changed<GlobalTransform>),
+    >,
+    mut removed: RemovedComponents<Occluder2d>,
+    mut warned: Local<HashSet<Entity>>,
+) {
+    for entity in removed.read() {
+        warned.remove(&entity);
+    }
+
+    for (entity, transform, name) in &occluders {
+        let up = transform.rotation() * Vec3::Z;
+        if up.z > 0.9999 || warned.contains(&entity) {
+            continue;
+        }
+
+        warned.insert(entity);
+
+        let name = name.map_or_else(|| format!("{entity}"), |name| format!("{name} ({entity})"));
+        warn!(
+            "Occluder {name} is rotated around the x or y axis. Only z-axis rotations are supported, so its shadows won't match its tilted shape."
+        );
+    }
+}
+
+/// Z values from which the 16 bit sprite stencil can't tell apart z positions closer than 0.25.
+const IMPRECISE_STENCIL_Z: f32 = 256.;
+
+/// Warns once if z-sorted occluders sit at z values where 16 bit stencils lose too much precision.
+///
+/// This includes cameras that enable 32 bit stencils on devices without `FLOAT32_BLENDABLE`, which fall back to 16 bit ones.
+///
+/// Each occluder is compared against the z of the sprite stored in the stencil on its own, so overlapping
+/// occluders at different z values are layered independently. What can break that layering is the stencil
+/// rounding the sprite's z past an occluder's z.
+fn warn_imprecise_z_sorting(
+    configs: Query<&FireflyConfig>,
+    occluders: Query<
+        (&GlobalTransform, &Occluder2d),
+        Or<(Changed<GlobalTransform>, Changed<Occluder2d>)>,
+    >,
+    render_device: Option<Res<RenderDevice>>,
+    mut warned: Local<bool>,
+) {
+    let uses_16bit_stencils = |config: &FireflyConfig| match &render_device {
+        Some(render_device) => {
+            stencil_format(config.enable_32bit_stencils, render_device)
+                == TextureFormat::Rgba16Float
+        }
+        None => !config.enable_32bit_stencils,
+    };
+
+    if *warned
+        || !configs
+            .iter()
+            .any(|config| config.z_sorting && uses_16bit_stencils(config))
+    {
+        return;
+    }
+
+    let imprecise = occluders.iter().any(|(transform, occluder)| {
+        occluder.z_sorting && occluder.sorting_z(transform).abs() >= IMPRECISE_STENCIL_Z
+    });
+
+    if imprecise {
+        *warned = true;
+        warn!(
+            "Some z-sorted occluders have z values above {IMPRECISE_STENCIL_Z}, where 16 bit stencils store sprite z positions with a precision of 0.25 or worse. \
+            Shadows may render over sprites that should be above them. Consider using smaller z values or enabling FireflyConfig::enable_32bit_stencils on devices that support FLOAT32_BLENDABLE."
+        );
+    }
 }
 
 /// Data that is transferred to the GPU to be read inside shaders.
@@ -406,8 +1860,21 @@ pub struct UniformOccluder {
     pub z: f32,
     pub opacity: f32,
     pub color: Vec4,
+    pub blocks_channels: Vec4,
     pub z_sorting: u32,
-    pub _pad1: [u32; 3],
+    pub tint_strength: f32,
+    /// See [`ExtractedOccluder::self_shading`].
+    pub shade_self: u32,
+    pub hard_shadows: u32,
+    pub casts_shadow: u32,
+    pub shadow_fade_distance: f32,
+    /// Position of the occluder, which its vertices are relative to.
+    pub pos: Vec2,
+    /// Cosine and sine of the occluder's rotation.
+    pub rotation: Vec2,
+    /// Whether the vertices have their own opacities.
+    pub vertex_opacity: u32,
+    pub glow_through: f32,
 }
 
 /// Data that is transferred to the GPU to be read inside shaders.
@@ -422,15 +1889,25 @@ pub struct UniformRoundOccluder {
     pub z: f32,
     pub opacity: f32,
     pub color: Vec4,
+    pub blocks_channels: Vec4,
     pub z_sorting: u32,
-    pub _pad1: [u32; 3],
+    pub tint_strength: f32,
+    /// See [`ExtractedOccluder::self_shading`].
+    pub shade_self: u32,
+    pub hard_shadows: u32,
+    pub casts_shadow: u32,
+    pub feather: f32,
+    pub shadow_fade_distance: f32,
+    pub glow_through: f32,
 }
 
 #[repr(C)]
 #[derive(ShaderType, Clone, Copy, Zeroable, Pod, Default)]
 pub(crate) struct UniformVertex {
-    pub angle: f32,
+    /// Position of the vertex, relative to its occluder.
     pub pos: Vec2,
+    pub opacity: f32,
+    pub _pad: f32,
 }
 
 /// The internal shape of an [`Occluder`](crate::prelude::Occluder2d). This is intended to be generated automatically through
@@ -441,9 +1918,13 @@ pub enum Occluder2dShape {
     Polygon {
         vertices: Vec<Vec2>,
         concave: bool,
+        /// Opacity of each vertex, see [`Occluder2d::polygon_with_opacity`]. Empty if the whole polygon is equally opaque.
+        #[cfg_attr(feature = "serde", serde(default))]
+        opacities: Vec<f32>,
     },
     Polyline {
         vertices: Vec<Vec2>,
+        double_sided: bool,
     },
     RoundRectangle {
         half_width: f32,
@@ -466,11 +1947,28 @@ impl Occluder2dShape {
     pub(crate) fn n_vertices(&self) -> u32 {
         match &self {
             Self::Polygon { vertices, .. } => vertices.len() as u32,
-            Self::Polyline { vertices } => vertices.len() as u32,
+            Self::Polyline { vertices, .. } => vertices.len() as u32,
             Self::RoundRectangle { .. } => 0,
         }
     }
 
+    /// The shape's vertices, relative to the occluder's position and rotation.
+    pub(crate) fn local_vertices(&self) -> &[Vec2] {
+        match &self {
+            Self::Polygon { vertices, .. } => vertices,
+            Self::Polyline { vertices, .. } => vertices,
+            Self::RoundRectangle { .. } => &[],
+        }
+    }
+
+    /// The opacity of each of the shape's [local vertices](Occluder2dShape::local_vertices). Empty if they're all equally opaque.
+    pub(crate) fn vertex_opacities(&self) -> &[f32] {
+        match &self {
+            Self::Polygon { opacities, .. } => opacities,
+            _ => &[],
+        }
+    }
+
     pub(crate) fn vertices(&self, pos: Vec2, rot: Rot2) -> Vec<Vec2> {
         match &self {
             Self::Polygon { vertices, .. } => translate_vertices(vertices.to_vec(), pos, rot),
@@ -506,23 +2004,1409 @@ impl Occluder2dShape {
     }
 }
 
-pub(crate) fn translate_vertices(vertices: Vec<Vec2>, pos: Vec2, rot: Rot2) -> Vec<Vec2> {
-    vertices.iter().map(|v| rot * *v + pos).collect()
+/// Get all the [occluders](Occluder2d) that block the line between a light and a target point.
+///
+/// Useful for gameplay logic such as line-of-sight checks. This runs entirely on the CPU, in the main world.
+///
+/// An occluder blocks the line if any of its edges properly crosses it, or if it contains either end of the line.
+/// Lines that only graze an occluder (touching a vertex or an edge without crossing it) are not blocked.
+/// Round occluders are tested against their actual rounded shape, and one-sided polylines
+/// (see [`Occluder2d::polyline_oriented()`]) only block lights on their front side.
+///
+/// Opacity is ignored, so semi-transparent occluders are returned as well.
+///
+/// # Example
+///
+/// ```
+/// fn line_of_sight(occluders: Query<(Entity, &GlobalTransform, &Occluder2d)>) {
+///     let blocking = occluders_blocking(vec2(0., 0.), vec2(100., 50.), &occluders);
+///     if blocking.is_empty() {
+///         info!("clear line!");
+///     }
+/// }
+/// ```
+pub fn occluders_blocking(
+    light_pos: Vec2,
+    target: Vec2,
+    occluders: &Query<(Entity, &GlobalTransform, &Occluder2d)>,
+) -> Vec<Entity> {
+    occluders
+        .iter()
+        .filter(|(_, transform, occluder)| occluder.blocks_segment(light_pos, target, transform))
+        .map(|(entity, _, _)| entity)
+        .collect()
 }
 
-pub(crate) fn translate_vertices_iter<'a>(
-    vertices: Box<dyn 'a + DoubleEndedIterator<Item = Vec2>>,
-    pos: Vec2,
-    rot: Rot2,
-) -> Box<dyn 'a + DoubleEndedIterator<Item = Vec2>> {
-    Box::new(vertices.map(move |v| rot * v + pos))
-}
+fn occluder_blocks(shape: &Occluder2dShape, pos: Vec2, rot: Rot2, a: Vec2, b: Vec2) -> bool {
+    match shape {
+        Occluder2dShape::Polygon {
+            vertices, concave, ..
+        } => {
+            let vertices = translate_vertices(vertices.to_vec(), pos, rot);
+            let aabb = Aabb2d::from_point_cloud(Isometry2d::IDENTITY, &vertices);
+            let n = vertices.len();
+
+            (0..n).any(|i| segments_cross(a, b, vertices[i], vertices[(i + 1) % n]))
+                || point_inside_poly(a, &vertices, aabb, *concave)
+                || point_inside_poly(b, &vertices, aabb, *concave)
+        }
+        Occluder2dShape::Polyline {
+            vertices,
+            double_sided,
+        } => {
+            let points = translate_vertices(polyline_points(vertices).to_vec(), pos, rot);
 
-#[derive(Component, Clone, Copy, Default)]
-pub struct RoundOccluderIndex(pub Option<BufferIndex>);
+            if !double_sided && !polyline_faces(&points, a) {
+                return false;
+            }
 
-#[derive(Component, Clone, Copy, Default)]
-pub struct PolyOccluderIndex {
-    pub occluder: Option<BufferIndex>,
-    pub vertices: Option<BufferIndex>,
+            points
+                .windows(2)
+                .any(|segment| segments_cross(a, b, segment[0], segment[1]))
+        }
+        Occluder2dShape::RoundRectangle {
+            half_width,
+            half_height,
+            radius,
+        } => {
+            // move the line in the occluder's local space, where the inner rectangle is axis-aligned
+            let a = rot.inverse() * (a - pos);
+            let b = rot.inverse() * (b - pos);
+            let half_size = vec2(*half_width, *half_height);
+
+            if *radius > 0. {
+                return segment_rect_distance(a, b, half_size) < *radius;
+            }
+
+            let corners = rect_corners(half_size);
+
+            (0..4).any(|i| segments_cross(a, b, corners[i], corners[(i + 1) % 4]))
+                || a.abs().cmplt(half_size).all()
+                || b.abs().cmplt(half_size).all()
+        }
+    }
+}
+
+/// Resource that indexes the area of every enabled [occluder](Occluder2d) in a uniform grid, for fast point queries.
+///
+/// Useful for picking occluders in editors or for gameplay, e.g. finding what a clicked object is.
+/// Polygons are tested against their actual shape and round occluders against their rounded shape, accounting for
+/// each occluder's transform and [offset](Occluder2d::offset). Polylines don't cover any area, so they are never found.
+///
+/// The index is updated in [`PostUpdate`], after transforms are propagated. Queries made during [`Update`] see
+/// the occluders as they were at the end of the previous frame.
+///
+/// The grid's cell size can be changed by inserting the resource with [`OccluderSpatialIndex::new`] before adding the [`FireflyPlugin`](crate::prelude::FireflyPlugin).
+///
+/// # Example
+///
+/// ```
+/// fn pick(index: Res<OccluderSpatialIndex>, cursor: Res<CursorWorldPos>) {
+///     if let Some(occluder) = index.occluder_at(cursor.0) {
+///         info!("hovering {occluder}");
+///     }
+/// }
+/// ```
+#[derive(Resource)]
+pub struct OccluderSpatialIndex {
+    cell_size: f32,
+    cells: HashMap<IVec2, Vec<Entity>>,
+    // occluders spanning too many cells are checked on every query instead
+    large: Vec<Entity>,
+    occluders: HashMap<Entity, IndexedOccluder>,
+}
+
+impl Default for OccluderSpatialIndex {
+    fn default() -> Self {
+        Self::new(128.)
+    }
+}
+
+/// Max number of grid cells an occluder can be stored in, before it's stored as a large occluder instead.
+const MAX_INDEXED_CELLS: i32 = 64;
+
+struct IndexedOccluder {
+    shape: IndexedShape,
+    aabb: Aabb2d,
+    z: f32,
+    cells: Option<(IVec2, IVec2)>,
+}
+
+enum IndexedShape {
+    Polygon {
+        vertices: Vec<Vec2>,
+        concave: bool,
+    },
+    Round {
+        pos: Vec2,
+        rot: Rot2,
+        half_size: Vec2,
+        radius: f32,
+    },
+}
+
+impl OccluderSpatialIndex {
+    /// Construct an empty index with the given cell size, in world units.
+    ///
+    /// Cells should be around the size of a typical occluder. Smaller cells make queries check fewer occluders,
+    /// but make moving occluders more expensive to update.
+    pub fn new(cell_size: f32) -> Self {
+        Self {
+            cell_size: cell_size.max(f32::EPSILON),
+            cells: default(),
+            large: vec![],
+            occluders: default(),
+        }
+    }
+
+    /// Get the occluder covering the given world position. If multiple occluders cover it, the one with the highest z is returned.
+    pub fn occluder_at(&self, pos: Vec2) -> Option<Entity> {
+        self.candidates(pos)
+            .filter_map(|entity| Some((entity, self.occluders.get(&entity)?)))
+            .filter(|(_, occluder)| occluder.contains(pos))
+            .max_by_key(|(_, occluder)| FloatOrd(occluder.z))
+            .map(|(entity, _)| entity)
+    }
+
+    /// Get all occluders covering the given world position, in no particular order.
+    pub fn occluders_at(&self, pos: Vec2) -> Vec<Entity> {
+        self.candidates(pos)
+            .filter(|entity| {
+                self.occluders
+                    .get(entity)
+                    .is_some_and(|occluder| occluder.contains(pos))
+            })
+            .collect()
+    }
+
+    fn candidates(&self, pos: Vec2) -> impl Iterator<Item = Entity> {
+        self.cells
+            .get(&self.cell(pos))
+            .into_iter()
+            .flatten()
+            .chain(&self.large)
+            .copied()
+    }
+
+    fn cell(&self, pos: Vec2) -> IVec2 {
+        (pos / self.cell_size).floor().as_ivec2()
+    }
+
+    fn insert(&mut self, entity: Entity, occluder: &Occluder2d, transform: &GlobalTransform) {
+        let pos = transform.translation().truncate() + occluder.offset.xy();
+        let rot = Rot2::radians(transform.rotation().to_euler(EulerRot::XYZ).2);
+
+        let (shape, aabb) = match occluder.shape() {
+            Occluder2dShape::Polygon {
+                vertices, concave, ..
+            } => {
+                let vertices = translate_vertices(vertices.clone(), pos, rot);
+                let aabb = Aabb2d::from_point_cloud(Isometry2d::IDENTITY, &vertices);
+                let shape = IndexedShape::Polygon {
+                    vertices,
+                    concave: *concave,
+                };
+                (shape, aabb)
+            }
+            Occluder2dShape::RoundRectangle {
+                half_width,
+                half_height,
+                radius,
+            } => {
+                let half_size = vec2(*half_width, *half_height);
+                let aabb = Aabb2d {
+                    min: -half_size - radius,
+                    max: half_size + radius,
+                }
+                .transformed_by(pos, rot);
+                let shape = IndexedShape::Round {
+                    pos,
+                    rot,
+                    half_size,
+                    radius: *radius,
+                };
+                (shape, aabb)
+            }
+            Occluder2dShape::Polyline { .. } => return,
+        };
+
+        let (min, max) = (self.cell(aabb.min), self.cell(aabb.max));
+        let span = max - min + 1;
+
+        let cells = match span.x.saturating_mul(span.y) <= MAX_INDEXED_CELLS {
+            true => {
+                for x in min.x..=max.x {
+                    for y in min.y..=max.y {
+                        self.cells.entry(ivec2(x, y)).or_default().push(entity);
+                    }
+                }
+                Some((min, max))
+            }
+            false => {
+                self.large.push(entity);
+                None
+            }
+        };
+
+        let z = transform.translation().z + occluder.offset.z;
+
+        self.occluders.insert(
+            entity,
+            IndexedOccluder {
+                shape,
+                aabb,
+                z,
+                cells,
+            },
+        );
+    }
+
+    fn remove(&mut self, entity: Entity) {
+        let Some(occluder) = self.occluders.remove(&entity) else {
+            return;
+        };
+
+        let Some((min, max)) = occluder.cells else {
+            self.large.retain(|x| *x != entity);
+            return;
+        };
+
+        for x in min.x..=max.x {
+            for y in min.y..=max.y {
+                let cell = ivec2(x, y);
+                if let Some(entities) = self.cells.get_mut(&cell) {
+                    entities.retain(|x| *x != entity);
+                    if entities.is_empty() {
+                        self.cells.remove(&cell);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl IndexedOccluder {
+    fn contains(&self, p: Vec2) -> bool {
+        match &self.shape {
+            IndexedShape::Polygon { vertices, concave } => {
+                point_inside_poly(p, vertices, self.aabb, *concave)
+            }
+            IndexedShape::Round {
+                pos,
+                rot,
+                half_size,
+                radius,
+            } => {
+                // move the point in the occluder's local space, where the inner rectangle is axis-aligned
+                let p = rot.inverse() * (p - pos);
+                p.distance(p.clamp(-half_size, *half_size)) <= *radius
+            }
+        }
+    }
+}
+
+fn update_occluder_spatial_index(
+    mut index: ResMut<OccluderSpatialIndex>,
+    occluders: Query<
+        (Entity, &Occluder2d, &GlobalTransform, &Occluder2dEnabled),
+        Or<(
+            Changed<GlobalTransform>,
+            Changed<Occluder2d>,
+            Changed<Occluder2dEnabled>,
+        )>,
+    >,
+    mut removed: RemovedComponents<Occluder2d>,
+) {
+    for entity in removed.read() {
+        index.remove(entity);
+    }
+
+    for (entity, occluder, transform, enabled) in &occluders {
+        index.remove(entity);
+
+        if enabled.0 {
+            index.insert(entity, occluder, transform);
+        }
+    }
+}
+
+/// Whether two segments properly cross each other. Touching or collinear segments don't count.
+fn segments_cross(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> bool {
+    let o1 = orientation(a1, a2, b1);
+    let o2 = orientation(a1, a2, b2);
+    let o3 = orientation(b1, b2, a1);
+    let o4 = orientation(b1, b2, a2);
+
+    o1 != Orientation::Touch
+        && o2 != Orientation::Touch
+        && o3 != Orientation::Touch
+        && o4 != Orientation::Touch
+        && o1 != o2
+        && o3 != o4
+}
+
+fn rect_corners(half_size: Vec2) -> [Vec2; 4] {
+    [
+        vec2(-half_size.x, -half_size.y),
+        vec2(-half_size.x, half_size.y),
+        vec2(half_size.x, half_size.y),
+        vec2(half_size.x, -half_size.y),
+    ]
+}
+
+/// Distance between a segment and an axis-aligned rectangle centered at the origin.
+fn segment_rect_distance(a: Vec2, b: Vec2, half_size: Vec2) -> f32 {
+    let corners = rect_corners(half_size);
+
+    let crosses = (0..4).any(|i| segments_cross(a, b, corners[i], corners[(i + 1) % 4]));
+    if crosses || a.abs().cmple(half_size).all() || b.abs().cmple(half_size).all() {
+        return 0.;
+    }
+
+    let endpoints = [a, b]
+        .into_iter()
+        .map(|p| p.distance(p.clamp(-half_size, half_size)));
+    let corners = corners.into_iter().map(|c| distance_to_segment(c, a, b));
+
+    endpoints.chain(corners).fold(f32::MAX, f32::min)
+}
+
+pub(crate) fn translate_vertices(vertices: Vec<Vec2>, pos: Vec2, rot: Rot2) -> Vec<Vec2> {
+    vertices.iter().map(|v| rot * *v + pos).collect()
+}
+
+pub(crate) fn translate_vertices_iter<'a>(
+    vertices: Box<dyn 'a + DoubleEndedIterator<Item = Vec2>>,
+    pos: Vec2,
+    rot: Rot2,
+) -> Box<dyn 'a + DoubleEndedIterator<Item = Vec2>> {
+    Box::new(vertices.map(move |v| rot * v + pos))
+}
+
+#[derive(Component, Clone, Copy, Default)]
+pub struct RoundOccluderIndex(pub Option<BufferIndex>);
+
+#[derive(Component, Clone, Copy, Default)]
+pub struct PolyOccluderIndex {
+    pub occluder: Option<BufferIndex>,
+    pub vertices: Option<BufferIndex>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::ecs::system::RunSystemOnce;
+
+    fn blocking(world: &mut World, a: Vec2, b: Vec2) -> Vec<Entity> {
+        world
+            .run_system_once(
+                move |occluders: Query<(Entity, &GlobalTransform, &Occluder2d)>| {
+                    occluders_blocking(a, b, &occluders)
+                },
+            )
+            .unwrap()
+    }
+
+    #[test]
+    fn clear_line() {
+        let mut world = World::new();
+        world.spawn((
+            Occluder2d::rectangle(10., 10.),
+            GlobalTransform::from_xyz(0., 20., 0.),
+        ));
+        world.spawn((
+            Occluder2d::circle(5.),
+            GlobalTransform::from_xyz(0., -20., 0.),
+        ));
+
+        assert!(blocking(&mut world, vec2(-50., 0.), vec2(50., 0.)).is_empty());
+    }
+
+    #[test]
+    fn blocked_line() {
+        let mut world = World::new();
+        let rect = world
+            .spawn((
+                Occluder2d::rectangle(10., 10.),
+                GlobalTransform::from_xyz(-20., 0., 0.),
+            ))
+            .id();
+        let circle = world
+            .spawn((
+                Occluder2d::circle(5.),
+                GlobalTransform::from_xyz(20., 2., 0.),
+            ))
+            .id();
+        world.spawn((
+            Occluder2d::polygon(vec![vec2(-5., -5.), vec2(0., 5.), vec2(5., -5.)]).unwrap(),
+            GlobalTransform::from_xyz(0., 30., 0.),
+        ));
+
+        let mut blocking = blocking(&mut world, vec2(-50., 0.), vec2(50., 0.));
+        blocking.sort();
+        let mut expected = vec![rect, circle];
+        expected.sort();
+
+        assert_eq!(blocking, expected);
+    }
+
+    #[test]
+    fn line_inside_occluder() {
+        let mut world = World::new();
+        let polygon = world
+            .spawn((
+                Occluder2d::polygon(vec![
+                    vec2(-10., -10.),
+                    vec2(-10., 10.),
+                    vec2(10., 10.),
+                    vec2(10., -10.),
+                ])
+                .unwrap(),
+                GlobalTransform::default(),
+            ))
+            .id();
+
+        assert_eq!(
+            blocking(&mut world, vec2(-1., 0.), vec2(1., 0.)),
+            vec![polygon]
+        );
+    }
+
+    #[test]
+    fn grazing_line() {
+        let mut world = World::new();
+        world.spawn((Occluder2d::rectangle(10., 10.), GlobalTransform::default()));
+        world.spawn((
+            Occluder2d::circle(5.),
+            GlobalTransform::from_xyz(0., 20., 0.),
+        ));
+        world.spawn((
+            Occluder2d::polygon(vec![
+                vec2(-5., -5.),
+                vec2(-5., 5.),
+                vec2(5., 5.),
+                vec2(5., -5.),
+            ])
+            .unwrap(),
+            GlobalTransform::from_xyz(40., 0., 0.),
+        ));
+
+        // along the rectangle's top edge and tangent to the circle
+        assert!(blocking(&mut world, vec2(-50., 15.), vec2(50., 15.)).is_empty());
+        assert!(blocking(&mut world, vec2(-50., 5.), vec2(30., 5.)).is_empty());
+
+        // through the polygon's corner
+        assert!(blocking(&mut world, vec2(40., 10.), vec2(55., -5.)).is_empty());
+    }
+
+    #[test]
+    fn offset_z_only_affects_sorting() {
+        let transform = GlobalTransform::from_xyz(10., 20., 3.);
+        let occluder = Occluder2d::rectangle(10., 10.);
+        let offset = occluder.with_offset(vec3(0., 0., 5.));
+
+        assert_eq!(occluder.sorting_z(&transform), 3.);
+        assert_eq!(offset.sorting_z(&transform), 8.);
+
+        assert_eq!(
+            occluder.debug_vertices(&transform),
+            offset.debug_vertices(&transform)
+        );
+
+        // a shadow layer overrides the offset
+        let layered = offset.with_shadow_layer(-1.);
+        assert_eq!(layered.sorting_z(&transform), -1.);
+        assert_eq!(
+            occluder.debug_vertices(&transform),
+            layered.debug_vertices(&transform)
+        );
+    }
+
+    #[test]
+    fn offset_xy_moves_geometry() {
+        let transform = GlobalTransform::from_xyz(10., 20., 3.);
+        let occluder = Occluder2d::rectangle(10., 10.);
+        let offset = occluder.with_offset(vec3(30., 0., 5.));
+
+        let (a, b) = (vec2(40., 0.), vec2(40., 40.));
+        assert!(!occluder.blocks_segment(a, b, &transform));
+        assert!(offset.blocks_segment(a, b, &transform));
+        assert_eq!(offset.sorting_z(&transform), 8.);
+    }
+
+    #[test]
+    fn mesh_outline_fills_holes() {
+        // a square ring, with each side split into two triangles
+        let positions = [
+            vec2(0., 0.),
+            vec2(3., 0.),
+            vec2(3., 3.),
+            vec2(0., 3.),
+            vec2(1., 1.),
+            vec2(2., 1.),
+            vec2(2., 2.),
+            vec2(1., 2.),
+        ];
+        let indices = [
+            0, 1, 5, 0, 5, 4, // bottom
+            1, 2, 6, 1, 6, 5, // right
+            2, 3, 7, 2, 7, 6, // top
+            3, 0, 4, 3, 4, 7, // left
+        ];
+
+        let outline = mesh_outline(&positions, &indices).unwrap();
+        assert_eq!(outline.len(), 4);
+        assert_eq!(polygon_area(&outline).abs(), 9.);
+    }
+
+    #[test]
+    fn disconnected_mesh_uses_convex_hull() {
+        let positions = [
+            vec2(0., 0.),
+            vec2(1., 0.),
+            vec2(0., 1.),
+            vec2(5., 0.),
+            vec2(6., 0.),
+            vec2(5., 1.),
+        ];
+        let indices = [0, 1, 2, 3, 4, 5];
+
+        assert!(mesh_outline(&positions, &indices).is_none());
+
+        let mesh = Mesh::new(PrimitiveTopology::TriangleList, default())
+            .with_inserted_attribute(
+                Mesh::ATTRIBUTE_POSITION,
+                positions.map(|p| [p.x, p.y, 0.]).to_vec(),
+            )
+            .with_inserted_indices(bevy::mesh::Indices::U32(indices.map(|i| i as u32).to_vec()));
+
+        let occluder = Occluder2d::from_mesh(&mesh).unwrap();
+        let Occluder2dShape::Polygon { vertices, .. } = occluder.shape() else {
+            panic!("expected a polygon");
+        };
+
+        // the hull spans both triangles
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(polygon_area(vertices).abs(), 5.5);
+    }
+
+    fn polygon_vertices(occluder: &Occluder2d) -> &Vec<Vec2> {
+        match occluder.shape() {
+            Occluder2dShape::Polygon { vertices, .. } => vertices,
+            _ => panic!("expected a polygon"),
+        }
+    }
+
+    // number of pieces covering the point, which shouldn't be on any of their edges
+    fn coverage(pieces: &[Occluder2d], p: Vec2) -> usize {
+        pieces
+            .iter()
+            .map(polygon_vertices)
+            .filter(|vertices| {
+                let aabb = Aabb2d::from_point_cloud(Isometry2d::IDENTITY, vertices);
+                point_inside_poly(p, vertices, aabb, false)
+            })
+            .count()
+    }
+
+    fn assert_convex(vertices: &[Vec2]) {
+        let n = vertices.len();
+        let turns = (0..n).map(|i| {
+            (vertices[(i + 1) % n] - vertices[i]).perp_dot(vertices[(i + 2) % n] - vertices[i])
+        });
+        assert!(
+            turns.clone().all(|turn| turn >= -1e-4) || turns.clone().all(|turn| turn <= 1e-4),
+            "piece isn't convex: {vertices:?}"
+        );
+    }
+
+    #[test]
+    fn square_with_hole() {
+        let outer = vec![vec2(0., 0.), vec2(10., 0.), vec2(10., 10.), vec2(0., 10.)];
+        let hole = vec![vec2(4., 4.), vec2(6., 4.), vec2(6., 6.), vec2(4., 6.)];
+
+        let pieces = Occluder2d::polygon_with_holes(outer, vec![hole]).unwrap();
+        assert!(pieces.len() >= 2);
+
+        let area: f32 = pieces
+            .iter()
+            .map(|piece| polygon_area(polygon_vertices(piece)).abs())
+            .sum();
+        assert!((area - 96.).abs() < 1e-3);
+
+        for piece in &pieces {
+            assert_convex(polygon_vertices(piece));
+        }
+
+        assert_eq!(coverage(&pieces, vec2(5.1, 4.9)), 0);
+        assert_eq!(coverage(&pieces, vec2(1.3, 2.7)), 1);
+        assert_eq!(coverage(&pieces, vec2(8.9, 7.3)), 1);
+        assert_eq!(coverage(&pieces, vec2(5.2, 9.1)), 1);
+        assert_eq!(coverage(&pieces, vec2(11., 5.)), 0);
+    }
+
+    #[test]
+    fn stone_arch() {
+        // an arch with a window above its opening
+        let outer = vec![
+            vec2(-10., 0.),
+            vec2(-10., 20.),
+            vec2(10., 20.),
+            vec2(10., 0.),
+            vec2(5., 0.),
+            vec2(5., 10.),
+            vec2(-5., 10.),
+            vec2(-5., 0.),
+        ];
+        let window = vec![vec2(-2., 13.), vec2(2., 13.), vec2(2., 17.), vec2(-2., 17.)];
+
+        let pieces = Occluder2d::polygon_with_holes(outer, vec![window]).unwrap();
+
+        let area: f32 = pieces
+            .iter()
+            .map(|piece| polygon_area(polygon_vertices(piece)).abs())
+            .sum();
+        assert!((area - 284.).abs() < 1e-3);
+
+        for piece in &pieces {
+            assert_convex(polygon_vertices(piece));
+        }
+
+        // under the arch and through the window
+        assert_eq!(coverage(&pieces, vec2(0.3, 5.1)), 0);
+        assert_eq!(coverage(&pieces, vec2(0.3, 15.1)), 0);
+
+        // legs, sides of the window and top
+        assert_eq!(coverage(&pieces, vec2(-7.3, 5.1)), 1);
+        assert_eq!(coverage(&pieces, vec2(7.3, 5.1)), 1);
+        assert_eq!(coverage(&pieces, vec2(-3.7, 15.1)), 1);
+        assert_eq!(coverage(&pieces, vec2(3.7, 15.1)), 1);
+        assert_eq!(coverage(&pieces, vec2(0.3, 18.6)), 1);
+        assert_eq!(coverage(&pieces, vec2(0.3, 11.4)), 1);
+    }
+
+    #[test]
+    fn holes_outside_are_ignored() {
+        let outer = vec![vec2(0., 0.), vec2(10., 0.), vec2(10., 10.), vec2(0., 10.)];
+        let hole = vec![vec2(20., 4.), vec2(22., 4.), vec2(22., 6.), vec2(20., 6.)];
+
+        let pieces = Occluder2d::polygon_with_holes(outer, vec![hole]).unwrap();
+        let area: f32 = pieces
+            .iter()
+            .map(|piece| polygon_area(polygon_vertices(piece)).abs())
+            .sum();
+        assert!((area - 100.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn decimate_keeps_corners() {
+        // a square with 10 points on each side
+        let vertices: Vec<Vec2> = (0..40)
+            .map(|i| {
+                let t = (i % 10) as f32;
+                match i / 10 {
+                    0 => vec2(t, 0.),
+                    1 => vec2(10., t),
+                    2 => vec2(10. - t, 10.),
+                    _ => vec2(0., 10. - t),
+                }
+            })
+            .collect();
+
+        let mut decimated = decimate(vertices, 4);
+        decimated.sort_by_key(|v| (FloatOrd(v.x), FloatOrd(v.y)));
+
+        assert_eq!(
+            decimated,
+            vec![vec2(0., 0.), vec2(0., 10.), vec2(10., 0.), vec2(10., 10.)]
+        );
+    }
+
+    #[test]
+    fn decimate_respects_budget() {
+        let circle: Vec<Vec2> = (0..1000)
+            .map(|i| Vec2::from_angle(i as f32 / 1000. * std::f32::consts::TAU) * 100.)
+            .collect();
+
+        let decimated = decimate(circle.clone(), 32);
+        assert_eq!(decimated.len(), 32);
+
+        // the decimated circle keeps most of the area
+        let ratio = polygon_area(&decimated) / polygon_area(&circle);
+        assert!(ratio > 0.95 && ratio <= 1., "{ratio}");
+
+        let occluder = Occluder2d::polygon_decimated(circle, 2).unwrap();
+        assert_eq!(polygon_vertices(&occluder).len(), 3);
+    }
+
+    // parses a grid of solid tiles given from the top row down, returning it bottom row first with its columns
+    fn tile_grid(rows: &[&str]) -> (Vec<bool>, usize) {
+        let solid = rows
+            .iter()
+            .rev()
+            .flat_map(|row| row.chars().map(|c| c == '#'))
+            .collect();
+        (solid, rows[0].len())
+    }
+
+    // every solid tile as its own rect
+    fn tile_rects(solid: &[bool], columns: usize, tile_size: Vec2) -> Vec<Rect> {
+        solid
+            .iter()
+            .enumerate()
+            .filter(|(_, solid)| **solid)
+            .map(|(i, _)| {
+                let tile = vec2((i % columns) as f32, (i / columns) as f32);
+                Rect::from_corners(tile * tile_size, (tile + 1.) * tile_size)
+            })
+            .collect()
+    }
+
+    // points inside each tile of the grid and of a border of empty tiles around it, away from any tile edges
+    fn tile_samples(solid: &[bool], columns: usize, tile_size: Vec2) -> Vec<Vec2> {
+        let rows = solid.len().div_ceil(columns) as i32;
+        let mut samples = vec![];
+        for y in -1..=rows {
+            for x in -1..=columns as i32 {
+                for offset in [
+                    vec2(0.25, 0.25),
+                    vec2(0.75, 0.25),
+                    vec2(0.25, 0.75),
+                    vec2(0.75, 0.75),
+                ] {
+                    samples.push((vec2(x as f32, y as f32) + offset) * tile_size);
+                }
+            }
+        }
+        samples
+    }
+
+    fn rect_coverage(rects: &[Rect], p: Vec2) -> usize {
+        rects.iter().filter(|rect| rect.contains(p)).count()
+    }
+
+    fn occluder_coverage(occluders: &[Occluder2d], p: Vec2) -> usize {
+        occluders
+            .iter()
+            .filter(|occluder| {
+                let Occluder2dShape::RoundRectangle {
+                    half_width,
+                    half_height,
+                    radius,
+                } = *occluder.shape()
+                else {
+                    panic!("expected a rectangle");
+                };
+                assert_eq!(radius, 0.);
+
+                let distance = (p - occluder.offset.xy()).abs();
+                distance.x <= half_width && distance.y <= half_height
+            })
+            .count()
+    }
+
+    fn assert_same_coverage(rows: &[&str]) {
+        let tile_size = vec2(16., 8.);
+        let (solid, columns) = tile_grid(rows);
+        let tiles = tile_rects(&solid, columns, tile_size);
+
+        let rects = grid_rects(&solid, columns, tile_size);
+        let merged_tiles = Occluder2d::merge_rects(&tiles);
+        let merged_rects = Occluder2d::merge_rects(&rects);
+
+        assert!(rects.len() <= tiles.len());
+        assert!(merged_tiles.len() <= tiles.len());
+        assert!(merged_rects.len() <= rects.len());
+
+        for p in tile_samples(&solid, columns, tile_size) {
+            let covered = rect_coverage(&tiles, p) > 0;
+
+            // grid rects cover every solid tile exactly once
+            assert_eq!(
+                rect_coverage(&rects, p),
+                covered as usize,
+                "grid rects at {p}"
+            );
+            assert_eq!(
+                occluder_coverage(&merged_tiles, p) > 0,
+                covered,
+                "merged tiles at {p}"
+            );
+            assert_eq!(
+                occluder_coverage(&merged_rects, p) > 0,
+                covered,
+                "merged rects at {p}"
+            );
+        }
+    }
+
+    #[test]
+    fn merge_l_shaped_grid() {
+        assert_same_coverage(&["#..", "#..", "###"]);
+        assert_eq!(
+            grid_rects(&tile_grid(&["#..", "#..", "###"]).0, 3, Vec2::ONE).len(),
+            2
+        );
+    }
+
+    #[test]
+    fn merge_disjoint_grid() {
+        assert_same_coverage(&["#...#", "##..#", "##.##"]);
+    }
+
+    #[test]
+    fn merge_checkerboard_grid() {
+        // no two tiles share an edge, so nothing can be merged
+        assert_same_coverage(&["#.#.#", ".#.#.", "#.#.#"]);
+
+        let (solid, columns) = tile_grid(&["#.#.#", ".#.#.", "#.#.#"]);
+        let tiles = tile_rects(&solid, columns, Vec2::ONE);
+        assert_eq!(Occluder2d::merge_rects(&tiles).len(), tiles.len());
+    }
+
+    #[test]
+    fn merge_full_grid_into_one_rect() {
+        assert_same_coverage(&["####", "####"]);
+
+        let (solid, columns) = tile_grid(&["####", "####"]);
+        let merged = Occluder2d::merge_rects(&tile_rects(&solid, columns, Vec2::ONE));
+        assert_eq!(merged.len(), 1);
+    }
+
+    // builds an index with the default cell size of 128 from the given occluders
+    fn spatial_index(
+        occluders: Vec<(Occluder2d, Transform)>,
+    ) -> (OccluderSpatialIndex, Vec<Entity>) {
+        let mut world = World::new();
+        let mut index = OccluderSpatialIndex::default();
+
+        let entities = occluders
+            .into_iter()
+            .map(|(occluder, transform)| {
+                let entity = world.spawn_empty().id();
+                index.insert(entity, &occluder, &GlobalTransform::from(transform));
+                entity
+            })
+            .collect();
+
+        (index, entities)
+    }
+
+    #[test]
+    fn index_rotated_offset_rectangle() {
+        // the offset isn't rotated, so the rectangle is centered on a cell border at (128, 50)
+        let occluder = Occluder2d::rectangle(40., 10.).with_offset(vec3(100., 0., 0.));
+        let transform = Transform::from_xyz(28., 50., 0.)
+            .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2));
+
+        let (index, entities) = spatial_index(vec![(occluder, transform)]);
+        let world = |local: Vec2| vec2(128., 50.) + Rot2::degrees(90.) * local;
+
+        for inside in [
+            vec2(15., 0.),
+            vec2(-15., 0.),
+            vec2(-18., 4.),
+            vec2(18., -4.),
+        ] {
+            assert_eq!(
+                index.occluder_at(world(inside)),
+                Some(entities[0]),
+                "{inside}"
+            );
+        }
+        for outside in [vec2(0., 8.), vec2(22., 0.), vec2(-22., 0.)] {
+            assert_eq!(index.occluder_at(world(outside)), None, "{outside}");
+        }
+
+        // it would cover this if it weren't rotated
+        assert_eq!(index.occluder_at(vec2(145., 50.)), None);
+    }
+
+    #[test]
+    fn index_rotated_round_rectangle() {
+        let pos = vec2(300., 300.);
+        let rot = Rot2::degrees(45.);
+        let occluder = Occluder2d::round_rectangle(20., 10., 5.);
+        let transform = Transform::from_translation(pos.extend(0.))
+            .with_rotation(Quat::from_rotation_z(rot.as_radians()));
+
+        let (index, entities) = spatial_index(vec![(occluder, transform)]);
+        let world = |local: Vec2| pos + rot * local;
+
+        for inside in [vec2(0., 0.), vec2(14., 5.), vec2(10., 9.), vec2(-13., -7.)] {
+            assert_eq!(
+                index.occluder_at(world(inside)),
+                Some(entities[0]),
+                "{inside}"
+            );
+        }
+        // past the rounded corners, but inside the bounding rectangle
+        for outside in [vec2(14.5, 9.5), vec2(-14.5, -9.5), vec2(16., 0.)] {
+            assert_eq!(index.occluder_at(world(outside)), None, "{outside}");
+        }
+    }
+
+    #[test]
+    fn index_concave_polygon() {
+        // a U shape, open towards +y
+        let occluder = Occluder2d::polygon([
+            vec2(0., 0.),
+            vec2(30., 0.),
+            vec2(30., 30.),
+            vec2(20., 30.),
+            vec2(20., 10.),
+            vec2(10., 10.),
+            vec2(10., 30.),
+            vec2(0., 30.),
+        ])
+        .unwrap();
+        let pos = vec2(200., -100.);
+        let rot = Rot2::degrees(90.);
+        let transform = Transform::from_translation(pos.extend(0.))
+            .with_rotation(Quat::from_rotation_z(rot.as_radians()));
+
+        let (index, entities) = spatial_index(vec![(occluder, transform)]);
+        let world = |local: Vec2| pos + rot * local;
+
+        for inside in [vec2(5., 20.), vec2(25., 20.), vec2(15., 5.)] {
+            assert_eq!(
+                index.occluder_at(world(inside)),
+                Some(entities[0]),
+                "{inside}"
+            );
+        }
+        for outside in [vec2(15., 20.), vec2(15., 29.), vec2(-5., 5.)] {
+            assert_eq!(index.occluder_at(world(outside)), None, "{outside}");
+        }
+    }
+
+    #[test]
+    fn index_large_occluders() {
+        let (mut index, entities) = spatial_index(vec![
+            (Occluder2d::rectangle(2000., 2000.), Transform::default()),
+            (
+                Occluder2d::circle(10.),
+                Transform::from_xyz(900., -900., 1.),
+            ),
+        ]);
+
+        assert_eq!(index.large, vec![entities[0]]);
+        assert!(index.occluders[&entities[1]].cells.is_some());
+
+        assert_eq!(index.occluder_at(vec2(-900., 900.)), Some(entities[0]));
+        assert_eq!(index.occluder_at(vec2(1100., 0.)), None);
+
+        // the circle is above the large occluder
+        assert_eq!(index.occluder_at(vec2(900., -900.)), Some(entities[1]));
+        let mut both = index.occluders_at(vec2(900., -900.));
+        both.sort();
+        let mut expected = entities.clone();
+        expected.sort();
+        assert_eq!(both, expected);
+
+        index.remove(entities[0]);
+        assert!(index.large.is_empty());
+        assert_eq!(index.occluder_at(vec2(-900., 900.)), None);
+        assert_eq!(index.occluder_at(vec2(900., -900.)), Some(entities[1]));
+    }
+
+    #[test]
+    fn index_removal() {
+        let (mut index, entities) = spatial_index(vec![
+            (Occluder2d::rectangle(300., 20.), Transform::default()),
+            (
+                Occluder2d::rectangle(20., 20.),
+                Transform::from_xyz(100., 0., -1.),
+            ),
+        ]);
+
+        assert_eq!(index.occluder_at(vec2(100., 0.)), Some(entities[0]));
+
+        index.remove(entities[0]);
+        assert_eq!(index.occluder_at(vec2(100., 0.)), Some(entities[1]));
+        assert_eq!(index.occluder_at(vec2(-100., 0.)), None);
+
+        // removing twice is fine, and no empty cells are left behind
+        index.remove(entities[0]);
+        index.remove(entities[1]);
+        assert!(index.cells.is_empty());
+        assert!(index.occluders.is_empty());
+        assert_eq!(index.occluder_at(vec2(100., 0.)), None);
+    }
+
+    fn assert_near(a: Vec2, b: Vec2) {
+        assert!(a.distance(b) < 1e-3, "{a} != {b}");
+    }
+
+    #[test]
+    fn debug_vertices_close_polygons() {
+        let occluder =
+            Occluder2d::polygon([vec2(0., 0.), vec2(10., 0.), vec2(10., 5.), vec2(0., 8.)])
+                .unwrap()
+                .with_offset(vec3(5., 0., 0.));
+        let transform = GlobalTransform::from(
+            Transform::from_xyz(10., 20., 0.)
+                .with_rotation(Quat::from_rotation_z(std::f32::consts::FRAC_PI_2)),
+        );
+
+        let local = polygon_vertices(&occluder);
+        let outline = occluder.debug_vertices(&transform);
+
+        assert_eq!(outline.len(), local.len() + 1);
+        assert_eq!(outline.first(), outline.last());
+
+        // the offset moves the occluder without being rotated
+        let center = vec2(15., 20.);
+        for (point, vertex) in outline.iter().zip(local) {
+            assert_near(*point, center + Rot2::degrees(90.) * *vertex);
+        }
+    }
+
+    #[test]
+    fn debug_vertices_sharp_rectangle() {
+        let occluder = Occluder2d::rectangle(20., 10.);
+        let transform = GlobalTransform::from_xyz(-50., 30., 0.);
+
+        let outline = occluder.debug_vertices(&transform);
+
+        assert_eq!(outline.len(), 5);
+        assert_eq!(outline.first(), outline.last());
+        for (point, corner) in outline.iter().zip([
+            vec2(10., 5.),
+            vec2(-10., 5.),
+            vec2(-10., -5.),
+            vec2(10., -5.),
+        ]) {
+            assert_near(*point, vec2(-50., 30.) + corner);
+        }
+    }
+
+    #[test]
+    fn debug_vertices_tessellate_round_rectangles() {
+        let occluder = Occluder2d::round_rectangle(20., 10., 2.).with_offset(vec3(0., 3., 0.));
+        let rot = Rot2::degrees(30.);
+        let transform = GlobalTransform::from(
+            Transform::from_xyz(100., 0., 0.)
+                .with_rotation(Quat::from_rotation_z(rot.as_radians())),
+        );
+
+        let outline = occluder.debug_vertices(&transform);
+
+        // 4 corners of 8 segments each, and the closing point
+        assert_eq!(outline.len(), 4 * 9 + 1);
+        assert_eq!(outline.first(), outline.last());
+
+        let local: Vec<Vec2> = outline
+            .iter()
+            .map(|point| rot.inverse() * (*point - vec2(100., 3.)))
+            .collect();
+
+        let half_size = vec2(10., 5.);
+        for point in &local {
+            let distance = point.distance(point.clamp(-half_size, half_size));
+            assert!((distance - 2.).abs() < 1e-3, "{point} is {distance} away");
+        }
+
+        // the outline reaches the middle of every side
+        let max = local.iter().fold(Vec2::MIN, |max, point| max.max(*point));
+        let min = local.iter().fold(Vec2::MAX, |min, point| min.min(*point));
+        assert_near(max, vec2(12., 7.));
+        assert_near(min, vec2(-12., -7.));
+
+        // and no segment skips over a corner
+        for pair in outline.windows(2) {
+            assert!(pair[0].distance(pair[1]) <= 10. * 2. + 1e-3);
+        }
+    }
+
+    // rotated a quarter turn, so shapes along the x axis end up along the y axis, around (110, 50)
+    fn rotated_offset(occluder: Occluder2d) -> (Occluder2d, GlobalTransform) {
+        let transform = Transform::from_xyz(100., 50., 0.)
+            .with_rotation(Quat::from_rotation_z(f32::consts::FRAC_PI_2));
+
+        (occluder.with_offset(vec3(10., 0., 0.)), transform.into())
+    }
+
+    #[test]
+    fn blocks_segment_rotated_polygon() {
+        let (occluder, transform) = rotated_offset(
+            Occluder2d::polygon(vec![
+                vec2(-20., -2.),
+                vec2(20., -2.),
+                vec2(20., 2.),
+                vec2(-20., 2.),
+            ])
+            .unwrap(),
+        );
+
+        // covers x in [108, 112] and y in [30, 70]
+        assert!(occluder.blocks_segment(vec2(90., 60.), vec2(130., 60.), &transform));
+        assert!(occluder.blocks_segment(vec2(105., 60.), vec2(115., 60.), &transform));
+        assert!(occluder.blocks_segment(vec2(109., 40.), vec2(111., 41.), &transform));
+
+        assert!(!occluder.blocks_segment(vec2(90., 75.), vec2(130., 75.), &transform));
+        assert!(!occluder.blocks_segment(vec2(125., 0.), vec2(125., 100.), &transform));
+
+        // would be blocked without the offset
+        assert!(!occluder.blocks_segment(vec2(99., 40.), vec2(101., 41.), &transform));
+    }
+
+    #[test]
+    fn blocks_segment_rotated_polyline() {
+        let points = [vec2(-20., 0.), vec2(20., 0.)];
+
+        // along x = 110, with y in [30, 70]
+        let (occluder, transform) = rotated_offset(Occluder2d::polyline(points).unwrap());
+
+        assert!(occluder.blocks_segment(vec2(90., 60.), vec2(130., 60.), &transform));
+        assert!(occluder.blocks_segment(vec2(130., 60.), vec2(90., 60.), &transform));
+        assert!(!occluder.blocks_segment(vec2(90., 75.), vec2(130., 75.), &transform));
+        assert!(!occluder.blocks_segment(vec2(125., 0.), vec2(125., 100.), &transform));
+        assert!(!occluder.blocks_segment(vec2(95., 60.), vec2(105., 60.), &transform));
+
+        // the front side points to -x after the rotation
+        let (occluder, transform) =
+            rotated_offset(Occluder2d::polyline_oriented(points, false).unwrap());
+
+        assert!(occluder.blocks_segment(vec2(90., 60.), vec2(130., 60.), &transform));
+        assert!(!occluder.blocks_segment(vec2(130., 60.), vec2(90., 60.), &transform));
+    }
+
+    #[test]
+    fn blocks_segment_rotated_capsule() {
+        // covers x in [108, 112] and y in [28, 72], with round ends centered on (110, 30) and (110, 70)
+        let (occluder, transform) = rotated_offset(Occluder2d::horizontal_capsule(40., 2.));
+
+        assert!(occluder.blocks_segment(vec2(90., 60.), vec2(130., 60.), &transform));
+        assert!(occluder.blocks_segment(vec2(90., 71.), vec2(130., 71.), &transform));
+        assert!(!occluder.blocks_segment(vec2(90., 73.), vec2(130., 73.), &transform));
+        assert!(!occluder.blocks_segment(vec2(125., 0.), vec2(125., 100.), &transform));
+
+        // near the corner of the bounding box, but outside of the round end
+        assert!(occluder.blocks_segment(vec2(110.5, 71.5), vec2(130., 71.5), &transform));
+        assert!(!occluder.blocks_segment(vec2(111.5, 71.5), vec2(130., 71.5), &transform));
+    }
+
+    fn polyline_vertices(occluder: &Occluder2d) -> &[Vec2] {
+        match occluder.shape() {
+            Occluder2dShape::Polyline {
+                vertices,
+                double_sided: true,
+            } => polyline_points(vertices),
+            _ => panic!("expected a double-sided polyline"),
+        }
+    }
+
+    fn square() -> [Vec2; 4] {
+        [vec2(0., 0.), vec2(0., 10.), vec2(10., 10.), vec2(10., 0.)]
+    }
+
+    #[test]
+    fn polygon_removes_duplicate_points() {
+        let [a, b, c, d] = square();
+        let expected = Occluder2d::polygon(square()).unwrap();
+
+        let repeated = Occluder2d::polygon(vec![a, a, b, c, c, c, d, a]).unwrap();
+        assert_eq!(polygon_vertices(&repeated).len(), 4);
+        assert!(repeated.shape() == expected.shape());
+
+        let with_opacity =
+            Occluder2d::polygon_with_opacity(vec![(a, 1.), (b, 0.5), (b, 0.5), (c, 0.), (d, 1.)])
+                .unwrap();
+        match with_opacity.shape() {
+            Occluder2dShape::Polygon {
+                vertices,
+                opacities,
+                ..
+            } => {
+                assert_eq!(vertices.len(), 4);
+                assert_eq!(opacities.len(), 4);
+            }
+            _ => panic!("expected a polygon"),
+        }
+    }
+
+    #[test]
+    fn polygon_keeps_collinear_points_on_edges() {
+        let [a, b, c, d] = square();
+        let occluder = Occluder2d::polygon(vec![a, vec2(0., 5.), b, c, d]).unwrap();
+
+        assert_eq!(polygon_vertices(&occluder).len(), 5);
+        assert_eq!(polygon_area(polygon_vertices(&occluder)).abs(), 100.);
+    }
+
+    #[test]
+    fn collinear_polygon_becomes_polyline() {
+        let occluder = Occluder2d::polygon(vec![
+            vec2(0., 0.),
+            vec2(5., 5.),
+            vec2(10., 10.),
+            vec2(2., 2.),
+        ])
+        .unwrap();
+
+        let mut ends = polyline_vertices(&occluder).to_vec();
+        ends.sort_by(|a, b| a.x.total_cmp(&b.x));
+        assert_eq!(ends, vec![vec2(0., 0.), vec2(10., 10.)]);
+    }
+
+    #[test]
+    fn two_point_polygon_becomes_polyline() {
+        let (a, b) = (vec2(0., 0.), vec2(10., 5.));
+
+        for vertices in [vec![a, b], vec![a, b, a], vec![a, a, b, b]] {
+            let occluder = Occluder2d::polygon(vertices).unwrap();
+
+            let mut ends = polyline_vertices(&occluder).to_vec();
+            ends.sort_by(|a, b| a.x.total_cmp(&b.x));
+            assert_eq!(ends, vec![a, b]);
+        }
+
+        assert!(Occluder2d::polygon(vec![a]).is_none());
+        assert!(Occluder2d::polygon(vec![a, a, a]).is_none());
+    }
+
+    #[test]
+    fn set_shape_normalizes_polygons() {
+        let [a, b, c, d] = square();
+        let expected = Occluder2d::polygon(square()).unwrap();
+
+        // counter-clockwise, with a repeated point and a stale concave flag
+        let mut occluder = Occluder2d::circle(5.);
+        assert!(occluder.set_shape(Occluder2dShape::Polygon {
+            vertices: vec![d, c, c, b, a],
+            concave: true,
+            opacities: vec![],
+        }));
+        assert!(occluder.shape() == expected.shape());
+
+        // vertex opacities are kept along with their points
+        assert!(occluder.set_shape(Occluder2dShape::Polygon {
+            vertices: vec![d, c, b, a],
+            concave: false,
+            opacities: vec![1., 0.5, 0., 0.25],
+        }));
+        let expected =
+            Occluder2d::polygon_with_opacity(vec![(d, 1.), (c, 0.5), (b, 0.), (a, 0.25)]).unwrap();
+        assert!(occluder.shape() == expected.shape());
+
+        // degenerate polygons become polylines
+        assert!(occluder.set_shape(Occluder2dShape::Polygon {
+            vertices: vec![a, b, a],
+            concave: false,
+            opacities: vec![],
+        }));
+        let expected = Occluder2d::polygon(vec![a, b, a]).unwrap();
+        assert!(occluder.shape() == expected.shape());
+        assert!(matches!(occluder.shape(), Occluder2dShape::Polyline { .. }));
+    }
+
+    #[test]
+    fn set_shape_rejects_invalid_shapes() {
+        let [a, b, c, d] = square();
+        let mut occluder = Occluder2d::polygon(square()).unwrap();
+        let shape = occluder.shape().clone();
+
+        assert!(!occluder.set_shape(Occluder2dShape::Polygon {
+            vertices: vec![a, b, c, d],
+            concave: false,
+            opacities: vec![1., 0.5],
+        }));
+        assert!(!occluder.set_shape(Occluder2dShape::Polygon {
+            vertices: vec![a, a],
+            concave: false,
+            opacities: vec![],
+        }));
+        assert!(!occluder.set_shape(Occluder2dShape::Polyline {
+            vertices: vec![],
+            double_sided: true,
+        }));
+
+        assert!(*occluder.shape() == shape);
+    }
+
+    #[test]
+    fn set_shape_accepts_polyline_points_and_shapes() {
+        let points = vec![vec2(0., 0.), vec2(10., 0.), vec2(10., 10.), vec2(20., 10.)];
+        let expected = Occluder2d::polyline_oriented(points.clone(), false).unwrap();
+
+        let mut occluder = Occluder2d::circle(5.);
+        assert!(occluder.set_shape(Occluder2dShape::Polyline {
+            vertices: points,
+            double_sided: false,
+        }));
+        assert!(occluder.shape() == expected.shape());
+
+        // an existing polyline's mirrored vertices aren't mirrored again
+        assert!(occluder.set_shape(expected.shape().clone()));
+        assert!(occluder.shape() == expected.shape());
+
+        let two_points = Occluder2d::polyline(vec![vec2(0., 0.), vec2(5., 5.)]).unwrap();
+        assert!(occluder.set_shape(two_points.shape().clone()));
+        assert!(occluder.shape() == two_points.shape());
+    }
+
+    fn thick_polyline_area(points: &[Vec2], thickness: f32) -> f32 {
+        let occluder = Occluder2d::thick_polyline(points, thickness).unwrap();
+        polygon_area(polygon_vertices(&occluder)).abs()
+    }
+
+    fn has_vertex(occluder: &Occluder2d, vertex: Vec2) -> bool {
+        polygon_vertices(occluder)
+            .iter()
+            .any(|v| v.distance(vertex) < 1e-3)
+    }
+
+    #[test]
+    fn thick_polyline_straight_segment() {
+        let area = thick_polyline_area(&[vec2(0., 0.), vec2(30., 0.)], 4.);
+        assert!((area - 30. * 4.).abs() < 1e-3);
+
+        // extra points along the line don't change it
+        let area = thick_polyline_area(&[vec2(0., 0.), vec2(10., 0.), vec2(30., 0.)], 4.);
+        assert!((area - 30. * 4.).abs() < 1e-3);
+
+        let diagonal = thick_polyline_area(&[vec2(0., 0.), vec2(30., 40.)], 4.);
+        assert!((diagonal - 50. * 4.).abs() < 1e-3);
+
+        assert!(Occluder2d::thick_polyline([vec2(0., 0.), vec2(0., 0.)], 4.).is_none());
+        assert!(Occluder2d::thick_polyline([vec2(0., 0.), vec2(30., 0.)], 0.).is_none());
+    }
+
+    #[test]
+    fn thick_polyline_mitered_corner() {
+        let points = [vec2(0., 0.), vec2(10., 0.), vec2(10., 10.)];
+        let occluder = Occluder2d::thick_polyline(points, 2.).unwrap();
+
+        assert_eq!(polygon_vertices(&occluder).len(), 6);
+        assert!(has_vertex(&occluder, vec2(9., 1.)));
+        assert!(has_vertex(&occluder, vec2(11., -1.)));
+
+        // a right-angled miter adds as much area on the outside as it removes on the inside
+        assert!((thick_polyline_area(&points, 2.) - 20. * 2.).abs() < 1e-3);
+
+        // very sharp corners are clamped to the miter limit
+        let sharp = [vec2(0., 0.), vec2(10., 0.), vec2(0., 1.)];
+        let occluder = Occluder2d::thick_polyline(sharp, 2.).unwrap();
+        assert!(
+            polygon_vertices(&occluder)
+                .iter()
+                .all(|v| sharp.iter().any(|p| p.distance(*v) <= 4. + 1e-3))
+        );
+    }
+
+    #[test]
+    fn thick_polyline_u_turn() {
+        let points = [vec2(0., 0.), vec2(10., 0.), vec2(10., 4.), vec2(0., 4.)];
+        assert!((thick_polyline_area(&points, 2.) - 24. * 2.).abs() < 1e-3);
+
+        // turning back on itself, the line covers its single leg without sticking out
+        let points = [vec2(0., 0.), vec2(10., 0.), vec2(0., 0.)];
+        let occluder = Occluder2d::thick_polyline(points, 2.).unwrap();
+
+        assert!((thick_polyline_area(&points, 2.) - 10. * 2.).abs() < 1e-3);
+        assert!(polygon_vertices(&occluder).iter().all(|v| {
+            v.is_finite() && (-1e-3..=10. + 1e-3).contains(&v.x) && v.y.abs() <= 1. + 1e-3
+        }));
+    }
 }
@@ -11,7 +11,7 @@ use bevy::render::render_phase::{
 };
 use bevy::render::render_resource::CachedRenderPipelineId;
 use bevy::render::sync_world::MainEntity;
-use bevy::render::view::ExtractedView;
+use bevy::render::view::{ExtractedView, RetainedViewEntity};
 use indexmap::IndexMap;
 
 /// Binned Render Phase that uses lights to render the lightmap texture.
@@ -22,6 +22,15 @@ pub struct LightmapPhase {
     pub extra_index: PhaseItemExtraIndex,
 }
 
+/// The view under which the [`LightmapPhase`] of one of a camera's [extra light channels](crate::prelude::FireflyConfig::extra_channels) is stored.
+pub(crate) fn light_channel_view(view: RetainedViewEntity, channel: u8) -> RetainedViewEntity {
+    // the camera's own lightmap uses the first subview index
+    RetainedViewEntity {
+        subview_index: channel as u32 + 1,
+        ..view
+    }
+}
+
 /// Sorted Render Phase that uses sprites to render the stencil and normal textures.
 pub struct SpritePhase {
     pub sort_key: FloatOrd,
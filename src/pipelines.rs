@@ -1,6 +1,12 @@
 //! Module containing the custom `Render Pipelines` used by Firefly.
 
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
 use bevy::{
     asset::{embedded_asset, load_embedded_asset},
@@ -8,19 +14,21 @@ use bevy::{
     mesh::{PrimitiveTopology, VertexBufferLayout, VertexFormat},
     prelude::*,
     render::{
-        RenderApp, RenderStartup,
+        Render, RenderApp, RenderStartup, RenderSystems,
         render_resource::{
             BindGroupLayoutDescriptor, BindGroupLayoutEntries, BlendComponent, BlendFactor,
-            BlendOperation, BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites,
-            FilterMode, FragmentState, FrontFace, MultisampleState, PolygonMode, PrimitiveState,
-            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderStages,
-            SpecializedRenderPipeline, SpecializedRenderPipelines, TextureFormat,
-            TextureSampleType, VertexAttribute, VertexState, VertexStepMode,
+            BlendOperation, BlendState, CachedPipelineState, CachedRenderPipelineId,
+            ColorTargetState, ColorWrites, FilterMode, FragmentState, FrontFace, MultisampleState,
+            PipelineCache, PolygonMode, PrimitiveState, RenderPipelineDescriptor, Sampler,
+            SamplerBindingType, SamplerDescriptor, ShaderStages, SpecializedRenderPipeline,
+            SpecializedRenderPipelines, TextureFormat, TextureSampleType, VertexAttribute,
+            VertexState, VertexStepMode,
             binding_types::{
                 sampler, storage_buffer_read_only, texture_2d, texture_2d_array, uniform_buffer,
             },
         },
         renderer::RenderDevice,
+        settings::WgpuFeatures,
         view::{
             COLOR_TARGET_FORMAT_MASK_BITS, ViewTarget, ViewUniform, texture_format_from_code,
             texture_format_to_code,
@@ -31,12 +39,16 @@ use bevy::{
 
 use crate::{
     buffers::{BinIndices, OccluderPointer},
-    data::UniformFireflyConfig,
-    lights::UniformPointLight,
-    occluders::{UniformOccluder, UniformRoundOccluder},
+    data::{FireflyConfig, UniformFireflyConfig},
+    lights::{UniformPointLight, UniformVolumetricLight},
+    occluders::{UniformOccluder, UniformRoundOccluder, UniformVertex},
 };
 
 /// Plugin that initializes various Pipelines. Added automatically by [`FireflyPlugin`](crate::prelude::FireflyPlugin).
+///
+/// The shaders are embedded in the binary. With the `hot_reload` feature, they're instead watched in the crate's
+/// source directory, and pipelines are recompiled whenever one of them is modified. A shader that fails to compile
+/// only skips the passes that use it, with an error naming the pass, until it's fixed.
 pub struct PipelinePlugin;
 
 impl Plugin for PipelinePlugin {
@@ -47,16 +59,35 @@ impl Plugin for PipelinePlugin {
         embedded_asset!(app, "shaders/create_lightmap.wgsl");
         embedded_asset!(app, "shaders/apply_lightmap.wgsl");
         embedded_asset!(app, "shaders/combine_lightmaps.wgsl");
+        embedded_asset!(app, "shaders/resolve_lightmap.wgsl");
+        embedded_asset!(app, "shaders/volumetric_lightmap.wgsl");
         embedded_asset!(app, "shaders/sprite.wgsl");
+        embedded_asset!(app, "shaders/occluder_coverage.wgsl");
+        embedded_asset!(app, "shaders/lightmap_exposure.wgsl");
+        embedded_asset!(app, "shaders/lightmap_readback.wgsl");
+
+        let ready = PipelinesReadyFlag::default();
+
+        app.init_resource::<FireflyPipelinesReady>();
+        app.insert_resource(ready.clone());
+        app.add_systems(PreUpdate, update_pipelines_ready);
 
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
+        render_app.insert_resource(ready);
+        render_app.add_systems(
+            Render,
+            check_pipelines_ready.in_set(RenderSystems::PrepareBindGroups),
+        );
+
         render_app
             .init_resource::<SpecializedRenderPipelines<LightmapCreationPipeline>>()
             .init_resource::<SpecializedRenderPipelines<LightmapApplicationPipeline>>()
             .init_resource::<SpecializedRenderPipelines<LightmapCombinationPipeline>>()
+            .init_resource::<SpecializedRenderPipelines<LightmapResolvePipeline>>()
+            .init_resource::<SpecializedRenderPipelines<LightmapVolumetricPipeline>>()
             .init_resource::<SpecializedRenderPipelines<SpritePipeline>>();
 
         render_app.add_systems(
@@ -65,12 +96,141 @@ impl Plugin for PipelinePlugin {
                 init_lightmap_creation_pipeline,
                 init_lightmap_application_pipeline,
                 init_lightmap_combination_pipeline,
+                init_lightmap_resolve_pipeline,
+                init_lightmap_volumetric_pipeline,
                 init_sprite_pipeline,
+                init_occluder_coverage_pipeline,
+                init_lightmap_exposure_pipeline,
+                init_lightmap_readback_pipeline,
             ),
         );
     }
 }
 
+/// Format of the sprite stencil texture, based on [`enable_32bit_stencils`](crate::prelude::FireflyConfig::enable_32bit_stencils).
+///
+/// Sprites are alpha blended into the stencil, so 32 bit stencils require the `FLOAT32_BLENDABLE` feature.
+/// If the device doesn't support it (e.g. most WebGPU devices), 16 bit stencils are used instead.
+pub(crate) fn stencil_format(
+    enable_32bit_stencils: bool,
+    render_device: &RenderDevice,
+) -> TextureFormat {
+    if !enable_32bit_stencils {
+        return TextureFormat::Rgba16Float;
+    }
+
+    if !render_device
+        .features()
+        .contains(WgpuFeatures::FLOAT32_BLENDABLE)
+    {
+        warn_once!(
+            "FireflyConfig::enable_32bit_stencils is enabled, but the device doesn't support the FLOAT32_BLENDABLE feature. Falling back to 16 bit stencils."
+        );
+        return TextureFormat::Rgba16Float;
+    }
+
+    TextureFormat::Rgba32Float
+}
+
+/// Resource that tells whether Firefly's pipelines are compiled and ready to render.
+///
+/// This is true once every camera with a [`FireflyConfig`] has both its lightmap creation and application pipelines compiled,
+/// and false while there are no such cameras. Until then, lit cameras can render black or unlit frames, so this can be used to
+/// gate systems (e.g. hiding a loading screen) or custom render nodes that depend on the lightmap.
+/// It also helps telling apart pipelines that are still compiling from ones that failed, which are reported as errors.
+///
+/// It's updated at the start of each frame, based on the last frame that was rendered.
+///
+/// # Example
+///
+/// ```
+/// app.add_systems(Update, hide_loading_screen.run_if(|ready: Res<FireflyPipelinesReady>| ready.get()));
+/// ```
+#[derive(Resource, Default, Clone, Copy, Debug)]
+pub struct FireflyPipelinesReady(bool);
+
+impl FireflyPipelinesReady {
+    /// Whether the pipelines are ready.
+    pub fn get(&self) -> bool {
+        self.0
+    }
+}
+
+/// Flag written by the Render World and read by the Main World.
+#[derive(Resource, Clone, Default)]
+struct PipelinesReadyFlag(Arc<AtomicBool>);
+
+/// Lightmap creation pipelines of a camera, for its main channel and its [extra channels](FireflyConfig::extra_channels).
+#[derive(Component)]
+pub struct SpecializedCreationPipelines {
+    pub main: CachedRenderPipelineId,
+    pub channels: CachedRenderPipelineId,
+}
+
+fn check_pipelines_ready(
+    flag: Res<PipelinesReadyFlag>,
+    views: Query<(
+        &FireflyConfig,
+        Option<&SpecializedCreationPipelines>,
+        Option<&SpecializedApplicationPipeline>,
+    )>,
+    pipeline_cache: Res<PipelineCache>,
+) {
+    let is_ready = |id| pipeline_cache.get_render_pipeline(id).is_some();
+
+    // cameras whose target can't be lit have no application pipeline, and are skipped
+    let ready = !views.is_empty()
+        && views.iter().all(|(config, creation, application)| {
+            creation.is_some_and(|creation| {
+                is_ready(creation.main)
+                    && (config.extra_channels.is_empty() || is_ready(creation.channels))
+            }) && application.is_none_or(|application| is_ready(application.id))
+        });
+
+    flag.0.store(ready, Ordering::Relaxed);
+}
+
+fn update_pipelines_ready(flag: Res<PipelinesReadyFlag>, mut ready: ResMut<FireflyPipelinesReady>) {
+    let value = flag.0.load(Ordering::Relaxed);
+    if ready.0 != value {
+        ready.0 = value;
+    }
+}
+
+/// Logs an error if the given pipeline failed to be created, since the passes using it are otherwise skipped silently.
+///
+/// Each error is logged once per pipeline, so a pipeline that fails again after its shader is
+/// [hot reloaded](PipelinePlugin) logs the new error.
+pub(crate) fn report_pipeline_error(
+    pipeline_cache: &PipelineCache,
+    id: CachedRenderPipelineId,
+    name: &str,
+) {
+    static REPORTED: Mutex<Vec<(CachedRenderPipelineId, String)>> = Mutex::new(vec![]);
+
+    let CachedPipelineState::Err(err) = pipeline_cache.get_render_pipeline_state(id) else {
+        return;
+    };
+
+    let err = err.to_string();
+    let Ok(mut reported) = REPORTED.lock() else {
+        return;
+    };
+
+    match reported
+        .iter_mut()
+        .find(|(reported_id, _)| *reported_id == id)
+    {
+        Some((_, reported_err)) if *reported_err == err => return,
+        Some((_, reported_err)) => *reported_err = err.clone(),
+        None => reported.push((id, err.clone())),
+    }
+
+    error!(
+        "Firefly's {name} pipeline couldn't be created, so it will be skipped until it's fixed: {err}"
+    );
+}
+
 /// Pipeline that creates the lightmap from the relevant bindings.
 #[derive(Resource)]
 pub struct LightmapCreationPipeline {
@@ -96,23 +256,52 @@ fn init_lightmap_creation_pipeline(
                 (0, sampler(SamplerBindingType::Filtering)),
                 // point lights
                 (1, storage_buffer_read_only::<UniformPointLight>(false)),
-                (2, storage_buffer_read_only::<u32>(false)),
+                // light index, selected per light by a dynamic offset
+                (2, uniform_buffer::<u32>(true)),
                 // round occluders
                 (3, storage_buffer_read_only::<UniformRoundOccluder>(false)),
                 // poly occluders
                 (4, storage_buffer_read_only::<UniformOccluder>(false)),
                 // vertices
-                (5, storage_buffer_read_only::<Vec2>(false)),
+                (5, storage_buffer_read_only::<UniformVertex>(false)),
                 // occluders
                 (6, storage_buffer_read_only::<OccluderPointer>(false)),
                 // bins
                 (7, storage_buffer_read_only::<BinIndices>(false)),
                 // sprite stencil
-                (8, texture_2d(TextureSampleType::Float { filterable: true })),
+                (
+                    8,
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
                 // sprite normal map
                 (9, texture_2d(TextureSampleType::Float { filterable: true })),
                 // config,
                 (10, uniform_buffer::<UniformFireflyConfig>(false)),
+                // sprite specular map
+                (
+                    11,
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                ),
+                // light cookie
+                (
+                    12,
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                ),
+                // light falloff gradient
+                (
+                    13,
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                ),
+                // occluder coverage
+                (
+                    14,
+                    texture_2d(TextureSampleType::Float { filterable: false }),
+                ),
+                // sprite parallax
+                (
+                    15,
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                ),
             ),
         ),
     );
@@ -171,9 +360,12 @@ bitflags::bitflags! {
         const TONEMAP_METHOD_TONY_MC_MAPFACE    = 6 << Self::TONEMAP_METHOD_SHIFT_BITS;
         const TONEMAP_METHOD_BLENDER_FILMIC     = 7 << Self::TONEMAP_METHOD_SHIFT_BITS;
         const TONEMAP_METHOD_PBR_NEUTRAL        = 8 << Self::TONEMAP_METHOD_SHIFT_BITS;
+        const SOFT_SHADOW_SAMPLES_RESERVED_BITS = Self::SOFT_SHADOW_SAMPLES_MASK_BITS << Self::SOFT_SHADOW_SAMPLES_SHIFT_BITS;
 
         const COMBINE_LIGHTMAPS                 = 1 << 31;
         const LIGHTMAP_FILTERING                = 1 << 30;
+        const SHADOW_MASK                       = 1 << 29;
+        const ADDITIVE_LIGHTS                   = 1 << 28;
     }
 }
 
@@ -185,6 +377,23 @@ impl LightPipelineKey {
     const TONEMAP_METHOD_MASK_BITS: u32 = 0b1111;
     const TONEMAP_METHOD_SHIFT_BITS: u32 =
         Self::MSAA_SHIFT_BITS - Self::TONEMAP_METHOD_MASK_BITS.count_ones();
+    const SOFT_SHADOW_SAMPLES_MASK_BITS: u32 = 0xFF;
+    const SOFT_SHADOW_SAMPLES_SHIFT_BITS: u32 = 16;
+
+    /// Key for the given soft shadow sample count, clamped to `1..=255`.
+    #[inline]
+    pub fn from_soft_shadow_samples(samples: u32) -> Self {
+        let samples = samples.clamp(1, Self::SOFT_SHADOW_SAMPLES_MASK_BITS);
+        Self::from_bits_retain(samples << Self::SOFT_SHADOW_SAMPLES_SHIFT_BITS)
+    }
+
+    /// Soft shadow sample count of the key. Keys without any count use 1.
+    #[inline]
+    pub const fn soft_shadow_samples(&self) -> u32 {
+        let samples = (self.bits() >> Self::SOFT_SHADOW_SAMPLES_SHIFT_BITS)
+            & Self::SOFT_SHADOW_SAMPLES_MASK_BITS;
+        if samples == 0 { 1 } else { samples }
+    }
 
     #[inline]
     pub const fn from_msaa_samples(msaa_samples: u32) -> Self {
@@ -260,24 +469,57 @@ impl SpecializedRenderPipeline for LightmapCreationPipeline {
         }
 
         let format = key.target_format();
+
+        let light_operation = match key.contains(LightPipelineKey::ADDITIVE_LIGHTS) {
+            true => {
+                shader_defs.push("ADDITIVE_LIGHTS".into());
+                BlendOperation::Add
+            }
+            false => BlendOperation::Max,
+        };
+
+        let mut targets = vec![Some(ColorTargetState {
+            format,
+            blend: Some(BlendState {
+                color: BlendComponent {
+                    src_factor: BlendFactor::One,
+                    dst_factor: BlendFactor::One,
+                    operation: light_operation,
+                },
+                alpha: BlendComponent::REPLACE,
+            }),
+            write_mask: ColorWrites::ALL,
+        })];
+
+        shader_defs.push(ShaderDefVal::UInt(
+            "SOFT_SHADOW_SAMPLES".into(),
+            key.soft_shadow_samples(),
+        ));
+
+        if key.contains(LightPipelineKey::SHADOW_MASK) {
+            shader_defs.push("SHADOW_MASK".into());
+
+            targets.push(Some(ColorTargetState {
+                format: TextureFormat::R8Unorm,
+                blend: Some(BlendState {
+                    color: BlendComponent {
+                        src_factor: BlendFactor::One,
+                        dst_factor: BlendFactor::One,
+                        operation: BlendOperation::Max,
+                    },
+                    alpha: BlendComponent::REPLACE,
+                }),
+                write_mask: ColorWrites::ALL,
+            }));
+        }
+
         RenderPipelineDescriptor {
             label: Some(Cow::Borrowed("lightmap creation pipeline")),
             layout: vec![self.lut_layout.clone(), self.layout.clone()],
             vertex: self.vertex_state.clone(),
             fragment: Some(FragmentState {
                 shader: self.shader.clone(),
-                targets: vec![Some(ColorTargetState {
-                    format,
-                    blend: Some(BlendState {
-                        color: BlendComponent {
-                            src_factor: BlendFactor::One,
-                            dst_factor: BlendFactor::One,
-                            operation: BlendOperation::Max,
-                        },
-                        alpha: BlendComponent::REPLACE,
-                    }),
-                    write_mask: ColorWrites::ALL,
-                })],
+                targets,
                 shader_defs,
                 entry_point: Some(Cow::Borrowed("fragment")),
             }),
@@ -318,7 +560,7 @@ impl LightmapApplicationPipeline {
         if combined {
             layout.entries.push(
                 texture_2d_array(TextureSampleType::Float { filterable: true })
-                    .build(5, ShaderStages::FRAGMENT),
+                    .build(10, ShaderStages::FRAGMENT),
             );
         }
 
@@ -354,6 +596,16 @@ fn init_lightmap_application_pipeline(
                 sampler(SamplerBindingType::Filtering),
                 // config
                 uniform_buffer::<UniformFireflyConfig>(false),
+                // sprite normal map
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // ambient texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // volumetric light texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // exposure texture
+                texture_2d(TextureSampleType::Float { filterable: false }),
+                // occluder refraction texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
             ),
         ),
     );
@@ -497,6 +749,383 @@ impl SpecializedRenderPipeline for LightmapCombinationPipeline {
     }
 }
 
+/// Pipeline that accumulates the (possibly jittered) lightmap into the full resolution history, used for
+/// [temporal upsampling](crate::prelude::FireflyConfig::temporal_upsampling) and [accumulation](crate::prelude::FireflyConfig::temporal_accumulation).
+#[derive(Resource)]
+pub struct LightmapResolvePipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub sampler: Sampler,
+    pub vertex_state: VertexState,
+    pub shader: Handle<Shader>,
+}
+
+#[derive(Component)]
+pub struct SpecializedResolvePipeline(pub CachedRenderPipelineId);
+
+fn init_lightmap_resolve_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    fullscreen_shader: Res<FullscreenShader>,
+    asset_server: Res<AssetServer>,
+) {
+    let layout = BindGroupLayoutDescriptor::new(
+        "resolve lightmap layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                // current lightmap
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // previous frame's history
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+                // config
+                uniform_buffer::<UniformFireflyConfig>(false),
+            ),
+        ),
+    );
+
+    let sampler = render_device.create_sampler(&SamplerDescriptor {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+    let vertex_state = fullscreen_shader.to_vertex_state();
+
+    commands.insert_resource(LightmapResolvePipeline {
+        layout,
+        sampler,
+        vertex_state,
+        shader: load_embedded_asset!(asset_server.as_ref(), "shaders/resolve_lightmap.wgsl"),
+    });
+}
+
+impl SpecializedRenderPipeline for LightmapResolvePipeline {
+    type Key = LightPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let format = key.target_format();
+        RenderPipelineDescriptor {
+            label: Some(Cow::Borrowed("lightmap resolve pipeline")),
+            layout: vec![self.layout.clone()],
+            vertex: self.vertex_state.clone(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+                shader_defs: default(),
+                entry_point: Some(Cow::Borrowed("fragment")),
+            }),
+            primitive: default(),
+            depth_stencil: default(),
+            multisample: MultisampleState {
+                count: key.msaa_samples(),
+                ..default()
+            },
+            ..default()
+        }
+    }
+}
+
+/// Pipeline that ray-marches the lightmap to produce the light shafts of [volumetric lights](crate::prelude::VolumetricLight).
+#[derive(Resource)]
+pub struct LightmapVolumetricPipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub sampler: Sampler,
+    pub vertex_state: VertexState,
+    pub shader: Handle<Shader>,
+}
+
+#[derive(Component)]
+pub struct SpecializedVolumetricPipeline(pub CachedRenderPipelineId);
+
+fn init_lightmap_volumetric_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    fullscreen_shader: Res<FullscreenShader>,
+    asset_server: Res<AssetServer>,
+) {
+    let layout = BindGroupLayoutDescriptor::new(
+        "volumetric lightmap layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                // lightmap
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+                // volumetric lights
+                storage_buffer_read_only::<UniformVolumetricLight>(false),
+            ),
+        ),
+    );
+
+    let sampler = render_device.create_sampler(&SamplerDescriptor {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+    let vertex_state = fullscreen_shader.to_vertex_state();
+
+    commands.insert_resource(LightmapVolumetricPipeline {
+        layout,
+        sampler,
+        vertex_state,
+        shader: load_embedded_asset!(asset_server.as_ref(), "shaders/volumetric_lightmap.wgsl"),
+    });
+}
+
+impl SpecializedRenderPipeline for LightmapVolumetricPipeline {
+    type Key = LightPipelineKey;
+
+    fn specialize(&self, key: Self::Key) -> RenderPipelineDescriptor {
+        let format = key.target_format();
+        RenderPipelineDescriptor {
+            label: Some(Cow::Borrowed("lightmap volumetric pipeline")),
+            layout: vec![self.layout.clone()],
+            vertex: self.vertex_state.clone(),
+            fragment: Some(FragmentState {
+                shader: self.shader.clone(),
+                targets: vec![Some(ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                })],
+                shader_defs: default(),
+                entry_point: Some(Cow::Borrowed("fragment")),
+            }),
+            primitive: default(),
+            depth_stencil: default(),
+            multisample: MultisampleState {
+                count: key.msaa_samples(),
+                ..default()
+            },
+            ..default()
+        }
+    }
+}
+
+/// Pipeline that draws the occluders into the [`OccluderCoverageTexture`](crate::OccluderCoverageTexture)
+/// for [screen-space shadows](crate::prelude::FireflyConfig::screen_space_shadows), and into the
+/// [`OccluderRefractionTexture`](crate::OccluderRefractionTexture) for [refraction](crate::prelude::Occluder2d::refraction).
+#[derive(Resource)]
+pub struct OccluderCoveragePipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub id: CachedRenderPipelineId,
+}
+
+fn init_occluder_coverage_pipeline(
+    mut commands: Commands,
+    pipeline_cache: Res<PipelineCache>,
+    asset_server: Res<AssetServer>,
+) {
+    let layout = BindGroupLayoutDescriptor::new(
+        "occluder coverage layout",
+        &BindGroupLayoutEntries::single(ShaderStages::VERTEX, uniform_buffer::<ViewUniform>(true)),
+    );
+
+    let shader = load_embedded_asset!(asset_server.as_ref(), "shaders/occluder_coverage.wgsl");
+
+    let vertex_buffer_layout = VertexBufferLayout {
+        array_stride: 24,
+        step_mode: VertexStepMode::Vertex,
+        attributes: vec![
+            // @location(0) pos: vec2<f32>,
+            VertexAttribute {
+                format: VertexFormat::Float32x2,
+                offset: 0,
+                shader_location: 0,
+            },
+            // @location(1) opacity: f32,
+            VertexAttribute {
+                format: VertexFormat::Float32,
+                offset: 8,
+                shader_location: 1,
+            },
+            // @location(2) refraction: vec2<f32>,
+            VertexAttribute {
+                format: VertexFormat::Float32x2,
+                offset: 12,
+                shader_location: 2,
+            },
+            // @location(3) refracts: f32,
+            VertexAttribute {
+                format: VertexFormat::Float32,
+                offset: 20,
+                shader_location: 3,
+            },
+        ],
+    };
+
+    let id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+        label: Some(Cow::Borrowed("occluder coverage pipeline")),
+        layout: vec![layout.clone()],
+        vertex: VertexState {
+            shader: shader.clone(),
+            entry_point: Some("vertex".into()),
+            shader_defs: default(),
+            buffers: vec![vertex_buffer_layout],
+        },
+        fragment: Some(FragmentState {
+            shader,
+            shader_defs: default(),
+            entry_point: Some("fragment".into()),
+            targets: vec![
+                Some(ColorTargetState {
+                    format: TextureFormat::R8Unorm,
+                    blend: Some(BlendState {
+                        color: BlendComponent {
+                            src_factor: BlendFactor::One,
+                            dst_factor: BlendFactor::One,
+                            operation: BlendOperation::Max,
+                        },
+                        alpha: BlendComponent::REPLACE,
+                    }),
+                    write_mask: ColorWrites::ALL,
+                }),
+                // non-refracting occluders leave the refraction of the ones below untouched
+                Some(ColorTargetState {
+                    format: TextureFormat::Rgba16Float,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    write_mask: ColorWrites::ALL,
+                }),
+            ],
+        }),
+        primitive: default(),
+        depth_stencil: None,
+        multisample: default(),
+        ..default()
+    });
+
+    commands.insert_resource(OccluderCoveragePipeline { layout, id });
+}
+
+/// Pipeline that measures the lightmap's luminance and adapts the gain of the
+/// [automatic exposure](crate::prelude::FireflyConfig::auto_exposure).
+#[derive(Resource)]
+pub struct LightmapExposurePipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub sampler: Sampler,
+    pub id: CachedRenderPipelineId,
+}
+
+fn init_lightmap_exposure_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    fullscreen_shader: Res<FullscreenShader>,
+    pipeline_cache: Res<PipelineCache>,
+    asset_server: Res<AssetServer>,
+) {
+    let layout = BindGroupLayoutDescriptor::new(
+        "lightmap exposure layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                // lightmap
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+                // previous gain
+                texture_2d(TextureSampleType::Float { filterable: false }),
+                // config
+                uniform_buffer::<UniformFireflyConfig>(false),
+            ),
+        ),
+    );
+
+    let sampler = render_device.create_sampler(&SamplerDescriptor {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+
+    let id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+        label: Some(Cow::Borrowed("lightmap exposure pipeline")),
+        layout: vec![layout.clone()],
+        vertex: fullscreen_shader.to_vertex_state(),
+        fragment: Some(FragmentState {
+            shader: load_embedded_asset!(asset_server.as_ref(), "shaders/lightmap_exposure.wgsl"),
+            shader_defs: default(),
+            entry_point: Some(Cow::Borrowed("fragment")),
+            targets: vec![Some(ColorTargetState {
+                format: TextureFormat::R32Float,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: default(),
+        depth_stencil: None,
+        multisample: default(),
+        ..default()
+    });
+
+    commands.insert_resource(LightmapExposurePipeline {
+        layout,
+        sampler,
+        id,
+    });
+}
+
+/// Pipeline that downscales the lightmap into the texture of a [`LightmapReadback`](crate::prelude::LightmapReadback).
+#[derive(Resource)]
+pub struct LightmapReadbackPipeline {
+    pub layout: BindGroupLayoutDescriptor,
+    pub sampler: Sampler,
+    pub id: CachedRenderPipelineId,
+}
+
+fn init_lightmap_readback_pipeline(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    fullscreen_shader: Res<FullscreenShader>,
+    pipeline_cache: Res<PipelineCache>,
+    asset_server: Res<AssetServer>,
+) {
+    let layout = BindGroupLayoutDescriptor::new(
+        "lightmap readback layout",
+        &BindGroupLayoutEntries::sequential(
+            ShaderStages::FRAGMENT,
+            (
+                // lightmap
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                sampler(SamplerBindingType::Filtering),
+            ),
+        ),
+    );
+
+    let sampler = render_device.create_sampler(&SamplerDescriptor {
+        mag_filter: FilterMode::Linear,
+        min_filter: FilterMode::Linear,
+        ..default()
+    });
+
+    let id = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+        label: Some(Cow::Borrowed("lightmap readback pipeline")),
+        layout: vec![layout.clone()],
+        vertex: fullscreen_shader.to_vertex_state(),
+        fragment: Some(FragmentState {
+            shader: load_embedded_asset!(asset_server.as_ref(), "shaders/lightmap_readback.wgsl"),
+            shader_defs: default(),
+            entry_point: Some(Cow::Borrowed("fragment")),
+            targets: vec![Some(ColorTargetState {
+                format: TextureFormat::Rgba32Float,
+                blend: None,
+                write_mask: ColorWrites::ALL,
+            })],
+        }),
+        primitive: default(),
+        depth_stencil: None,
+        multisample: default(),
+        ..default()
+    });
+
+    commands.insert_resource(LightmapReadbackPipeline {
+        layout,
+        sampler,
+        id,
+    });
+}
+
 /// Pipeline that produces the stencil and normal textures from the sprite bindings.
 #[derive(Resource)]
 #[allow(dead_code)]
@@ -539,6 +1168,14 @@ fn init_sprite_pipeline(mut commands: Commands, asset_server: Res<AssetServer>)
                 sampler(SamplerBindingType::Filtering),
                 // dummy normal bool
                 uniform_buffer::<u32>(false),
+                // specular map texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // dummy specular bool
+                uniform_buffer::<u32>(false),
+                // height map texture
+                texture_2d(TextureSampleType::Float { filterable: true }),
+                // dummy height map bool
+                uniform_buffer::<u32>(false),
             ),
         ),
     );
@@ -594,7 +1231,7 @@ impl SpecializedRenderPipeline for SpritePipeline {
         }
 
         let instance_rate_vertex_buffer_layout = VertexBufferLayout {
-            array_stride: 80,
+            array_stride: 96,
             step_mode: VertexStepMode::Instance,
             attributes: vec![
                 // @location(0) i_model_transpose_col0: vec4<f32>,
@@ -639,6 +1276,36 @@ impl SpecializedRenderPipeline for SpritePipeline {
                     offset: 72,
                     shader_location: 6,
                 },
+                // @location(7) shininess: f32,
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: 76,
+                    shader_location: 7,
+                },
+                // @location(8) light_group: f32,
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: 80,
+                    shader_location: 8,
+                },
+                // @location(9) parallax: f32,
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: 84,
+                    shader_location: 9,
+                },
+                // @location(10) normal_mode: f32,
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: 88,
+                    shader_location: 10,
+                },
+                // @location(11) coverage: f32,
+                VertexAttribute {
+                    format: VertexFormat::Float32,
+                    offset: 92,
+                    shader_location: 11,
+                },
             ],
         };
 
@@ -669,6 +1336,17 @@ impl SpecializedRenderPipeline for SpritePipeline {
                         blend: Some(BlendState::ALPHA_BLENDING),
                         write_mask: ColorWrites::ALL,
                     }),
+                    key.contains(SpritePipelineKey::SPECULAR_MAP)
+                        .then_some(ColorTargetState {
+                            format: TextureFormat::Rgba16Float,
+                            blend: Some(BlendState::ALPHA_BLENDING),
+                            write_mask: ColorWrites::ALL,
+                        }),
+                    Some(ColorTargetState {
+                        format: TextureFormat::Rg16Float,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        write_mask: ColorWrites::ALL,
+                    }),
                 ],
             }),
             layout: vec![self.view_layout.clone(), self.material_layout.clone()],
@@ -716,6 +1394,7 @@ bitflags::bitflags! {
         const TONEMAP_METHOD_PBR_NEUTRAL        = 8 << Self::TONEMAP_METHOD_SHIFT_BITS;
 
         const ENABLED_32BIT_STENCIL = 1 << 31;
+        const SPECULAR_MAP          = 1 << 30;
     }
 }
 
@@ -1,20 +1,32 @@
 //! Module that prepares BindGroups for GPU use.
 
 use core::f32;
+use std::cmp::Reverse;
 use std::f32::consts::{FRAC_PI_2, PI, TAU};
 
 use crate::{
-    CombinedLightMapTextures, LightmapPhase, NormalMapTexture, SpriteStencilTexture,
-    buffers::{BinBuffer, BinBuffers, BufferManager, OccluderData, OccluderPointer, VertexBuffer},
+    CombinedLightMapTextures, ExposureTextures, LightChannelTextures, LightmapHistoryTextures,
+    LightmapPhase, NormalMapTexture, OccluderCoverageTexture, OccluderRefractionTexture,
+    ParallaxTexture, ShadowMaskTexture, SpecularMapTexture, SpriteStencilTexture,
+    VolumetricLightTexture,
+    buffers::{
+        BinBuffers, BufferManager, CachedOccluderData, OccluderData, OccluderDataCache,
+        OccluderPointer, VertexBuffer,
+    },
     data::{
-        CombinationMode, ExtractedCombinedLightmaps, ExtractedWorldData, LightmapSize, NormalMode,
+        CombinationMode, ExtractedCombineLightmapTo, ExtractedCombinedLightmaps,
+        ExtractedWorldData, LightmapBlend, LightmapSize,
+    },
+    lights::{
+        BudgetSkippedLights, LightBatch, LightBatches, LightBindGroups, LightIndex, LightLut,
+        LightPointer, LightPointers, PointLight2d, UniformVolumetricLight,
     },
-    lights::{LightBatch, LightBatches, LightBindGroups, LightIndex, LightLut, LightPointer},
     occluders::{PolyOccluderIndex, RoundOccluderIndex, point_inside_poly, translate_vertices},
     phases::SpritePhase,
     pipelines::{
         LightPipelineKey, LightmapApplicationPipeline, LightmapCreationPipeline,
-        SpecializedApplicationPipeline, SpritePipeline,
+        LightmapResolvePipeline, LightmapVolumetricPipeline, SpecializedApplicationPipeline,
+        SpecializedResolvePipeline, SpecializedVolumetricPipeline, SpritePipeline, stencil_format,
     },
     sprites::{
         ExtractedSlices, ExtractedSpriteKind, ExtractedSprites, ImageBindGroups, SpriteAssetEvents,
@@ -23,12 +35,15 @@ use crate::{
     utils::apply_scaling,
 };
 
+use bytemuck::{Pod, Zeroable};
+
 use bevy::{
     camera::visibility::RenderLayers,
     core_pipeline::tonemapping::{Tonemapping, TonemappingLuts, get_lut_bindings},
+    diagnostic::FrameCount,
     math::{
-        Affine3A,
-        bounding::{Aabb2d, IntersectsVolume},
+        Affine3A, FloatOrd,
+        bounding::{Aabb2d, BoundingVolume, IntersectsVolume},
     },
     platform::{
         collections::{HashMap, HashSet},
@@ -40,12 +55,16 @@ use bevy::{
         render_asset::RenderAssets,
         render_phase::{PhaseItem, ViewBinnedRenderPhases, ViewSortedRenderPhases},
         render_resource::{
-            BindGroup, BindGroupEntries, Extent3d, PipelineCache, SpecializedRenderPipelines,
-            TextureDescriptor, TextureDimension, TextureFormat, TextureUsages, UniformBuffer,
+            BindGroup, BindGroupEntries, BufferUsages, Extent3d, PipelineCache, RawBufferVec,
+            SpecializedRenderPipelines, StorageBuffer, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureUsages, TextureViewDescriptor, UniformBuffer,
         },
         renderer::{RenderDevice, RenderQueue},
-        texture::{FallbackImage, GpuImage, TextureCache},
-        view::{ExtractedView, RetainedViewEntity, ViewTarget, ViewUniforms},
+        texture::{CachedTexture, FallbackImage, FallbackImageZero, GpuImage, TextureCache},
+        view::{
+            ExtractedView, RenderVisibleEntities, RetainedViewEntity, ViewTarget, ViewUniforms,
+            texture_format_to_code,
+        },
     },
     tasks::{ComputeTaskPool, ParallelSliceMut},
 };
@@ -61,6 +80,45 @@ use crate::{
 #[derive(Component)]
 pub struct BufferedFireflyConfig(pub UniformBuffer<UniformFireflyConfig>);
 
+/// Camera buffer component containing the [volumetric lights](crate::prelude::VolumetricLight) visible to it.
+#[derive(Component)]
+pub(crate) struct VolumetricLightsBuffer(pub StorageBuffer<Vec<UniformVolumetricLight>>);
+
+/// Camera buffer component containing the triangles drawn into the [`OccluderCoverageTexture`].
+#[derive(Component)]
+pub(crate) struct OccluderCoverageVertices(pub RawBufferVec<CoverageVertex>);
+
+/// Vertex of an occluder's triangle in the [`OccluderCoverageTexture`].
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub(crate) struct CoverageVertex {
+    pub pos: Vec2,
+    pub opacity: f32,
+    /// World space offset the scene is sampled from, for [refracting](crate::prelude::Occluder2d::refraction) occluders.
+    pub refraction: Vec2,
+    /// 1 if the occluder is refracting, 0 otherwise.
+    pub refracts: f32,
+}
+
+/// Camera component storing the state of the previous frame, used to reproject the lightmap history
+/// for [temporal upsampling](FireflyConfig::temporal_upsampling) and [accumulation](FireflyConfig::temporal_accumulation).
+#[derive(Component)]
+pub(crate) struct LightmapHistory {
+    camera_pos: Vec2,
+    size: UVec2,
+}
+
+/// Camera component owning the two textures of the [`LightmapHistoryTextures`], whose content has to persist between frames.
+///
+/// They're created once and only recreated when the view's size or format changes. Going through the [`TextureCache`]
+/// instead could hand them to another camera requesting textures with the same descriptor.
+#[derive(Component)]
+pub(crate) struct LightmapHistoryStorage {
+    textures: [CachedTexture; 2],
+    size: Extent3d,
+    format: TextureFormat,
+}
+
 /// Plugin responsible for processing extracted entities and
 /// sending relevant BindGroups to the GPU. Automatically added by
 /// [`FireflyPlugin`](crate::prelude::FireflyPlugin).  
@@ -76,12 +134,24 @@ impl Plugin for PreparePlugin {
 
         render_app.add_systems(
             Render,
-            specialize_light_application_pipeline.in_set(RenderSystems::Prepare),
+            (
+                specialize_light_application_pipeline,
+                specialize_lightmap_resolve_pipeline,
+            )
+                .in_set(RenderSystems::Prepare),
         );
 
         render_app.add_systems(Render, prepare_data.in_set(RenderSystems::Prepare));
         render_app.add_systems(Render, prepare_config.in_set(RenderSystems::Prepare));
         render_app.add_systems(Render, prepare_lightmap.in_set(RenderSystems::Prepare));
+        render_app.add_systems(
+            Render,
+            prepare_volumetric_lights.in_set(RenderSystems::Prepare),
+        );
+        render_app.add_systems(
+            Render,
+            prepare_occluder_coverage.in_set(RenderSystems::Prepare),
+        );
 
         render_app.add_systems(
             Render,
@@ -97,8 +167,7 @@ impl Plugin for PreparePlugin {
 fn specialize_light_application_pipeline(
     views: Query<(
         Entity,
-        &ExtractedView,
-        &Msaa,
+        &ViewTarget,
         &FireflyConfig,
         Has<CombinedLightMapTextures>,
     )>,
@@ -107,8 +176,21 @@ fn specialize_light_application_pipeline(
     mut pipelines: ResMut<SpecializedRenderPipelines<LightmapApplicationPipeline>>,
     mut commands: Commands,
 ) {
-    for (entity, view, _msaa, config, is_combined) in views {
-        let mut key = LightPipelineKey::from_target_format(view.target_format);
+    for (entity, view_target, config, is_combined) in views {
+        // the lightmap is applied to the view's main texture, which is Rgba16Float for Hdr cameras
+        // regardless of the format of the window or image it ends up in
+        let format = view_target.main_texture_format();
+        if texture_format_to_code(format).is_none() {
+            warn_once!(
+                "Firefly can't apply the lightmap to a render target with the {format:?} format, so it will be skipped."
+            );
+            commands
+                .entity(entity)
+                .remove::<SpecializedApplicationPipeline>();
+            continue;
+        }
+
+        let mut key = LightPipelineKey::from_target_format(format);
         if is_combined {
             key |= LightPipelineKey::COMBINE_LIGHTMAPS;
         }
@@ -129,18 +211,73 @@ fn specialize_light_application_pipeline(
     }
 }
 
+fn specialize_lightmap_resolve_pipeline(
+    views: Query<(Entity, &ExtractedView, &FireflyConfig)>,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<LightmapResolvePipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<LightmapResolvePipeline>>,
+    mut commands: Commands,
+) {
+    for (entity, view, config) in views {
+        if !config.uses_lightmap_history() {
+            commands
+                .entity(entity)
+                .remove::<SpecializedResolvePipeline>();
+            continue;
+        }
+
+        let key = LightPipelineKey::from_target_format(view.target_format);
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &pipeline, key);
+
+        commands
+            .entity(entity)
+            .insert(SpecializedResolvePipeline(pipeline_id));
+    }
+}
+
+/// Element of the Halton low-discrepancy sequence, in [0, 1).
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut fraction = 1.0;
+    let mut result = 0.0;
+
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+
+    result
+}
+
 fn prepare_config(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
+    frame_count: Res<FrameCount>,
+    time: Res<Time>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
     configs: Query<(
         Entity,
         &FireflyConfig,
         &ViewTarget,
+        &ExtractedView,
+        Option<&ExtractedWorldData>,
         Option<&ExtractedCombinedLightmaps>,
+        Option<&LightmapHistory>,
+        Has<ExtractedCombineLightmapTo>,
     )>,
     mut commands: Commands,
 ) {
-    for (entity, config, view_target, combined_lightmap) in &configs {
+    for (
+        entity,
+        config,
+        view_target,
+        view,
+        world_data,
+        combined_lightmap,
+        history,
+        is_combined_to,
+    ) in &configs
+    {
         let window_size = view_target.main_texture().size();
         let scale = match config.lightmap_size {
             LightmapSize::Window => vec2(1.0, 1.0),
@@ -148,20 +285,24 @@ fn prepare_config(
                 size.x as f32 / window_size.width as f32,
                 size.y as f32 / window_size.height as f32,
             ),
-            LightmapSize::Scaled(scale) => vec2(1.0 / scale, 1.0 / scale),
+            LightmapSize::Scaled(scale) => Vec2::splat(1.0 / scale.max(MIN_LIGHTMAP_SCALE)),
         };
 
-        let uniform = UniformFireflyConfig {
+        let mut uniform = UniformFireflyConfig {
             ambient_color: config.ambient_color.to_linear().to_vec3(),
-            ambient_brightness: config.ambient_brightness,
+            ambient_brightness: config.ambient_brightness.max(0.0),
 
-            light_bands: config.light_bands.unwrap_or(0.0),
+            // 0 disables the bands
+            light_bands: config.light_bands.unwrap_or(0.0).max(0.0),
 
             soft_shadows: match config.soft_shadows {
                 true => 1,
                 false => 0,
             },
 
+            shadow_edge_feather: config.shadow_edge_feather.max(0.0),
+            min_shadow_light: config.min_shadow_light.clamp(0.0, 1.0),
+
             z_sorting: match config.z_sorting {
                 false => 0,
                 true => 1,
@@ -169,12 +310,7 @@ fn prepare_config(
 
             z_sorting_error_margin: config.z_sorting_error_margin,
 
-            normal_mode: match config.normal_mode {
-                NormalMode::None => 0,
-                NormalMode::Simple => 1,
-                NormalMode::TopDownY => 2,
-                NormalMode::TopDownZ => 3,
-            },
+            normal_mode: config.normal_mode.as_u32(),
 
             normal_attenuation: config.normal_attenuation,
 
@@ -192,7 +328,116 @@ fn prepare_config(
             },
 
             texture_scale: scale,
+
+            shadow_bias: config.shadow_bias.max(0.0),
+
+            lightmap_blend: match config.lightmap_blend {
+                LightmapBlend::Multiply => 0,
+                LightmapBlend::Add => 1,
+                LightmapBlend::Screen => 2,
+                LightmapBlend::SoftLight => 3,
+            },
+
+            fill_direction: config
+                .fill_light
+                .map_or(Vec2::Y, |(dir, _, _)| dir.normalize_or(Vec2::Y)),
+            fill_color: config
+                .fill_light
+                .map_or(Vec3::ZERO, |(_, color, _)| color.to_linear().to_vec3()),
+            fill_strength: config.fill_light.map_or(0.0, |(_, _, strength)| strength),
+
+            jitter: Vec2::ZERO,
+            history_offset: Vec2::ZERO,
+            history_valid: 0,
+            temporal_blend: config
+                .temporal_accumulation
+                .unwrap_or_default()
+                .blend
+                .clamp(0.0, 1.0),
+            temporal_clamp_margin: config
+                .temporal_accumulation
+                .unwrap_or_default()
+                .clamp_margin
+                .max(0.0),
+
+            exposure: config.exposure.max(0.0),
+            max_luminance: config.max_luminance.map_or(0.0, |max| max.max(0.0)),
+
+            auto_exposure: match config.auto_exposure {
+                Some(_) => 1,
+                None => 0,
+            },
+            auto_exposure_target: config
+                .auto_exposure
+                .map_or(0.0, |auto| auto.target_luminance.max(0.0)),
+            auto_exposure_adaptation: config.auto_exposure.map_or(0.0, |auto| {
+                1.0 - (-auto.speed.max(0.0) * time.delta_secs()).exp()
+            }),
+            auto_exposure_min_gain: config
+                .auto_exposure
+                .map_or(0.0, |auto| auto.min_gain.max(0.0)),
+            auto_exposure_max_gain: config
+                .auto_exposure
+                .map_or(0.0, |auto| auto.max_gain.max(auto.min_gain.max(0.0))),
+
+            // until the texture is loaded, the ambient color is used
+            has_ambient_texture: match config
+                .ambient_texture
+                .as_ref()
+                .is_some_and(|image| gpu_images.get(image).is_some())
+            {
+                true => 1,
+                false => 0,
+            },
+
+            ao_strength: config.ao_strength.clamp(0.0, 1.0),
+            ao_radius: config.ao_radius.max(0.0),
+
+            screen_space_shadows: match config.screen_space_shadows {
+                true => 1,
+                false => 0,
+            },
+
+            viewport: {
+                let size = vec2(window_size.width as f32, window_size.height as f32);
+                let min = view.viewport.xy().as_vec2() / size;
+                let max = (view.viewport.xy() + view.viewport.zw()).as_vec2() / size;
+                vec4(min.x, min.y, max.x, max.y)
+            },
         };
+
+        if config.uses_lightmap_history() && !is_combined_to {
+            if config.temporal_upsampling {
+                // 8 frame cycle over the Halton (2, 3) sequence, centered around the texel
+                let index = frame_count.0 % 8 + 1;
+                uniform.jitter = vec2(halton(index, 2), halton(index, 3)) - 0.5;
+            }
+
+            let camera_pos = world_data.map_or(Vec2::ZERO, |data| data.camera_pos);
+            let size = uvec2(window_size.width, window_size.height);
+
+            if let Some(history) = history
+                && history.size == size
+            {
+                // convert the camera's movement to uv space, where y points down
+                let delta = view
+                    .world_from_view
+                    .affine()
+                    .inverse()
+                    .transform_vector3((camera_pos - history.camera_pos).extend(0.0));
+                let clip = view.clip_from_view * delta.extend(0.0);
+
+                uniform.history_offset = vec2(clip.x, -clip.y) * 0.5;
+                uniform.history_valid = 1;
+            }
+
+            commands
+                .entity(entity)
+                .insert(LightmapHistory { camera_pos, size });
+        } else {
+            commands.entity(entity).remove::<LightmapHistory>();
+        }
+
         let mut buffer = UniformBuffer::<UniformFireflyConfig>::from(uniform);
         buffer.write_buffer(&render_device, &render_queue);
         commands
@@ -205,6 +450,9 @@ fn prepare_lightmap(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     mut texture_cache: ResMut<TextureCache>,
+    frame_count: Res<FrameCount>,
+    extracted_sprites: Res<ExtractedSprites>,
+    lights: Query<&ExtractedPointLight>,
     view_targets: Query<(
         Entity,
         &ViewTarget,
@@ -212,25 +460,26 @@ fn prepare_lightmap(
         Option<&ExtractedCombinedLightmaps>,
         &FireflyConfig,
         &Msaa,
+        Option<&LightmapHistoryStorage>,
+        Has<ExtractedCombineLightmapTo>,
     )>,
 ) {
-    for (entity, view_target, view, combined_lightmaps, config, _msaa) in &view_targets {
+    let needs_specular_map = extracted_sprites.needs_specular_map(lights.iter());
+
+    for (
+        entity,
+        view_target,
+        view,
+        combined_lightmaps,
+        config,
+        _msaa,
+        history_storage,
+        is_combined_to,
+    ) in &view_targets
+    {
         let format = view.target_format;
         let window_size = view_target.main_texture().size();
-
-        let size = match config.lightmap_size {
-            LightmapSize::Window => window_size,
-            LightmapSize::Fixed(size) => Extent3d {
-                width: size.x,
-                height: size.y,
-                depth_or_array_layers: 1,
-            },
-            LightmapSize::Scaled(scale) => Extent3d {
-                width: (window_size.width as f32 * scale) as u32,
-                height: (window_size.height as f32 * scale) as u32,
-                depth_or_array_layers: 1,
-            },
-        };
+        let size = lightmap_size(config, window_size);
 
         let light_map_texture = texture_cache.get(
             &render_device,
@@ -246,10 +495,7 @@ fn prepare_lightmap(
             },
         );
 
-        let stencil_format = match config.enable_32bit_stencils {
-            false => TextureFormat::Rgba16Float,
-            true => TextureFormat::Rgba32Float,
-        };
+        let stencil_format = stencil_format(config.enable_32bit_stencils, &render_device);
 
         let sprite_stencil_texture = texture_cache.get(
             &render_device,
@@ -279,12 +525,187 @@ fn prepare_lightmap(
             },
         );
 
+        if needs_specular_map {
+            let specular_map_texture = texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some("specular map"),
+                    size: view_target.main_texture().size(),
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::Rgba16Float,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+            );
+
+            commands
+                .entity(entity)
+                .insert(SpecularMapTexture(specular_map_texture));
+        } else {
+            commands.entity(entity).remove::<SpecularMapTexture>();
+        }
+
+        let parallax_texture = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("parallax"),
+                size: view_target.main_texture().size(),
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rg16Float,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        if !config.extra_channels.is_empty() && !is_combined_to {
+            let channel_textures = config
+                .extra_channels
+                .iter()
+                .map(|channel| {
+                    let texture = texture_cache.get(
+                        &render_device,
+                        TextureDescriptor {
+                            label: Some("light channel lightmap"),
+                            size,
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: TextureDimension::D2,
+                            format,
+                            usage: TextureUsages::RENDER_ATTACHMENT
+                                | TextureUsages::TEXTURE_BINDING,
+                            view_formats: &[],
+                        },
+                    );
+                    (*channel, texture)
+                })
+                .collect();
+
+            commands
+                .entity(entity)
+                .insert(LightChannelTextures(channel_textures));
+        } else {
+            commands.entity(entity).remove::<LightChannelTextures>();
+        }
+
         commands.entity(entity).insert((
             LightMapTexture(light_map_texture),
             SpriteStencilTexture(sprite_stencil_texture),
             NormalMapTexture(normal_map_texture),
+            ParallaxTexture(parallax_texture),
         ));
 
+        if config.shadow_mask && !is_combined_to {
+            let shadow_mask_texture = texture_cache.get(
+                &render_device,
+                TextureDescriptor {
+                    label: Some("shadow mask"),
+                    size,
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: TextureDimension::D2,
+                    format: TextureFormat::R8Unorm,
+                    usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                },
+            );
+
+            commands
+                .entity(entity)
+                .insert(ShadowMaskTexture(shadow_mask_texture));
+        } else {
+            commands.entity(entity).remove::<ShadowMaskTexture>();
+        }
+
+        if config.uses_lightmap_history() && !is_combined_to {
+            let [history_1, history_2] = match history_storage {
+                Some(storage) if storage.size == window_size && storage.format == format => {
+                    storage.textures.clone()
+                }
+                _ => {
+                    let textures = ["lightmap history 1", "lightmap history 2"].map(|label| {
+                        let texture = render_device.create_texture(&TextureDescriptor {
+                            label: Some(label),
+                            size: window_size,
+                            mip_level_count: 1,
+                            sample_count: 1,
+                            dimension: TextureDimension::D2,
+                            format,
+                            usage: TextureUsages::RENDER_ATTACHMENT
+                                | TextureUsages::TEXTURE_BINDING,
+                            view_formats: &[],
+                        });
+                        let default_view = texture.create_view(&TextureViewDescriptor::default());
+                        CachedTexture {
+                            texture,
+                            default_view,
+                        }
+                    });
+
+                    commands.entity(entity).insert(LightmapHistoryStorage {
+                        textures: textures.clone(),
+                        size: window_size,
+                        format,
+                    });
+                    textures
+                }
+            };
+
+            // the textures are swapped every frame, so the last result can be read while writing the new one
+            let (read, write) = match frame_count.0 % 2 == 0 {
+                true => (history_1, history_2),
+                false => (history_2, history_1),
+            };
+
+            commands
+                .entity(entity)
+                .insert(LightmapHistoryTextures { read, write });
+        } else {
+            commands
+                .entity(entity)
+                .remove::<(LightmapHistoryTextures, LightmapHistoryStorage)>();
+        }
+
+        if config.auto_exposure.is_some() && !is_combined_to {
+            let mut exposure_texture = |label| {
+                texture_cache.get(
+                    &render_device,
+                    TextureDescriptor {
+                        label: Some(label),
+                        size: Extent3d {
+                            width: 1,
+                            height: 1,
+                            depth_or_array_layers: 1,
+                        },
+                        mip_level_count: 1,
+                        sample_count: 1,
+                        dimension: TextureDimension::D2,
+                        format: TextureFormat::R32Float,
+                        usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                        view_formats: &[],
+                    },
+                )
+            };
+
+            let exposure_1 = exposure_texture("exposure 1");
+            let exposure_2 = exposure_texture("exposure 2");
+
+            // swapped every frame, so the gain can adapt from the previous one
+            let (read, write) = match frame_count.0 % 2 == 0 {
+                true => (exposure_1, exposure_2),
+                false => (exposure_2, exposure_1),
+            };
+
+            commands
+                .entity(entity)
+                .insert(ExposureTextures { read, write });
+        } else {
+            commands.entity(entity).remove::<ExposureTextures>();
+        }
+
         if let Some(combined_lightmaps) = combined_lightmaps
             && !combined_lightmaps.0.is_empty()
         {
@@ -312,6 +733,278 @@ fn prepare_lightmap(
     }
 }
 
+/// Smallest [scale](LightmapSize::Scaled) a lightmap is rendered at. Invalid scales are clamped to it.
+const MIN_LIGHTMAP_SCALE: f32 = 0.01;
+
+/// Size of the lightmap of a camera, based on its [`LightmapSize`]. The lightmap is always at least 1x1.
+fn lightmap_size(config: &FireflyConfig, window_size: Extent3d) -> Extent3d {
+    let (width, height) = match config.lightmap_size {
+        LightmapSize::Window => (window_size.width, window_size.height),
+        LightmapSize::Fixed(size) => (size.x, size.y),
+        LightmapSize::Scaled(scale) => {
+            let scale = scale.max(MIN_LIGHTMAP_SCALE);
+            (
+                (window_size.width as f32 * scale) as u32,
+                (window_size.height as f32 * scale) as u32,
+            )
+        }
+    };
+
+    Extent3d {
+        width: width.max(1),
+        height: height.max(1),
+        depth_or_array_layers: 1,
+    }
+}
+
+fn prepare_volumetric_lights(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut texture_cache: ResMut<TextureCache>,
+    pipeline_cache: Res<PipelineCache>,
+    pipeline: Res<LightmapVolumetricPipeline>,
+    mut pipelines: ResMut<SpecializedRenderPipelines<LightmapVolumetricPipeline>>,
+    views: Query<(
+        Entity,
+        &ViewTarget,
+        &ExtractedView,
+        &RenderVisibleEntities,
+        &FireflyConfig,
+        Has<ExtractedCombineLightmapTo>,
+    )>,
+    lights: Query<&ExtractedPointLight>,
+) {
+    for (entity, view_target, view, visible_entities, config, is_combined_to) in &views {
+        // lightmaps that are combined into another camera's are never applied on their own
+        let mut volumetric_lights = vec![];
+
+        if !is_combined_to && let Some(visible) = visible_entities.get::<PointLight2d>() {
+            let view_from_world = view.world_from_view.affine().inverse();
+
+            for (render_entity, _) in visible.iter_visible() {
+                let Ok(light) = lights.get(*render_entity) else {
+                    continue;
+                };
+                let Some(volumetric) = light.volumetric else {
+                    continue;
+                };
+
+                // convert the light to uv space, where y points down
+                let pos = view.clip_from_view
+                    * view_from_world
+                        .transform_point3(light.pos.extend(0.0))
+                        .extend(1.0);
+                let radius = view.clip_from_view
+                    * view_from_world
+                        .transform_vector3(Vec3::splat(light.radius).with_z(0.0))
+                        .extend(0.0);
+
+                volumetric_lights.push(UniformVolumetricLight {
+                    pos: vec2(pos.x, -pos.y) * 0.5 + 0.5,
+                    radius: (radius.truncate().truncate() * 0.5).abs(),
+                    strength: volumetric.strength,
+                    samples: volumetric.samples,
+                });
+            }
+        }
+
+        if volumetric_lights.is_empty() {
+            commands.entity(entity).remove::<(
+                VolumetricLightTexture,
+                VolumetricLightsBuffer,
+                SpecializedVolumetricPipeline,
+            )>();
+            continue;
+        }
+
+        let size = lightmap_size(config, view_target.main_texture().size());
+
+        let texture = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("volumetric lights"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: view.target_format,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        let mut buffer = StorageBuffer::from(volumetric_lights);
+        buffer.write_buffer(&render_device, &render_queue);
+
+        let key = LightPipelineKey::from_target_format(view.target_format);
+        let pipeline_id = pipelines.specialize(&pipeline_cache, &pipeline, key);
+
+        commands.entity(entity).insert((
+            VolumetricLightTexture(texture),
+            VolumetricLightsBuffer(buffer),
+            SpecializedVolumetricPipeline(pipeline_id),
+        ));
+    }
+}
+
+fn prepare_occluder_coverage(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut texture_cache: ResMut<TextureCache>,
+    mut views: Query<(
+        Entity,
+        &ViewTarget,
+        &FireflyConfig,
+        &RenderLayers,
+        &ExtractedWorldData,
+        &Projection,
+        Option<&mut OccluderCoverageVertices>,
+    )>,
+    occluders: Query<&ExtractedOccluder>,
+) {
+    for (entity, view_target, config, render_layers, world_data, projection, vertices) in &mut views
+    {
+        let Projection::Orthographic(projection) = projection else {
+            continue;
+        };
+
+        let camera_aabb = Aabb2d {
+            min: projection.area.min + world_data.camera_pos,
+            max: projection.area.max + world_data.camera_pos,
+        };
+
+        let visible_occluders: Vec<_> = occluders
+            .iter()
+            .filter(|occluder| {
+                ((config.screen_space_shadows && occluder.casts_shadow)
+                    || occluder.refraction != 0.0)
+                    && render_layers.intersects(&occluder.render_layers)
+                    && occluder.aabb.intersects(&camera_aabb)
+            })
+            .collect();
+
+        // refracting occluders need the pass even without screen-space shadows
+        if !config.screen_space_shadows && visible_occluders.is_empty() {
+            commands.entity(entity).remove::<(
+                OccluderCoverageTexture,
+                OccluderRefractionTexture,
+                OccluderCoverageVertices,
+            )>();
+            continue;
+        }
+
+        let size = lightmap_size(config, view_target.main_texture().size());
+
+        let texture = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("occluder coverage"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Unorm,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        let refraction_texture = texture_cache.get(
+            &render_device,
+            TextureDescriptor {
+                label: Some("occluder refraction"),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba16Float,
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+        );
+
+        let mut new_vertices = None;
+        let vertices = match vertices {
+            Some(vertices) => &mut vertices.into_inner().0,
+            None => new_vertices.insert(RawBufferVec::new(BufferUsages::VERTEX)),
+        };
+
+        vertices.clear();
+
+        for occluder in visible_occluders {
+            // refracting occluders that don't cast shadows are drawn without covering anything
+            let opacity = match config.screen_space_shadows && occluder.casts_shadow {
+                true => occluder.opacity.clamp(0.0, 1.0),
+                false => 0.0,
+            };
+
+            let refracts = match occluder.refraction != 0.0 {
+                true => 1.0,
+                false => 0.0,
+            };
+
+            // convex pieces are drawn as triangle fans around their center, where the surface normal is flat
+            for piece in occluder.convex_pieces() {
+                if piece.len() < 3 {
+                    continue;
+                }
+
+                let center = piece.iter().sum::<Vec2>() / piece.len() as f32;
+                let normals = vertex_normals(&piece, center);
+
+                let vertex = |pos: Vec2, normal: Vec2| CoverageVertex {
+                    pos,
+                    opacity,
+                    refraction: -normal * occluder.refraction,
+                    refracts,
+                };
+
+                for i in 0..piece.len() {
+                    let j = (i + 1) % piece.len();
+
+                    vertices.push(vertex(center, Vec2::ZERO));
+                    vertices.push(vertex(piece[i], normals[i]));
+                    vertices.push(vertex(piece[j], normals[j]));
+                }
+            }
+        }
+
+        vertices.write_buffer(&render_device, &render_queue);
+
+        commands.entity(entity).insert((
+            OccluderCoverageTexture(texture),
+            OccluderRefractionTexture(refraction_texture),
+        ));
+
+        if let Some(vertices) = new_vertices {
+            commands
+                .entity(entity)
+                .insert(OccluderCoverageVertices(vertices));
+        }
+    }
+}
+
+/// Outward normals at the vertices of a convex polygon, averaged from its two adjacent edges.
+fn vertex_normals(vertices: &[Vec2], center: Vec2) -> Vec<Vec2> {
+    let edge_normal = |a: Vec2, b: Vec2| {
+        let normal = (b - a).perp().normalize_or_zero();
+        match normal.dot((a + b) * 0.5 - center) < 0.0 {
+            true => -normal,
+            false => normal,
+        }
+    };
+
+    (0..vertices.len())
+        .map(|i| {
+            let prev = vertices[(i + vertices.len() - 1) % vertices.len()];
+            let next = vertices[(i + 1) % vertices.len()];
+            (edge_normal(prev, vertices[i]) + edge_normal(vertices[i], next)).normalize_or_zero()
+        })
+        .collect()
+}
+
 pub(crate) fn prepare_data(
     render_device: Res<RenderDevice>,
     render_queue: Res<RenderQueue>,
@@ -321,8 +1014,14 @@ pub(crate) fn prepare_data(
         &mut LightPointer,
         &LightIndex,
         &mut BinBuffers,
+        &mut OccluderDataCache,
+    )>,
+    occluders: Query<(
+        Entity,
+        &ExtractedOccluder,
+        &RoundOccluderIndex,
+        &PolyOccluderIndex,
     )>,
-    occluders: Query<(&ExtractedOccluder, &RoundOccluderIndex, &PolyOccluderIndex)>,
     cameras: Query<(
         &ExtractedView,
         &RenderLayers,
@@ -332,36 +1031,54 @@ pub(crate) fn prepare_data(
         &NormalMapTexture,
         &BufferedFireflyConfig,
         &FireflyConfig,
+        Option<&SpecularMapTexture>,
+        Option<&OccluderCoverageTexture>,
+        &ParallaxTexture,
     )>,
     _phases: Res<ViewBinnedRenderPhases<LightmapPhase>>,
+    skipped_lights: Res<BudgetSkippedLights>,
     lightmap_pipeline: Res<LightmapCreationPipeline>,
     mut light_bind_groups: ResMut<LightBindGroups>,
+    mut light_pointers: ResMut<LightPointers>,
     mut batches: ResMut<LightBatches>,
     round_occluders: Res<BufferManager<UniformRoundOccluder>>,
     poly_occluders: Res<BufferManager<UniformOccluder>>,
     light_buffer: Res<BufferManager<UniformPointLight>>,
     vertices: Res<VertexBuffer>,
     pipeline_cache: Res<PipelineCache>,
+    gpu_images: Res<RenderAssets<GpuImage>>,
+    fallback_image: Res<FallbackImage>,
+    fallback_image_zero: Res<FallbackImageZero>,
 ) {
     batches.clear();
 
+    // the indices of all lights are written in a single buffer, each light binding its own offset
+    light_pointers.0.clear();
+    for (_, _, mut light_pointer, light_index, _, _) in &mut lights {
+        if let Some(index) = light_index.0 {
+            light_pointer.0 = light_pointers.0.push(&(index.index as u32));
+        }
+    }
+    light_pointers.0.write_buffer(&render_device, &render_queue);
+
+    let light_pointer_binding = light_pointers.0.binding();
+
     let light_bind_groups = &mut *light_bind_groups;
 
     let mut lights: Vec<_> = lights.iter_mut().collect();
 
     lights
         .par_splat_map_mut(ComputeTaskPool::get(), None, |_, lights| {
-            let mut bind_groups: Vec<(Entity, HashMap<RetainedViewEntity, BindGroup>)> = vec![];
+            let mut bind_groups: Vec<(Entity, u32, HashMap<RetainedViewEntity, BindGroup>)> =
+                vec![];
 
-            for (entity, light, light_pointer, light_index, bins) in lights {
-                let Some(index) = light_index.0 else {
-                    continue;
-                };
+            for (entity, light, light_pointer, light_index, bins, cache) in lights {
+                // entries that aren't reused this frame are dropped
+                let mut previous_cache = std::mem::take(&mut cache.0);
 
-                light_pointer.0.set(index.index as u32);
-                light_pointer.0.write_buffer(&render_device, &render_queue);
-
-                let Some(light_pointer_binding) = light_pointer.0.binding() else {
+                let (Some(_), Some(light_pointer_binding)) =
+                    (light_index.0, &light_pointer_binding)
+                else {
                     continue;
                 };
 
@@ -372,6 +1089,14 @@ pub(crate) fn prepare_data(
                             return None;
                         }
 
+                        // lights out of the camera's budget aren't drawn, so their shadows aren't needed either
+                        if skipped_lights
+                            .0
+                            .contains(&(camera.0.retained_view_entity, *entity))
+                        {
+                            return None;
+                        }
+
                         let Projection::Orthographic(projection) = camera.3 else {
                             return None;
                         };
@@ -405,13 +1130,49 @@ pub(crate) fn prepare_data(
                     })
                     .collect::<Vec<_>>();
 
-                for (occluder, round_index, poly_index) in &occluders {
-                    if !light.cast_shadows
-                        || !light.render_layers.intersects(&occluder.render_layers)
-                    {
-                        continue;
-                    }
+                if cameras.is_empty() {
+                    bind_groups.push((*entity, light_pointer.0, default()));
+                    continue;
+                }
+
+                // screen-space shadows don't need the occluders, unless a camera renders the regular shadows
+                let screen_space_shadows = light.screen_space_shadows
+                    && cameras
+                        .iter()
+                        .all(|(camera, _)| camera.7.screen_space_shadows);
 
+                let mut light_occluders: Vec<_> = occluders
+                    .iter()
+                    .filter(|(_, occluder, _, _)| {
+                        light.cast_shadows
+                            && !screen_space_shadows
+                            && (occluder.casts_shadow || occluder.shade_self)
+                            && light.render_layers.intersects(&occluder.render_layers)
+                            && light
+                                .occlusion_layers
+                                .intersects(&occluder.occlusion_layers)
+                            && occluder.faces(light.pos)
+                            && in_light_cone(occluder, light)
+                    })
+                    .collect();
+
+                if let Some(max_occluders) = light.max_occluders
+                    && light_occluders.len() > max_occluders as usize
+                {
+                    light_occluders.retain(|(_, occluder, _, _)| {
+                        cameras.iter().any(|(camera, light_aabb)| {
+                            occluder.aabb.intersects(light_aabb)
+                                && camera.1.intersects(&occluder.render_layers)
+                        })
+                    });
+
+                    light_occluders.sort_by_cached_key(|(_, occluder, _, _)| {
+                        Reverse(FloatOrd(occluder_angular_size(occluder, light.pos)))
+                    });
+                    light_occluders.truncate(max_occluders as usize);
+                }
+
+                for (occluder_entity, occluder, round_index, poly_index) in light_occluders {
                     let mut any_soft_shadows = false;
 
                     let mut retained_views: HashSet<_, FixedHasher> = HashSet::default();
@@ -435,16 +1196,41 @@ pub(crate) fn prepare_data(
                         .map(|(_, x)| x)
                         .collect::<Vec<_>>();
 
-                    if let Occluder2dShape::RoundRectangle {
+                    let (occluder_index, start_vertex) = match occluder.shape {
+                        Occluder2dShape::RoundRectangle { .. } => {
+                            let Some(occluder_index) = round_index.0 else {
+                                continue;
+                            };
+                            (occluder_index.index as u32, 0)
+                        }
+                        _ => {
+                            let Some(occluder_index) = poly_index.occluder else {
+                                continue;
+                            };
+                            let Some(vertex_index) = poly_index.vertices else {
+                                continue;
+                            };
+                            (occluder_index.index as u32, vertex_index.index as u32)
+                        }
+                    };
+
+                    // reuse last frame's data if neither the light nor the occluder changed
+                    let cached = previous_cache.remove(&occluder_entity).filter(|cached| {
+                        !light.changes.0
+                            && !occluder.changes.0
+                            && cached.occluder_index == occluder_index
+                            && cached.start_vertex == start_vertex
+                            && cached.soft_shadows == any_soft_shadows
+                    });
+
+                    let data = if let Some(cached) = cached {
+                        cached.data
+                    } else if let Occluder2dShape::RoundRectangle {
                         half_width,
                         half_height,
                         radius,
                     } = occluder.shape
                     {
-                        let Some(occluder_index) = round_index.0 else {
-                            continue;
-                        };
-
                         let vertices = vec![
                             vec2(-half_width - radius, -half_height - radius),
                             vec2(-half_width - radius, half_height + radius),
@@ -472,28 +1258,19 @@ pub(crate) fn prepare_data(
                         let light_inside_occluder = closest == light_pos;
 
                         push_vertices(
-                            bins,
                             &vertices,
                             light.pos,
                             light.core.radius,
-                            0,
-                            occluder_index.index as u32,
+                            start_vertex,
+                            occluder_index,
                             closest.distance(light_pos),
                             // 0.0,
                             light_inside_occluder,
                             false,
                             any_soft_shadows,
                             true,
-                        );
+                        )
                     } else {
-                        let Some(occluder_index) = poly_index.occluder else {
-                            continue;
-                        };
-
-                        let Some(vertex_index) = poly_index.vertices else {
-                            continue;
-                        };
-
                         let vertices = occluder.vertices();
 
                         let light_inside_occluder =
@@ -508,21 +1285,47 @@ pub(crate) fn prepare_data(
                         let closest = occluder.aabb.closest_point(light.pos);
 
                         push_vertices(
-                            bins,
                             &vertices,
                             light.pos,
                             light.core.radius,
-                            vertex_index.index as u32,
-                            occluder_index.index as u32,
+                            start_vertex,
+                            occluder_index,
                             closest.distance(light.pos),
                             light_inside_occluder,
                             true,
                             any_soft_shadows,
                             occluder.shape.is_concave(),
-                        );
+                        )
+                    };
+
+                    for bins in bins {
+                        for data in &data {
+                            bins.add_occluder(data);
+                        }
                     }
+
+                    cache.0.insert(
+                        occluder_entity,
+                        CachedOccluderData {
+                            occluder_index,
+                            start_vertex,
+                            soft_shadows: any_soft_shadows,
+                            data,
+                        },
+                    );
                 }
 
+                // the cookie is looked up every frame, so render target images are always up to date
+                let cookie = light
+                    .cookie
+                    .and_then(|id| gpu_images.get(id))
+                    .map_or(&fallback_image.d2.texture_view, |image| &image.texture_view);
+
+                let falloff_gradient = light
+                    .falloff_gradient
+                    .and_then(|id| gpu_images.get(id))
+                    .map_or(&fallback_image.d2.texture_view, |image| &image.texture_view);
+
                 let mut bind_group = HashMap::default();
                 for (camera, _) in cameras {
                     let bins = bins.0.get_mut(&camera.0.retained_view_entity).unwrap();
@@ -544,27 +1347,41 @@ pub(crate) fn prepare_data(
                                 &camera.4.0.default_view,
                                 &camera.5.0.default_view,
                                 camera.6.0.binding().unwrap(),
+                                camera
+                                    .8
+                                    .map_or(&fallback_image_zero.texture_view, |texture| {
+                                        &texture.0.default_view
+                                    }),
+                                cookie,
+                                falloff_gradient,
+                                camera
+                                    .9
+                                    .map_or(&fallback_image_zero.texture_view, |texture| {
+                                        &texture.0.default_view
+                                    }),
+                                &camera.10.0.default_view,
                             )),
                         ),
                     );
                 }
 
-                bind_groups.push((*entity, bind_group));
+                bind_groups.push((*entity, light_pointer.0, bind_group));
             }
             bind_groups
         })
         .iter()
         .for_each(|bind_groups| {
-            for (entity, bind_group) in bind_groups {
+            for (entity, pointer_offset, bind_group) in bind_groups {
                 light_bind_groups
                     .values
                     .entry(*entity)
                     .insert(bind_group.clone());
 
                 for retained_view in bind_group.keys() {
-                    batches
-                        .entry((*retained_view, *entity))
-                        .insert(LightBatch { id: *entity });
+                    batches.entry((*retained_view, *entity)).insert(LightBatch {
+                        id: *entity,
+                        pointer_offset: *pointer_offset,
+                    });
                 }
             }
         });
@@ -601,8 +1418,56 @@ struct Vertex {
     pub angle: f32,
 }
 
+/// Approximate angular size of an occluder as seen from the light, used to prioritize occluders
+/// when a light's [`max_occluders`](crate::prelude::PointLight2d::max_occluders) is exceeded.
+fn occluder_angular_size(occluder: &ExtractedOccluder, light_pos: Vec2) -> f32 {
+    let distance = occluder.aabb.closest_point(light_pos).distance(light_pos);
+
+    if distance == 0. {
+        return PI;
+    }
+
+    let half_size = (occluder.aabb.max - occluder.aabb.min).length() / 2.;
+    half_size.atan2(distance)
+}
+
+/// Whether an occluder overlaps the angular sector lit by a light, and can thus cast visible shadows from it.
+///
+/// The occluder's AABB is grown by the light's core radius, so soft shadows reaching into the sector are kept.
+fn in_light_cone(occluder: &ExtractedOccluder, light: &ExtractedPointLight) -> bool {
+    let half_angle = light.angle.outer.to_radians() / 2.;
+    if half_angle >= PI || light.dir == Vec2::ZERO {
+        return true;
+    }
+
+    let aabb = occluder
+        .aabb
+        .grow(Vec2::splat(light.core.radius.clamp(0.0, light.radius)));
+
+    if aabb.closest_point(light.pos) == light.pos {
+        return true;
+    }
+
+    // the AABB spans less than half a turn as seen from the light, centered around the direction to its center
+    let center = aabb.center() - light.pos;
+    let (min, max) = [
+        aabb.min,
+        vec2(aabb.min.x, aabb.max.y),
+        aabb.max,
+        vec2(aabb.max.x, aabb.min.y),
+    ]
+    .iter()
+    .map(|corner| center.angle_to(corner - light.pos))
+    .fold((0.0_f32, 0.0_f32), |(min, max), angle| {
+        (min.min(angle), max.max(angle))
+    });
+
+    let mid = Rot2::radians(light.dir.angle_to(center) + (min + max) / 2.).as_radians();
+    mid.abs() - (max - min) / 2. <= half_angle
+}
+
+/// Computes the [`OccluderData`] of an occluder as seen from a light, splitting polygonal occluders into chains of vertices.
 fn push_vertices(
-    mut bins: Vec<&mut BinBuffer>,
     occluder_vertices: &[Vec2],
     light_pos: Vec2,
     light_radius: f32,
@@ -613,25 +1478,25 @@ fn push_vertices(
     poly: bool,
     soft_shadows: bool,
     concave: bool,
-) {
+) -> Vec<OccluderData> {
+    let mut result = vec![];
+
     let index = match poly {
         true => (1 << 31) | index,
         false => index,
     };
 
     if !poly && rev {
-        bins.iter_mut().for_each(|bins| {
-            bins.add_occluder(&OccluderData {
-                pointer: OccluderPointer {
-                    index,
-                    distance,
-                    ..default()
-                },
-                min_angle: 0.0,
-                angle: TAU,
-            })
+        result.push(OccluderData {
+            pointer: OccluderPointer {
+                index,
+                distance,
+                ..default()
+            },
+            min_angle: 0.0,
+            angle: TAU,
         });
-        return;
+        return result;
     }
     // info!("pushing vertices: {occluder_vertices:?}");
     // info!("start vertex: {start_vertex}");
@@ -741,9 +1606,7 @@ fn push_vertices(
                         angle: slice.angle + angle_left + angle_right,
                     };
 
-                    bins.iter_mut().for_each(|bins| {
-                        bins.add_occluder(&data);
-                    });
+                    result.push(data);
                 }
                 Some(split) => {
                     let data1 = OccluderData {
@@ -770,10 +1633,8 @@ fn push_vertices(
                         angle: slice.angle + angle_left + angle_right,
                     };
 
-                    bins.iter_mut().for_each(|bins| {
-                        bins.add_occluder(&data1);
-                        bins.add_occluder(&data2);
-                    });
+                    result.push(data1);
+                    result.push(data2);
                 }
             }
         }
@@ -837,6 +1698,8 @@ fn push_vertices(
         }
         push_slice(&slice, &vertices);
     }
+
+    result
 }
 
 fn prepare_light_luts(
@@ -926,7 +1789,7 @@ fn prepare_sprite_image_bind_groups(
             // Images don't have dependencies
             AssetEvent::LoadedWithDependencies { .. } => {}
             AssetEvent::Unused { id } | AssetEvent::Modified { id } | AssetEvent::Removed { id } => {
-                image_bind_groups.values.retain(|k, _| k.0 != *id && k.1 != *id);
+                image_bind_groups.values.retain(|k, _| k.0 != *id && k.1 != *id && k.3 != *id && k.5 != *id);
             }
         };
     }
@@ -948,6 +1811,12 @@ fn prepare_sprite_image_bind_groups(
         let mut batch_image_handle = AssetId::invalid();
         let mut batch_normal_handle;
         let mut is_dummy;
+        let mut batch_specular_handle = None;
+        let mut specular_handle;
+        let mut is_specular_dummy;
+        let mut batch_height_map_handle = None;
+        let mut height_map_handle;
+        let mut is_height_map_dummy;
 
         // Iterate through the phase items and detect when successive sprites that can be batched.
         // Spawn an entity with a `SpriteBatch` component for each possible batch.
@@ -967,7 +1836,10 @@ fn prepare_sprite_image_bind_groups(
                 continue;
             };
 
-            if batch_image_handle != extracted_sprite.image_handle_id {
+            if batch_image_handle != extracted_sprite.image_handle_id
+                || batch_specular_handle != extracted_sprite.specular_handle_id
+                || batch_height_map_handle != extracted_sprite.height_map_handle_id
+            {
                 let Some(gpu_image) = gpu_images.get(extracted_sprite.image_handle_id) else {
                     continue;
                 };
@@ -988,6 +1860,36 @@ fn prepare_sprite_image_bind_groups(
                     continue;
                 };
 
+                batch_specular_handle = extracted_sprite.specular_handle_id;
+
+                (specular_handle, is_specular_dummy) = match batch_specular_handle {
+                    None => (batch_image_handle, true),
+                    Some(x) => (x, false),
+                };
+
+                let Some(specular_image) = (if is_specular_dummy {
+                    Some(gpu_image)
+                } else {
+                    gpu_images.get(specular_handle)
+                }) else {
+                    continue;
+                };
+
+                batch_height_map_handle = extracted_sprite.height_map_handle_id;
+
+                (height_map_handle, is_height_map_dummy) = match batch_height_map_handle {
+                    None => (batch_image_handle, true),
+                    Some(x) => (x, false),
+                };
+
+                let Some(height_map_image) = (if is_height_map_dummy {
+                    Some(gpu_image)
+                } else {
+                    gpu_images.get(height_map_handle)
+                }) else {
+                    continue;
+                };
+
                 let mut dummy_buffer = UniformBuffer::<u32>::from(if is_dummy { 1 } else { 0 });
                 dummy_buffer.write_buffer(&render_device, &render_queue);
 
@@ -995,9 +1897,34 @@ fn prepare_sprite_image_bind_groups(
                     continue;
                 };
 
+                let mut specular_dummy_buffer =
+                    UniformBuffer::<u32>::from(if is_specular_dummy { 1 } else { 0 });
+                specular_dummy_buffer.write_buffer(&render_device, &render_queue);
+
+                let Some(specular_dummy_buffer_binding) = specular_dummy_buffer.binding() else {
+                    continue;
+                };
+
+                let mut height_map_dummy_buffer =
+                    UniformBuffer::<u32>::from(if is_height_map_dummy { 1 } else { 0 });
+                height_map_dummy_buffer.write_buffer(&render_device, &render_queue);
+
+                let Some(height_map_dummy_buffer_binding) = height_map_dummy_buffer.binding()
+                else {
+                    continue;
+                };
+
                 image_bind_groups
                     .values
-                    .entry((batch_image_handle, batch_normal_handle, is_dummy))
+                    .entry((
+                        batch_image_handle,
+                        batch_normal_handle,
+                        is_dummy,
+                        specular_handle,
+                        is_specular_dummy,
+                        height_map_handle,
+                        is_height_map_dummy,
+                    ))
                     .or_insert_with(|| {
                         render_device.create_bind_group(
                             "sprite_material_bind_group",
@@ -1007,6 +1934,10 @@ fn prepare_sprite_image_bind_groups(
                                 &normal_image.texture_view,
                                 &gpu_image.sampler,
                                 dummy_buffer_binding,
+                                &specular_image.texture_view,
+                                specular_dummy_buffer_binding,
+                                &height_map_image.texture_view,
+                                height_map_dummy_buffer_binding,
                             )),
                         )
                     });
@@ -1017,6 +1948,10 @@ fn prepare_sprite_image_bind_groups(
                         image_handle_id: batch_image_handle,
                         normal_handle_id: batch_normal_handle,
                         normal_dummy: is_dummy,
+                        specular_handle_id: specular_handle,
+                        specular_dummy: is_specular_dummy,
+                        height_map_handle_id: height_map_handle,
+                        height_map_dummy: is_height_map_dummy,
                         range: index..index,
                     },
                 ));
@@ -1090,9 +2025,7 @@ fn prepare_sprite_image_bind_groups(
                         .push(SpriteInstance::from(
                             &transform,
                             &uv_offset_scale,
-                            extracted_sprite.transform.translation().z,
-                            extracted_sprite.height,
-                            extracted_sprite.transform.translation().y,
+                            extracted_sprite,
                         ));
 
                     if let Some(batch) = current_batch.as_mut() {
@@ -1140,9 +2073,7 @@ fn prepare_sprite_image_bind_groups(
                             .push(SpriteInstance::from(
                                 &transform,
                                 &uv_offset_scale,
-                                extracted_sprite.transform.translation().z,
-                                extracted_sprite.height,
-                                extracted_sprite.transform.translation().y,
+                                extracted_sprite,
                             ));
 
                         if let Some(batch) = current_batch.as_mut() {
@@ -0,0 +1,295 @@
+//! Module containing the CPU readback of the lightmap.
+
+use bevy::{
+    asset::RenderAssetUsages,
+    prelude::*,
+    render::{
+        gpu_readback::{Readback, ReadbackComplete},
+        render_resource::{Extent3d, TextureDimension, TextureFormat, TextureUsages},
+    },
+};
+
+/// Optional camera component that copies a downscaled version of the camera's lightmap to the CPU every frame.
+///
+/// This gives GPU-accurate light values in the main world, e.g. for stealth mechanics that check how lit the player is.
+/// The copied lightmap is the one applied to the camera's output, before the ambient light, exposure and light bands.
+/// It covers the camera's whole render target, so with a custom [viewport](Camera::viewport) only part of it is sampled.
+///
+/// The copy is read back asynchronously, so the values lag **at least one frame** behind what is rendered, usually two or three.
+/// Until the first copy arrives, [`sampled_light_at`](LightmapReadback::sampled_light_at) returns None.
+///
+/// **Performance Impact:** One small extra pass, plus copying [size](LightmapReadback::new) texels from the GPU every frame.
+/// Readbacks are expensive, so keep the size small, and only add this component to cameras that need it.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((Camera2d, FireflyConfig::default(), LightmapReadback::new(uvec2(64, 36))));
+///
+/// fn player_visibility(camera: Single<&LightmapReadback>, window: Single<&Window>) {
+///     if let Some(cursor) = window.cursor_position()
+///         && let Some(light) = camera.sampled_light_at(cursor)
+///     {
+///         info!("light under the cursor: {light}");
+///     }
+/// }
+/// ```
+#[derive(Component)]
+pub struct LightmapReadback {
+    size: UVec2,
+    viewport: Rect,
+    target_size: Vec2,
+    image: Option<Handle<Image>>,
+    pixels: Vec<Vec3>,
+}
+
+impl Default for LightmapReadback {
+    fn default() -> Self {
+        Self::new(uvec2(64, 36))
+    }
+}
+
+/// Size of a texel of the readback texture, in bytes.
+const READBACK_TEXEL_SIZE: usize = 16;
+
+/// Number of texels each row of the readback texture is padded to, since GPU copies need 256 byte aligned rows.
+const ROW_ALIGNMENT: u32 = 256 / READBACK_TEXEL_SIZE as u32;
+
+impl LightmapReadback {
+    /// Construct a new readback of the given size, in texels.
+    ///
+    /// The width is rounded up to a multiple of 16.
+    pub fn new(size: UVec2) -> Self {
+        Self {
+            size: uvec2(size.x.max(1).next_multiple_of(ROW_ALIGNMENT), size.y.max(1)),
+            viewport: Rect::default(),
+            target_size: Vec2::ZERO,
+            image: None,
+            pixels: vec![],
+        }
+    }
+
+    /// Size of the copied lightmap, in texels.
+    pub fn size(&self) -> UVec2 {
+        self.size
+    }
+
+    /// Get the light at the given position on the camera's viewport, in logical pixels (like [`Window::cursor_position`]).
+    ///
+    /// Returns None if the position is outside of the viewport, or if no copy of the lightmap has arrived yet.
+    pub fn sampled_light_at(&self, screen_pos: Vec2) -> Option<Vec3> {
+        if self.pixels.is_empty() || self.target_size.cmple(Vec2::ZERO).any() {
+            return None;
+        }
+
+        if screen_pos.cmplt(Vec2::ZERO).any() || screen_pos.cmpge(self.viewport.size()).any() {
+            return None;
+        }
+
+        let uv = (self.viewport.min + screen_pos) / self.target_size;
+
+        let texel = (uv * self.size.as_vec2()).as_uvec2().min(self.size - 1);
+        self.pixels
+            .get((texel.y * self.size.x + texel.x) as usize)
+            .copied()
+    }
+
+    pub(crate) fn image(&self) -> Option<&Handle<Image>> {
+        self.image.as_ref()
+    }
+
+    fn read_pixels(&mut self, data: &[u8]) {
+        let (width, height) = (self.size.x as usize, self.size.y as usize);
+
+        // rows can be padded by the copy
+        let row_length = data.len() / height;
+        if row_length < width * READBACK_TEXEL_SIZE {
+            return;
+        }
+
+        self.pixels.clear();
+        self.pixels
+            .extend(data.chunks_exact(row_length).flat_map(|row| {
+                row[..width * READBACK_TEXEL_SIZE]
+                    .chunks_exact(READBACK_TEXEL_SIZE)
+                    .map(|texel| {
+                        let [r, g, b, _]: [f32; 4] = bytemuck::pod_read_unaligned(texel);
+                        vec3(r, g, b)
+                    })
+            }));
+    }
+}
+
+/// Render World component of cameras with a [`LightmapReadback`], containing the texture the lightmap is copied into.
+#[derive(Component)]
+pub struct ExtractedLightmapReadback(pub AssetId<Image>);
+
+/// Readback entity of a camera with a [`LightmapReadback`].
+#[derive(Component)]
+struct LightmapReadbackOf(Entity);
+
+/// Plugin that handles the [`LightmapReadback`] of cameras. Added automatically by [`FireflyPlugin`](crate::prelude::FireflyPlugin).
+pub struct ReadbackPlugin;
+
+impl Plugin for ReadbackPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PostUpdate, (update_readbacks, despawn_readbacks));
+    }
+}
+
+fn update_readbacks(
+    mut commands: Commands,
+    mut cameras: Query<(Entity, &Camera, &mut LightmapReadback)>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    for (entity, camera, mut readback) in &mut cameras {
+        if let Some(viewport) = camera.logical_viewport_rect()
+            && readback.viewport != viewport
+        {
+            readback.viewport = viewport;
+        }
+
+        if let Some(target_size) = camera.logical_target_size()
+            && readback.target_size != target_size
+        {
+            readback.target_size = target_size;
+        }
+
+        if readback.image.is_some() {
+            continue;
+        }
+
+        let mut image = Image::new_uninit(
+            Extent3d {
+                width: readback.size.x,
+                height: readback.size.y,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            TextureFormat::Rgba32Float,
+            RenderAssetUsages::RENDER_WORLD,
+        );
+        image.texture_descriptor.usage |= TextureUsages::RENDER_ATTACHMENT
+            | TextureUsages::COPY_SRC
+            | TextureUsages::TEXTURE_BINDING;
+
+        let image = images.add(image);
+        readback.image = Some(image.clone());
+
+        commands
+            .spawn((Readback::texture(image), LightmapReadbackOf(entity)))
+            .observe(
+                move |event: On<ReadbackComplete>, mut cameras: Query<&mut LightmapReadback>| {
+                    if let Ok(mut readback) = cameras.get_mut(entity) {
+                        readback.read_pixels(&event.data);
+                    }
+                },
+            );
+    }
+}
+
+fn despawn_readbacks(
+    mut commands: Commands,
+    readbacks: Query<(Entity, &LightmapReadbackOf)>,
+    cameras: Query<(), With<LightmapReadback>>,
+) {
+    for (entity, readback_of) in &readbacks {
+        if !cameras.contains(readback_of.0) {
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // texels store their own coordinates, with each row padded to `row_length` bytes
+    fn padded_texels(size: UVec2, row_length: usize) -> Vec<u8> {
+        let mut data = vec![];
+        for y in 0..size.y {
+            let start = data.len();
+            for x in 0..size.x {
+                data.extend_from_slice(bytemuck::bytes_of(&[x as f32, y as f32, 1.0, 1.0]));
+            }
+            data.resize(start + row_length, 0xFF);
+        }
+        data
+    }
+
+    fn readback(viewport: Rect) -> LightmapReadback {
+        let mut readback = LightmapReadback::new(uvec2(16, 2));
+        readback.viewport = viewport;
+        readback.target_size = vec2(160., 40.);
+        readback.read_pixels(&padded_texels(readback.size, 320));
+        readback
+    }
+
+    #[test]
+    fn read_pixels_strips_row_padding() {
+        let readback = readback(Rect::new(0., 0., 160., 40.));
+
+        assert_eq!(readback.pixels.len(), 32);
+        assert_eq!(readback.pixels[0], vec3(0., 0., 1.));
+        assert_eq!(readback.pixels[15], vec3(15., 0., 1.));
+        assert_eq!(readback.pixels[16], vec3(0., 1., 1.));
+        assert_eq!(readback.pixels[31], vec3(15., 1., 1.));
+    }
+
+    #[test]
+    fn read_pixels_ignores_short_data() {
+        let mut readback = readback(Rect::new(0., 0., 160., 40.));
+        readback.read_pixels(&padded_texels(uvec2(8, 2), 128));
+
+        assert_eq!(readback.pixels.len(), 32);
+    }
+
+    #[test]
+    fn sample_inside_viewport() {
+        // each texel covers 10x20 pixels of the target
+        let readback = readback(Rect::new(40., 10., 120., 30.));
+
+        assert_eq!(
+            readback.sampled_light_at(vec2(0., 0.)),
+            Some(vec3(4., 0., 1.))
+        );
+        assert_eq!(
+            readback.sampled_light_at(vec2(25., 15.)),
+            Some(vec3(6., 1., 1.))
+        );
+        assert_eq!(
+            readback.sampled_light_at(vec2(79.9, 19.9)),
+            Some(vec3(11., 1., 1.))
+        );
+    }
+
+    #[test]
+    fn sample_outside_viewport() {
+        let readback = readback(Rect::new(40., 10., 120., 30.));
+
+        assert_eq!(readback.sampled_light_at(vec2(-1., 0.)), None);
+        assert_eq!(readback.sampled_light_at(vec2(0., -1.)), None);
+        assert_eq!(readback.sampled_light_at(vec2(80., 0.)), None);
+        assert_eq!(readback.sampled_light_at(vec2(0., 20.)), None);
+
+        assert_eq!(
+            LightmapReadback::new(uvec2(16, 2)).sampled_light_at(vec2(0., 0.)),
+            None
+        );
+    }
+
+    #[test]
+    fn sample_is_clamped_to_the_last_texel() {
+        // the viewport reaches past the bottom right corner of the target
+        let readback = readback(Rect::new(100., 20., 200., 60.));
+
+        assert_eq!(
+            readback.sampled_light_at(vec2(55., 15.)),
+            Some(vec3(15., 1., 1.))
+        );
+        assert_eq!(
+            readback.sampled_light_at(vec2(90., 30.)),
+            Some(vec3(15., 1., 1.))
+        );
+    }
+}
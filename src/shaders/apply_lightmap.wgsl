@@ -1,5 +1,5 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
-#import firefly::types::FireflyConfig
+#import firefly::types::{FireflyConfig, NO_NORMAL_MAP}
 
 #import firefly::utils::blend
 
@@ -18,14 +18,44 @@ var texture_sampler2: sampler;
 @group(0) @binding(4)
 var<uniform> config: FireflyConfig;
 
-#ifdef IS_COMBINED
 @group(0) @binding(5)
+var normal_map: texture_2d<f32>;
+
+@group(0) @binding(6)
+var ambient_texture: texture_2d<f32>;
+
+@group(0) @binding(7)
+var volumetric_texture: texture_2d<f32>;
+
+@group(0) @binding(8)
+var exposure_texture: texture_2d<f32>;
+
+@group(0) @binding(9)
+var refraction_texture: texture_2d<f32>;
+
+#ifdef IS_COMBINED
+@group(0) @binding(10)
 var light_map_textures: texture_2d_array<f32>;
 #endif
 
+// largest finite half-float value
+const MAX_LIGHT: f32 = 65504.0;
+
 @fragment
 fn fragment(vo: FullscreenVertexOutput) -> @location(0) vec4<f32> {
-    var light_frag = blend(textureSample(light_map_texture, texture_sampler2, vo.uv), vec4f(config.ambient_color, 0), config.ambient_brightness);
+    var ambient_color = config.ambient_color;
+    if config.has_ambient_texture != 0u {
+        ambient_color = textureSample(ambient_texture, texture_sampler, vo.uv).rgb;
+    }
+
+    var light_frag = blend(textureSample(light_map_texture, texture_sampler2, vo.uv), vec4f(ambient_color, 0), config.ambient_brightness);
+
+    // light shafts of volumetric lights, black if there are none
+    light_frag += vec4f(textureSample(volumetric_texture, texture_sampler2, vo.uv).rgb, 0);
+
+    if config.fill_strength > 0.0 {
+        light_frag += vec4f(fill_light(vo.uv), 0);
+    }
 
 #ifdef IS_COMBINED
     for (var i = 0u; i < config.n_combined_lightmaps; i += 1) {
@@ -45,11 +75,71 @@ fn fragment(vo: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     }
 #endif    
 
+    // additively accumulated lights can overflow half-float lightmaps, and infinite light turns dark pixels into NaNs
+    light_frag = min(light_frag, vec4f(MAX_LIGHT));
+
+    light_frag = vec4f(expose(light_frag.rgb), light_frag.a);
+
     if config.light_bands > 0 {
         light_frag = floor(light_frag / vec4f(config.light_bands)) * config.light_bands;
     }
 
-    let scene_frag = textureSample(screen_texture, texture_sampler, vo.uv);
+    // refracting occluders offset where the scene is sampled from, zero everywhere else
+    let refraction = textureSample(refraction_texture, texture_sampler, vo.uv).rg;
+    let scene_frag = textureSample(screen_texture, texture_sampler, clamp(vo.uv + refraction, config.viewport.xy, config.viewport.zw));
+
+    // the rest of the render target belongs to other cameras, and is left untouched
+    if any(vo.uv < config.viewport.xy) || any(vo.uv >= config.viewport.zw) {
+        return textureSampleLevel(screen_texture, texture_sampler, vo.uv, 0.0);
+    }
     
+    if config.lightmap_blend == 1u {
+        return vec4f(scene_frag.rgb + light_frag.rgb, scene_frag.a);
+    }
+    else if config.lightmap_blend == 2u {
+        // hdr scene values above 1 are left untouched instead of being darkened
+        return vec4f(scene_frag.rgb + light_frag.rgb * max(vec3f(0.0), vec3f(1.0) - scene_frag.rgb), scene_frag.a);
+    }
+    else if config.lightmap_blend == 3u {
+        let light = clamp(light_frag.rgb, vec3f(0.0), vec3f(1.0));
+        let scene = min(scene_frag.rgb, vec3f(1.0));
+        let soft = (vec3f(1.0) - 2.0 * light) * scene * scene + 2.0 * light * scene;
+        return vec4f(soft + scene_frag.rgb - scene, scene_frag.a);
+    }
+
     return scene_frag * light_frag;
 }
+
+// scales the light by the exposure, then limits its luminance while keeping the hue
+fn expose(light: vec3f) -> vec3f {
+    var exposure = config.exposure;
+    if config.auto_exposure == 1u {
+        exposure *= textureLoad(exposure_texture, vec2i(0), 0).r;
+    }
+
+    let exposed = light * exposure;
+
+    if config.max_luminance > 0.0 {
+        let luminance = dot(exposed, vec3f(0.2126, 0.7152, 0.0722));
+        if luminance > config.max_luminance {
+            return exposed * (config.max_luminance / luminance);
+        }
+    }
+
+    return exposed;
+}
+
+// hemispheric fill light, coming predominantly from the fill direction
+fn fill_light(uv: vec2f) -> vec3f {
+    let normal = textureLoad(normal_map, vec2<i32>(uv * vec2<f32>(textureDimensions(normal_map))), 0);
+
+    var normal_dir = vec3f(0f, 0f, 1f);
+
+    // sprites without a normal map use a dummy value of 0.1 in the blue channel
+    if config.normal_mode != 0 && normal.a > 0 && normal.b != NO_NORMAL_MAP {
+        normal_dir = mix(normalize(normal.xyz * 2f - 1f), vec3f(0f), config.normal_attenuation);
+    }
+
+    let fill_dir = normalize(vec3f(config.fill_direction, 0f));
+    return config.fill_color * config.fill_strength * (0.5 + 0.5 * dot(normal_dir, fill_dir));
+}
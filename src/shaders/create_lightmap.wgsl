@@ -1,5 +1,3 @@
-enable f16;
-
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 #import bevy_render::view::View
 
@@ -8,15 +6,15 @@ enable f16;
 #endif
 
 #import firefly::types::{
-    view, PointLight, LightingData, PolyOccluder, RoundOccluder, OccluderPointer, 
-    FireflyConfig, BinIndices, N_BINS,
+    view, PointLight, LightingData, PolyOccluder, PolyVertex, RoundOccluder, OccluderPointer, 
+    FireflyConfig, BinIndices, N_BINS, NO_NORMAL_MAP,
 }
 
 #import firefly::utils::{
-    ndc_to_world, frag_coord_to_ndc, orientation, same_orientation, intersect, blend, 
+    ndc_to_world, world_to_ndc, ndc_to_uv, frag_coord_to_ndc, orientation, same_orientation, intersect, blend, 
     shadow_blend, intersects_arc, rotate, rotate_arctan, between_arctan, distance_point_to_line,
     intersection_point, rect_intersection, rect_line_intersection, intersects_axis_edge, intersects_corner_arc,
-    rotate_90, rotate_90_cc, intersects_half, falloff
+    rotate_90, rotate_90_cc, intersects_half, falloff, distance_point_to_segment, IntersectsEdge,
 }
 
 @group(1) @binding(0)
@@ -26,7 +24,7 @@ var texture_sampler: sampler;
 var<storage> lights: array<PointLight>;
 
 @group(1) @binding(2)
-var<storage> light_index: u32; 
+var<uniform> light_index: u32;
 
 @group(1) @binding(3)
 var<storage> round_occluders: array<RoundOccluder>;
@@ -35,7 +33,7 @@ var<storage> round_occluders: array<RoundOccluder>;
 var<storage> poly_occluders: array<PolyOccluder>;
 
 @group(1) @binding(5)
-var<storage> vertices: array<vec2f>;
+var<storage> vertices: array<PolyVertex>;
 
 @group(1) @binding(6)
 var<storage> occluders: array<OccluderPointer>;
@@ -52,22 +50,109 @@ var normal_map: texture_2d<f32>;
 @group(1) @binding(10)
 var<uniform> config: FireflyConfig;
 
+@group(1) @binding(11)
+var specular_map: texture_2d<f32>;
+
+@group(1) @binding(12)
+var cookie: texture_2d<f32>;
+
+@group(1) @binding(13)
+var falloff_gradient: texture_2d<f32>;
+
+@group(1) @binding(14)
+var occluder_coverage: texture_2d<f32>;
+
+@group(1) @binding(15)
+var parallax_map: texture_2d<f32>;
+
 const PI2: f32 = 6.28318530717958647692528676655900577;
 const PI: f32 = 3.14159265358979323846264338327950288;
 const PIDIV2: f32 = 1.57079632679489661923132169163975144; 
 
+struct FragmentOutput {
+    @location(0) light: vec4f,
+#ifdef SHADOW_MASK
+    @location(1) shadow_mask: vec4f,
+#endif
+}
+
+// total occlusion of the current fragment, independent of the light's color
+var<private> occlusion: f32 = 0.0;
+
+// size of the current fragment in world units, used to anti-alias shadow edges
+var<private> pixel_size: f32 = 0.0;
+
 @fragment
-fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
+fn fragment(in: FullscreenVertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+
+    // derivatives need uniform control flow, so they're taken before any early returns
+    let world_pos = ndc_to_world(frag_coord_to_ndc(in.position.xy * config.texture_scale));
+    pixel_size = max(fwidth(world_pos).x, fwidth(world_pos).y);
+
+    out.light = light_fragment(in);
+#ifdef SHADOW_MASK
+    out.shadow_mask = vec4f(occlusion, 0, 0, 1);
+#endif
+    return out;
+}
+
+fn light_fragment(in: FullscreenVertexOutput) -> vec4f {
     // return vec4f(0.5);
     let light = lights[light_index];
 
     var res = vec4f(0);
     
-    let pos = ndc_to_world(frag_coord_to_ndc(in.position.xy * config.texture_scale));
-    let normal = textureLoad(normal_map, vec2<i32>(in.uv * vec2<f32>(textureDimensions(normal_map))), 0);
-    let stencil = textureSample(sprite_stencil, texture_sampler, in.uv);
+    var pos = ndc_to_world(frag_coord_to_ndc((in.position.xy + config.jitter) * config.texture_scale));
+    var normal = textureLoad(normal_map, vec2<i32>(in.uv * vec2<f32>(textureDimensions(normal_map))), 0);
+    let specular = textureLoad(specular_map, vec2<i32>(in.uv * vec2<f32>(textureDimensions(specular_map))), 0);
+
+    // sprites with a height map are lit as if their taller pixels were shifted towards the light
+    let parallax_texel = textureLoad(parallax_map, vec2<i32>(in.uv * vec2<f32>(textureDimensions(parallax_map))), 0);
+    let parallax = min(parallax_texel.r, distance(pos, light.pos));
+    if parallax > 0.0 {
+        pos += normalize(light.pos - pos) * parallax;
+
+        let uv = clamp(ndc_to_uv(world_to_ndc(pos)), vec2f(0.0), vec2f(1.0));
+        let shifted_normal = textureLoad(normal_map, vec2<i32>(uv * vec2<f32>(textureDimensions(normal_map) - 1u)), 0);
+        if shifted_normal.a > 0 {
+            normal = shifted_normal;
+        }
+    }
+    // loaded instead of sampled, since 32 bit float textures aren't filterable on every device
+    let stencil = textureLoad(sprite_stencil, vec2<i32>(in.uv * vec2<f32>(textureDimensions(sprite_stencil))), 0);
+
+    // lights with a light group only affect the sprites in that group
+    if light.has_light_group == 1 && (specular.a == 0 || u32(round(specular.b)) != light.light_group) {
+        return res;
+    }
+
+    // sprites in groups excluded by the light's mask
+    let sprite_group = u32(round(specular.b));
+    if specular.a > 0 && sprite_group < 32 && (light.excluded_groups & (1u << sprite_group)) != 0 {
+        return res;
+    }
+
+    // lights with a z range only affect the sprites inside it
+    if light.has_z_range == 1 && (stencil.a <= 0.1 || stencil.g < light.z_min || stencil.g >= light.z_max) {
+        return res;
+    }
 
     let dist = distance(pos, light.pos);
+
+    // samples of line lights cast shadows from their own position, but emit from the closest point of the segment
+    var emit_dist = dist;
+    var emit_radius = light.radius;
+    var intensity = light.intensity;
+    if light.segment_samples > 0u {
+        emit_dist = distance_point_to_segment(pos, light.segment_a, light.segment_b);
+        emit_radius = light.segment_range;
+
+#ifndef ADDITIVE_LIGHTS
+        // the line's intensity is split between its samples, but only the brightest sample is kept
+        intensity *= f32(light.segment_samples);
+#endif
+    }
     
     let a = pos - light.pos;
     let b = light.dir;
@@ -75,15 +160,26 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
 
     let angle = acos(dot_a_b);
 
-    var light_color = light.color;
+    var light_color = mix(light.inner_color, light.color, clamp(emit_dist / emit_radius, 0.0, 1.0));
 
 #ifdef TONEMAP_IN_SHADER
     light_color = tonemapping::tone_mapping(light_color, view.color_grading);
 #endif
 
+    // project the cookie over the light's radius, with its top side facing the light's direction
+    var cookie_pos = vec2f(dot(a, vec2f(b.y, -b.x)), dot(a, b)) / (2.0 * light.radius);
+
+    // then rotate, scale and offset it, repeating it past its edges
+    let cr = light.cookie_rotation;
+    cookie_pos = vec2f(cr.x * cookie_pos.x + cr.y * cookie_pos.y, -cr.y * cookie_pos.x + cr.x * cookie_pos.y);
+    cookie_pos = cookie_pos / light.cookie_scale - light.cookie_offset;
+
+    let cookie_color = textureSampleLevel(cookie, texture_sampler, fract(vec2f(0.5 + cookie_pos.x, 0.5 - cookie_pos.y)), 0.0);
+    light_color = vec4f(light_color.rgb * cookie_color.rgb, light_color.a);
+
     // light_color = pow(light_color, vec4<f32>(2.2));
 
-    if (dist < light.radius && angle <= light.outer_angle / 2.) {
+    if (emit_dist < emit_radius && angle <= light.outer_angle / 2.) {
         
         var angle_multi = 1.0; 
 
@@ -93,9 +189,18 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
         }
 
         var normal_multi = 1.0;
+        var specular_multi = 0.0;
     
-        if config.normal_mode != 0 && normal.a > 0 && normal.b != 0.1 {
+        // sprites can override the config's normal mode, storing it + 1 in the parallax texture
+        var normal_mode = config.normal_mode;
+        let sprite_normal_mode = u32(round(parallax_texel.g));
+        if sprite_normal_mode > 0 {
+            normal_mode = sprite_normal_mode - 1;
+        }
+
+        if normal_mode != 0 && normal.a > 0 && normal.b != 0.1 {
             let normal_dir = mix(normalize(normal.xyz * 2f - 1f), vec3f(0f), config.normal_attenuation);
+            var light_dir = vec3f(0f);
 
             if normal.b == 0.0 {
                 normal_multi = 0.0;
@@ -103,36 +208,56 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
             else if normal.b == 0.1 {
                 normal_multi = 1.0;
             }
-            else if config.normal_mode == 1 {
-                let light_dir = normalize(vec3f(light.pos.x - pos.x, light.pos.y - pos.y, light.z - stencil.g));
+            else if normal_mode == 1 {
+                light_dir = normalize(vec3f(light.pos.x - pos.x, light.pos.y - pos.y, light.z - stencil.g));
                 normal_multi = max(0f, dot(normal_dir, light_dir));
             }
-            else if config.normal_mode == 2 {
-                let light_dir = normalize(vec3f(light.pos.x - pos.x, light.height - stencil.b, stencil.r - light.pos.y));
+            else if normal_mode == 2 {
+                light_dir = normalize(vec3f(light.pos.x - pos.x, light.height - stencil.b, stencil.r - light.pos.y));
                 normal_multi = max(0f, dot(normal_dir, light_dir));
             }
-            else if config.normal_mode == 3 {
-                let light_dir = normalize(vec3f(light.pos.x - pos.x, light.height - stencil.b, light.z - stencil.g));
+            else if normal_mode == 3 {
+                light_dir = normalize(vec3f(light.pos.x - pos.x, light.height - stencil.b, light.z - stencil.g));
                 normal_multi = max(0f, dot(normal_dir, light_dir));
             }
+
+            // blinn-phong specular, with the viewer looking straight at the screen.
+            // pixels that aren't shaded by their normal don't have a light direction to reflect
+            if specular.a > 0 && specular.r > 0 && normal_multi > 0 && any(light_dir != vec3f(0f)) {
+                let half_dir = normalize(light_dir + vec3f(0f, 0f, 1f));
+                specular_multi = specular.r * pow(max(0f, dot(normal_dir, half_dir)), specular.g);
+            }
         }; 
 
-        if normal.b == f32(f16(0.1)) {
+        if normal.b == NO_NORMAL_MAP {
             normal_multi = 1.0;
+            specular_multi = 0.0;
         }
 
-        if dist <= light.core_radius {
-            res = vec4f(light_color.xyz, 0) * angle_multi * normal_multi * (light.intensity + light.core_boost * falloff(dist / light.core_radius, light.core_falloff, light.core_falloff_intensity));
+        normal_multi += specular_multi;
+
+        if light.has_falloff_gradient == 1 {
+            let gradient = textureSampleLevel(falloff_gradient, texture_sampler, vec2f(emit_dist / emit_radius, 0.5), 0.0);
+            res = vec4f(light_color.xyz * gradient.rgb, 0) * intensity * angle_multi * normal_multi * gradient.a;
+        }
+        else if emit_dist <= light.core_radius {
+            res = vec4f(light_color.xyz, 0) * angle_multi * normal_multi * (intensity + light.core_boost * falloff(emit_dist / max(light.core_radius, 0.0001), light.core_falloff, light.core_falloff_intensity));
         }
         else {
-            let x = (dist - light.core_radius) / (light.radius - light.core_radius);
-            res = vec4f(light_color.xyz, 0) * light.intensity * angle_multi * normal_multi * falloff(x, light.falloff, light.falloff_intensity);
+            // the falloff starts at the core's edge, where it's continuous with the core's own falloff
+            let x = (emit_dist - light.core_radius) / max(emit_radius - light.core_radius, 0.0001);
+            res = vec4f(light_color.xyz, 0) * intensity * angle_multi * normal_multi * falloff(x, light.falloff, light.falloff_intensity);
         }
 
         if dot(res, res) < 0.0001 {
             return res;
         }
 
+        if light.screen_space_shadows == 1 && config.screen_space_shadows == 1 {
+            occlusion = screen_space_occlusion(in.uv, light.pos);
+            return res * max(1.0 - occlusion, config.min_shadow_light);
+        }
+
         var round_index = 0u;
         var start_vertex = 0u;
         var sequence_index = 0u;
@@ -152,14 +277,34 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
         var prev_index = 0u; 
         var accumulated_occlusion = 0.0;
 
+        // position tested against occluders, biased towards the light to avoid shadow acne
+        let shadow_pos = pos - normalize(pos - light.pos) * min(config.shadow_bias, dist);
+
         // if left >= right {
         //     return vec4<f32>(1.0, 0.0, 0.0, 1.0);
         // }
 
+        // ambient occlusion: distance to the nearest occluder edge, capped at the ao radius
+        let ao_enabled = config.ao_strength > 0.0 && config.ao_radius > 0.0;
+        var edge_distance = config.ao_radius;
+
+        // color emitted by the glowing occluders the fragment is inside of
+        var glow = vec3f(0);
+
         for (var pointer_index = left; pointer_index < right; pointer_index += 1) {
             let pointer = occluders[pointer_index];
+
+            if ao_enabled && pointer.distance <= dist + config.ao_radius {
+                edge_distance = min(edge_distance, occluder_edge_distance(pos, pointer.index));
+            }
             
-            if pointer.distance > dist { break; }
+            if pointer.distance > dist {
+                // occluders past the fragment can't shadow it, but can still be close enough to occlude it
+                if ao_enabled && pointer.distance <= dist + config.ao_radius {
+                    continue;
+                }
+                break;
+            }
             
             // return vec4<f32>(1.0, 0.0, 0.0, 1.0);
             let occluder_type = pointer.index & 2147483648u;
@@ -173,11 +318,45 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
                     }
                 }
 
-                let result = round_check(pos, occluder_index); 
+                if round_occluders[occluder_index].glow_through > 0.0 && inside_round(pos, occluder_index) {
+                    glow += round_occluders[occluder_index].color.rgb * round_occluders[occluder_index].glow_through;
+                }
+
+                let feathered = round_occluders[occluder_index].feather > 0.0;
+
+                var result = 0.0;
+                if round_occluders[occluder_index].casts_shadow == 1 {
+                    if feathered {
+                        result = round_fog_check(shadow_pos, occluder_index);
+                    }
+                    else {
+                        result = round_check(shadow_pos, occluder_index);
+                    }
+
+                    if result > 0.0 {
+                        result *= shadow_fade(pos, pointer.index, round_occluders[occluder_index].shadow_fade_distance);
+                    }
+                }
+
+                // the occluder's own area is fully shaded if it shades itself, never if it's self-lit,
+                // and otherwise keeps the shadow of its own edges
+                let shade_self = round_occluders[occluder_index].shade_self;
+                if (shade_self == 1 || (result > 0.0 && (shade_self == 2 || feathered))) && inside_round(pos, occluder_index) {
+                    if shade_self == 2 {
+                        result = 0.0;
+                    }
+                    else if feathered {
+                        // fog is only as thick as the path the light took to get here
+                        result = round_fog_check(pos, occluder_index);
+                    }
+                    else {
+                        result = 1.0;
+                    }
+                }
 
 
                 if result > 0.0 {
-                    shadow = shadow_blend(shadow, round_occluders[occluder_index].color.rgb, round_occluders[occluder_index].opacity * result);
+                    shadow = mix(shadow, shadow_blend(shadow, round_occluders[occluder_index].color.rgb * round_occluders[occluder_index].tint_strength, round_occluders[occluder_index].opacity * result), round_occluders[occluder_index].blocks_channels.rgb);
                 }            
             }
             // poly occluder
@@ -189,11 +368,19 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
                 }
 
                 if prev_index != occluder_index {
-                    if prev_index != 0u && accumulated_occlusion > 0.0 {
-                        shadow = shadow_blend(shadow, poly_occluders[prev_index].color.rgb, poly_occluders[prev_index].opacity * accumulated_occlusion);
+                    if prev_index != 0u && accumulated_occlusion > 0.0 && poly_shades(pos, prev_index) {
+                        shadow = mix(shadow, shadow_blend(shadow, poly_occluders[prev_index].color.rgb * poly_occluders[prev_index].tint_strength, poly_occluders[prev_index].opacity * accumulated_occlusion), poly_occluders[prev_index].blocks_channels.rgb);
                     }
                     accumulated_occlusion = 0.0;
                     prev_index = occluder_index;
+
+                    if poly_occluders[occluder_index].shade_self == 1 && inside_poly(pos, occluder_index) {
+                        accumulated_occlusion = 1.0;
+                    }
+
+                    if poly_occluders[occluder_index].glow_through > 0.0 && inside_poly(pos, occluder_index) {
+                        glow += poly_occluders[occluder_index].color.rgb * poly_occluders[occluder_index].glow_through;
+                    }
                 }
 
                 let term = (pointer.min_v & 3221225472u) >> 30u;
@@ -204,8 +391,13 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
                 let split = pointer.split;
                 let length = pointer.length & 1073741823u;
 
-                let result = poly_check(pos, occluder_index, term, rev, min_v, split, length); 
-                accumulated_occlusion = max(accumulated_occlusion, result);
+                if poly_occluders[occluder_index].casts_shadow == 1 {
+                    var result = poly_check(shadow_pos, occluder_index, term, rev, min_v, split, length); 
+                    if result > accumulated_occlusion {
+                        result *= shadow_fade(pos, pointer.index, poly_occluders[occluder_index].shadow_fade_distance);
+                    }
+                    accumulated_occlusion = max(accumulated_occlusion, result);
+                }
             }
 
             if dot(shadow, shadow) < 0.001 {
@@ -213,17 +405,140 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
             }
         }
             
-        if prev_index != 0u && accumulated_occlusion > 0.0 {
-            shadow = shadow_blend(shadow, poly_occluders[prev_index].color.rgb, poly_occluders[prev_index].opacity * accumulated_occlusion);
+        if prev_index != 0u && accumulated_occlusion > 0.0 && poly_shades(pos, prev_index) {
+            shadow = mix(shadow, shadow_blend(shadow, poly_occluders[prev_index].color.rgb * poly_occluders[prev_index].tint_strength, poly_occluders[prev_index].opacity * accumulated_occlusion), poly_occluders[prev_index].blocks_channels.rgb);
         }
 
+        occlusion = 1.0 - max(shadow.r, max(shadow.g, shadow.b));
+
+        // the shadow mask keeps the actual occlusion, only the light reaching the fragment is floored
+        shadow = max(shadow, vec3f(config.min_shadow_light));
         res *= vec4f(shadow, 1);
+
+        // glowing occluders add back the light reaching them, tinted by their color
+        res += vec4f(res.rgb * glow, 0);
+
+        if ao_enabled {
+            res *= 1.0 - config.ao_strength * (1.0 - smoothstep(0.0, config.ao_radius, edge_distance));
+        }
     }
 
     // return pow(res, vec4<f32>(1.0/2.2));
     return res;
 }
 
+// max number of samples taken by screen-space shadows between a pixel and the light
+const SCREEN_SPACE_SHADOW_STEPS: f32 = 128.0;
+
+// occlusion between the pixel and the light, found by marching through the occluder coverage texture
+fn screen_space_occlusion(uv: vec2f, light_pos: vec2f) -> f32 {
+    let dimensions = vec2f(textureDimensions(occluder_coverage));
+
+    let light_clip = view.clip_from_world * vec4f(light_pos, 0.0, 1.0);
+    let light_uv = light_clip.xy / light_clip.w * vec2f(0.5, -0.5) + 0.5;
+
+    // about one sample every texel, so thin occluders aren't skipped when possible
+    let steps = clamp(ceil(distance(uv * dimensions, light_uv * dimensions)), 1.0, SCREEN_SPACE_SHADOW_STEPS);
+
+    // the occluder covering the pixel doesn't shadow it, so samples only count once the ray leaves it
+    var outside = textureLoad(occluder_coverage, vec2<i32>(uv * dimensions), 0).r == 0.0;
+    var result = 0.0;
+
+    for (var i = 1.0; i <= steps; i += 1.0) {
+        let p = mix(uv, light_uv, i / steps);
+
+        // occluders off the screen aren't in the texture
+        if any(p < vec2f(0.0)) || any(p >= vec2f(1.0)) {
+            break;
+        }
+
+        let coverage = textureLoad(occluder_coverage, vec2<i32>(p * dimensions), 0).r;
+
+        if !outside {
+            outside = coverage == 0.0;
+            continue;
+        }
+
+        result = max(result, coverage);
+
+        if result >= 1.0 {
+            break;
+        }
+    }
+
+    return result;
+}
+
+// distance from the point to the closest edge of the occluder the pointer points to
+fn occluder_edge_distance(pos: vec2f, pointer_index: u32) -> f32 {
+    let index = pointer_index & 2147483647u;
+
+    if (pointer_index & 2147483648u) == 0 {
+        let occ = round_occluders[index];
+
+        let relative_pos = pos - occ.pos;
+        let c = cos(occ.rot);
+        let s = sin(occ.rot);
+        let p_local = vec2f(relative_pos.x * c + relative_pos.y * s, -relative_pos.x * s + relative_pos.y * c);
+
+        return max(length(max(abs(p_local) - vec2f(occ.half_width, occ.half_height), vec2f(0))) - occ.radius, 0.0);
+    }
+
+    let occluder = poly_occluders[index];
+    var res = distance(pos, poly_vertex(occluder, occluder.start_vertex));
+
+    for (var i = 0u; i < occluder.n_vertices; i += 1) {
+        let a = poly_vertex(occluder, occluder.start_vertex + i);
+        let b = poly_vertex(occluder, occluder.start_vertex + (i + 1) % occluder.n_vertices);
+        res = min(res, distance_point_to_segment(pos, a, b));
+    }
+
+    return res;
+}
+
+// fraction of an occluder's shadow left at the point, which fades out with the distance from the occluder's edge
+fn shadow_fade(pos: vec2f, pointer_index: u32, fade_distance: f32) -> f32 {
+    if fade_distance <= 0.0 {
+        return 1.0;
+    }
+
+    return 1.0 - clamp(occluder_edge_distance(pos, pointer_index) / fade_distance, 0.0, 1.0);
+}
+
+// whether a poly occluder's shadow can reach the pixel, which self-lit occluders keep out of their own area
+fn poly_shades(pos: vec2f, index: u32) -> bool {
+    return poly_occluders[index].shade_self != 2 || !inside_poly(pos, index);
+}
+
+// whether the point is inside the polygon occluder (even-odd rule)
+fn inside_poly(pos: vec2f, index: u32) -> bool {
+    let occluder = poly_occluders[index];
+    var inside = false;
+
+    for (var i = 0u; i < occluder.n_vertices; i += 1) {
+        let a = poly_vertex(occluder, occluder.start_vertex + i);
+        let b = poly_vertex(occluder, occluder.start_vertex + (i + 1) % occluder.n_vertices);
+
+        if (a.y > pos.y) != (b.y > pos.y) && pos.x < a.x + (pos.y - a.y) * (b.x - a.x) / (b.y - a.y) {
+            inside = !inside;
+        }
+    }
+
+    return inside;
+}
+
+// whether the point is inside the round occluder
+fn inside_round(pos: vec2f, index: u32) -> bool {
+    let occ = round_occluders[index];
+
+    let relative_pos = pos - occ.pos;
+    let c = cos(occ.rot);
+    let s = sin(occ.rot);
+    let p_local = vec2f(relative_pos.x * c + relative_pos.y * s, -relative_pos.x * s + relative_pos.y * c);
+
+    return length(max(abs(p_local) - vec2f(occ.half_width, occ.half_height), vec2f(0))) <= occ.radius;
+}
+
 fn poly_check(pos: vec2f, index: u32, term: u32, rev: u32, min_v: u32, split: u32, length: u32) -> f32 {
     let light = lights[light_index];
     let occluder = poly_occluders[index];
@@ -245,7 +560,7 @@ fn poly_check(pos: vec2f, index: u32, term: u32, rev: u32, min_v: u32, split: u3
             len = length - split + 1;
         }
 
-        maybe_prev = bs_vertex_forward(angle, start, len, term, occluder.start_vertex, occluder.n_vertices);
+        maybe_prev = bs_vertex_forward(angle, start, len, term, occluder);
     }
     else {
         if term == 1 {
@@ -256,50 +571,128 @@ fn poly_check(pos: vec2f, index: u32, term: u32, rev: u32, min_v: u32, split: u3
             len = length - split + 1;
         }
 
-        maybe_prev = bs_vertex_reverse(angle, start, len, term, occluder.start_vertex, occluder.n_vertices);
+        maybe_prev = bs_vertex_reverse(angle, start, len, term, occluder);
     }
 
     var is_occluded = false;
+    var i1 = 0u;
+    var i2 = 0u;
+    var v1 = vec2f(0.0);
+    var v2 = vec2f(0.0);
 
     let out_of_bounds = maybe_prev < 0 || maybe_prev + 1 >= i32(len);
 
     if !out_of_bounds {
         if rev == 0 {
-            let v1 = vertices[start + u32(maybe_prev) - select(0, occluder.n_vertices, start + u32(maybe_prev) >= occluder.start_vertex + occluder.n_vertices)];
-            let v2 = vertices[start + u32(maybe_prev) + 1 - select(0, occluder.n_vertices, start + u32(maybe_prev) + 1 >= occluder.start_vertex + occluder.n_vertices)];
-
-            is_occluded = !same_orientation(v1, v2, pos, light.pos);
+            i1 = start + u32(maybe_prev) - select(0, occluder.n_vertices, start + u32(maybe_prev) >= occluder.start_vertex + occluder.n_vertices);
+            i2 = start + u32(maybe_prev) + 1 - select(0, occluder.n_vertices, start + u32(maybe_prev) + 1 >= occluder.start_vertex + occluder.n_vertices);
         }
         else {
-            let v1 = vertices[i32(start) - maybe_prev + select(0, i32(occluder.n_vertices), i32(start) - maybe_prev < i32(occluder.start_vertex))];
-            let v2 = vertices[i32(start) - maybe_prev - 1 + select(0, i32(occluder.n_vertices), i32(start) - maybe_prev - 1 < i32(occluder.start_vertex))];
-
-            is_occluded = !same_orientation(v1, v2, pos, light.pos);
+            i1 = u32(i32(start) - maybe_prev + select(0, i32(occluder.n_vertices), i32(start) - maybe_prev < i32(occluder.start_vertex)));
+            i2 = u32(i32(start) - maybe_prev - 1 + select(0, i32(occluder.n_vertices), i32(start) - maybe_prev - 1 < i32(occluder.start_vertex)));
         }
+
+        v1 = poly_vertex(occluder, i1);
+        v2 = poly_vertex(occluder, i2);
+
+        is_occluded = !same_orientation(v1, v2, pos, light.pos);
     }
 
-    if config.soft_shadows > 0 && light.core_radius > 0.0 && out_of_bounds {
-        if rev == 0 {
-            let loops = min_v + length - 1 >= occluder.start_vertex + occluder.n_vertices;
-            let last = min_v + length - 1 - select(0, occluder.n_vertices, loops);
-    
-            return get_softness_multi(light.core_radius, light.pos, pos, vertices[min_v], vertices[last]);
+    // last vertex of the chain, which together with the first one casts the sides of the shadow
+    var last = 0u;
+    if rev == 0 {
+        let loops = min_v + length - 1 >= occluder.start_vertex + occluder.n_vertices;
+        last = min_v + length - 1 - select(0, occluder.n_vertices, loops);
+    }
+    else {
+        let loops = i32(min_v) - i32(length) + 1 < i32(occluder.start_vertex);
+        last = u32(i32(min_v) - i32(length) + 1 + select(0, i32(occluder.n_vertices), loops));
+    }
+
+    // with vertex opacities, the shadow is as opaque as the point the light passes through on the edge,
+    // or as the nearest extreme vertex past the edges
+    var opacity = 1.0;
+    if occluder.vertex_opacity == 1 {
+        if out_of_bounds {
+            let first_closer = distance(pos, poly_vertex(occluder, min_v)) < distance(pos, poly_vertex(occluder, last));
+            opacity = select(vertices[last].opacity, vertices[min_v].opacity, first_closer);
         }
         else {
-            let loops = i32(min_v) - i32(length) + 1 < i32(occluder.start_vertex);
-            let last = u32(i32(min_v) - i32(length) + 1 + select(0, i32(occluder.n_vertices), loops));
-            
-            return get_softness_multi(light.core_radius, light.pos, pos, vertices[min_v], vertices[last]);
+            opacity = mix(vertices[i1].opacity, vertices[i2].opacity, edge_crossing(light.pos, pos, v1, v2));
+        }
+    }
+
+    let soft = config.soft_shadows > 0 && occluder.hard_shadows == 0 && light.core_radius > 0.0;
+
+    if soft && out_of_bounds {
+        return opacity * get_softness_multi(light.core_radius, light.pos, pos, poly_vertex(occluder, min_v), poly_vertex(occluder, last));
+    }
+
+    let feather = config.shadow_edge_feather * pixel_size;
+    if feather > 0.0 {
+        // soft shadows already fade out past the sides, so only the occluder's edge is anti-aliased
+        var side_distance = select(-1e9, 1e9, !out_of_bounds);
+        if !soft {
+            side_distance = shadow_side_distance(light.pos, pos, poly_vertex(occluder, min_v), poly_vertex(occluder, last), side_distance);
+            side_distance = shadow_side_distance(light.pos, pos, poly_vertex(occluder, last), poly_vertex(occluder, min_v), side_distance);
+        }
+
+        if out_of_bounds {
+            // pixels past a side belong to another part of the chain, or aren't shadowed at all
+            if side_distance >= 0.0 {
+                return 0.0;
+            }
+            return opacity * feathered_edge(side_distance, feather);
         }
+
+        // signed distance to the occluder's edge, positive on the side facing away from the light
+        let edge = v2 - v1;
+        let normal = rotate_90(edge) / max(length(edge), 0.0001);
+        let edge_distance = dot(normal, pos - v1) * -sign(dot(normal, light.pos - v1));
+
+        return opacity * feathered_edge(min(edge_distance, side_distance), feather);
     }
 
     if is_occluded {
-        return 1.0;
+        return opacity;
     }
 
     return 0.0;
 }
 
+// position along the edge from v1 to v2 where the line from the light through pos crosses it, from 0 to 1
+fn edge_crossing(light_pos: vec2f, pos: vec2f, v1: vec2f, v2: vec2f) -> f32 {
+    let edge = v2 - v1;
+    let dir = pos - light_pos;
+
+    let denom = edge.x * dir.y - edge.y * dir.x;
+    if abs(denom) < 0.0001 {
+        return 0.5;
+    }
+
+    return clamp(((light_pos.x - v1.x) * dir.y - (light_pos.y - v1.y) * dir.x) / denom, 0.0, 1.0);
+}
+
+// coverage of a hard shadow edge at a signed distance into the shadow, smoothed over the feather's width
+fn feathered_edge(dist: f32, feather: f32) -> f32 {
+    return clamp(0.5 + dist / feather, 0.0, 1.0);
+}
+
+// signed distance to the side of the shadow cast past `extreme`, positive towards the inside of the shadow (where `other` is),
+// if it's closer than the current one. Pixels before the extreme vertex aren't next to that side.
+fn shadow_side_distance(light_pos: vec2f, pos: vec2f, extreme: vec2f, other: vec2f, current: f32) -> f32 {
+    let dir = normalize(extreme - light_pos);
+
+    if dot(pos - extreme, dir) <= 0.0 {
+        return current;
+    }
+
+    let inside = sign(orientation(light_pos, extreme, other));
+    let dist = orientation(light_pos, light_pos + dir, pos) * inside;
+
+    return select(current, dist, abs(dist) < abs(current));
+}
+
 fn get_softness_multi(light_range: f32, light_pos: vec2<f32>, pos: vec2<f32>, extreme_left: vec2<f32>, extreme_right: vec2<f32>) -> f32 {
     // if distance(pos, extreme_right) < 30.0 {
     //     return 1.0;
@@ -331,6 +724,15 @@ fn get_softness_multi(light_range: f32, light_pos: vec2<f32>, pos: vec2<f32>, ex
     var left = 0.0;
     var right = 0.0;
 
+#if SOFT_SHADOW_SAMPLES > 1
+    if inside_left {
+        left = sampled_penumbra(left_t2, left_t1, extreme_left, pos, 1.0);
+    }
+
+    if inside_right {
+        right = sampled_penumbra(right_t1, right_t2, extreme_right, pos, -1.0);
+    }
+#else
     if inside_left {
         let left2 = normalize(extreme_left - left_t2);
         var d1 = dot(normalize(pos - extreme_left), left2);
@@ -352,10 +754,29 @@ fn get_softness_multi(light_range: f32, light_pos: vec2<f32>, pos: vec2<f32>, ex
 
         right = 1.0 - acos(d1) / acos(d2);
     }
+#endif
 
     return max(left, right);
 }
 
+#if SOFT_SHADOW_SAMPLES > 1
+// fraction of the samples between the light's center and the edge of its core from which pos is hidden by the extreme vertex.
+// side is the sign of the orientation on the hidden side
+fn sampled_penumbra(center: vec2f, edge: vec2f, extreme: vec2f, pos: vec2f, side: f32) -> f32 {
+    var hidden = 0u;
+
+    for (var i = 0u; i < #{SOFT_SHADOW_SAMPLES}u; i += 1) {
+        let core_point = mix(center, edge, (f32(i) + 0.5) / f32(#{SOFT_SHADOW_SAMPLES}u));
+
+        if orientation(core_point, extreme, pos) * side > 0.0 {
+            hidden += 1u;
+        }
+    }
+
+    return f32(hidden) / f32(#{SOFT_SHADOW_SAMPLES}u);
+}
+#endif
+
 fn angle_term(p: vec2f, i: u32, length: u32, term: u32) -> f32 {
     let light = lights[light_index];
     var angle = atan2(p.y - light.pos.y, p.x - light.pos.x);
@@ -370,21 +791,27 @@ fn angle_term(p: vec2f, i: u32, length: u32, term: u32) -> f32 {
     return angle;
 }
 
-fn vertex_forward(start: u32, index: u32, start_vertex: u32, n_vertices: u32) -> vec2<f32> {
-    if start + index >= start_vertex + n_vertices {
-        return vertices[start + index - n_vertices];
+// vertices are stored relative to their occluder, so identical shapes can share them
+fn poly_vertex(occluder: PolyOccluder, index: u32) -> vec2<f32> {
+    let v = vertices[index].pos;
+    return occluder.pos + vec2f(v.x * occluder.rotation.x - v.y * occluder.rotation.y, v.x * occluder.rotation.y + v.y * occluder.rotation.x);
+}
+
+fn vertex_forward(start: u32, index: u32, occluder: PolyOccluder) -> vec2<f32> {
+    if start + index >= occluder.start_vertex + occluder.n_vertices {
+        return poly_vertex(occluder, start + index - occluder.n_vertices);
     }
-    return vertices[start + index];
+    return poly_vertex(occluder, start + index);
 }
 
-fn vertex_reverse(start: u32, index: i32, start_vertex: u32, n_vertices: u32) -> vec2<f32> {
-    if i32(start) - i32(index) < i32(start_vertex) {
-        return vertices[u32(i32(start) - i32(index) + i32(n_vertices))];
+fn vertex_reverse(start: u32, index: i32, occluder: PolyOccluder) -> vec2<f32> {
+    if i32(start) - i32(index) < i32(occluder.start_vertex) {
+        return poly_vertex(occluder, u32(i32(start) - i32(index) + i32(occluder.n_vertices)));
     }
-    return vertices[u32(i32(start) - i32(index))];
+    return poly_vertex(occluder, u32(i32(start) - i32(index)));
 } 
 
-fn bs_vertex_forward(angle: f32, start: u32, length: u32, term: u32, start_vertex: u32, n_vertices: u32) -> i32 {
+fn bs_vertex_forward(angle: f32, start: u32, length: u32, term: u32, occluder: PolyOccluder) -> i32 {
     let light = lights[light_index];
 
     var ans = -1;
@@ -392,17 +819,17 @@ fn bs_vertex_forward(angle: f32, start: u32, length: u32, term: u32, start_verte
     var low = 0i; 
     var high = i32(length) - 1; 
 
-    if angle < angle_term(vertex_forward(start, u32(low), start_vertex, n_vertices), u32(low), length, term) {
+    if angle < angle_term(vertex_forward(start, u32(low), occluder), u32(low), length, term) {
         return -1;
     }
 
-    if angle > angle_term(vertex_forward(start, u32(high), start_vertex, n_vertices), u32(high), length, term) {
+    if angle > angle_term(vertex_forward(start, u32(high), occluder), u32(high), length, term) {
         return high + 1;
     }
 
     while (low <= high) {
         let mid = low + (high - low + 1) / 2;
-        let val = angle_term(vertex_forward(start, u32(mid), start_vertex, n_vertices), u32(mid), length, term);
+        let val = angle_term(vertex_forward(start, u32(mid), occluder), u32(mid), length, term);
 
         if (val < angle) {
             ans = i32(mid);
@@ -416,7 +843,7 @@ fn bs_vertex_forward(angle: f32, start: u32, length: u32, term: u32, start_verte
     return ans;
 }
 
-fn bs_vertex_reverse(angle: f32, start: u32, length: u32, term: u32, start_vertex: u32, n_vertices: u32) -> i32 {
+fn bs_vertex_reverse(angle: f32, start: u32, length: u32, term: u32, occluder: PolyOccluder) -> i32 {
     let light = lights[light_index];
 
     var ans = -1;
@@ -424,17 +851,17 @@ fn bs_vertex_reverse(angle: f32, start: u32, length: u32, term: u32, start_verte
     var low = 0i; 
     var high = i32(length) - 1;
 
-    if angle <= angle_term(vertex_reverse(start, low, start_vertex, n_vertices), u32(low), length, term) {
+    if angle <= angle_term(vertex_reverse(start, low, occluder), u32(low), length, term) {
         return -1;
     }
 
-    if angle >= angle_term(vertex_reverse(start, high, start_vertex, n_vertices), u32(high), length, term) {
+    if angle >= angle_term(vertex_reverse(start, high, occluder), u32(high), length, term) {
         return high + 1;
     }
 
     while (low <= high) {
         let mid = low + (high - low + 1) / 2;
-        let val = angle_term(vertex_reverse(start, mid, start_vertex, n_vertices), u32(mid), length, term);
+        let val = angle_term(vertex_reverse(start, mid, occluder), u32(mid), length, term);
 
         if (val < angle) {
             ans = i32(mid);
@@ -457,101 +884,188 @@ fn round_check(pos: vec2f, occluder: u32) -> f32 {
     let half_h = occ.half_height;
     let radius = occ.radius;
 
+    // a light inside the occluder can only reach the rest of the occluder. this also avoids the
+    // tangent math below, which has no solution in that case
+    if inside_round(light.pos, occluder) {
+        return select(1.0, 0.0, inside_round(pos, occluder));
+    }
+
     let relative_pos = pos - occ.pos; 
     let relative_light = light.pos - occ.pos; 
 
     let c = cos(occ.rot);
     let s = sin(occ.rot);
 
-    // let c = 1.0; 
-    // let s = 0.0;
-
     let p_local = vec2f(relative_pos.x * c + relative_pos.y * s, -relative_pos.x * s + relative_pos.y * c);
     let l_local = vec2f(relative_light.x * c + relative_light.y * s, -relative_light.x * s + relative_light.y * c);
-    
-    var half_intersection = false; 
-    
-    let rect = vec4f(-(half_w + radius), -(half_h + radius), half_w + radius, half_h + radius);
 
-    if !rect_line_intersection(p_local, l_local, rect) {
+    let intersection = round_intersection(p_local, l_local, half_w, half_h, radius);
+    let soft = config.soft_shadows > 0 && occ.hard_shadows == 0 && light.core_radius > 0.0;
 
-        if config.soft_shadows > 0 && light.core_radius > 0.0 {
-            return get_round_extreme_angle(half_w, half_h, p_local, l_local, light.core_radius, radius);
+    if soft {
+        if !intersection.full_intersection && !intersection.half_intersection {
+            let extremes = get_round_extremes(half_w, half_h, l_local, radius);
+            return get_softness_multi(light.core_radius, l_local, p_local, extremes.xy, extremes.zw);
         }
 
-        return 0.0;
+        return select(0.0, 1.0, intersection.full_intersection);
+    }
+
+    // the sides of the shadow are the tangents from the light, which are smoothed over the feather's width.
+    // the local space is only rotated and translated, so distances in it are the same as in world space
+    let feather = config.shadow_edge_feather * pixel_size;
+    if feather > 0.0 {
+        let extremes = get_round_extremes(half_w, half_h, l_local, radius);
+
+        var side_distance = select(-1e9, 1e9, intersection.full_intersection);
+        side_distance = shadow_side_distance(l_local, p_local, extremes.xy, extremes.zw, side_distance);
+        side_distance = shadow_side_distance(l_local, p_local, extremes.zw, extremes.xy, side_distance);
+
+        return feathered_edge(side_distance, feather);
+    }
+
+    return select(0.0, 1.0, intersection.full_intersection);
+}
+
+// whether the segment from the pixel to the light crosses the round occluder, in the occluder's local space.
+// half intersections only graze it, and neither cast a shadow nor a penumbra
+fn round_intersection(p_local: vec2f, l_local: vec2f, half_w: f32, half_h: f32, radius: f32) -> IntersectsEdge {
+    var res: IntersectsEdge;
+
+    let rect = vec4f(-(half_w + radius), -(half_h + radius), half_w + radius, half_h + radius);
+
+    if !rect_line_intersection(p_local, l_local, rect) {
+        return res;
     }
 
     if (half_w > 0) {
         let top_edge = intersects_axis_edge(p_local, l_local, half_h + radius, -half_w, half_w, false);
 
         if top_edge.full_intersection {
-            return 1.0;
+            return top_edge;
         }
         
-        half_intersection |= top_edge.half_intersection;
+        res.half_intersection |= top_edge.half_intersection;
 
         let bottom_edge = intersects_axis_edge(p_local, l_local, -(half_h + radius), -half_w, half_w, false);
 
         if bottom_edge.full_intersection {
-            return 1.0;
+            return bottom_edge;
         }
 
-        half_intersection |= bottom_edge.half_intersection;
+        res.half_intersection |= bottom_edge.half_intersection;
     }
 
     if (half_h > 0) {
         let right_edge = intersects_axis_edge(p_local, l_local, half_w + radius, -half_h, half_h, true);
 
         if right_edge.full_intersection {
-            return 1.0;
+            return right_edge;
         }
 
-        half_intersection |= right_edge.half_intersection;
+        res.half_intersection |= right_edge.half_intersection;
 
         let left_edge = intersects_axis_edge(p_local, l_local, -(half_w + radius), -half_h, half_h, true);
 
         if left_edge.full_intersection {
-            return 1.0;
+            return left_edge;
         }
 
-        half_intersection |= left_edge.half_intersection;
+        res.half_intersection |= left_edge.half_intersection;
     }
 
     if (radius > 0) {
         let arc1 = intersects_corner_arc(p_local, l_local, vec2f(half_w, half_h), radius, vec2f(1,1)); 
         if arc1.full_intersection { 
-            return 1.0;
+            return arc1;
         }
-        half_intersection |= arc1.half_intersection;
+        res.half_intersection |= arc1.half_intersection;
 
         let arc2 = intersects_corner_arc(p_local, l_local, vec2f(-half_w, half_h), radius, vec2f(-1,1)); 
         if arc2.full_intersection { 
-            return 1.0;
+            return arc2;
         }
-        half_intersection |= arc2.half_intersection;
+        res.half_intersection |= arc2.half_intersection;
 
         let arc3 = intersects_corner_arc(p_local, l_local, vec2f(half_w, -half_h), radius, vec2f(1,-1)); 
         if arc3.full_intersection { 
-            return 1.0;
+            return arc3;
         }
-        half_intersection |= arc3.half_intersection;
+        res.half_intersection |= arc3.half_intersection;
 
         let arc4 = intersects_corner_arc(p_local, l_local, vec2f(-half_w, -half_h), radius, vec2f(-1,-1)); 
         if arc4.full_intersection { 
-            return 1.0;
+            return arc4;
         }
-        half_intersection |= arc4.half_intersection;
+        res.half_intersection |= arc4.half_intersection;
     }
 
-    if config.soft_shadows > 0 && light.core_radius > 0.0 && !half_intersection {
-        return get_round_extreme_angle(half_w, half_h, p_local, l_local, light.core_radius, radius);
+    return res;
+}
+
+// signed distance from a point to a round occluder, in the occluder's local space
+fn round_sdf(p_local: vec2f, half_size: vec2f, radius: f32) -> f32 {
+    let q = abs(p_local) - half_size;
+    return length(max(q, vec2f(0))) + min(max(q.x, q.y), 0.0) - radius;
+}
+
+// distance along the segment from `start` to `end` before it enters the occluder, or the full length if it never does.
+// round occluders are convex, so sphere tracing from both ends gives the exact entry and exit points
+fn round_march(start: vec2f, end: vec2f, half_size: vec2f, radius: f32) -> f32 {
+    let len = distance(start, end);
+    if len < 0.0001 {
+        return len;
     }
 
-    return 0.0;
+    let dir = (end - start) / len;
+
+    var t = 0.0;
+    for (var i = 0; i < 32; i++) {
+        let d = round_sdf(start + dir * t, half_size, radius);
+        if d < 0.01 {
+            return t;
+        }
+
+        t += d;
+        if t >= len {
+            break;
+        }
+    }
+
+    // grazing rays that don't converge only cross a negligible chord of the occluder
+    return len;
+}
+
+// shadow of a feathered occluder, proportional to the length of the light's path through it
+fn round_fog_check(pos: vec2f, occluder: u32) -> f32 {
+    let light = lights[light_index];
+    let occ = round_occluders[occluder];
+
+    let c = cos(occ.rot);
+    let s = sin(occ.rot);
+
+    let relative_pos = pos - occ.pos;
+    let relative_light = light.pos - occ.pos;
+
+    let p_local = vec2f(relative_pos.x * c + relative_pos.y * s, -relative_pos.x * s + relative_pos.y * c);
+    let l_local = vec2f(relative_light.x * c + relative_light.y * s, -relative_light.x * s + relative_light.y * c);
+
+    let half_size = vec2f(occ.half_width, occ.half_height);
+    let len = distance(p_local, l_local);
+
+    let entry = round_march(p_local, l_local, half_size, occ.radius);
+    if entry >= len {
+        return 0.0;
+    }
+
+    let exit = round_march(l_local, p_local, half_size, occ.radius);
+    let chord = max(len - entry - exit, 0.0);
+
+    return clamp(chord / occ.feather, 0.0, 1.0);
 }
 
-fn get_round_extreme_angle(half_w: f32, half_h: f32, p_local: vec2f, l_local: vec2f, light_radius: f32, radius: f32) -> f32 {
+// the two points of the round occluder whose tangents from the light cast the sides of its shadow, as (left, right)
+fn get_round_extremes(half_w: f32, half_h: f32, l_local: vec2f, radius: f32) -> vec4f {
     var left_right = vec4<f32>(half_w + radius, half_h, half_w + radius, half_h);
 
     if radius == 0.0 {
@@ -603,7 +1117,7 @@ fn get_round_extreme_angle(half_w: f32, half_h: f32, p_local: vec2f, l_local: ve
         }
     }
 
-    return get_softness_multi(light_radius, l_local, p_local, left_right.xy, left_right.zw);
+    return left_right;
 }
 
 fn update_left_right(light_pos: vec2<f32>, left_right: vec4<f32>, p: vec2<f32>) -> vec4<f32> {
@@ -630,7 +1144,8 @@ fn get_arc_extremes(l_local: vec2f, c: vec2f, r: f32, start_angle: f32, end_angl
     // Pixel is inside the corner radius
     // if (dist_sq <= r * r) { return 10.0; } 
 
-    let dist = sqrt(dist_sq);
+    // clamped, since a light grazing the arc can end up slightly inside it due to precision
+    let dist = max(sqrt(dist_sq), r);
     let th = acos(r / dist);
     let d = atan2(diff.y, diff.x);
     
@@ -0,0 +1,44 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import firefly::types::FireflyConfig
+
+@group(0) @binding(0)
+var light_map_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var texture_sampler: sampler;
+
+@group(0) @binding(2)
+var previous_exposure: texture_2d<f32>;
+
+@group(0) @binding(3)
+var<uniform> config: FireflyConfig;
+
+// the lightmap is measured on a grid of GRID_SIZE x GRID_SIZE filtered samples
+const GRID_SIZE: u32 = 32u;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
+    // log-average luminance, so a few very bright pixels don't dominate the result
+    var log_sum = 0.0;
+    for (var y = 0u; y < GRID_SIZE; y += 1u) {
+        for (var x = 0u; x < GRID_SIZE; x += 1u) {
+            // only the camera's viewport is measured, since that's where the lightmap is applied
+            let uv = mix(config.viewport.xy, config.viewport.zw, (vec2f(f32(x), f32(y)) + 0.5) / f32(GRID_SIZE));
+            let light = textureSampleLevel(light_map_texture, texture_sampler, uv, 0.0).rgb;
+            let luminance = dot(light, vec3f(0.2126, 0.7152, 0.0722));
+            log_sum += log(max(luminance, 0.0001));
+        }
+    }
+
+    let average = exp(log_sum / f32(GRID_SIZE * GRID_SIZE));
+    let gain = clamp(config.auto_exposure_target / average, config.auto_exposure_min_gain, config.auto_exposure_max_gain);
+
+    // a gain of 0 means there's no previous one yet, e.g. on the first frame
+    let previous = textureLoad(previous_exposure, vec2i(0), 0).r;
+    if previous <= 0.0 {
+        return vec4f(gain, 0.0, 0.0, 1.0);
+    }
+
+    // adapting in log space makes brightening and darkening feel equally fast
+    return vec4f(exp(mix(log(previous), log(gain), config.auto_exposure_adaptation)), 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+@group(0) @binding(0)
+var light_map_texture: texture_2d<f32>;
+
+@group(0) @binding(1)
+var texture_sampler: sampler;
+
+// downscales the lightmap into the readback texture
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4f {
+    return vec4f(textureSampleLevel(light_map_texture, texture_sampler, in.uv, 0.0).rgb, 1.0);
+}
@@ -0,0 +1,41 @@
+#import bevy_render::view::View
+
+@group(0) @binding(0)
+var<uniform> view: View;
+
+struct VertexOutput {
+    @builtin(position) position: vec4f,
+    @location(0) opacity: f32,
+    @location(1) refraction: vec2f,
+    @location(2) refracts: f32,
+}
+
+struct FragmentOutput {
+    @location(0) coverage: vec4f,
+    @location(1) refraction: vec4f,
+}
+
+@vertex
+fn vertex(@location(0) pos: vec2f, @location(1) opacity: f32, @location(2) refraction: vec2f, @location(3) refracts: f32) -> VertexOutput {
+    var out: VertexOutput;
+
+    // occluders are flat, so their z is ignored to keep them inside the clip volume
+    let clip = view.clip_from_world * vec4f(pos, 0.0, 1.0);
+    out.position = vec4f(clip.xy, 0.0, clip.w);
+    out.opacity = opacity;
+
+    // the world space offset is converted to uv space, where y points down
+    let clip_offset = (view.clip_from_world * vec4f(refraction, 0.0, 0.0)).xy;
+    out.refraction = vec2f(clip_offset.x, -clip_offset.y) * 0.5;
+    out.refracts = refracts;
+
+    return out;
+}
+
+@fragment
+fn fragment(in: VertexOutput) -> FragmentOutput {
+    var out: FragmentOutput;
+    out.coverage = vec4f(in.opacity, 0.0, 0.0, 1.0);
+    out.refraction = vec4f(in.refraction, 0.0, in.refracts);
+    return out;
+}
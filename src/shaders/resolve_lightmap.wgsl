@@ -0,0 +1,43 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import firefly::types::FireflyConfig
+
+@group(0) @binding(0)
+var lightmap: texture_2d<f32>;
+
+@group(0) @binding(1)
+var history: texture_2d<f32>;
+
+@group(0) @binding(2)
+var texture_sampler: sampler;
+
+@group(0) @binding(3)
+var<uniform> config: FireflyConfig;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2f(textureDimensions(lightmap));
+
+    // the lightmap was rendered over a jittered grid, so it's sampled at the un-jittered position
+    let uv = in.uv - config.jitter * texel;
+    let current = textureSampleLevel(lightmap, texture_sampler, uv, 0.0);
+
+    let history_uv = in.uv + config.history_offset;
+    if config.history_valid == 0 || any(history_uv < vec2f(0.0)) || any(history_uv > vec2f(1.0)) {
+        return current;
+    }
+
+    // clamp the history to the neighbourhood of the current frame (plus a margin), to avoid ghosting from moving lights and occluders
+    var min_color = current;
+    var max_color = current;
+    for (var x = -1; x <= 1; x += 1) {
+        for (var y = -1; y <= 1; y += 1) {
+            let color = textureSampleLevel(lightmap, texture_sampler, uv + vec2f(f32(x), f32(y)) * texel, 0.0);
+            min_color = min(min_color, color);
+            max_color = max(max_color, color);
+        }
+    }
+
+    let margin = (max_color - min_color) * config.temporal_clamp_margin;
+    let previous = clamp(textureSampleLevel(history, texture_sampler, history_uv, 0.0), min_color - margin, max_color + margin);
+    return mix(previous, current, config.temporal_blend);
+}
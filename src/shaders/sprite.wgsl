@@ -1,5 +1,3 @@
-enable f16;
-
 #ifdef TONEMAP_IN_SHADER
 #import bevy_core_pipeline::tonemapping
 #endif
@@ -9,7 +7,7 @@ enable f16;
     view::View,
 }
 
-#import firefly::types::SpriteId
+#import firefly::types::{SpriteId, NO_NORMAL_MAP}
 
 #import bevy_sprite::sprite_view_bindings::view
 
@@ -25,6 +23,11 @@ struct VertexInput {
     @location(4) z: f32,
     @location(5) height: f32,
     @location(6) y: f32,
+    @location(7) shininess: f32,
+    @location(8) light_group: f32,
+    @location(9) parallax: f32,
+    @location(10) normal_mode: f32,
+    @location(11) coverage: f32,
 }
 
 struct VertexOutput {
@@ -33,6 +36,11 @@ struct VertexOutput {
     @location(1) z: f32,
     @location(2) height: f32,
     @location(3) y: f32,
+    @location(4) shininess: f32,
+    @location(5) light_group: f32,
+    @location(6) parallax: f32,
+    @location(7) normal_mode: f32,
+    @location(8) coverage: f32,
 };
 
 @vertex
@@ -54,6 +62,11 @@ fn vertex(in: VertexInput) -> VertexOutput {
     out.z = in.z;
     out.height = in.height;
     out.y = in.y;
+    out.shininess = in.shininess;
+    out.light_group = in.light_group;
+    out.parallax = in.parallax;
+    out.normal_mode = in.normal_mode;
+    out.coverage = in.coverage;
 
     return out;
 }
@@ -62,10 +75,16 @@ fn vertex(in: VertexInput) -> VertexOutput {
 @group(1) @binding(1) var normal_texture: texture_2d<f32>;
 @group(1) @binding(2) var sprite_sampler: sampler;
 @group(1) @binding(3) var<uniform> normal_dummy: u32;
+@group(1) @binding(4) var specular_texture: texture_2d<f32>;
+@group(1) @binding(5) var<uniform> specular_dummy: u32;
+@group(1) @binding(6) var height_map_texture: texture_2d<f32>;
+@group(1) @binding(7) var<uniform> height_map_dummy: u32;
 
 struct FragmentOutput {
     @location(0) stencil: vec4<f32>, 
     @location(1) normal: vec4<f32>,
+    @location(2) specular: vec4<f32>,
+    @location(3) parallax: vec4<f32>,
 }
 
 @fragment
@@ -74,17 +93,22 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
 
     var color = textureSample(sprite_texture, sprite_sampler, in.uv);
     var normal = textureSample(normal_texture, sprite_sampler, in.uv);
+    var specular = textureSample(specular_texture, sprite_sampler, in.uv);
+    var height = textureSample(height_map_texture, sprite_sampler, in.uv);
+
+    // whether the sprite hides what's behind it, based on its blend mode
+    let covered = color.a >= in.coverage;
     
-    if color.a >= 1.0 {
+    if covered {
         res.stencil = vec4<f32>(in.y, in.z, in.height, 1.0);
     }
     else {
         res.stencil = vec4<f32>(0, 0, 0, 0);
     }
 
-    if color.a >= 1.0 {
+    if covered {
         if normal_dummy == 1 {
-            res.normal = vec4<f32>(0, 0, f32(f16(0.1)), 1.0);
+            res.normal = vec4<f32>(0, 0, NO_NORMAL_MAP, 1.0);
         }
         else {
             res.normal = normal;
@@ -94,5 +118,29 @@ fn fragment(in: VertexOutput) -> FragmentOutput {
         res.normal = vec4<f32>(0.0);
     }
 
+    // r: specular intensity, g: shininess, b: light group
+    if covered {
+        var specular_intensity = 0.0;
+        if specular_dummy == 0 {
+            specular_intensity = specular.r;
+        }
+        res.specular = vec4<f32>(specular_intensity, in.shininess, in.light_group, 1.0);
+    }
+    else {
+        res.specular = vec4<f32>(0.0);
+    }
+
+    // r: parallax offset, in world units, g: normal mode override (0 follows the config)
+    if covered {
+        var parallax = 0.0;
+        if height_map_dummy == 0 {
+            parallax = height.r * in.parallax;
+        }
+        res.parallax = vec4<f32>(max(parallax, 0.0), in.normal_mode, 0.0, 1.0);
+    }
+    else {
+        res.parallax = vec4<f32>(0.0);
+    }
+
     return res; 
 }
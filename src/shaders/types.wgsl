@@ -9,12 +9,18 @@
 
 @group(0) @binding(2) var dt_lut_sampler: sampler;
 
+// blue channel written to the normal map by sprites without a normal map: 0.1 rounded to f16 precision.
+// this avoids requiring the `shader-f16` feature, which isn't available on many WebGPU devices.
+const NO_NORMAL_MAP: f32 = 0.0999755859375;
+
 struct PointLight {
     pos: vec2<f32>,
     intensity: f32,
     radius: f32,
 
     color: vec4<f32>, 
+    // color at the light's center, equal to the color if the light has no inner color
+    inner_color: vec4<f32>,
 
     core_radius: f32,
     core_boost: f32, 
@@ -34,7 +40,34 @@ struct PointLight {
 
     z: f32,
     height: f32,
-    
+
+    // 1 if the light only affects sprites of its light group
+    has_light_group: u32,
+    light_group: u32,
+
+    // 1 if the falloff gradient texture replaces the analytic falloff
+    has_falloff_gradient: u32,
+    // bitmask of the light groups the light doesn't illuminate
+    excluded_groups: u32,
+
+    // 1 if the light uses screen-space shadows, when the camera supports them
+    screen_space_shadows: u32,
+
+    // 1 if the light only affects sprites with a z in [z_min, z_max)
+    has_z_range: u32,
+    z_min: f32,
+    z_max: f32,
+
+    // cosine and sine of the cookie's rotation, its scale and its offset, from its LightTextureTransform
+    cookie_rotation: vec2<f32>,
+    cookie_scale: vec2<f32>,
+    cookie_offset: vec2<f32>,
+
+    // number of samples of the line light this light is a sample of, which emits from the segment within its range. 0 for other lights
+    segment_samples: u32,
+    segment_range: f32,
+    segment_a: vec2<f32>,
+    segment_b: vec2<f32>,
 }
 
 struct PolyOccluder {
@@ -43,7 +76,29 @@ struct PolyOccluder {
     z: f32,
     opacity: f32,
     color: vec4<f32>, 
+    blocks_channels: vec4<f32>,
     z_sorting: u32,
+    tint_strength: f32,
+    // 0 if only the shadow of its edges shades its own area, 1 if it's fully shaded, 2 if it's never shaded
+    shade_self: u32,
+    hard_shadows: u32,
+    casts_shadow: u32,
+    // distance from the occluder over which its shadow fades out, 0 for no fade
+    shadow_fade_distance: f32,
+    // vertices are stored relative to the occluder, so identical shapes can share them
+    pos: vec2f,
+    // cosine and sine of the occluder's rotation
+    rotation: vec2f,
+    // whether the vertices have their own opacities
+    vertex_opacity: u32,
+    // fraction of the light reaching the occluder emitted back in its color, inside its area
+    glow_through: f32,
+}
+
+struct PolyVertex {
+    // relative to the occluder
+    pos: vec2f,
+    opacity: f32,
 }
 
 struct OccluderPointer {
@@ -63,7 +118,19 @@ struct RoundOccluder {
     z: f32, 
     opacity: f32, 
     color: vec4f,
+    blocks_channels: vec4f,
     z_sorting: u32, 
+    tint_strength: f32,
+    // 0 if only the shadow of its edges shades its own area, 1 if it's fully shaded, 2 if it's never shaded
+    shade_self: u32,
+    hard_shadows: u32,
+    casts_shadow: u32,
+    // distance light travels through the occluder before being fully blocked, 0 for a hard edge
+    feather: f32,
+    // distance from the occluder over which its shadow fades out, 0 for no fade
+    shadow_fade_distance: f32,
+    // fraction of the light reaching the occluder emitted back in its color, inside its area
+    glow_through: f32,
 }
 
 struct FireflyConfig {
@@ -71,6 +138,10 @@ struct FireflyConfig {
     ambient_brightness: f32, 
     light_bands: f32,
     soft_shadows: u32,
+    // width in pixels of the anti-aliased edge of hard polygon shadows
+    shadow_edge_feather: f32,
+    // fraction of the light that always reaches shadowed fragments
+    min_shadow_light: f32,
     z_sorting: u32,
     z_sorting_error_margin: f32,
     // 0 - none, 1 - simple, 2 - topdown
@@ -81,7 +152,47 @@ struct FireflyConfig {
     // 0 - multiply, 1 - add, 2 - max, 3 - min, 4 - none
     combination_mode: u32,
 
-    texture_scale: vec2<f32>
+    texture_scale: vec2<f32>,
+    shadow_bias: f32,
+
+    // 0 - multiply, 1 - add, 2 - screen, 3 - soft light
+    lightmap_blend: u32,
+
+    fill_direction: vec2<f32>,
+    fill_color: vec3<f32>,
+    fill_strength: f32,
+
+    // temporal upsampling: sub-texel offset of the lightmap grid, and uv offset of the previous frame
+    jitter: vec2<f32>,
+    history_offset: vec2<f32>,
+    history_valid: u32,
+    // how much the current frame contributes to the history, and how far the history can stray from it
+    temporal_blend: f32,
+    temporal_clamp_margin: f32,
+
+    exposure: f32,
+    // 0 if unlimited
+    max_luminance: f32,
+
+    // 1 if the light is scaled by the gain in the exposure texture
+    auto_exposure: u32,
+    auto_exposure_target: f32,
+    // how much of the new gain is blended in this frame
+    auto_exposure_adaptation: f32,
+    auto_exposure_min_gain: f32,
+    auto_exposure_max_gain: f32,
+
+    // 1 if the ambient color is sampled from the ambient texture
+    has_ambient_texture: u32,
+
+    ao_strength: f32,
+    ao_radius: f32,
+
+    // 1 if opted-in lights use the occluder coverage texture for their shadows
+    screen_space_shadows: u32,
+
+    // uv rect (min, max) of the camera's viewport in its render target
+    viewport: vec4<f32>,
 }
 
 // Should correspond to the value in buffers.rs!
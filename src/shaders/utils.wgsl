@@ -153,6 +153,17 @@ fn distance_point_to_line(p: vec2f, a: vec2f, b: vec2f) -> f32 {
     return abs((b.y - a.y) * p.x - (b.x - a.x) * p.y + b.x * a.y - b.y * a.x) / distance(a, b);
 }
 
+fn distance_point_to_segment(p: vec2f, a: vec2f, b: vec2f) -> f32 {
+    let ab = b - a;
+    let len = dot(ab, ab);
+    if len == 0. {
+        return distance(p, a);
+    }
+
+    let t = clamp(dot(p - a, ab) / len, 0., 1.);
+    return distance(p, a + ab * t);
+}
+
 // get intersection point of [a, b] and [c, d]
 fn intersection_point(a: vec2f, b: vec2f, c: vec2f, d: vec2f) -> vec2f {
     let denom = (a.x - b.x) * (c.y - d.y) - (a.y - b.y) * (c.x - d.x);
@@ -217,7 +228,8 @@ fn intersects_axis_edge(p: vec2f, l: vec2f, coord: f32, min_v: f32, max_v: f32,
     
     if (is_vertical) {
         // Vertical edge (fixed x)
-        // if (abs(d.x) < 1e-6) { return res; } 
+        // parallel to the edge, the neighbouring edges handle it
+        if (abs(d.x) < 1e-6) { return res; }
         let t = (coord - p.x) / d.x; 
         let y_hit = p.y + t * d.y;
         
@@ -227,7 +239,7 @@ fn intersects_axis_edge(p: vec2f, l: vec2f, coord: f32, min_v: f32, max_v: f32,
         }
     } else {
         // Horizontal edge (fixed y)
-        // if (abs(d.y) < 1e-6) { return res; }
+        if (abs(d.y) < 1e-6) { return res; }
         let t = (coord - p.y) / d.y; 
         let x_hit = p.x + t * d.x;
         
@@ -276,7 +288,8 @@ fn intersects_corner_arc(p1: vec2f, p2: vec2f, c: vec2f, r: f32, quadrant: vec2f
             
             res.half_intersection = true; 
 
-            if sign(local_hit.x) == quadrant.x && sign(local_hit.y) == quadrant.y {
+            // inclusive, so hits exactly where the arc meets an edge aren't missed by both
+            if local_hit.x * quadrant.x >= 0.0 && local_hit.y * quadrant.y >= 0.0 {
                 res.full_intersection = true;
                 return res; 
             }
@@ -294,7 +307,10 @@ fn intersects_half(a: vec2<f32>, b: vec2<f32>, c: vec2<f32>, d: vec2<f32>) -> bo
     return on_ab && on_cd; 
 }
 
-fn falloff(x: f32, falloff: u32, falloff_intensity: f32) -> f32 {
+// intensity multiplier at the normalized distance d, going from 1 at 0 to 0 at 1 (except for no falloff)
+fn falloff(d: f32, falloff: u32, falloff_intensity: f32) -> f32 {
+    let x = clamp(d, 0.0, 1.0);
+
     if falloff == 0 {
         let x2 = x * x; 
         return ((1.0 - x2) * (1.0 - x2) / (1.0 + falloff_intensity * x2));
@@ -302,6 +318,13 @@ fn falloff(x: f32, falloff: u32, falloff_intensity: f32) -> f32 {
     else if falloff == 1 { 
         return ((1.0 - x) / (1.0 + falloff_intensity * x));
     }
+    // exponent, stored in the falloff intensity
+    else if falloff == 3 {
+        return pow(1.0 - x, falloff_intensity);
+    }
+    else if falloff == 4 {
+        return 1.0 - smoothstep(0.0, 1.0, x);
+    }
     
     return 1.0;
 }
\ No newline at end of file
@@ -0,0 +1,50 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+
+struct VolumetricLight {
+    // uv position of the light
+    pos: vec2<f32>,
+    // radius of the light, in uv units on each axis
+    radius: vec2<f32>,
+    strength: f32,
+    samples: u32,
+}
+
+@group(0) @binding(0)
+var lightmap: texture_2d<f32>;
+
+@group(0) @binding(1)
+var texture_sampler: sampler;
+
+@group(0) @binding(2)
+var<storage> lights: array<VolumetricLight>;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    var res = vec3f(0.0);
+
+    for (var i = 0u; i < arrayLength(&lights); i += 1) {
+        let light = lights[i];
+        let to_light = light.pos - in.uv;
+
+        // distance to the light, relative to its radius
+        let x = length(to_light / light.radius);
+        if x >= 1.0 {
+            continue;
+        }
+
+        // march towards the light, accumulating the light scattered along the way.
+        // shadowed samples are dark, which carves the shafts out of the glow
+        let samples = max(light.samples, 1u);
+        var scattered = vec3f(0.0);
+
+        for (var s = 0u; s < samples; s += 1) {
+            let uv = in.uv + to_light * (f32(s) + 0.5) / f32(samples);
+            scattered += textureSampleLevel(lightmap, texture_sampler, uv, 0.0).rgb;
+        }
+
+        let fade = (1.0 - x) * (1.0 - x);
+        res += scattered / f32(samples) * light.strength * fade;
+    }
+
+    return vec4f(res, 1.0);
+}
@@ -6,9 +6,11 @@
 
 use std::ops::Range;
 
-use crate::data::FireflyConfig;
+use crate::data::{FireflyConfig, NormalMode};
+use crate::lights::ExtractedPointLight;
+use crate::occluders::Occluder2d;
 use crate::phases::SpritePhase;
-use crate::pipelines::{SpritePipeline, SpritePipelineKey};
+use crate::pipelines::{SpritePipeline, SpritePipelineKey, report_pipeline_error, stencil_format};
 use crate::utils::{compute_slices_on_asset_event, compute_slices_on_sprite_change};
 
 use bevy::asset::{AssetEventSystems, AssetPath};
@@ -38,6 +40,7 @@ use bevy::{
             sort_phase_system,
         },
         render_resource::*,
+        renderer::RenderDevice,
         view::{ExtractedView, Msaa, RenderVisibleEntities, RetainedViewEntity, ViewUniformOffset},
     },
 };
@@ -60,6 +63,15 @@ pub(crate) struct ExtractedSprite {
     /// PERF: storing an `AssetId` instead of `Handle<Image>` enables some optimizations (`ExtractedSprite` becomes `Copy` and doesn't need to be dropped)
     pub image_handle_id: AssetId<Image>,
     pub normal_handle_id: Option<AssetId<Image>>,
+    pub specular_handle_id: Option<AssetId<Image>>,
+    pub shininess: f32,
+    pub height_map_handle_id: Option<AssetId<Image>>,
+    pub parallax: f32,
+    pub light_group: u32,
+    /// The sprite's own [normal mode](NormalMode), overriding the config's.
+    pub normal_mode: Option<NormalMode>,
+    /// Minimum alpha at which the sprite covers what's behind it. See [`SpriteBlendMode::coverage_threshold`].
+    pub coverage: f32,
     pub flip_x: bool,
     pub flip_y: bool,
     pub kind: ExtractedSpriteKind,
@@ -85,6 +97,22 @@ pub(crate) struct ExtractedSprites {
     pub sprites: Vec<ExtractedSprite>,
 }
 
+impl ExtractedSprites {
+    /// Whether the [`SpecularMapTexture`](crate::SpecularMapTexture) is needed this frame: either a sprite has a [`SpecularMap`],
+    /// or a light reads the sprites' [light groups](LightGroup) from it.
+    ///
+    /// Otherwise the texture isn't allocated and the lightmap reads every pixel as not covered by a sprite.
+    pub fn needs_specular_map<'a>(
+        &self,
+        mut lights: impl Iterator<Item = &'a ExtractedPointLight>,
+    ) -> bool {
+        self.sprites
+            .iter()
+            .any(|sprite| sprite.specular_handle_id.is_some())
+            || lights.any(|light| light.light_group.is_some() || light.excluded_groups != 0)
+    }
+}
+
 #[derive(Resource, Default)]
 pub(crate) struct ExtractedSlices {
     pub slices: Vec<ExtractedSlice>,
@@ -104,12 +132,17 @@ pub(crate) struct SpriteInstance {
     pub z: f32,
     pub height: f32,
     pub y: f32,
-    pub _padding: f32,
+    pub shininess: f32,
+    pub light_group: f32,
+    pub parallax: f32,
+    /// 0 if the sprite follows the config's normal mode, otherwise its own mode + 1.
+    pub normal_mode: f32,
+    pub coverage: f32,
 }
 
 impl SpriteInstance {
     #[inline]
-    pub fn from(transform: &Affine3A, uv_offset_scale: &Vec4, z: f32, height: f32, y: f32) -> Self {
+    pub fn from(transform: &Affine3A, uv_offset_scale: &Vec4, sprite: &ExtractedSprite) -> Self {
         let transpose_model_3x3 = transform.matrix3.transpose();
         Self {
             i_model_transpose: [
@@ -117,11 +150,18 @@ impl SpriteInstance {
                 transpose_model_3x3.y_axis.extend(transform.translation.y),
                 transpose_model_3x3.z_axis.extend(transform.translation.z),
             ],
-            z,
+            z: sprite.transform.translation().z,
             i_uv_offset_scale: uv_offset_scale.to_array(),
-            height,
-            y,
-            _padding: 0.0,
+            height: sprite.height,
+            y: sprite.transform.translation().y,
+            shininess: sprite.shininess,
+            light_group: sprite.light_group as f32,
+            parallax: sprite.parallax,
+            normal_mode: sprite
+                .normal_mode
+                .as_ref()
+                .map_or(0.0, |mode| (mode.as_u32() + 1) as f32),
+            coverage: sprite.coverage,
         }
     }
 }
@@ -154,12 +194,28 @@ pub(crate) struct SpriteBatch {
     pub image_handle_id: AssetId<Image>,
     pub normal_handle_id: AssetId<Image>,
     pub normal_dummy: bool,
+    pub specular_handle_id: AssetId<Image>,
+    pub specular_dummy: bool,
+    pub height_map_handle_id: AssetId<Image>,
+    pub height_map_dummy: bool,
     pub range: Range<u32>,
 }
 
+/// Key of a sprite material bind group: sprite image, normal map, normal dummy, specular map, specular dummy,
+/// height map, height map dummy.
+pub(crate) type ImageBindGroupKey = (
+    AssetId<Image>,
+    AssetId<Image>,
+    bool,
+    AssetId<Image>,
+    bool,
+    AssetId<Image>,
+    bool,
+);
+
 #[derive(Resource, Default)]
 pub(crate) struct ImageBindGroups {
-    pub values: HashMap<(AssetId<Image>, AssetId<Image>, bool), BindGroup>,
+    pub values: HashMap<ImageBindGroupKey, BindGroup>,
 }
 
 /// Component you can add to an entity that also has a Sprite, containing the corresponding sprite's normal map.
@@ -167,13 +223,17 @@ pub(crate) struct ImageBindGroups {
 /// The image **MUST** correspond 1:1 with the size and format of the sprite image.
 /// E.g. if the sprite image is a sprite sheet, the normal map will also need to be a sprite sheet of exactly the same dimensions, padding, etc.
 ///
+/// The normal map is sampled with the same uvs as the sprite image, including the sprite's [rect](Sprite::rect) and
+/// [texture atlas](Sprite::texture_atlas), so each animation frame uses its matching region of the normal map.
+/// Check the `sprite_sheet` example.
+///
 /// # Example
 ///
 /// Automatic image loading:
 /// ```
 /// commands.spawn((
 ///     Sprite::from_image(asset_server.load("some_sprite.png")),
-///     NormalMap::from_file("some_sprite_normal.png"),
+///     NormalMap::from_file("some_sprite_normal.png", &asset_server),
 /// ));
 /// ```
 ///
@@ -189,11 +249,126 @@ pub(crate) struct ImageBindGroups {
 /// ```
 ///  
 /// See [Sprite] for more information on using sprites.
-#[derive(Component)]
+#[derive(Component, Reflect)]
 pub struct NormalMap {
     image: Handle<Image>,
 }
 
+/// Component you can add to an entity that also has a Sprite, containing the corresponding sprite's specular map.
+///
+/// Sprites with a specular map get a Blinn-Phong specular highlight from each light, based on their [NormalMap]
+/// and a viewer looking straight at the screen. The red channel of the image describes the specular intensity of each pixel.
+///
+/// Just like the [NormalMap], the image **MUST** correspond 1:1 with the size and format of the sprite image.
+///
+/// Specular highlights are only rendered if the [normal mode](crate::prelude::FireflyConfig::normal_mode) is not [None](crate::prelude::NormalMode::None).
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((
+///     Sprite::from_image(asset_server.load("some_sprite.png")),
+///     NormalMap::from_file("some_sprite_normal.png", &asset_server),
+///     SpecularMap::from_file("some_sprite_specular.png", &asset_server).with_shininess(16.0),
+/// ));
+/// ```
+#[derive(Component, Reflect)]
+pub struct SpecularMap {
+    image: Handle<Image>,
+    /// The shininess (Blinn-Phong exponent) of the sprite. Higher values create smaller, sharper highlights.
+    ///
+    /// **Default:** 32.
+    pub shininess: f32,
+}
+
+impl SpecularMap {
+    /// Get the handle of the specular map image.
+    pub fn handle(&self) -> Handle<Image> {
+        self.image.clone()
+    }
+
+    /// Construct a new [SpecularMap] from the [path](AssetPath) to the image and the [AssetServer].
+    ///
+    /// This image file needs to match the corresponding [Sprite] image 1:1.  
+    pub fn from_file<'a>(path: impl Into<AssetPath<'a>>, asset_server: &AssetServer) -> Self {
+        let image: Handle<Image> =
+            asset_server.load_with_settings(path, |x: &mut ImageLoaderSettings| x.is_srgb = false);
+
+        Self {
+            image,
+            shininess: 32.0,
+        }
+    }
+
+    /// Construct a new [SpecularMap] from an image handle. Just like with the [NormalMap], this image should be loaded without gamma correction.
+    pub fn from_image(image: Handle<Image>) -> Self {
+        Self {
+            image,
+            shininess: 32.0,
+        }
+    }
+
+    /// Construct a new specular map with the specified [shininess](SpecularMap::shininess).
+    pub fn with_shininess(mut self, shininess: f32) -> Self {
+        self.shininess = shininess;
+        self
+    }
+}
+
+/// Component you can add to an entity that also has a Sprite, containing the corresponding sprite's height map.
+///
+/// The red channel of the image describes the height of each pixel, from 0 (ground) to 1 (the sprite's full [scale](HeightMap::scale)).
+/// Taller pixels are lit as if they were shifted towards each light by their height, so the lighting on the sprite
+/// moves slightly as lights move around it, faking depth.
+///
+/// Just like the [NormalMap], the image **MUST** correspond 1:1 with the size and format of the sprite image.
+///
+/// # Example
+///
+/// ```
+/// commands.spawn((
+///     Sprite::from_image(asset_server.load("some_sprite.png")),
+///     NormalMap::from_file("some_sprite_normal.png", &asset_server),
+///     HeightMap::from_file("some_sprite_height.png", &asset_server).with_scale(8.0),
+/// ));
+/// ```
+#[derive(Component, Reflect)]
+pub struct HeightMap {
+    image: Handle<Image>,
+    /// The offset, in world units, of the tallest pixels of the sprite.
+    ///
+    /// **Default:** 4.
+    pub scale: f32,
+}
+
+impl HeightMap {
+    /// Get the handle of the height map image.
+    pub fn handle(&self) -> Handle<Image> {
+        self.image.clone()
+    }
+
+    /// Construct a new [HeightMap] from the [path](AssetPath) to the image and the [AssetServer].
+    ///
+    /// This image file needs to match the corresponding [Sprite] image 1:1.  
+    pub fn from_file<'a>(path: impl Into<AssetPath<'a>>, asset_server: &AssetServer) -> Self {
+        let image: Handle<Image> =
+            asset_server.load_with_settings(path, |x: &mut ImageLoaderSettings| x.is_srgb = false);
+
+        Self { image, scale: 4.0 }
+    }
+
+    /// Construct a new [HeightMap] from an image handle. Just like with the [NormalMap], this image should be loaded without gamma correction.
+    pub fn from_image(image: Handle<Image>) -> Self {
+        Self { image, scale: 4.0 }
+    }
+
+    /// Construct a new height map with the specified [scale](HeightMap::scale).
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+}
+
 /// Optional component you can add to sprites.
 ///
 /// Describes the sprite object's 2d height, useful for emulating 3d lighting in top-down 2d games.
@@ -202,6 +377,77 @@ pub struct NormalMap {
 #[derive(Component, Default, Reflect)]
 pub struct SpriteHeight(pub f32);
 
+/// Optional component you can add to sprites.
+///
+/// Lights with a matching [light group](crate::prelude::PointLight2d::light_group) only illuminate
+/// the sprites in their group, and lights with a [`LightMask`](crate::prelude::LightMask) skip the groups it excludes.
+/// Sprites without this component are part of group 0.
+///
+/// The group is stored in a 16-bit float texture, so only values up to [2048](LightGroup::MAX) are supported.
+/// A warning is logged once if a sprite or light uses a higher group.
+#[derive(Component, Default, Clone, Copy, Reflect)]
+pub struct LightGroup(pub u32);
+
+impl LightGroup {
+    /// The highest supported light group. This is the largest integer stored exactly in a 16-bit float.
+    pub const MAX: u32 = 2048;
+
+    /// Logs a warning once if the group can't be stored exactly.
+    pub(crate) fn warn_unsupported(group: u32) {
+        if group > Self::MAX {
+            warn_once!(
+                "Light group {group} is higher than the supported maximum of {}. Lights and sprites in it may not match.",
+                Self::MAX
+            );
+        }
+    }
+}
+
+/// Optional component you can add to sprites, describing how they are blended when rendered.
+///
+/// Sprites only write their z (used for [z-sorting](crate::prelude::FireflyConfig::z_sorting)), normals and specular data
+/// where they cover what's behind them, which is decided by the [coverage threshold](SpriteBlendMode::coverage_threshold).
+/// Alpha blended sprites only cover where they're fully opaque, while premultiplied sprites, which usually have soft edges,
+/// choose how opaque a pixel has to be. Additive sprites don't hide what's behind them, so they don't cover anything:
+/// the pixels below them keep their own z-sorting, normals and light groups.
+///
+/// Sprites without this component are [alpha blended](SpriteBlendMode::Alpha).
+#[derive(Component, Default, Clone, Copy, PartialEq, Debug, Reflect)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SpriteBlendMode {
+    /// Regular alpha blending, with straight (non-premultiplied) alpha.
+    #[default]
+    Alpha,
+
+    /// Alpha blending with colors that are premultiplied by their alpha.
+    Premultiplied {
+        /// Pixels with at least this alpha cover what's behind them. Clamped to be above 0 and at most 1.
+        ///
+        /// Lower values let the soft edges of the sprite hide the z-sorting and normals of what's behind it.
+        coverage: f32,
+    },
+
+    /// Additive blending, e.g. for glows and particles. The sprite is left out of the stencil pass.
+    Additive,
+}
+
+impl SpriteBlendMode {
+    /// Get the minimum alpha at which a pixel of the sprite covers what's behind it, or None if it never does.
+    pub fn coverage_threshold(&self) -> Option<f32> {
+        match self {
+            Self::Alpha => Some(1.0),
+            Self::Premultiplied { coverage } => Some(coverage.clamp(f32::EPSILON, 1.0)),
+            Self::Additive => None,
+        }
+    }
+
+    /// Whether a pixel of the sprite with the given alpha covers what's behind it. This matches the stencil pass.
+    pub fn covers(&self, alpha: f32) -> bool {
+        self.coverage_threshold()
+            .is_some_and(|threshold| alpha >= threshold)
+    }
+}
+
 impl NormalMap {
     /// Get the handle of the normal map image.
     ///
@@ -215,6 +461,22 @@ impl NormalMap {
     /// This image file needs to match the corresponding [Sprite] image 1:1.  
     ///
     /// You can use [`.handle()`](NormalMap::handle) to get the resulting image handle.
+    ///
+    /// ```
+    /// # use bevy::prelude::*;
+    /// # use bevy_firefly::prelude::*;
+    /// fn spawn_sprite(mut commands: Commands, asset_server: Res<AssetServer>) {
+    ///     let normal_map = NormalMap::from_file("some_sprite_normal.png", &asset_server);
+    ///     let handle: Handle<Image> = normal_map.handle();
+    ///
+    ///     commands.spawn((
+    ///         Sprite::from_image(asset_server.load("some_sprite.png")),
+    ///         normal_map,
+    ///     ));
+    ///     # drop(handle);
+    /// }
+    /// # bevy::ecs::system::assert_is_system(spawn_sprite);
+    /// ```
     pub fn from_file<'a>(path: impl Into<AssetPath<'a>>, asset_server: &AssetServer) -> Self {
         let image: Handle<Image> =
             asset_server.load_with_settings(path, |x: &mut ImageLoaderSettings| x.is_srgb = false);
@@ -235,6 +497,36 @@ impl NormalMap {
     }
 }
 
+/// Get a lit sprite that is normal mapped and casts shadows, loading the sprite and normal map images from their [paths](AssetPath).
+///
+/// Add a [Transform] and any other components (e.g. [SpriteHeight], [Anchor](bevy::sprite::Anchor)) alongside it.
+///
+/// # Example
+///
+/// ```
+/// # use bevy::prelude::*;
+/// # use bevy_firefly::prelude::*;
+/// fn spawn_crate(mut commands: Commands, asset_server: Res<AssetServer>) {
+///     commands.spawn((
+///         lit_sprite("crate.png", "crate_normal.png", Occluder2d::rectangle(12., 5.), &asset_server),
+///         Transform::from_translation(vec3(0., -20., 0.)),
+///     ));
+/// }
+/// # bevy::ecs::system::assert_is_system(spawn_crate);
+/// ```
+pub fn lit_sprite<'a, 'b>(
+    image: impl Into<AssetPath<'a>>,
+    normal_map: impl Into<AssetPath<'b>>,
+    occluder: Occluder2d,
+    asset_server: &AssetServer,
+) -> (Sprite, NormalMap, Occluder2d) {
+    (
+        Sprite::from_image(asset_server.load(image)),
+        NormalMap::from_file(normal_map, asset_server),
+        occluder,
+    )
+}
+
 /// Plugin that processed and queues sprites into render phases. Added
 /// automatically by [`FireflyPlugin`](crate::prelude::FireflyPlugin).
 pub struct SpritesPlugin;
@@ -286,7 +578,9 @@ fn queue_sprites(
     pipeline: Res<SpritePipeline>,
     mut pipelines: ResMut<SpecializedRenderPipelines<SpritePipeline>>,
     pipeline_cache: Res<PipelineCache>,
+    render_device: Res<RenderDevice>,
     extracted_sprites: Res<ExtractedSprites>,
+    lights: Query<&ExtractedPointLight>,
     mut phases: ResMut<ViewSortedRenderPhases<SpritePhase>>,
     mut views: Query<(
         &FireflyConfig,
@@ -299,6 +593,7 @@ fn queue_sprites(
     )>,
 ) {
     let draw_function = draw_functions.read().id::<DrawSprite>();
+    let needs_specular_map = extracted_sprites.needs_specular_map(lights.iter());
 
     for (config, visible_entities, camera, view, msaa, tonemapping, dither) in &mut views {
         let Some(phase) = phases.get_mut(&view.retained_view_entity) else {
@@ -332,11 +627,18 @@ fn queue_sprites(
             }
         }
 
-        if config.enable_32bit_stencils {
+        if stencil_format(config.enable_32bit_stencils, &render_device)
+            == TextureFormat::Rgba32Float
+        {
             view_key |= SpritePipelineKey::ENABLED_32BIT_STENCIL;
         }
 
+        if needs_specular_map {
+            view_key |= SpritePipelineKey::SPECULAR_MAP;
+        }
+
         let pipeline = pipelines.specialize(&pipeline_cache, &pipeline, view_key);
+        report_pipeline_error(&pipeline_cache, pipeline, "sprite stencil");
 
         view_entities.clear();
         if let Some(visible_entities) = visible_entities.get::<Sprite>() {
@@ -424,6 +726,10 @@ impl<P: PhaseItem, const I: usize> RenderCommand<P> for SetSpriteTextureBindGrou
             batch.image_handle_id,
             batch.normal_handle_id,
             batch.normal_dummy,
+            batch.specular_handle_id,
+            batch.specular_dummy,
+            batch.height_map_handle_id,
+            batch.height_map_dummy,
         )) else {
             return RenderCommandResult::Skip;
         };
@@ -465,3 +771,78 @@ impl<P: PhaseItem> RenderCommand<P> for DrawSpriteBatch {
         RenderCommandResult::Success
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::{
+        asset::RenderAssetUsages,
+        image::{CompressedImageFormats, ImageSampler, ImageType},
+    };
+
+    // a 16x16 soft orange disc, with colors premultiplied by alpha
+    fn premultiplied_glow() -> Vec<[u8; 4]> {
+        let image = Image::from_buffer(
+            include_bytes!("../assets/glow_premultiplied.png"),
+            ImageType::Extension("png"),
+            CompressedImageFormats::NONE,
+            false,
+            ImageSampler::Default,
+            RenderAssetUsages::default(),
+        )
+        .unwrap();
+
+        let pixels: Vec<[u8; 4]> = image
+            .data
+            .unwrap()
+            .chunks_exact(4)
+            .map(|pixel| pixel.try_into().unwrap())
+            .collect();
+
+        assert_eq!(pixels.len(), 16 * 16);
+        assert!(pixels.iter().all(|[r, g, b, a]| r <= a && g <= a && b <= a));
+        pixels
+    }
+
+    fn covered_pixels(blend_mode: SpriteBlendMode, pixels: &[[u8; 4]]) -> usize {
+        pixels
+            .iter()
+            .filter(|pixel| blend_mode.covers(pixel[3] as f32 / 255.0))
+            .count()
+    }
+
+    #[test]
+    fn premultiplied_coverage_threshold() {
+        let pixels = premultiplied_glow();
+        let opaque = pixels.iter().filter(|pixel| pixel[3] == 255).count();
+        let visible = pixels.iter().filter(|pixel| pixel[3] > 0).count();
+
+        assert_eq!(opaque, 52);
+        assert_eq!(visible, 156);
+
+        // alpha blended sprites only cover their opaque center
+        assert_eq!(covered_pixels(SpriteBlendMode::Alpha, &pixels), opaque);
+        assert_eq!(
+            covered_pixels(SpriteBlendMode::Premultiplied { coverage: 1.0 }, &pixels),
+            opaque
+        );
+
+        // premultiplied sprites can also cover their soft edges
+        assert_eq!(
+            covered_pixels(SpriteBlendMode::Premultiplied { coverage: 0.5 }, &pixels),
+            88
+        );
+
+        // the threshold is clamped, so fully transparent pixels never cover anything
+        assert_eq!(
+            covered_pixels(SpriteBlendMode::Premultiplied { coverage: 0.0 }, &pixels),
+            visible
+        );
+        assert_eq!(
+            covered_pixels(SpriteBlendMode::Premultiplied { coverage: 2.0 }, &pixels),
+            opaque
+        );
+
+        assert_eq!(covered_pixels(SpriteBlendMode::Additive, &pixels), 0);
+    }
+}
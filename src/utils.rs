@@ -1,3 +1,5 @@
+//! Module containing helpers used across the crate, along with public ones such as [`world_cursor`].
+
 use bevy::{platform::collections::HashSet, prelude::*, sprite::Anchor};
 
 use crate::sprites::ExtractedSlice;
@@ -237,3 +239,35 @@ pub(crate) fn apply_scaling(
         }
     }
 }
+
+/// Get the world position of the cursor, as seen by the given camera.
+///
+/// Returns None if the cursor isn't over the window, or if it's outside of the camera's [viewport](Camera::viewport).
+///
+/// # Example
+///
+/// ```
+/// fn follow_cursor(
+///     window: Single<&Window>,
+///     camera: Single<(&Camera, &GlobalTransform)>,
+///     mut light: Single<&mut Transform, With<PointLight2d>>,
+/// ) {
+///     if let Some(pos) = world_cursor(&window, *camera) {
+///         light.translation = pos.extend(0.);
+///     }
+/// }
+/// ```
+pub fn world_cursor(
+    window: &Window,
+    (camera, camera_transform): (&Camera, &GlobalTransform),
+) -> Option<Vec2> {
+    let cursor = window.cursor_position()?;
+
+    if let Some(viewport) = camera.logical_viewport_rect()
+        && !viewport.contains(cursor)
+    {
+        return None;
+    }
+
+    camera.viewport_to_world_2d(camera_transform, cursor).ok()
+}
@@ -3,7 +3,7 @@
 //! for instance occluders can be off-screen and still visible because they can block light
 //! that would be otherwise visible on-screen.
 
-use std::any::TypeId;
+use std::{any::TypeId, time::Duration};
 
 use bevy::{
     camera::visibility::{SetViewVisibility, VisibilitySystems, VisibleEntities},
@@ -13,7 +13,7 @@ use bevy::{
 
 use crate::{
     data::FireflyConfig,
-    lights::{LightHeight, PointLight2d},
+    lights::{LightHeight, LineLightSample, PointLight2d},
     occluders::{Occluder2dEnabled, Occluder2dShape},
     prelude::Occluder2d,
 };
@@ -31,7 +31,14 @@ pub struct NotVisible;
 
 impl Default for VisibilityTimer {
     fn default() -> Self {
-        Self(Timer::from_seconds(0.1, TimerMode::Once))
+        Self::new(Duration::from_secs_f32(0.1))
+    }
+}
+
+impl VisibilityTimer {
+    /// Construct a new timer that finishes after the given delay.
+    pub fn new(delay: Duration) -> Self {
+        Self(Timer::new(delay, TimerMode::Once))
     }
 }
 
@@ -71,6 +78,7 @@ fn mark_visible_lights(
         &GlobalTransform,
         &PointLight2d,
         &LightHeight,
+        Option<&LineLightSample>,
         &mut ViewVisibility,
         &mut VisibilityTimer,
     )>,
@@ -100,12 +108,24 @@ fn mark_visible_lights(
 
     light_rect.0 = Rect::EMPTY;
 
-    for (entity, transform, light, height, mut visibility, mut visibility_timer) in &mut lights {
+    for (entity, transform, light, height, line_sample, mut visibility, mut visibility_timer) in
+        &mut lights
+    {
         let pos = transform.translation().truncate() - vec2(0.0, height.0) + light.offset.xy();
 
-        let light_aabb = Aabb2d {
-            min: pos - light.radius,
-            max: pos + light.radius,
+        // samples of line lights only light the area around their segment
+        let light_aabb = match line_sample {
+            Some(sample) => {
+                let [a, b] = sample.world_segment(transform);
+                Aabb2d {
+                    min: a.min(b) - sample.range,
+                    max: a.max(b) + sample.range,
+                }
+            }
+            None => Aabb2d {
+                min: pos - light.radius,
+                max: pos + light.radius,
+            },
         };
 
         for (camera_aabb, camera_rect, visible_entities) in camera_rects.iter_mut() {
@@ -121,8 +141,8 @@ fn mark_visible_lights(
                 light_rect.0 = light_rect
                     .0
                     .union(camera_rect.union_point(pos).intersect(Rect {
-                        min: pos - light.radius,
-                        max: pos + light.radius,
+                        min: light_aabb.min,
+                        max: light_aabb.max,
                     }));
             }
         }
@@ -138,9 +158,16 @@ fn mark_visible_occluders(
         &mut ViewVisibility,
         &mut VisibilityTimer,
     )>,
+    configs: Query<&FireflyConfig>,
     light_rect: Res<LightRect>,
     time: Res<Time>,
 ) {
+    let unload_delay = configs
+        .iter()
+        .map(|config| config.occluder_unload_delay)
+        .max()
+        .unwrap_or(Duration::from_secs_f32(0.1));
+
     let light_rect_aabb = Aabb2d {
         min: light_rect.0.min,
         max: light_rect.0.max,
@@ -153,7 +180,7 @@ fn mark_visible_occluders(
             // let visible_occluders = camera.get_mut(TypeId::of::<Occluder2d>());
             // visible_occluders.push(entity);
 
-            *visibility_timer = default();
+            *visibility_timer = VisibilityTimer::new(unload_delay);
         }
 
         visibility_timer.0.tick(time.delta());
@@ -186,7 +213,9 @@ fn occluder_aabb(
             Occluder2dShape::Polygon { vertices, .. } => {
                 Aabb2d::from_point_cloud(isometry, vertices)
             }
-            Occluder2dShape::Polyline { vertices } => Aabb2d::from_point_cloud(isometry, vertices),
+            Occluder2dShape::Polyline { vertices, .. } => {
+                Aabb2d::from_point_cloud(isometry, vertices)
+            }
         }
     }
 }